@@ -3,48 +3,1040 @@ import type { ActorMethod } from '@dfinity/agent';
 
 export interface Accessory {
   'id' : bigint,
+  'reorder_quantity' : [] | [bigint],
   'updated_at' : [] | [bigint],
+  'inventory_count' : bigint,
   'name' : string,
   'description' : string,
   'created_at' : bigint,
+  'seller' : Principal,
+  'version' : [] | [bigint],
+  'barcode' : [] | [string],
+  'currency' : [] | [string],
+  'deleted_at' : [] | [bigint],
+  'deleted_by' : [] | [Principal],
   'category' : string,
   'is_available' : boolean,
+  'reorder_point' : [] | [bigint],
   'price' : bigint,
+  'weight_grams' : [] | [bigint],
+  'dimensions' : [] | [Dimensions],
+  'manufacturer_part_number' : [] | [string],
+}
+export interface AccessoryCostOfGoodsSold {
+  'accessory_id' : bigint,
+  'revenue' : bigint,
+  'cost' : bigint,
+  'currency' : [] | [string],
+  'quantity' : bigint,
+}
+export interface AccessoryPatch {
+  'reorder_quantity' : [] | [[] | [bigint]],
+  'inventory_count' : [] | [bigint],
+  'name' : [] | [string],
+  'description' : [] | [string],
+  'barcode' : [] | [[] | [string]],
+  'currency' : [] | [string],
+  'category' : [] | [string],
+  'is_available' : [] | [boolean],
+  'reorder_point' : [] | [[] | [bigint]],
+  'price' : [] | [bigint],
+  'weight_grams' : [] | [[] | [bigint]],
+  'dimensions' : [] | [[] | [Dimensions]],
+  'manufacturer_part_number' : [] | [[] | [string]],
 }
 export interface AccessoryPayload {
+  'reorder_quantity' : [] | [bigint],
+  'inventory_count' : bigint,
   'name' : string,
   'description' : string,
+  'barcode' : [] | [string],
+  'currency' : [] | [string],
   'category' : string,
   'is_available' : boolean,
+  'reorder_point' : [] | [bigint],
   'price' : bigint,
+  'weight_grams' : [] | [bigint],
+  'dimensions' : [] | [Dimensions],
+  'manufacturer_part_number' : [] | [string],
+}
+export type AlertStatus = { 'New' : null } |
+  { 'Acknowledged' : null } |
+  { 'Resolved' : null };
+export interface AppliedDiscount {
+  'code' : string,
+  'description' : string,
+  'amount' : bigint,
+}
+export type AuditAction = { 'OwnerChanged' : null } |
+  { 'AccessoryUpdated' : null } |
+  { 'AccessoryPurged' : null } |
+  { 'TransferCancelled' : null } |
+  { 'AccessoryRestored' : null } |
+  { 'AccessoryDeleted' : null } |
+  { 'TransferProposed' : null } |
+  { 'TransferAccepted' : null };
+export interface AuditEntry {
+  'id' : bigint,
+  'accessory_id' : [] | [bigint],
+  'action' : AuditAction,
+  'actor' : Principal,
+  'recorded_at' : bigint,
+  'details' : string,
+}
+export interface Batch {
+  'id' : bigint,
+  'accessory_id' : bigint,
+  'expired' : boolean,
+  'created_at' : bigint,
+  'lot_number' : string,
+  'quantity' : bigint,
+  'remaining' : bigint,
+  'expires_at' : [] | [bigint],
+  'manufactured_at' : [] | [bigint],
+}
+export interface BatchPayload {
+  'unit_cost' : [] | [bigint],
+  'lot_number' : string,
+  'quantity' : bigint,
+  'expires_at' : [] | [bigint],
+  'manufactured_at' : [] | [bigint],
+}
+export interface Block {
+  'principal' : Principal,
+  'blocked_until' : bigint,
+  'blocked_by' : Principal,
+  'reason' : string,
+}
+export interface BulkChange { 'after' : Accessory, 'before' : [] | [Accessory] }
+export type BulkMode = { 'Atomic' : null } |
+  { 'BestEffort' : null };
+export interface BulkReport {
+  'applied' : bigint,
+  'results' : Array<Result_13>,
+  'dry_run' : boolean,
+  'failed' : bigint,
+}
+export interface CallerStats {
+  'principal' : Principal,
+  'rate_limited' : bigint,
+  'calls' : bigint,
+}
+export interface ConvertedAmounts {
+  'total' : Money,
+  'shipping_total' : Money,
+  'discount_total' : Money,
+  'tax_total' : Money,
+  'rounding' : RoundingMode,
+  'subtotal' : Money,
+}
+export interface CostOfGoodsSoldReport {
+  'to' : bigint,
+  'from' : bigint,
+  'lines' : Array<AccessoryCostOfGoodsSold>,
+  'totals' : Array<CostOfGoodsSoldTotal>,
+}
+export interface CostOfGoodsSoldTotal {
+  'revenue' : bigint,
+  'cost' : bigint,
+  'currency' : [] | [string],
+  'quantity' : bigint,
+}
+export type CostingMethod = { 'Fifo' : null } |
+  { 'WeightedAverage' : null };
+export interface Coupon {
+  'max_uses_per_principal' : [] | [bigint],
+  'active' : boolean,
+  'max_uses' : [] | [bigint],
+  'starts_at' : bigint,
+  'code' : string,
+  'ends_at' : [] | [bigint],
+  'kind' : DiscountKind,
+  'uses' : bigint,
+  'created_at' : bigint,
+  'created_by' : Principal,
+  'seller' : [] | [Principal],
+  'version' : [] | [bigint],
+  'currency' : [] | [string],
+  'category' : [] | [string],
+  'min_order_value' : bigint,
+}
+export interface CouponPayload {
+  'max_uses_per_principal' : [] | [bigint],
+  'max_uses' : [] | [bigint],
+  'starts_at' : [] | [bigint],
+  'code' : string,
+  'ends_at' : [] | [bigint],
+  'kind' : DiscountKind,
+  'seller' : [] | [Principal],
+  'currency' : [] | [string],
+  'category' : [] | [string],
+  'min_order_value' : [] | [bigint],
+}
+export interface Currency {
+  'decimals' : number,
+  'code' : string,
+  'created_at' : bigint,
+}
+export interface CurrencyConfig { 'default_currency' : [] | [string] }
+export interface Dimensions {
+  'length_mm' : bigint,
+  'height_mm' : bigint,
+  'width_mm' : bigint,
+}
+export type DiscountKind = { 'FixedAmount' : { 'amount' : bigint } } |
+  { 'Percentage' : { 'basis_points' : bigint } } |
+  { 'BuyXGetY' : { 'buy' : bigint, 'get' : bigint } };
+export type Error = { 'ValidationFailed' : { 'msg' : string } } |
+  { 'PaymentFailed' : { 'msg' : string } } |
+  { 'NotFound' : { 'msg' : string } } |
+  { 'RateLimited' : { 'msg' : string, 'retry_after' : bigint } } |
+  { 'AuthenticationFailed' : { 'msg' : string } } |
+  { 'Conflict' : { 'msg' : string, 'current_version' : bigint } };
+export interface ExchangeRate {
+  'updated_at' : bigint,
+  'updated_by' : Principal,
+  'base' : string,
+  'rate' : bigint,
+  'quote' : string,
+}
+export interface GoodsReceiptLine {
+  'accessory_id' : bigint,
+  'quantity' : bigint,
+}
+export interface GrossMargin {
+  'accessory_id' : bigint,
+  'revenue' : bigint,
+  'cost_of_goods_sold' : bigint,
+  'quantity_sold' : bigint,
+  'margin_bps' : [] | [bigint],
+  'gross_margin' : bigint,
+}
+export interface HttpRequest {
+  'url' : string,
+  'method' : string,
+  'body' : Uint8Array | number[],
+  'headers' : Array<[string, string]>,
+}
+export interface HttpResponse {
+  'body' : Uint8Array | number[],
+  'headers' : Array<[string, string]>,
+  'status_code' : number,
+}
+export interface InventoryValuation {
+  'value' : bigint,
+  'seller' : Principal,
+  'currency' : [] | [string],
+  'quantity' : bigint,
+  'category' : string,
+}
+export interface Invoice {
+  'total' : bigint,
+  'issued_at' : bigint,
+  'seller_contact' : [] | [string],
+  'discount_total' : bigint,
+  'shipping' : [] | [ShippingCharge],
+  'seller' : Principal,
+  'lines' : Array<OrderLine>,
+  'voided_at' : [] | [bigint],
+  'jurisdiction' : [] | [string],
+  'currency' : [] | [string],
+  'number' : bigint,
+  'buyer' : Principal,
+  'order_id' : bigint,
+  'tax_total' : bigint,
+  'seller_name' : [] | [string],
+  'subtotal' : bigint,
+}
+export interface InvoiceLinks { 'html' : string, 'json' : string }
+export type ItemCondition = { 'Opened' : null } |
+  { 'Damaged' : null } |
+  { 'Unopened' : null };
+export interface LedgerTransfer {
+  'block_index' : [] | [bigint],
+  'memo' : Uint8Array | number[],
+  'ledger' : Principal,
+  'account' : Principal,
+  'created_at_time' : bigint,
+  'amount' : bigint,
+}
+export interface LineTax {
+  'inclusive' : boolean,
+  'jurisdiction' : string,
+  'category' : [] | [string],
+  'rule_id' : bigint,
+  'amount' : bigint,
+  'rate_basis_points' : bigint,
+}
+export interface Money {
+  'decimals' : number,
+  'currency' : string,
+  'amount' : bigint,
+}
+export interface Order {
+  'id' : bigint,
+  'status' : OrderStatus,
+  'updated_at' : [] | [bigint],
+  'total' : bigint,
+  'cancellation_refund' : [] | [LedgerTransfer],
+  'discount_total' : [] | [bigint],
+  'refunded_amount' : [] | [bigint],
+  'shipping' : [] | [ShippingCharge],
+  'created_at' : bigint,
+  'seller' : Principal,
+  'lines' : Array<OrderLine>,
+  'version' : [] | [bigint],
+  'jurisdiction' : [] | [string],
+  'currency' : [] | [string],
+  'buyer' : Principal,
+  'refunded_units' : [] | [bigint],
+  'tax_total' : [] | [bigint],
+  'payment' : [] | [LedgerTransfer],
+  'subtotal' : [] | [bigint],
+}
+export interface OrderLine {
+  'tax' : [] | [LineTax],
+  'accessory_id' : bigint,
+  'name' : string,
+  'discounts' : [] | [Array<AppliedDiscount>],
+  'unit_price' : bigint,
+  'quantity' : bigint,
+  'line_total' : [] | [bigint],
+  'sale_id' : [] | [bigint],
+  'serials' : Array<string>,
+}
+export interface OrderLinePayload {
+  'accessory_id' : bigint,
+  'quantity' : bigint,
+  'serials' : Array<string>,
+}
+export interface OrderPayload {
+  'coupon_codes' : [] | [Array<string>],
+  'lines' : Array<OrderLinePayload>,
+  'jurisdiction' : [] | [string],
+  'shipping_method_id' : [] | [bigint],
+}
+export type OrderStatus = { 'Delivered' : null } |
+  { 'Placed' : null } |
+  { 'Cancelled' : null } |
+  { 'Shipped' : null };
+export interface OwnershipTransfer {
+  'id' : bigint,
+  'to' : Principal,
+  'status' : TransferStatus,
+  'from' : Principal,
+  'created_at' : bigint,
+  'transferred' : bigint,
+  'scope' : TransferScope,
+  'proposed_by' : Principal,
+  'resolved_at' : [] | [bigint],
+}
+export interface PaymentConfig {
+  'ledger' : [] | [Principal],
+  'currency' : [] | [string],
+}
+export interface PriceChange {
+  'id' : bigint,
+  'accessory_id' : bigint,
+  'changed_at' : bigint,
+  'changed_by' : Principal,
+  'new_price' : bigint,
+  'old_price' : bigint,
+  'reason' : PriceChangeReason,
+}
+export type PriceChangeReason = { 'SaleEnded' : null } |
+  { 'SaleStarted' : null } |
+  { 'Manual' : null };
+export interface PriceQuote {
+  'total' : bigint,
+  'discount_total' : bigint,
+  'shipping' : [] | [ShippingCharge],
+  'coupon_codes' : Array<string>,
+  'lines' : Array<OrderLine>,
+  'jurisdiction' : [] | [string],
+  'currency' : [] | [string],
+  'tax_total' : bigint,
+  'converted' : [] | [ConvertedAmounts],
+  'subtotal' : bigint,
+}
+export interface PurchaseOrder {
+  'id' : bigint,
+  'status' : PurchaseOrderStatus,
+  'supplier_id' : bigint,
+  'updated_at' : [] | [bigint],
+  'created_at' : bigint,
+  'seller' : Principal,
+  'lines' : Array<PurchaseOrderLine>,
+  'version' : [] | [bigint],
+  'expected_at' : bigint,
+}
+export interface PurchaseOrderLine {
+  'accessory_id' : bigint,
+  'received_quantity' : bigint,
+  'unit_cost' : bigint,
+  'quantity' : bigint,
+}
+export interface PurchaseOrderLinePayload {
+  'accessory_id' : bigint,
+  'unit_cost' : [] | [bigint],
+  'quantity' : bigint,
+}
+export interface PurchaseOrderPayload {
+  'supplier_id' : bigint,
+  'lines' : Array<PurchaseOrderLinePayload>,
+  'expected_at' : [] | [bigint],
+}
+export type PurchaseOrderStatus = { 'Open' : null } |
+  { 'PartiallyReceived' : null } |
+  { 'Received' : null } |
+  { 'Cancelled' : null };
+export interface RateQuota { 'capacity' : number, 'refill_interval' : bigint }
+export interface RateTier { 'up_to' : bigint, 'amount' : bigint }
+export interface ReorderLine {
+  'suggested_quantity' : bigint,
+  'accessory_id' : bigint,
+  'inventory_count' : bigint,
+  'name' : string,
+  'unit_cost' : [] | [bigint],
+  'reorder_point' : bigint,
 }
-export type Error = { 'NotFound' : { 'msg' : string } };
-export type Result = { 'Ok' : Accessory } |
+export type Result = { 'Ok' : OwnershipTransfer } |
+  { 'Err' : Error };
+export type Result_1 = { 'Ok' : StockAlert } |
+  { 'Err' : Error };
+export type Result_10 = { 'Ok' : StocktakeReport } |
+  { 'Err' : Error };
+export type Result_11 = { 'Ok' : null } |
+  { 'Err' : Error };
+export type Result_12 = { 'Ok' : Block } |
+  { 'Err' : Error };
+export type Result_13 = { 'Ok' : BulkChange } |
+  { 'Err' : Error };
+export type Result_14 = { 'Ok' : BulkReport } |
+  { 'Err' : Error };
+export type Result_15 = { 'Ok' : Array<Result_2> } |
+  { 'Err' : Error };
+export type Result_16 = { 'Ok' : Order } |
+  { 'Err' : Error };
+export type Result_17 = { 'Ok' : PurchaseOrder } |
+  { 'Err' : Error };
+export type Result_18 = { 'Ok' : Sale } |
+  { 'Err' : Error };
+export type Result_19 = { 'Ok' : Stocktake } |
+  { 'Err' : Error };
+export type Result_2 = { 'Ok' : Accessory } |
+  { 'Err' : Error };
+export type Result_20 = { 'Ok' : Coupon } |
+  { 'Err' : Error };
+export type Result_21 = { 'Ok' : Shipment } |
+  { 'Err' : Error };
+export type Result_22 = { 'Ok' : bigint } |
+  { 'Err' : Error };
+export type Result_23 = { 'Ok' : Money } |
+  { 'Err' : Error };
+export type Result_24 = { 'Ok' : Array<AuditEntry> } |
+  { 'Err' : Error };
+export type Result_25 = { 'Ok' : Array<Block> } |
+  { 'Err' : Error };
+export type Result_26 = { 'Ok' : GrossMargin } |
+  { 'Err' : Error };
+export type Result_27 = { 'Ok' : Invoice } |
+  { 'Err' : Error };
+export type Result_28 = { 'Ok' : InvoiceLinks } |
+  { 'Err' : Error };
+export type Result_29 = { 'Ok' : Array<Invoice> } |
+  { 'Err' : Error };
+export type Result_3 = { 'Ok' : Batch } |
+  { 'Err' : Error };
+export type Result_30 = { 'Ok' : Array<Shipment> } |
+  { 'Err' : Error };
+export type Result_31 = { 'Ok' : Array<PriceChange> } |
+  { 'Err' : Error };
+export type Result_32 = { 'Ok' : Seller } |
+  { 'Err' : Error };
+export type Result_33 = { 'Ok' : SellerScorecard } |
+  { 'Err' : Error };
+export type Result_34 = { 'Ok' : Storefront } |
+  { 'Err' : Error };
+export type Result_35 = { 'Ok' : Array<Seller> } |
+  { 'Err' : Error };
+export type Result_36 = { 'Ok' : SerialHistory } |
+  { 'Err' : Error };
+export type Result_37 = { 'Ok' : Array<TaxRule> } |
+  { 'Err' : Error };
+export type Result_38 = { 'Ok' : Array<CallerStats> } |
+  { 'Err' : Error };
+export type Result_39 = { 'Ok' : SupplierLink } |
+  { 'Err' : Error };
+export type Result_4 = { 'Ok' : Currency } |
+  { 'Err' : Error };
+export type Result_40 = { 'Ok' : PriceQuote } |
+  { 'Err' : Error };
+export type Result_41 = { 'Ok' : TaxRule } |
+  { 'Err' : Error };
+export type Result_42 = { 'Ok' : CurrencyConfig } |
+  { 'Err' : Error };
+export type Result_43 = { 'Ok' : TaxConfig } |
+  { 'Err' : Error };
+export type Result_44 = { 'Ok' : ExchangeRate } |
+  { 'Err' : Error };
+export type Result_45 = { 'Ok' : SerialUnit } |
+  { 'Err' : Error };
+export type Result_5 = { 'Ok' : Review } |
+  { 'Err' : Error };
+export type Result_6 = { 'Ok' : Array<SerialUnit> } |
+  { 'Err' : Error };
+export type Result_7 = { 'Ok' : ShippingMethod } |
   { 'Err' : Error };
-export type Result_1 = { 'Ok' : bigint } |
+export type Result_8 = { 'Ok' : Supplier } |
   { 'Err' : Error };
+export type Result_9 = { 'Ok' : ReturnRequest } |
+  { 'Err' : Error };
+export type ReturnDisposition = { 'Restock' : null } |
+  { 'Damaged' : null };
+export type ReturnReason = { 'NotAsDescribed' : null } |
+  { 'ChangedMind' : null } |
+  { 'WrongItem' : null } |
+  { 'Other' : null } |
+  { 'Defective' : null };
+export interface ReturnRequest {
+  'id' : bigint,
+  'status' : ReturnStatus,
+  'accessory_id' : bigint,
+  'updated_at' : [] | [bigint],
+  'created_at' : bigint,
+  'seller' : Principal,
+  'refund_amount' : [] | [bigint],
+  'version' : [] | [bigint],
+  'comment' : string,
+  'refund_transfer' : [] | [LedgerTransfer],
+  'quantity' : bigint,
+  'buyer' : Principal,
+  'refund_block_index' : [] | [bigint],
+  'order_id' : bigint,
+  'disposition' : [] | [ReturnDisposition],
+  'seller_note' : [] | [string],
+  'serials' : Array<string>,
+  'condition' : ItemCondition,
+  'reason' : ReturnReason,
+}
+export interface ReturnRequestPayload {
+  'accessory_id' : bigint,
+  'comment' : string,
+  'quantity' : bigint,
+  'order_id' : bigint,
+  'serials' : Array<string>,
+  'condition' : ItemCondition,
+  'reason' : ReturnReason,
+}
+export type ReturnStatus = { 'Refunded' : null } |
+  { 'Approved' : null } |
+  { 'RefundPending' : null } |
+  { 'Rejected' : null } |
+  { 'Requested' : null } |
+  { 'Received' : null };
+export interface Review {
+  'id' : bigint,
+  'accessory_id' : bigint,
+  'created_at' : bigint,
+  'user_id' : bigint,
+  'comment' : string,
+  'rating' : number,
+}
+export interface ReviewPayload {
+  'accessory_id' : bigint,
+  'user_id' : bigint,
+  'comment' : string,
+  'rating' : number,
+}
+export type Role = { 'Staff' : null } |
+  { 'Admin' : null };
+export type RoundingMode = { 'Up' : null } |
+  { 'Down' : null } |
+  { 'HalfUp' : null } |
+  { 'HalfEven' : null };
+export interface Sale {
+  'id' : bigint,
+  'status' : SaleStatus,
+  'accessory_id' : bigint,
+  'updated_at' : [] | [bigint],
+  'starts_at' : bigint,
+  'ends_at' : bigint,
+  'created_at' : bigint,
+  'created_by' : Principal,
+  'sale_price' : bigint,
+}
+export type SaleStatus = { 'Ended' : null } |
+  { 'Active' : null } |
+  { 'Scheduled' : null } |
+  { 'Cancelled' : null };
+export interface Seller {
+  'updated_at' : [] | [bigint],
+  'principal' : Principal,
+  'contact' : string,
+  'return_policy' : string,
+  'created_at' : bigint,
+  'verification_status' : VerificationStatus,
+  'display_name' : string,
+  'version' : [] | [bigint],
+}
+export interface SellerConfig { 'require_verification' : boolean }
+export interface SellerPayload {
+  'contact' : string,
+  'return_policy' : string,
+  'display_name' : string,
+}
+export interface SellerScorecard {
+  'orders_placed' : bigint,
+  'seller' : Principal,
+  'review_count' : bigint,
+  'return_rate' : [] | [number],
+  'fulfilment_rate' : [] | [number],
+  'average_rating' : [] | [number],
+  'average_ship_time_ns' : [] | [bigint],
+  'cancellation_rate' : [] | [number],
+}
+export type SellerSort = { 'ShipTime' : null } |
+  { 'CancellationRate' : null } |
+  { 'FulfilmentRate' : null } |
+  { 'ReturnRate' : null } |
+  { 'Rating' : null };
+export interface SerialEvent {
+  'id' : bigint,
+  'status' : SerialStatus,
+  'actor' : Principal,
+  'note' : string,
+  'created_at' : bigint,
+  'order_id' : [] | [bigint],
+  'location' : string,
+}
+export interface SerialHistory {
+  'unit' : SerialUnit,
+  'events' : Array<SerialEvent>,
+}
+export type SerialStatus = { 'Reserved' : null } |
+  { 'Sold' : null } |
+  { 'Returned' : null } |
+  { 'Defective' : null } |
+  { 'InStock' : null };
+export interface SerialUnit {
+  'id' : bigint,
+  'status' : SerialStatus,
+  'accessory_id' : bigint,
+  'updated_at' : [] | [bigint],
+  'created_at' : bigint,
+  'serial' : string,
+  'sold_to' : [] | [Principal],
+  'order_id' : [] | [bigint],
+  'location' : string,
+}
+export interface Shipment {
+  'id' : bigint,
+  'status' : ShipmentStatus,
+  'updated_at' : [] | [bigint],
+  'created_at' : bigint,
+  'tracking_number' : string,
+  'events' : Array<ShipmentEvent>,
+  'order_id' : bigint,
+  'carrier' : string,
+}
+export interface ShipmentEvent {
+  'status' : ShipmentStatus,
+  'note' : string,
+  'created_at' : bigint,
+}
+export type ShipmentStatus = { 'InTransit' : null } |
+  { 'Failed' : null } |
+  { 'Delivered' : null } |
+  { 'OutForDelivery' : null };
+export interface ShippingCharge {
+  'name' : string,
+  'amount' : bigint,
+  'method_id' : bigint,
+}
+export interface ShippingMethod {
+  'id' : bigint,
+  'updated_at' : [] | [bigint],
+  'active' : boolean,
+  'free_shipping_threshold' : [] | [bigint],
+  'name' : string,
+  'rate' : ShippingRate,
+  'created_at' : bigint,
+  'seller' : Principal,
+  'version' : [] | [bigint],
+}
+export interface ShippingMethodPayload {
+  'active' : boolean,
+  'free_shipping_threshold' : [] | [bigint],
+  'name' : string,
+  'rate' : ShippingRate,
+}
+export type ShippingRate = { 'Flat' : { 'amount' : bigint } } |
+  { 'ByOrderValue' : { 'tiers' : Array<RateTier> } } |
+  { 'ByWeight' : { 'tiers' : Array<RateTier> } };
+export interface StockAlert {
+  'id' : bigint,
+  'status' : AlertStatus,
+  'accessory_id' : bigint,
+  'inventory_count' : bigint,
+  'created_at' : bigint,
+  'seller' : Principal,
+  'reorder_point' : bigint,
+  'acknowledged_at' : [] | [bigint],
+  'resolved_at' : [] | [bigint],
+}
+export interface StockMovement {
+  'id' : bigint,
+  'accessory_id' : bigint,
+  'balance_after' : bigint,
+  'actor' : Principal,
+  'note' : string,
+  'created_at' : bigint,
+  'delta' : bigint,
+  'reason' : StockReason,
+}
+export type StockReason = { 'Lost' : null } |
+  { 'Sold' : null } |
+  { 'Correction' : null } |
+  { 'Received' : null } |
+  { 'Returned' : null } |
+  { 'Stocktake' : null } |
+  { 'Damaged' : null };
+export interface Stocktake {
+  'id' : bigint,
+  'status' : StocktakeStatus,
+  'closed_at' : [] | [bigint],
+  'approved_by' : [] | [Principal],
+  'created_at' : bigint,
+  'created_by' : Principal,
+  'seller' : Principal,
+  'lines' : Array<StocktakeLine>,
+  'version' : [] | [bigint],
+}
+export interface StocktakeLine {
+  'accessory_id' : bigint,
+  'counted' : [] | [bigint],
+  'expected' : bigint,
+  'variance' : [] | [bigint],
+  'counted_by' : [] | [Principal],
+}
+export interface StocktakeReport {
+  'stocktake' : Stocktake,
+  'lines_with_variance' : bigint,
+  'uncounted_lines' : bigint,
+  'counted_lines' : bigint,
+  'total_variance' : bigint,
+}
+export type StocktakeScope = { 'Accessories' : BigUint64Array | bigint[] } |
+  { 'Category' : string };
+export type StocktakeStatus = { 'Open' : null } |
+  { 'Approved' : null } |
+  { 'Cancelled' : null };
+export interface Storefront {
+  'total' : bigint,
+  'listings' : Array<Accessory>,
+  'offset' : bigint,
+  'seller' : Seller,
+  'limit' : bigint,
+}
+export interface Supplier {
+  'id' : bigint,
+  'updated_at' : [] | [bigint],
+  'contact' : string,
+  'owner' : Principal,
+  'name' : string,
+  'lead_time_days' : number,
+  'created_at' : bigint,
+  'version' : [] | [bigint],
+}
+export interface SupplierLink {
+  'accessory_id' : bigint,
+  'supplier_id' : bigint,
+  'cost_price' : bigint,
+  'preferred' : boolean,
+}
+export interface SupplierPayload {
+  'contact' : string,
+  'name' : string,
+  'lead_time_days' : number,
+}
+export interface SupplierReorderSuggestion {
+  'supplier_id' : [] | [bigint],
+  'supplier_name' : [] | [string],
+  'lines' : Array<ReorderLine>,
+}
+export interface TaxConfig { 'default_jurisdiction' : [] | [string] }
+export interface TaxRule {
+  'id' : bigint,
+  'inclusive' : boolean,
+  'updated_at' : [] | [bigint],
+  'created_at' : bigint,
+  'jurisdiction' : string,
+  'category' : [] | [string],
+  'rate_basis_points' : bigint,
+}
+export interface TaxSummary {
+  'to' : bigint,
+  'from' : bigint,
+  'lines' : Array<TaxSummaryLine>,
+}
+export interface TaxSummaryLine {
+  'inclusive' : boolean,
+  'tax_amount' : bigint,
+  'order_count' : bigint,
+  'jurisdiction' : string,
+  'currency' : [] | [string],
+  'category' : [] | [string],
+  'taxable_amount' : bigint,
+  'rate_basis_points' : bigint,
+}
 export interface TransactionRecord {
   'transaction_type' : string,
   'change_type' : string,
   'timestamp' : bigint,
 }
+export type TransferScope = { 'Accessory' : { 'id' : bigint } } |
+  { 'AllListings' : null };
+export type TransferStatus = { 'Accepted' : null } |
+  { 'Cancelled' : null } |
+  { 'Pending' : null };
+export interface TrashConfig { 'retention_days' : bigint }
+export type VerificationStatus = { 'Suspended' : null } |
+  { 'Rejected' : null } |
+  { 'Unverified' : null } |
+  { 'Verified' : null };
 export interface _SERVICE {
-  'add_accessory' : ActorMethod<[AccessoryPayload], [] | [Accessory]>,
+  'accept_ownership_transfer' : ActorMethod<[bigint], Result>,
+  'acknowledge_stock_alert' : ActorMethod<[bigint], Result_1>,
+  'add_accessory' : ActorMethod<[AccessoryPayload], Result_2>,
+  'add_batch' : ActorMethod<[bigint, BatchPayload], Result_3>,
+  'add_currency' : ActorMethod<[string, number], Result_4>,
+  'add_review' : ActorMethod<[ReviewPayload], Result_5>,
+  'add_serials' : ActorMethod<
+    [bigint, Array<string>, string, [] | [bigint]],
+    Result_6
+  >,
+  'add_shipping_method' : ActorMethod<[ShippingMethodPayload], Result_7>,
+  'add_supplier' : ActorMethod<[SupplierPayload], Result_8>,
+  'adjust_inventory' : ActorMethod<
+    [bigint, bigint, StockReason, string, [] | [bigint], [] | [bigint]],
+    Result_2
+  >,
+  'approve_return' : ActorMethod<
+    [bigint, [] | [string], [] | [bigint]],
+    Result_9
+  >,
+  'approve_stocktake' : ActorMethod<[bigint, [] | [bigint]], Result_10>,
+  'assign_role' : ActorMethod<[Principal, Role], Result_11>,
+  'block_principal' : ActorMethod<[Principal, bigint, string], Result_12>,
+  'bulk_add' : ActorMethod<
+    [Array<AccessoryPayload>, BulkMode, boolean],
+    Result_14
+  >,
+  'bulk_delete' : ActorMethod<
+    [BigUint64Array | bigint[], BulkMode, boolean],
+    Result_14
+  >,
+  'bulk_update' : ActorMethod<
+    [Array<[bigint, AccessoryPayload]>, BulkMode, boolean],
+    Result_14
+  >,
   'bulk_update_accessories' : ActorMethod<
     [Array<[bigint, AccessoryPayload]>],
-    Array<Result>
+    Result_15
+  >,
+  'cancel_order' : ActorMethod<[bigint, [] | [bigint]], Result_16>,
+  'cancel_ownership_transfer' : ActorMethod<[bigint], Result>,
+  'cancel_purchase_order' : ActorMethod<[bigint, [] | [bigint]], Result_17>,
+  'cancel_sale' : ActorMethod<[bigint], Result_18>,
+  'cancel_stocktake' : ActorMethod<[bigint, [] | [bigint]], Result_19>,
+  'check_inventory_levels' : ActorMethod<[bigint], Array<Accessory>>,
+  'create_coupon' : ActorMethod<[CouponPayload], Result_20>,
+  'create_purchase_order' : ActorMethod<[PurchaseOrderPayload], Result_17>,
+  'create_shipment' : ActorMethod<[bigint, string, string], Result_21>,
+  'deactivate_coupon' : ActorMethod<[string, [] | [bigint]], Result_20>,
+  'delete_accessory' : ActorMethod<[bigint, [] | [bigint]], Result_2>,
+  'enable_serial_tracking' : ActorMethod<
+    [bigint, Array<string>, string],
+    Result_2
   >,
-  'delete_accessory' : ActorMethod<[bigint], Result>,
   'get_accessories_by_category' : ActorMethod<[string], Array<Accessory>>,
-  'get_accessory' : ActorMethod<[bigint], Result>,
-  'get_accessory_price' : ActorMethod<[bigint], Result_1>,
+  'get_accessory' : ActorMethod<[bigint], Result_2>,
+  'get_accessory_by_barcode' : ActorMethod<[string], Result_2>,
+  'get_accessory_price' : ActorMethod<[bigint], Result_22>,
+  'get_accessory_price_in_currency' : ActorMethod<
+    [bigint, [] | [string], [] | [RoundingMode]],
+    Result_23
+  >,
+  'get_accessory_suppliers' : ActorMethod<[bigint], Array<SupplierLink>>,
   'get_accessory_transaction_history' : ActorMethod<
     [bigint],
     Array<TransactionRecord>
   >,
+  'get_audit_log' : ActorMethod<[[] | [bigint]], Result_24>,
   'get_available_accessories' : ActorMethod<[], Array<Accessory>>,
-  'mark_accessory_as_available' : ActorMethod<[bigint], Result>,
-  'mark_accessory_as_unavailable' : ActorMethod<[bigint], Result>,
-  'search_accessories' : ActorMethod<[string], Array<Accessory>>,
-  'update_accessory' : ActorMethod<[bigint, AccessoryPayload], Result>,
+  'get_batches' : ActorMethod<[bigint], Array<Batch>>,
+  'get_blocked_principals' : ActorMethod<[], Result_25>,
+  'get_cost_of_goods_sold' : ActorMethod<
+    [bigint, bigint],
+    CostOfGoodsSoldReport
+  >,
+  'get_costing_method' : ActorMethod<[], CostingMethod>,
+  'get_coupon' : ActorMethod<[string], Result_20>,
+  'get_currencies' : ActorMethod<[], Array<Currency>>,
+  'get_currency_config' : ActorMethod<[], CurrencyConfig>,
+  'get_damaged_stock' : ActorMethod<[bigint], Result_22>,
+  'get_exchange_rates' : ActorMethod<[], Array<ExchangeRate>>,
+  'get_expiring_batches' : ActorMethod<[bigint], Array<Batch>>,
+  'get_gross_margin' : ActorMethod<[bigint], Result_26>,
+  'get_inventory_valuation' : ActorMethod<[], Array<InventoryValuation>>,
+  'get_invoice' : ActorMethod<[bigint], Result_27>,
+  'get_invoice_links' : ActorMethod<[bigint], Result_28>,
+  'get_invoices' : ActorMethod<[[] | [Principal]], Result_29>,
+  'get_my_coupons' : ActorMethod<[], Array<Coupon>>,
+  'get_my_orders' : ActorMethod<[], Array<Order>>,
+  'get_my_returns' : ActorMethod<[], Array<ReturnRequest>>,
+  'get_order' : ActorMethod<[bigint], Result_16>,
+  'get_order_invoice' : ActorMethod<[bigint], Result_27>,
+  'get_order_shipments' : ActorMethod<[bigint], Result_30>,
+  'get_overdue_purchase_orders' : ActorMethod<[], Array<PurchaseOrder>>,
+  'get_ownership_transfers' : ActorMethod<
+    [[] | [TransferStatus]],
+    Array<OwnershipTransfer>
+  >,
+  'get_payment_config' : ActorMethod<[], PaymentConfig>,
+  'get_price_history' : ActorMethod<[bigint], Result_31>,
+  'get_purchase_order' : ActorMethod<[bigint], Result_17>,
+  'get_purchase_orders' : ActorMethod<
+    [[] | [PurchaseOrderStatus]],
+    Array<PurchaseOrder>
+  >,
+  'get_rate_limits' : ActorMethod<[], [RateQuota, Array<[string, RateQuota]>]>,
+  'get_reorder_suggestions' : ActorMethod<[], Array<SupplierReorderSuggestion>>,
+  'get_return' : ActorMethod<[bigint], Result_9>,
+  'get_reviews' : ActorMethod<[bigint], Array<Review>>,
+  'get_role' : ActorMethod<[Principal], [] | [Role]>,
+  'get_sales' : ActorMethod<[bigint], Array<Sale>>,
+  'get_seller' : ActorMethod<[Principal], Result_32>,
+  'get_seller_config' : ActorMethod<[], SellerConfig>,
+  'get_seller_orders' : ActorMethod<[], Array<Order>>,
+  'get_seller_returns' : ActorMethod<
+    [[] | [ReturnStatus]],
+    Array<ReturnRequest>
+  >,
+  'get_seller_scorecard' : ActorMethod<[Principal], Result_33>,
+  'get_seller_storefront' : ActorMethod<[Principal, bigint, bigint], Result_34>,
+  'get_sellers' : ActorMethod<[[] | [VerificationStatus]], Result_35>,
+  'get_serial' : ActorMethod<[string], Result_36>,
+  'get_serials' : ActorMethod<[bigint, [] | [SerialStatus]], Result_6>,
+  'get_shipping_methods' : ActorMethod<[Principal], Array<ShippingMethod>>,
+  'get_stock_alerts' : ActorMethod<[boolean], Array<StockAlert>>,
+  'get_stock_movements' : ActorMethod<[bigint], Array<StockMovement>>,
+  'get_stocktake_report' : ActorMethod<[bigint], Result_10>,
+  'get_stocktakes' : ActorMethod<[], Array<Stocktake>>,
+  'get_supplier' : ActorMethod<[bigint], Result_8>,
+  'get_suppliers' : ActorMethod<[], Array<Supplier>>,
+  'get_tax_config' : ActorMethod<[], TaxConfig>,
+  'get_tax_rules' : ActorMethod<[[] | [string]], Result_37>,
+  'get_tax_summary' : ActorMethod<[bigint, bigint], TaxSummary>,
+  'get_top_callers' : ActorMethod<[bigint], Result_38>,
+  'get_trash_config' : ActorMethod<[], TrashConfig>,
+  'http_request' : ActorMethod<[HttpRequest], HttpResponse>,
+  'issue_invoice' : ActorMethod<[bigint], Result_27>,
+  'issue_refund' : ActorMethod<[bigint, [] | [bigint]], Result_9>,
+  'link_accessory_supplier' : ActorMethod<
+    [bigint, bigint, bigint, boolean],
+    Result_39
+  >,
+  'list_trash' : ActorMethod<[], Array<Accessory>>,
+  'patch_accessory' : ActorMethod<
+    [bigint, AccessoryPatch, [] | [bigint]],
+    Result_2
+  >,
+  'pay_order' : ActorMethod<[bigint], Result_16>,
+  'place_order' : ActorMethod<[OrderPayload], Result_16>,
+  'price_quote' : ActorMethod<
+    [OrderPayload, [] | [string], [] | [RoundingMode]],
+    Result_40
+  >,
+  'propose_listing_transfer' : ActorMethod<[bigint, Principal], Result>,
+  'propose_seller_transfer' : ActorMethod<[Principal, Principal], Result>,
+  'receive_purchase_order' : ActorMethod<
+    [bigint, Array<GoodsReceiptLine>, [] | [bigint]],
+    Result_17
+  >,
+  'receive_return' : ActorMethod<
+    [bigint, ReturnDisposition, [] | [bigint]],
+    Result_9
+  >,
+  'record_stocktake_counts' : ActorMethod<
+    [bigint, Array<[bigint, bigint]>, [] | [bigint]],
+    Result_19
+  >,
+  'register_seller' : ActorMethod<[SellerPayload], Result_32>,
+  'reject_return' : ActorMethod<
+    [bigint, [] | [string], [] | [bigint]],
+    Result_9
+  >,
+  'remove_rate_limit' : ActorMethod<[string], Result_11>,
+  'remove_tax_rule' : ActorMethod<[bigint], Result_41>,
+  'request_return' : ActorMethod<[ReturnRequestPayload], Result_9>,
+  'restore_accessory' : ActorMethod<[bigint], Result_2>,
+  'revoke_role' : ActorMethod<[Principal], Result_11>,
+  'schedule_sale' : ActorMethod<[bigint, bigint, bigint, bigint], Result_18>,
+  'search_accessories' : ActorMethod<
+    [string, [] | [SellerSort]],
+    Array<Accessory>
+  >,
+  'set_costing_method' : ActorMethod<[CostingMethod], Result_11>,
+  'set_default_currency' : ActorMethod<[string], Result_42>,
+  'set_default_jurisdiction' : ActorMethod<[[] | [string]], Result_43>,
+  'set_exchange_rate' : ActorMethod<[string, string, bigint], Result_44>,
+  'set_payment_ledger' : ActorMethod<
+    [[] | [Principal], [] | [string]],
+    Result_11
+  >,
+  'set_rate_limit' : ActorMethod<[string, RateQuota], Result_11>,
+  'set_seller_verification' : ActorMethod<
+    [Principal, VerificationStatus],
+    Result_32
+  >,
+  'set_seller_verification_required' : ActorMethod<[boolean], Result_11>,
+  'set_tax_rule' : ActorMethod<
+    [string, [] | [string], bigint, boolean],
+    Result_41
+  >,
+  'set_trash_retention' : ActorMethod<[bigint], Result_11>,
+  'start_stocktake' : ActorMethod<[StocktakeScope], Result_19>,
+  'toggle_accessory_availability' : ActorMethod<
+    [bigint, [] | [bigint]],
+    Result_2
+  >,
+  'unblock_principal' : ActorMethod<[Principal], Result_11>,
+  'unlink_accessory_supplier' : ActorMethod<[bigint, bigint], Result_39>,
+  'update_accessory' : ActorMethod<
+    [bigint, AccessoryPayload, [] | [bigint]],
+    Result_2
+  >,
+  'update_inventory' : ActorMethod<[bigint, bigint, [] | [bigint]], Result_2>,
+  'update_seller_profile' : ActorMethod<
+    [SellerPayload, [] | [bigint]],
+    Result_32
+  >,
+  'update_serial_status' : ActorMethod<
+    [string, SerialStatus, [] | [string], string, [] | [Principal]],
+    Result_45
+  >,
+  'update_shipment_status' : ActorMethod<
+    [bigint, bigint, ShipmentStatus, string],
+    Result_21
+  >,
+  'update_shipping_method' : ActorMethod<
+    [bigint, ShippingMethodPayload, [] | [bigint]],
+    Result_7
+  >,
+  'update_supplier' : ActorMethod<
+    [bigint, SupplierPayload, [] | [bigint]],
+    Result_8
+  >,
 }
@@ -1,58 +1,1207 @@
 export const idlFactory = ({ IDL }) => {
+  const TransferStatus = IDL.Variant({
+    'Accepted' : IDL.Null,
+    'Cancelled' : IDL.Null,
+    'Pending' : IDL.Null,
+  });
+  const TransferScope = IDL.Variant({
+    'Accessory' : IDL.Record({ 'id' : IDL.Nat64 }),
+    'AllListings' : IDL.Null,
+  });
+  const OwnershipTransfer = IDL.Record({
+    'id' : IDL.Nat64,
+    'to' : IDL.Principal,
+    'status' : TransferStatus,
+    'from' : IDL.Principal,
+    'created_at' : IDL.Nat64,
+    'transferred' : IDL.Nat64,
+    'scope' : TransferScope,
+    'proposed_by' : IDL.Principal,
+    'resolved_at' : IDL.Opt(IDL.Nat64),
+  });
+  const Error = IDL.Variant({
+    'ValidationFailed' : IDL.Record({ 'msg' : IDL.Text }),
+    'PaymentFailed' : IDL.Record({ 'msg' : IDL.Text }),
+    'NotFound' : IDL.Record({ 'msg' : IDL.Text }),
+    'RateLimited' : IDL.Record({ 'msg' : IDL.Text, 'retry_after' : IDL.Nat64 }),
+    'AuthenticationFailed' : IDL.Record({ 'msg' : IDL.Text }),
+    'Conflict' : IDL.Record({
+      'msg' : IDL.Text,
+      'current_version' : IDL.Nat64,
+    }),
+  });
+  const Result = IDL.Variant({ 'Ok' : OwnershipTransfer, 'Err' : Error });
+  const AlertStatus = IDL.Variant({
+    'New' : IDL.Null,
+    'Acknowledged' : IDL.Null,
+    'Resolved' : IDL.Null,
+  });
+  const StockAlert = IDL.Record({
+    'id' : IDL.Nat64,
+    'status' : AlertStatus,
+    'accessory_id' : IDL.Nat64,
+    'inventory_count' : IDL.Nat64,
+    'created_at' : IDL.Nat64,
+    'seller' : IDL.Principal,
+    'reorder_point' : IDL.Nat64,
+    'acknowledged_at' : IDL.Opt(IDL.Nat64),
+    'resolved_at' : IDL.Opt(IDL.Nat64),
+  });
+  const Result_1 = IDL.Variant({ 'Ok' : StockAlert, 'Err' : Error });
+  const Dimensions = IDL.Record({
+    'length_mm' : IDL.Nat64,
+    'height_mm' : IDL.Nat64,
+    'width_mm' : IDL.Nat64,
+  });
   const AccessoryPayload = IDL.Record({
+    'reorder_quantity' : IDL.Opt(IDL.Nat64),
+    'inventory_count' : IDL.Nat64,
     'name' : IDL.Text,
     'description' : IDL.Text,
+    'barcode' : IDL.Opt(IDL.Text),
+    'currency' : IDL.Opt(IDL.Text),
     'category' : IDL.Text,
     'is_available' : IDL.Bool,
+    'reorder_point' : IDL.Opt(IDL.Nat64),
     'price' : IDL.Nat64,
+    'weight_grams' : IDL.Opt(IDL.Nat64),
+    'dimensions' : IDL.Opt(Dimensions),
+    'manufacturer_part_number' : IDL.Opt(IDL.Text),
   });
   const Accessory = IDL.Record({
     'id' : IDL.Nat64,
+    'reorder_quantity' : IDL.Opt(IDL.Nat64),
     'updated_at' : IDL.Opt(IDL.Nat64),
+    'inventory_count' : IDL.Nat64,
     'name' : IDL.Text,
     'description' : IDL.Text,
     'created_at' : IDL.Nat64,
+    'seller' : IDL.Principal,
+    'version' : IDL.Opt(IDL.Nat64),
+    'barcode' : IDL.Opt(IDL.Text),
+    'currency' : IDL.Opt(IDL.Text),
+    'deleted_at' : IDL.Opt(IDL.Nat64),
+    'deleted_by' : IDL.Opt(IDL.Principal),
     'category' : IDL.Text,
     'is_available' : IDL.Bool,
+    'reorder_point' : IDL.Opt(IDL.Nat64),
     'price' : IDL.Nat64,
+    'weight_grams' : IDL.Opt(IDL.Nat64),
+    'dimensions' : IDL.Opt(Dimensions),
+    'manufacturer_part_number' : IDL.Opt(IDL.Text),
+  });
+  const Result_2 = IDL.Variant({ 'Ok' : Accessory, 'Err' : Error });
+  const BatchPayload = IDL.Record({
+    'unit_cost' : IDL.Opt(IDL.Nat64),
+    'lot_number' : IDL.Text,
+    'quantity' : IDL.Nat64,
+    'expires_at' : IDL.Opt(IDL.Nat64),
+    'manufactured_at' : IDL.Opt(IDL.Nat64),
+  });
+  const Batch = IDL.Record({
+    'id' : IDL.Nat64,
+    'accessory_id' : IDL.Nat64,
+    'expired' : IDL.Bool,
+    'created_at' : IDL.Nat64,
+    'lot_number' : IDL.Text,
+    'quantity' : IDL.Nat64,
+    'remaining' : IDL.Nat64,
+    'expires_at' : IDL.Opt(IDL.Nat64),
+    'manufactured_at' : IDL.Opt(IDL.Nat64),
+  });
+  const Result_3 = IDL.Variant({ 'Ok' : Batch, 'Err' : Error });
+  const Currency = IDL.Record({
+    'decimals' : IDL.Nat8,
+    'code' : IDL.Text,
+    'created_at' : IDL.Nat64,
+  });
+  const Result_4 = IDL.Variant({ 'Ok' : Currency, 'Err' : Error });
+  const ReviewPayload = IDL.Record({
+    'accessory_id' : IDL.Nat64,
+    'user_id' : IDL.Nat64,
+    'comment' : IDL.Text,
+    'rating' : IDL.Nat8,
+  });
+  const Review = IDL.Record({
+    'id' : IDL.Nat64,
+    'accessory_id' : IDL.Nat64,
+    'created_at' : IDL.Nat64,
+    'user_id' : IDL.Nat64,
+    'comment' : IDL.Text,
+    'rating' : IDL.Nat8,
+  });
+  const Result_5 = IDL.Variant({ 'Ok' : Review, 'Err' : Error });
+  const SerialStatus = IDL.Variant({
+    'Reserved' : IDL.Null,
+    'Sold' : IDL.Null,
+    'Returned' : IDL.Null,
+    'Defective' : IDL.Null,
+    'InStock' : IDL.Null,
+  });
+  const SerialUnit = IDL.Record({
+    'id' : IDL.Nat64,
+    'status' : SerialStatus,
+    'accessory_id' : IDL.Nat64,
+    'updated_at' : IDL.Opt(IDL.Nat64),
+    'created_at' : IDL.Nat64,
+    'serial' : IDL.Text,
+    'sold_to' : IDL.Opt(IDL.Principal),
+    'order_id' : IDL.Opt(IDL.Nat64),
+    'location' : IDL.Text,
+  });
+  const Result_6 = IDL.Variant({ 'Ok' : IDL.Vec(SerialUnit), 'Err' : Error });
+  const RateTier = IDL.Record({ 'up_to' : IDL.Nat64, 'amount' : IDL.Nat64 });
+  const ShippingRate = IDL.Variant({
+    'Flat' : IDL.Record({ 'amount' : IDL.Nat64 }),
+    'ByOrderValue' : IDL.Record({ 'tiers' : IDL.Vec(RateTier) }),
+    'ByWeight' : IDL.Record({ 'tiers' : IDL.Vec(RateTier) }),
+  });
+  const ShippingMethodPayload = IDL.Record({
+    'active' : IDL.Bool,
+    'free_shipping_threshold' : IDL.Opt(IDL.Nat64),
+    'name' : IDL.Text,
+    'rate' : ShippingRate,
+  });
+  const ShippingMethod = IDL.Record({
+    'id' : IDL.Nat64,
+    'updated_at' : IDL.Opt(IDL.Nat64),
+    'active' : IDL.Bool,
+    'free_shipping_threshold' : IDL.Opt(IDL.Nat64),
+    'name' : IDL.Text,
+    'rate' : ShippingRate,
+    'created_at' : IDL.Nat64,
+    'seller' : IDL.Principal,
+    'version' : IDL.Opt(IDL.Nat64),
+  });
+  const Result_7 = IDL.Variant({ 'Ok' : ShippingMethod, 'Err' : Error });
+  const SupplierPayload = IDL.Record({
+    'contact' : IDL.Text,
+    'name' : IDL.Text,
+    'lead_time_days' : IDL.Nat32,
+  });
+  const Supplier = IDL.Record({
+    'id' : IDL.Nat64,
+    'updated_at' : IDL.Opt(IDL.Nat64),
+    'contact' : IDL.Text,
+    'owner' : IDL.Principal,
+    'name' : IDL.Text,
+    'lead_time_days' : IDL.Nat32,
+    'created_at' : IDL.Nat64,
+    'version' : IDL.Opt(IDL.Nat64),
+  });
+  const Result_8 = IDL.Variant({ 'Ok' : Supplier, 'Err' : Error });
+  const StockReason = IDL.Variant({
+    'Lost' : IDL.Null,
+    'Sold' : IDL.Null,
+    'Correction' : IDL.Null,
+    'Received' : IDL.Null,
+    'Returned' : IDL.Null,
+    'Stocktake' : IDL.Null,
+    'Damaged' : IDL.Null,
+  });
+  const ReturnStatus = IDL.Variant({
+    'Refunded' : IDL.Null,
+    'Approved' : IDL.Null,
+    'RefundPending' : IDL.Null,
+    'Rejected' : IDL.Null,
+    'Requested' : IDL.Null,
+    'Received' : IDL.Null,
+  });
+  const LedgerTransfer = IDL.Record({
+    'block_index' : IDL.Opt(IDL.Nat64),
+    'memo' : IDL.Vec(IDL.Nat8),
+    'ledger' : IDL.Principal,
+    'account' : IDL.Principal,
+    'created_at_time' : IDL.Nat64,
+    'amount' : IDL.Nat64,
+  });
+  const ReturnDisposition = IDL.Variant({
+    'Restock' : IDL.Null,
+    'Damaged' : IDL.Null,
+  });
+  const ItemCondition = IDL.Variant({
+    'Opened' : IDL.Null,
+    'Damaged' : IDL.Null,
+    'Unopened' : IDL.Null,
+  });
+  const ReturnReason = IDL.Variant({
+    'NotAsDescribed' : IDL.Null,
+    'ChangedMind' : IDL.Null,
+    'WrongItem' : IDL.Null,
+    'Other' : IDL.Null,
+    'Defective' : IDL.Null,
+  });
+  const ReturnRequest = IDL.Record({
+    'id' : IDL.Nat64,
+    'status' : ReturnStatus,
+    'accessory_id' : IDL.Nat64,
+    'updated_at' : IDL.Opt(IDL.Nat64),
+    'created_at' : IDL.Nat64,
+    'seller' : IDL.Principal,
+    'refund_amount' : IDL.Opt(IDL.Nat64),
+    'version' : IDL.Opt(IDL.Nat64),
+    'comment' : IDL.Text,
+    'refund_transfer' : IDL.Opt(LedgerTransfer),
+    'quantity' : IDL.Nat64,
+    'buyer' : IDL.Principal,
+    'refund_block_index' : IDL.Opt(IDL.Nat64),
+    'order_id' : IDL.Nat64,
+    'disposition' : IDL.Opt(ReturnDisposition),
+    'seller_note' : IDL.Opt(IDL.Text),
+    'serials' : IDL.Vec(IDL.Text),
+    'condition' : ItemCondition,
+    'reason' : ReturnReason,
+  });
+  const Result_9 = IDL.Variant({ 'Ok' : ReturnRequest, 'Err' : Error });
+  const StocktakeStatus = IDL.Variant({
+    'Open' : IDL.Null,
+    'Approved' : IDL.Null,
+    'Cancelled' : IDL.Null,
+  });
+  const StocktakeLine = IDL.Record({
+    'accessory_id' : IDL.Nat64,
+    'counted' : IDL.Opt(IDL.Nat64),
+    'expected' : IDL.Nat64,
+    'variance' : IDL.Opt(IDL.Int64),
+    'counted_by' : IDL.Opt(IDL.Principal),
+  });
+  const Stocktake = IDL.Record({
+    'id' : IDL.Nat64,
+    'status' : StocktakeStatus,
+    'closed_at' : IDL.Opt(IDL.Nat64),
+    'approved_by' : IDL.Opt(IDL.Principal),
+    'created_at' : IDL.Nat64,
+    'created_by' : IDL.Principal,
+    'seller' : IDL.Principal,
+    'lines' : IDL.Vec(StocktakeLine),
+    'version' : IDL.Opt(IDL.Nat64),
+  });
+  const StocktakeReport = IDL.Record({
+    'stocktake' : Stocktake,
+    'lines_with_variance' : IDL.Nat64,
+    'uncounted_lines' : IDL.Nat64,
+    'counted_lines' : IDL.Nat64,
+    'total_variance' : IDL.Int64,
+  });
+  const Result_10 = IDL.Variant({ 'Ok' : StocktakeReport, 'Err' : Error });
+  const Role = IDL.Variant({ 'Staff' : IDL.Null, 'Admin' : IDL.Null });
+  const Result_11 = IDL.Variant({ 'Ok' : IDL.Null, 'Err' : Error });
+  const Block = IDL.Record({
+    'principal' : IDL.Principal,
+    'blocked_until' : IDL.Nat64,
+    'blocked_by' : IDL.Principal,
+    'reason' : IDL.Text,
+  });
+  const Result_12 = IDL.Variant({ 'Ok' : Block, 'Err' : Error });
+  const BulkMode = IDL.Variant({
+    'Atomic' : IDL.Null,
+    'BestEffort' : IDL.Null,
+  });
+  const BulkChange = IDL.Record({
+    'after' : Accessory,
+    'before' : IDL.Opt(Accessory),
+  });
+  const Result_13 = IDL.Variant({ 'Ok' : BulkChange, 'Err' : Error });
+  const BulkReport = IDL.Record({
+    'applied' : IDL.Nat64,
+    'results' : IDL.Vec(Result_13),
+    'dry_run' : IDL.Bool,
+    'failed' : IDL.Nat64,
+  });
+  const Result_14 = IDL.Variant({ 'Ok' : BulkReport, 'Err' : Error });
+  const Result_15 = IDL.Variant({ 'Ok' : IDL.Vec(Result_2), 'Err' : Error });
+  const OrderStatus = IDL.Variant({
+    'Delivered' : IDL.Null,
+    'Placed' : IDL.Null,
+    'Cancelled' : IDL.Null,
+    'Shipped' : IDL.Null,
+  });
+  const ShippingCharge = IDL.Record({
+    'name' : IDL.Text,
+    'amount' : IDL.Nat64,
+    'method_id' : IDL.Nat64,
+  });
+  const LineTax = IDL.Record({
+    'inclusive' : IDL.Bool,
+    'jurisdiction' : IDL.Text,
+    'category' : IDL.Opt(IDL.Text),
+    'rule_id' : IDL.Nat64,
+    'amount' : IDL.Nat64,
+    'rate_basis_points' : IDL.Nat64,
+  });
+  const AppliedDiscount = IDL.Record({
+    'code' : IDL.Text,
+    'description' : IDL.Text,
+    'amount' : IDL.Nat64,
+  });
+  const OrderLine = IDL.Record({
+    'tax' : IDL.Opt(LineTax),
+    'accessory_id' : IDL.Nat64,
+    'name' : IDL.Text,
+    'discounts' : IDL.Opt(IDL.Vec(AppliedDiscount)),
+    'unit_price' : IDL.Nat64,
+    'quantity' : IDL.Nat64,
+    'line_total' : IDL.Opt(IDL.Nat64),
+    'sale_id' : IDL.Opt(IDL.Nat64),
+    'serials' : IDL.Vec(IDL.Text),
+  });
+  const Order = IDL.Record({
+    'id' : IDL.Nat64,
+    'status' : OrderStatus,
+    'updated_at' : IDL.Opt(IDL.Nat64),
+    'total' : IDL.Nat64,
+    'cancellation_refund' : IDL.Opt(LedgerTransfer),
+    'discount_total' : IDL.Opt(IDL.Nat64),
+    'refunded_amount' : IDL.Opt(IDL.Nat64),
+    'shipping' : IDL.Opt(ShippingCharge),
+    'created_at' : IDL.Nat64,
+    'seller' : IDL.Principal,
+    'lines' : IDL.Vec(OrderLine),
+    'version' : IDL.Opt(IDL.Nat64),
+    'jurisdiction' : IDL.Opt(IDL.Text),
+    'currency' : IDL.Opt(IDL.Text),
+    'buyer' : IDL.Principal,
+    'refunded_units' : IDL.Opt(IDL.Nat64),
+    'tax_total' : IDL.Opt(IDL.Nat64),
+    'payment' : IDL.Opt(LedgerTransfer),
+    'subtotal' : IDL.Opt(IDL.Nat64),
+  });
+  const Result_16 = IDL.Variant({ 'Ok' : Order, 'Err' : Error });
+  const PurchaseOrderStatus = IDL.Variant({
+    'Open' : IDL.Null,
+    'PartiallyReceived' : IDL.Null,
+    'Received' : IDL.Null,
+    'Cancelled' : IDL.Null,
+  });
+  const PurchaseOrderLine = IDL.Record({
+    'accessory_id' : IDL.Nat64,
+    'received_quantity' : IDL.Nat64,
+    'unit_cost' : IDL.Nat64,
+    'quantity' : IDL.Nat64,
+  });
+  const PurchaseOrder = IDL.Record({
+    'id' : IDL.Nat64,
+    'status' : PurchaseOrderStatus,
+    'supplier_id' : IDL.Nat64,
+    'updated_at' : IDL.Opt(IDL.Nat64),
+    'created_at' : IDL.Nat64,
+    'seller' : IDL.Principal,
+    'lines' : IDL.Vec(PurchaseOrderLine),
+    'version' : IDL.Opt(IDL.Nat64),
+    'expected_at' : IDL.Nat64,
+  });
+  const Result_17 = IDL.Variant({ 'Ok' : PurchaseOrder, 'Err' : Error });
+  const SaleStatus = IDL.Variant({
+    'Ended' : IDL.Null,
+    'Active' : IDL.Null,
+    'Scheduled' : IDL.Null,
+    'Cancelled' : IDL.Null,
+  });
+  const Sale = IDL.Record({
+    'id' : IDL.Nat64,
+    'status' : SaleStatus,
+    'accessory_id' : IDL.Nat64,
+    'updated_at' : IDL.Opt(IDL.Nat64),
+    'starts_at' : IDL.Nat64,
+    'ends_at' : IDL.Nat64,
+    'created_at' : IDL.Nat64,
+    'created_by' : IDL.Principal,
+    'sale_price' : IDL.Nat64,
+  });
+  const Result_18 = IDL.Variant({ 'Ok' : Sale, 'Err' : Error });
+  const Result_19 = IDL.Variant({ 'Ok' : Stocktake, 'Err' : Error });
+  const DiscountKind = IDL.Variant({
+    'FixedAmount' : IDL.Record({ 'amount' : IDL.Nat64 }),
+    'Percentage' : IDL.Record({ 'basis_points' : IDL.Nat64 }),
+    'BuyXGetY' : IDL.Record({ 'buy' : IDL.Nat64, 'get' : IDL.Nat64 }),
+  });
+  const CouponPayload = IDL.Record({
+    'max_uses_per_principal' : IDL.Opt(IDL.Nat64),
+    'max_uses' : IDL.Opt(IDL.Nat64),
+    'starts_at' : IDL.Opt(IDL.Nat64),
+    'code' : IDL.Text,
+    'ends_at' : IDL.Opt(IDL.Nat64),
+    'kind' : DiscountKind,
+    'seller' : IDL.Opt(IDL.Principal),
+    'currency' : IDL.Opt(IDL.Text),
+    'category' : IDL.Opt(IDL.Text),
+    'min_order_value' : IDL.Opt(IDL.Nat64),
+  });
+  const Coupon = IDL.Record({
+    'max_uses_per_principal' : IDL.Opt(IDL.Nat64),
+    'active' : IDL.Bool,
+    'max_uses' : IDL.Opt(IDL.Nat64),
+    'starts_at' : IDL.Nat64,
+    'code' : IDL.Text,
+    'ends_at' : IDL.Opt(IDL.Nat64),
+    'kind' : DiscountKind,
+    'uses' : IDL.Nat64,
+    'created_at' : IDL.Nat64,
+    'created_by' : IDL.Principal,
+    'seller' : IDL.Opt(IDL.Principal),
+    'version' : IDL.Opt(IDL.Nat64),
+    'currency' : IDL.Opt(IDL.Text),
+    'category' : IDL.Opt(IDL.Text),
+    'min_order_value' : IDL.Nat64,
+  });
+  const Result_20 = IDL.Variant({ 'Ok' : Coupon, 'Err' : Error });
+  const PurchaseOrderLinePayload = IDL.Record({
+    'accessory_id' : IDL.Nat64,
+    'unit_cost' : IDL.Opt(IDL.Nat64),
+    'quantity' : IDL.Nat64,
+  });
+  const PurchaseOrderPayload = IDL.Record({
+    'supplier_id' : IDL.Nat64,
+    'lines' : IDL.Vec(PurchaseOrderLinePayload),
+    'expected_at' : IDL.Opt(IDL.Nat64),
+  });
+  const ShipmentStatus = IDL.Variant({
+    'InTransit' : IDL.Null,
+    'Failed' : IDL.Null,
+    'Delivered' : IDL.Null,
+    'OutForDelivery' : IDL.Null,
+  });
+  const ShipmentEvent = IDL.Record({
+    'status' : ShipmentStatus,
+    'note' : IDL.Text,
+    'created_at' : IDL.Nat64,
+  });
+  const Shipment = IDL.Record({
+    'id' : IDL.Nat64,
+    'status' : ShipmentStatus,
+    'updated_at' : IDL.Opt(IDL.Nat64),
+    'created_at' : IDL.Nat64,
+    'tracking_number' : IDL.Text,
+    'events' : IDL.Vec(ShipmentEvent),
+    'order_id' : IDL.Nat64,
+    'carrier' : IDL.Text,
+  });
+  const Result_21 = IDL.Variant({ 'Ok' : Shipment, 'Err' : Error });
+  const Result_22 = IDL.Variant({ 'Ok' : IDL.Nat64, 'Err' : Error });
+  const RoundingMode = IDL.Variant({
+    'Up' : IDL.Null,
+    'Down' : IDL.Null,
+    'HalfUp' : IDL.Null,
+    'HalfEven' : IDL.Null,
+  });
+  const Money = IDL.Record({
+    'decimals' : IDL.Nat8,
+    'currency' : IDL.Text,
+    'amount' : IDL.Nat64,
+  });
+  const Result_23 = IDL.Variant({ 'Ok' : Money, 'Err' : Error });
+  const SupplierLink = IDL.Record({
+    'accessory_id' : IDL.Nat64,
+    'supplier_id' : IDL.Nat64,
+    'cost_price' : IDL.Nat64,
+    'preferred' : IDL.Bool,
   });
-  const Error = IDL.Variant({ 'NotFound' : IDL.Record({ 'msg' : IDL.Text }) });
-  const Result = IDL.Variant({ 'Ok' : Accessory, 'Err' : Error });
-  const Result_1 = IDL.Variant({ 'Ok' : IDL.Nat64, 'Err' : Error });
   const TransactionRecord = IDL.Record({
     'transaction_type' : IDL.Text,
     'change_type' : IDL.Text,
     'timestamp' : IDL.Nat64,
   });
+  const AuditAction = IDL.Variant({
+    'OwnerChanged' : IDL.Null,
+    'AccessoryUpdated' : IDL.Null,
+    'AccessoryPurged' : IDL.Null,
+    'TransferCancelled' : IDL.Null,
+    'AccessoryRestored' : IDL.Null,
+    'AccessoryDeleted' : IDL.Null,
+    'TransferProposed' : IDL.Null,
+    'TransferAccepted' : IDL.Null,
+  });
+  const AuditEntry = IDL.Record({
+    'id' : IDL.Nat64,
+    'accessory_id' : IDL.Opt(IDL.Nat64),
+    'action' : AuditAction,
+    'actor' : IDL.Principal,
+    'recorded_at' : IDL.Nat64,
+    'details' : IDL.Text,
+  });
+  const Result_24 = IDL.Variant({ 'Ok' : IDL.Vec(AuditEntry), 'Err' : Error });
+  const Result_25 = IDL.Variant({ 'Ok' : IDL.Vec(Block), 'Err' : Error });
+  const AccessoryCostOfGoodsSold = IDL.Record({
+    'accessory_id' : IDL.Nat64,
+    'revenue' : IDL.Nat64,
+    'cost' : IDL.Nat64,
+    'currency' : IDL.Opt(IDL.Text),
+    'quantity' : IDL.Nat64,
+  });
+  const CostOfGoodsSoldTotal = IDL.Record({
+    'revenue' : IDL.Nat64,
+    'cost' : IDL.Nat64,
+    'currency' : IDL.Opt(IDL.Text),
+    'quantity' : IDL.Nat64,
+  });
+  const CostOfGoodsSoldReport = IDL.Record({
+    'to' : IDL.Nat64,
+    'from' : IDL.Nat64,
+    'lines' : IDL.Vec(AccessoryCostOfGoodsSold),
+    'totals' : IDL.Vec(CostOfGoodsSoldTotal),
+  });
+  const CostingMethod = IDL.Variant({
+    'Fifo' : IDL.Null,
+    'WeightedAverage' : IDL.Null,
+  });
+  const CurrencyConfig = IDL.Record({ 'default_currency' : IDL.Opt(IDL.Text) });
+  const ExchangeRate = IDL.Record({
+    'updated_at' : IDL.Nat64,
+    'updated_by' : IDL.Principal,
+    'base' : IDL.Text,
+    'rate' : IDL.Nat64,
+    'quote' : IDL.Text,
+  });
+  const GrossMargin = IDL.Record({
+    'accessory_id' : IDL.Nat64,
+    'revenue' : IDL.Nat64,
+    'cost_of_goods_sold' : IDL.Nat64,
+    'quantity_sold' : IDL.Nat64,
+    'margin_bps' : IDL.Opt(IDL.Int64),
+    'gross_margin' : IDL.Int64,
+  });
+  const Result_26 = IDL.Variant({ 'Ok' : GrossMargin, 'Err' : Error });
+  const InventoryValuation = IDL.Record({
+    'value' : IDL.Nat64,
+    'seller' : IDL.Principal,
+    'currency' : IDL.Opt(IDL.Text),
+    'quantity' : IDL.Nat64,
+    'category' : IDL.Text,
+  });
+  const Invoice = IDL.Record({
+    'total' : IDL.Nat64,
+    'issued_at' : IDL.Nat64,
+    'seller_contact' : IDL.Opt(IDL.Text),
+    'discount_total' : IDL.Nat64,
+    'shipping' : IDL.Opt(ShippingCharge),
+    'seller' : IDL.Principal,
+    'lines' : IDL.Vec(OrderLine),
+    'voided_at' : IDL.Opt(IDL.Nat64),
+    'jurisdiction' : IDL.Opt(IDL.Text),
+    'currency' : IDL.Opt(IDL.Text),
+    'number' : IDL.Nat64,
+    'buyer' : IDL.Principal,
+    'order_id' : IDL.Nat64,
+    'tax_total' : IDL.Nat64,
+    'seller_name' : IDL.Opt(IDL.Text),
+    'subtotal' : IDL.Nat64,
+  });
+  const Result_27 = IDL.Variant({ 'Ok' : Invoice, 'Err' : Error });
+  const InvoiceLinks = IDL.Record({ 'html' : IDL.Text, 'json' : IDL.Text });
+  const Result_28 = IDL.Variant({ 'Ok' : InvoiceLinks, 'Err' : Error });
+  const Result_29 = IDL.Variant({ 'Ok' : IDL.Vec(Invoice), 'Err' : Error });
+  const Result_30 = IDL.Variant({ 'Ok' : IDL.Vec(Shipment), 'Err' : Error });
+  const PaymentConfig = IDL.Record({
+    'ledger' : IDL.Opt(IDL.Principal),
+    'currency' : IDL.Opt(IDL.Text),
+  });
+  const PriceChangeReason = IDL.Variant({
+    'SaleEnded' : IDL.Null,
+    'SaleStarted' : IDL.Null,
+    'Manual' : IDL.Null,
+  });
+  const PriceChange = IDL.Record({
+    'id' : IDL.Nat64,
+    'accessory_id' : IDL.Nat64,
+    'changed_at' : IDL.Nat64,
+    'changed_by' : IDL.Principal,
+    'new_price' : IDL.Nat64,
+    'old_price' : IDL.Nat64,
+    'reason' : PriceChangeReason,
+  });
+  const Result_31 = IDL.Variant({ 'Ok' : IDL.Vec(PriceChange), 'Err' : Error });
+  const RateQuota = IDL.Record({
+    'capacity' : IDL.Nat32,
+    'refill_interval' : IDL.Nat64,
+  });
+  const ReorderLine = IDL.Record({
+    'suggested_quantity' : IDL.Nat64,
+    'accessory_id' : IDL.Nat64,
+    'inventory_count' : IDL.Nat64,
+    'name' : IDL.Text,
+    'unit_cost' : IDL.Opt(IDL.Nat64),
+    'reorder_point' : IDL.Nat64,
+  });
+  const SupplierReorderSuggestion = IDL.Record({
+    'supplier_id' : IDL.Opt(IDL.Nat64),
+    'supplier_name' : IDL.Opt(IDL.Text),
+    'lines' : IDL.Vec(ReorderLine),
+  });
+  const VerificationStatus = IDL.Variant({
+    'Suspended' : IDL.Null,
+    'Rejected' : IDL.Null,
+    'Unverified' : IDL.Null,
+    'Verified' : IDL.Null,
+  });
+  const Seller = IDL.Record({
+    'updated_at' : IDL.Opt(IDL.Nat64),
+    'principal' : IDL.Principal,
+    'contact' : IDL.Text,
+    'return_policy' : IDL.Text,
+    'created_at' : IDL.Nat64,
+    'verification_status' : VerificationStatus,
+    'display_name' : IDL.Text,
+    'version' : IDL.Opt(IDL.Nat64),
+  });
+  const Result_32 = IDL.Variant({ 'Ok' : Seller, 'Err' : Error });
+  const SellerConfig = IDL.Record({ 'require_verification' : IDL.Bool });
+  const SellerScorecard = IDL.Record({
+    'orders_placed' : IDL.Nat64,
+    'seller' : IDL.Principal,
+    'review_count' : IDL.Nat64,
+    'return_rate' : IDL.Opt(IDL.Float64),
+    'fulfilment_rate' : IDL.Opt(IDL.Float64),
+    'average_rating' : IDL.Opt(IDL.Float64),
+    'average_ship_time_ns' : IDL.Opt(IDL.Nat64),
+    'cancellation_rate' : IDL.Opt(IDL.Float64),
+  });
+  const Result_33 = IDL.Variant({ 'Ok' : SellerScorecard, 'Err' : Error });
+  const Storefront = IDL.Record({
+    'total' : IDL.Nat64,
+    'listings' : IDL.Vec(Accessory),
+    'offset' : IDL.Nat64,
+    'seller' : Seller,
+    'limit' : IDL.Nat64,
+  });
+  const Result_34 = IDL.Variant({ 'Ok' : Storefront, 'Err' : Error });
+  const Result_35 = IDL.Variant({ 'Ok' : IDL.Vec(Seller), 'Err' : Error });
+  const SerialEvent = IDL.Record({
+    'id' : IDL.Nat64,
+    'status' : SerialStatus,
+    'actor' : IDL.Principal,
+    'note' : IDL.Text,
+    'created_at' : IDL.Nat64,
+    'order_id' : IDL.Opt(IDL.Nat64),
+    'location' : IDL.Text,
+  });
+  const SerialHistory = IDL.Record({
+    'unit' : SerialUnit,
+    'events' : IDL.Vec(SerialEvent),
+  });
+  const Result_36 = IDL.Variant({ 'Ok' : SerialHistory, 'Err' : Error });
+  const StockMovement = IDL.Record({
+    'id' : IDL.Nat64,
+    'accessory_id' : IDL.Nat64,
+    'balance_after' : IDL.Nat64,
+    'actor' : IDL.Principal,
+    'note' : IDL.Text,
+    'created_at' : IDL.Nat64,
+    'delta' : IDL.Int64,
+    'reason' : StockReason,
+  });
+  const TaxConfig = IDL.Record({ 'default_jurisdiction' : IDL.Opt(IDL.Text) });
+  const TaxRule = IDL.Record({
+    'id' : IDL.Nat64,
+    'inclusive' : IDL.Bool,
+    'updated_at' : IDL.Opt(IDL.Nat64),
+    'created_at' : IDL.Nat64,
+    'jurisdiction' : IDL.Text,
+    'category' : IDL.Opt(IDL.Text),
+    'rate_basis_points' : IDL.Nat64,
+  });
+  const Result_37 = IDL.Variant({ 'Ok' : IDL.Vec(TaxRule), 'Err' : Error });
+  const TaxSummaryLine = IDL.Record({
+    'inclusive' : IDL.Bool,
+    'tax_amount' : IDL.Nat64,
+    'order_count' : IDL.Nat64,
+    'jurisdiction' : IDL.Text,
+    'currency' : IDL.Opt(IDL.Text),
+    'category' : IDL.Opt(IDL.Text),
+    'taxable_amount' : IDL.Nat64,
+    'rate_basis_points' : IDL.Nat64,
+  });
+  const TaxSummary = IDL.Record({
+    'to' : IDL.Nat64,
+    'from' : IDL.Nat64,
+    'lines' : IDL.Vec(TaxSummaryLine),
+  });
+  const CallerStats = IDL.Record({
+    'principal' : IDL.Principal,
+    'rate_limited' : IDL.Nat64,
+    'calls' : IDL.Nat64,
+  });
+  const Result_38 = IDL.Variant({ 'Ok' : IDL.Vec(CallerStats), 'Err' : Error });
+  const TrashConfig = IDL.Record({ 'retention_days' : IDL.Nat64 });
+  const HttpRequest = IDL.Record({
+    'url' : IDL.Text,
+    'method' : IDL.Text,
+    'body' : IDL.Vec(IDL.Nat8),
+    'headers' : IDL.Vec(IDL.Tuple(IDL.Text, IDL.Text)),
+  });
+  const HttpResponse = IDL.Record({
+    'body' : IDL.Vec(IDL.Nat8),
+    'headers' : IDL.Vec(IDL.Tuple(IDL.Text, IDL.Text)),
+    'status_code' : IDL.Nat16,
+  });
+  const Result_39 = IDL.Variant({ 'Ok' : SupplierLink, 'Err' : Error });
+  const AccessoryPatch = IDL.Record({
+    'reorder_quantity' : IDL.Opt(IDL.Opt(IDL.Nat64)),
+    'inventory_count' : IDL.Opt(IDL.Nat64),
+    'name' : IDL.Opt(IDL.Text),
+    'description' : IDL.Opt(IDL.Text),
+    'barcode' : IDL.Opt(IDL.Opt(IDL.Text)),
+    'currency' : IDL.Opt(IDL.Text),
+    'category' : IDL.Opt(IDL.Text),
+    'is_available' : IDL.Opt(IDL.Bool),
+    'reorder_point' : IDL.Opt(IDL.Opt(IDL.Nat64)),
+    'price' : IDL.Opt(IDL.Nat64),
+    'weight_grams' : IDL.Opt(IDL.Opt(IDL.Nat64)),
+    'dimensions' : IDL.Opt(IDL.Opt(Dimensions)),
+    'manufacturer_part_number' : IDL.Opt(IDL.Opt(IDL.Text)),
+  });
+  const OrderLinePayload = IDL.Record({
+    'accessory_id' : IDL.Nat64,
+    'quantity' : IDL.Nat64,
+    'serials' : IDL.Vec(IDL.Text),
+  });
+  const OrderPayload = IDL.Record({
+    'coupon_codes' : IDL.Opt(IDL.Vec(IDL.Text)),
+    'lines' : IDL.Vec(OrderLinePayload),
+    'jurisdiction' : IDL.Opt(IDL.Text),
+    'shipping_method_id' : IDL.Opt(IDL.Nat64),
+  });
+  const ConvertedAmounts = IDL.Record({
+    'total' : Money,
+    'shipping_total' : Money,
+    'discount_total' : Money,
+    'tax_total' : Money,
+    'rounding' : RoundingMode,
+    'subtotal' : Money,
+  });
+  const PriceQuote = IDL.Record({
+    'total' : IDL.Nat64,
+    'discount_total' : IDL.Nat64,
+    'shipping' : IDL.Opt(ShippingCharge),
+    'coupon_codes' : IDL.Vec(IDL.Text),
+    'lines' : IDL.Vec(OrderLine),
+    'jurisdiction' : IDL.Opt(IDL.Text),
+    'currency' : IDL.Opt(IDL.Text),
+    'tax_total' : IDL.Nat64,
+    'converted' : IDL.Opt(ConvertedAmounts),
+    'subtotal' : IDL.Nat64,
+  });
+  const Result_40 = IDL.Variant({ 'Ok' : PriceQuote, 'Err' : Error });
+  const GoodsReceiptLine = IDL.Record({
+    'accessory_id' : IDL.Nat64,
+    'quantity' : IDL.Nat64,
+  });
+  const SellerPayload = IDL.Record({
+    'contact' : IDL.Text,
+    'return_policy' : IDL.Text,
+    'display_name' : IDL.Text,
+  });
+  const Result_41 = IDL.Variant({ 'Ok' : TaxRule, 'Err' : Error });
+  const ReturnRequestPayload = IDL.Record({
+    'accessory_id' : IDL.Nat64,
+    'comment' : IDL.Text,
+    'quantity' : IDL.Nat64,
+    'order_id' : IDL.Nat64,
+    'serials' : IDL.Vec(IDL.Text),
+    'condition' : ItemCondition,
+    'reason' : ReturnReason,
+  });
+  const SellerSort = IDL.Variant({
+    'ShipTime' : IDL.Null,
+    'CancellationRate' : IDL.Null,
+    'FulfilmentRate' : IDL.Null,
+    'ReturnRate' : IDL.Null,
+    'Rating' : IDL.Null,
+  });
+  const Result_42 = IDL.Variant({ 'Ok' : CurrencyConfig, 'Err' : Error });
+  const Result_43 = IDL.Variant({ 'Ok' : TaxConfig, 'Err' : Error });
+  const Result_44 = IDL.Variant({ 'Ok' : ExchangeRate, 'Err' : Error });
+  const StocktakeScope = IDL.Variant({
+    'Accessories' : IDL.Vec(IDL.Nat64),
+    'Category' : IDL.Text,
+  });
+  const Result_45 = IDL.Variant({ 'Ok' : SerialUnit, 'Err' : Error });
   return IDL.Service({
-    'add_accessory' : IDL.Func([AccessoryPayload], [IDL.Opt(Accessory)], []),
+    'accept_ownership_transfer' : IDL.Func([IDL.Nat64], [Result], []),
+    'acknowledge_stock_alert' : IDL.Func([IDL.Nat64], [Result_1], []),
+    'add_accessory' : IDL.Func([AccessoryPayload], [Result_2], []),
+    'add_batch' : IDL.Func([IDL.Nat64, BatchPayload], [Result_3], []),
+    'add_currency' : IDL.Func([IDL.Text, IDL.Nat8], [Result_4], []),
+    'add_review' : IDL.Func([ReviewPayload], [Result_5], []),
+    'add_serials' : IDL.Func(
+        [IDL.Nat64, IDL.Vec(IDL.Text), IDL.Text, IDL.Opt(IDL.Nat64)],
+        [Result_6],
+        [],
+      ),
+    'add_shipping_method' : IDL.Func([ShippingMethodPayload], [Result_7], []),
+    'add_supplier' : IDL.Func([SupplierPayload], [Result_8], []),
+    'adjust_inventory' : IDL.Func(
+        [
+          IDL.Nat64,
+          IDL.Int64,
+          StockReason,
+          IDL.Text,
+          IDL.Opt(IDL.Nat64),
+          IDL.Opt(IDL.Nat64),
+        ],
+        [Result_2],
+        [],
+      ),
+    'approve_return' : IDL.Func(
+        [IDL.Nat64, IDL.Opt(IDL.Text), IDL.Opt(IDL.Nat64)],
+        [Result_9],
+        [],
+      ),
+    'approve_stocktake' : IDL.Func(
+        [IDL.Nat64, IDL.Opt(IDL.Nat64)],
+        [Result_10],
+        [],
+      ),
+    'assign_role' : IDL.Func([IDL.Principal, Role], [Result_11], []),
+    'block_principal' : IDL.Func(
+        [IDL.Principal, IDL.Nat64, IDL.Text],
+        [Result_12],
+        [],
+      ),
+    'bulk_add' : IDL.Func(
+        [IDL.Vec(AccessoryPayload), BulkMode, IDL.Bool],
+        [Result_14],
+        [],
+      ),
+    'bulk_delete' : IDL.Func(
+        [IDL.Vec(IDL.Nat64), BulkMode, IDL.Bool],
+        [Result_14],
+        [],
+      ),
+    'bulk_update' : IDL.Func(
+        [IDL.Vec(IDL.Tuple(IDL.Nat64, AccessoryPayload)), BulkMode, IDL.Bool],
+        [Result_14],
+        [],
+      ),
     'bulk_update_accessories' : IDL.Func(
         [IDL.Vec(IDL.Tuple(IDL.Nat64, AccessoryPayload))],
-        [IDL.Vec(Result)],
+        [Result_15],
+        [],
+      ),
+    'cancel_order' : IDL.Func([IDL.Nat64, IDL.Opt(IDL.Nat64)], [Result_16], []),
+    'cancel_ownership_transfer' : IDL.Func([IDL.Nat64], [Result], []),
+    'cancel_purchase_order' : IDL.Func(
+        [IDL.Nat64, IDL.Opt(IDL.Nat64)],
+        [Result_17],
+        [],
+      ),
+    'cancel_sale' : IDL.Func([IDL.Nat64], [Result_18], []),
+    'cancel_stocktake' : IDL.Func(
+        [IDL.Nat64, IDL.Opt(IDL.Nat64)],
+        [Result_19],
+        [],
+      ),
+    'check_inventory_levels' : IDL.Func(
+        [IDL.Nat64],
+        [IDL.Vec(Accessory)],
+        ['query'],
+      ),
+    'create_coupon' : IDL.Func([CouponPayload], [Result_20], []),
+    'create_purchase_order' : IDL.Func([PurchaseOrderPayload], [Result_17], []),
+    'create_shipment' : IDL.Func(
+        [IDL.Nat64, IDL.Text, IDL.Text],
+        [Result_21],
+        [],
+      ),
+    'deactivate_coupon' : IDL.Func(
+        [IDL.Text, IDL.Opt(IDL.Nat64)],
+        [Result_20],
+        [],
+      ),
+    'delete_accessory' : IDL.Func(
+        [IDL.Nat64, IDL.Opt(IDL.Nat64)],
+        [Result_2],
+        [],
+      ),
+    'enable_serial_tracking' : IDL.Func(
+        [IDL.Nat64, IDL.Vec(IDL.Text), IDL.Text],
+        [Result_2],
         [],
       ),
-    'delete_accessory' : IDL.Func([IDL.Nat64], [Result], []),
     'get_accessories_by_category' : IDL.Func(
         [IDL.Text],
         [IDL.Vec(Accessory)],
         ['query'],
       ),
-    'get_accessory' : IDL.Func([IDL.Nat64], [Result], ['query']),
-    'get_accessory_price' : IDL.Func([IDL.Nat64], [Result_1], ['query']),
+    'get_accessory' : IDL.Func([IDL.Nat64], [Result_2], ['query']),
+    'get_accessory_by_barcode' : IDL.Func([IDL.Text], [Result_2], ['query']),
+    'get_accessory_price' : IDL.Func([IDL.Nat64], [Result_22], ['query']),
+    'get_accessory_price_in_currency' : IDL.Func(
+        [IDL.Nat64, IDL.Opt(IDL.Text), IDL.Opt(RoundingMode)],
+        [Result_23],
+        ['query'],
+      ),
+    'get_accessory_suppliers' : IDL.Func(
+        [IDL.Nat64],
+        [IDL.Vec(SupplierLink)],
+        ['query'],
+      ),
     'get_accessory_transaction_history' : IDL.Func(
         [IDL.Nat64],
         [IDL.Vec(TransactionRecord)],
         ['query'],
       ),
+    'get_audit_log' : IDL.Func([IDL.Opt(IDL.Nat64)], [Result_24], ['query']),
     'get_available_accessories' : IDL.Func([], [IDL.Vec(Accessory)], ['query']),
-    'mark_accessory_as_available' : IDL.Func([IDL.Nat64], [Result], []),
-    'mark_accessory_as_unavailable' : IDL.Func([IDL.Nat64], [Result], []),
+    'get_batches' : IDL.Func([IDL.Nat64], [IDL.Vec(Batch)], ['query']),
+    'get_blocked_principals' : IDL.Func([], [Result_25], ['query']),
+    'get_cost_of_goods_sold' : IDL.Func(
+        [IDL.Nat64, IDL.Nat64],
+        [CostOfGoodsSoldReport],
+        ['query'],
+      ),
+    'get_costing_method' : IDL.Func([], [CostingMethod], ['query']),
+    'get_coupon' : IDL.Func([IDL.Text], [Result_20], ['query']),
+    'get_currencies' : IDL.Func([], [IDL.Vec(Currency)], ['query']),
+    'get_currency_config' : IDL.Func([], [CurrencyConfig], ['query']),
+    'get_damaged_stock' : IDL.Func([IDL.Nat64], [Result_22], ['query']),
+    'get_exchange_rates' : IDL.Func([], [IDL.Vec(ExchangeRate)], ['query']),
+    'get_expiring_batches' : IDL.Func([IDL.Nat64], [IDL.Vec(Batch)], ['query']),
+    'get_gross_margin' : IDL.Func([IDL.Nat64], [Result_26], ['query']),
+    'get_inventory_valuation' : IDL.Func(
+        [],
+        [IDL.Vec(InventoryValuation)],
+        ['query'],
+      ),
+    'get_invoice' : IDL.Func([IDL.Nat64], [Result_27], ['query']),
+    'get_invoice_links' : IDL.Func([IDL.Nat64], [Result_28], ['query']),
+    'get_invoices' : IDL.Func([IDL.Opt(IDL.Principal)], [Result_29], ['query']),
+    'get_my_coupons' : IDL.Func([], [IDL.Vec(Coupon)], ['query']),
+    'get_my_orders' : IDL.Func([], [IDL.Vec(Order)], ['query']),
+    'get_my_returns' : IDL.Func([], [IDL.Vec(ReturnRequest)], ['query']),
+    'get_order' : IDL.Func([IDL.Nat64], [Result_16], ['query']),
+    'get_order_invoice' : IDL.Func([IDL.Nat64], [Result_27], ['query']),
+    'get_order_shipments' : IDL.Func([IDL.Nat64], [Result_30], ['query']),
+    'get_overdue_purchase_orders' : IDL.Func(
+        [],
+        [IDL.Vec(PurchaseOrder)],
+        ['query'],
+      ),
+    'get_ownership_transfers' : IDL.Func(
+        [IDL.Opt(TransferStatus)],
+        [IDL.Vec(OwnershipTransfer)],
+        ['query'],
+      ),
+    'get_payment_config' : IDL.Func([], [PaymentConfig], ['query']),
+    'get_price_history' : IDL.Func([IDL.Nat64], [Result_31], ['query']),
+    'get_purchase_order' : IDL.Func([IDL.Nat64], [Result_17], ['query']),
+    'get_purchase_orders' : IDL.Func(
+        [IDL.Opt(PurchaseOrderStatus)],
+        [IDL.Vec(PurchaseOrder)],
+        ['query'],
+      ),
+    'get_rate_limits' : IDL.Func(
+        [],
+        [RateQuota, IDL.Vec(IDL.Tuple(IDL.Text, RateQuota))],
+        ['query'],
+      ),
+    'get_reorder_suggestions' : IDL.Func(
+        [],
+        [IDL.Vec(SupplierReorderSuggestion)],
+        ['query'],
+      ),
+    'get_return' : IDL.Func([IDL.Nat64], [Result_9], ['query']),
+    'get_reviews' : IDL.Func([IDL.Nat64], [IDL.Vec(Review)], ['query']),
+    'get_role' : IDL.Func([IDL.Principal], [IDL.Opt(Role)], ['query']),
+    'get_sales' : IDL.Func([IDL.Nat64], [IDL.Vec(Sale)], ['query']),
+    'get_seller' : IDL.Func([IDL.Principal], [Result_32], ['query']),
+    'get_seller_config' : IDL.Func([], [SellerConfig], ['query']),
+    'get_seller_orders' : IDL.Func([], [IDL.Vec(Order)], ['query']),
+    'get_seller_returns' : IDL.Func(
+        [IDL.Opt(ReturnStatus)],
+        [IDL.Vec(ReturnRequest)],
+        ['query'],
+      ),
+    'get_seller_scorecard' : IDL.Func([IDL.Principal], [Result_33], ['query']),
+    'get_seller_storefront' : IDL.Func(
+        [IDL.Principal, IDL.Nat64, IDL.Nat64],
+        [Result_34],
+        ['query'],
+      ),
+    'get_sellers' : IDL.Func(
+        [IDL.Opt(VerificationStatus)],
+        [Result_35],
+        ['query'],
+      ),
+    'get_serial' : IDL.Func([IDL.Text], [Result_36], ['query']),
+    'get_serials' : IDL.Func(
+        [IDL.Nat64, IDL.Opt(SerialStatus)],
+        [Result_6],
+        ['query'],
+      ),
+    'get_shipping_methods' : IDL.Func(
+        [IDL.Principal],
+        [IDL.Vec(ShippingMethod)],
+        ['query'],
+      ),
+    'get_stock_alerts' : IDL.Func([IDL.Bool], [IDL.Vec(StockAlert)], ['query']),
+    'get_stock_movements' : IDL.Func(
+        [IDL.Nat64],
+        [IDL.Vec(StockMovement)],
+        ['query'],
+      ),
+    'get_stocktake_report' : IDL.Func([IDL.Nat64], [Result_10], ['query']),
+    'get_stocktakes' : IDL.Func([], [IDL.Vec(Stocktake)], ['query']),
+    'get_supplier' : IDL.Func([IDL.Nat64], [Result_8], ['query']),
+    'get_suppliers' : IDL.Func([], [IDL.Vec(Supplier)], ['query']),
+    'get_tax_config' : IDL.Func([], [TaxConfig], ['query']),
+    'get_tax_rules' : IDL.Func([IDL.Opt(IDL.Text)], [Result_37], ['query']),
+    'get_tax_summary' : IDL.Func(
+        [IDL.Nat64, IDL.Nat64],
+        [TaxSummary],
+        ['query'],
+      ),
+    'get_top_callers' : IDL.Func([IDL.Nat64], [Result_38], ['query']),
+    'get_trash_config' : IDL.Func([], [TrashConfig], ['query']),
+    'http_request' : IDL.Func([HttpRequest], [HttpResponse], ['query']),
+    'issue_invoice' : IDL.Func([IDL.Nat64], [Result_27], []),
+    'issue_refund' : IDL.Func([IDL.Nat64, IDL.Opt(IDL.Nat64)], [Result_9], []),
+    'link_accessory_supplier' : IDL.Func(
+        [IDL.Nat64, IDL.Nat64, IDL.Nat64, IDL.Bool],
+        [Result_39],
+        [],
+      ),
+    'list_trash' : IDL.Func([], [IDL.Vec(Accessory)], ['query']),
+    'patch_accessory' : IDL.Func(
+        [IDL.Nat64, AccessoryPatch, IDL.Opt(IDL.Nat64)],
+        [Result_2],
+        [],
+      ),
+    'pay_order' : IDL.Func([IDL.Nat64], [Result_16], []),
+    'place_order' : IDL.Func([OrderPayload], [Result_16], []),
+    'price_quote' : IDL.Func(
+        [OrderPayload, IDL.Opt(IDL.Text), IDL.Opt(RoundingMode)],
+        [Result_40],
+        ['query'],
+      ),
+    'propose_listing_transfer' : IDL.Func(
+        [IDL.Nat64, IDL.Principal],
+        [Result],
+        [],
+      ),
+    'propose_seller_transfer' : IDL.Func(
+        [IDL.Principal, IDL.Principal],
+        [Result],
+        [],
+      ),
+    'receive_purchase_order' : IDL.Func(
+        [IDL.Nat64, IDL.Vec(GoodsReceiptLine), IDL.Opt(IDL.Nat64)],
+        [Result_17],
+        [],
+      ),
+    'receive_return' : IDL.Func(
+        [IDL.Nat64, ReturnDisposition, IDL.Opt(IDL.Nat64)],
+        [Result_9],
+        [],
+      ),
+    'record_stocktake_counts' : IDL.Func(
+        [
+          IDL.Nat64,
+          IDL.Vec(IDL.Tuple(IDL.Nat64, IDL.Nat64)),
+          IDL.Opt(IDL.Nat64),
+        ],
+        [Result_19],
+        [],
+      ),
+    'register_seller' : IDL.Func([SellerPayload], [Result_32], []),
+    'reject_return' : IDL.Func(
+        [IDL.Nat64, IDL.Opt(IDL.Text), IDL.Opt(IDL.Nat64)],
+        [Result_9],
+        [],
+      ),
+    'remove_rate_limit' : IDL.Func([IDL.Text], [Result_11], []),
+    'remove_tax_rule' : IDL.Func([IDL.Nat64], [Result_41], []),
+    'request_return' : IDL.Func([ReturnRequestPayload], [Result_9], []),
+    'restore_accessory' : IDL.Func([IDL.Nat64], [Result_2], []),
+    'revoke_role' : IDL.Func([IDL.Principal], [Result_11], []),
+    'schedule_sale' : IDL.Func(
+        [IDL.Nat64, IDL.Nat64, IDL.Nat64, IDL.Nat64],
+        [Result_18],
+        [],
+      ),
     'search_accessories' : IDL.Func(
-        [IDL.Text],
+        [IDL.Text, IDL.Opt(SellerSort)],
         [IDL.Vec(Accessory)],
         ['query'],
       ),
-    'update_accessory' : IDL.Func([IDL.Nat64, AccessoryPayload], [Result], []),
+    'set_costing_method' : IDL.Func([CostingMethod], [Result_11], []),
+    'set_default_currency' : IDL.Func([IDL.Text], [Result_42], []),
+    'set_default_jurisdiction' : IDL.Func([IDL.Opt(IDL.Text)], [Result_43], []),
+    'set_exchange_rate' : IDL.Func(
+        [IDL.Text, IDL.Text, IDL.Nat64],
+        [Result_44],
+        [],
+      ),
+    'set_payment_ledger' : IDL.Func(
+        [IDL.Opt(IDL.Principal), IDL.Opt(IDL.Text)],
+        [Result_11],
+        [],
+      ),
+    'set_rate_limit' : IDL.Func([IDL.Text, RateQuota], [Result_11], []),
+    'set_seller_verification' : IDL.Func(
+        [IDL.Principal, VerificationStatus],
+        [Result_32],
+        [],
+      ),
+    'set_seller_verification_required' : IDL.Func([IDL.Bool], [Result_11], []),
+    'set_tax_rule' : IDL.Func(
+        [IDL.Text, IDL.Opt(IDL.Text), IDL.Nat64, IDL.Bool],
+        [Result_41],
+        [],
+      ),
+    'set_trash_retention' : IDL.Func([IDL.Nat64], [Result_11], []),
+    'start_stocktake' : IDL.Func([StocktakeScope], [Result_19], []),
+    'toggle_accessory_availability' : IDL.Func(
+        [IDL.Nat64, IDL.Opt(IDL.Nat64)],
+        [Result_2],
+        [],
+      ),
+    'unblock_principal' : IDL.Func([IDL.Principal], [Result_11], []),
+    'unlink_accessory_supplier' : IDL.Func(
+        [IDL.Nat64, IDL.Nat64],
+        [Result_39],
+        [],
+      ),
+    'update_accessory' : IDL.Func(
+        [IDL.Nat64, AccessoryPayload, IDL.Opt(IDL.Nat64)],
+        [Result_2],
+        [],
+      ),
+    'update_inventory' : IDL.Func(
+        [IDL.Nat64, IDL.Nat64, IDL.Opt(IDL.Nat64)],
+        [Result_2],
+        [],
+      ),
+    'update_seller_profile' : IDL.Func(
+        [SellerPayload, IDL.Opt(IDL.Nat64)],
+        [Result_32],
+        [],
+      ),
+    'update_serial_status' : IDL.Func(
+        [
+          IDL.Text,
+          SerialStatus,
+          IDL.Opt(IDL.Text),
+          IDL.Text,
+          IDL.Opt(IDL.Principal),
+        ],
+        [Result_45],
+        [],
+      ),
+    'update_shipment_status' : IDL.Func(
+        [IDL.Nat64, IDL.Nat64, ShipmentStatus, IDL.Text],
+        [Result_21],
+        [],
+      ),
+    'update_shipping_method' : IDL.Func(
+        [IDL.Nat64, ShippingMethodPayload, IDL.Opt(IDL.Nat64)],
+        [Result_7],
+        [],
+      ),
+    'update_supplier' : IDL.Func(
+        [IDL.Nat64, SupplierPayload, IDL.Opt(IDL.Nat64)],
+        [Result_8],
+        [],
+      ),
   });
 };
 export const init = ({ IDL }) => { return []; };
@@ -0,0 +1,123 @@
+// Append-only audit log of administrative changes to listings
+use crate::{next_id, roles, Error, Memory, TransactionRecord, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Define the kinds of changes recorded in the audit log
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum AuditAction {
+    TransferProposed,
+    TransferAccepted,
+    TransferCancelled,
+    // A single listing changed hands as part of an accepted transfer
+    OwnerChanged,
+    // Fields changed through an update or patch, listed in the details
+    AccessoryUpdated,
+    AccessoryDeleted,
+    AccessoryRestored,
+    // Removed for good once its retention period in the trash ended
+    AccessoryPurged,
+}
+
+// Define a single entry in the audit log
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AuditEntry {
+    id: u64,
+    action: AuditAction,
+    actor: Principal,
+    accessory_id: Option<u64>,
+    details: String,
+    recorded_at: u64,
+}
+
+// Implement the Storable trait for AuditEntry
+impl Storable for AuditEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for AuditEntry
+impl BoundedStorable for AuditEntry {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static AUDIT_LOG: RefCell<StableBTreeMap<u64, AuditEntry, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(42))))
+    );
+}
+
+// Query function to get the audit log, optionally for one accessory, oldest first (staff only)
+#[ic_cdk::query]
+fn get_audit_log(accessory_id: Option<u64>) -> Result<Vec<AuditEntry>, Error> {
+    if !roles::_is_staff(&caller()) {
+        return Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} isn't staff", caller()),
+        });
+    }
+    Ok(_entries(accessory_id))
+}
+
+// History of the changes to an accessory, newest first
+pub(crate) fn transaction_history(accessory_id: u64) -> Vec<TransactionRecord> {
+    _entries(Some(accessory_id))
+        .into_iter()
+        .rev()
+        .map(|entry| TransactionRecord {
+            timestamp: entry.recorded_at,
+            change_type: entry.details,
+            transaction_type: _transaction_type(entry.action).to_string(),
+        })
+        .collect()
+}
+
+// Append an entry made by the caller to the audit log
+pub(crate) fn record(action: AuditAction, accessory_id: Option<u64>, details: String) {
+    record_by(caller(), action, accessory_id, details);
+}
+
+// Append an entry to the audit log; scheduled jobs record the canister itself as actor
+pub(crate) fn record_by(actor: Principal, action: AuditAction, accessory_id: Option<u64>, details: String) {
+    let entry = AuditEntry {
+        id: next_id(),
+        action,
+        actor,
+        accessory_id,
+        details,
+        recorded_at: time(),
+    };
+    AUDIT_LOG.with(|service| service.borrow_mut().insert(entry.id, entry));
+}
+
+fn _entries(accessory_id: Option<u64>) -> Vec<AuditEntry> {
+    AUDIT_LOG.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, entry)| entry)
+            .filter(|entry| accessory_id.is_none_or(|id| entry.accessory_id == Some(id)))
+            .collect()
+    })
+}
+
+fn _transaction_type(action: AuditAction) -> &'static str {
+    match action {
+        AuditAction::TransferProposed => "TransferProposed",
+        AuditAction::TransferAccepted => "TransferAccepted",
+        AuditAction::TransferCancelled => "TransferCancelled",
+        AuditAction::OwnerChanged => "OwnerChange",
+        AuditAction::AccessoryUpdated => "Update",
+        AuditAction::AccessoryDeleted => "Deletion",
+        AuditAction::AccessoryRestored => "Restoration",
+        AuditAction::AccessoryPurged => "Purge",
+    }
+}
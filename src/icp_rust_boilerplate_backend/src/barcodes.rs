@@ -0,0 +1,126 @@
+// GTIN barcodes: check-digit validation and the barcode -> accessory index
+use crate::{_get_accessory, Accessory, Error, Memory, MEMORY_MANAGER};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use validator::ValidationError;
+
+// Barcodes are indexed in their GTIN-14 form, so a UPC-A and the EAN-13 with a
+// leading zero resolve to the same accessory
+pub(crate) type GtinKey = [u8; 14];
+
+thread_local! {
+    static BARCODE_INDEX: RefCell<StableBTreeMap<GtinKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19))))
+    );
+}
+
+// Query function to resolve a scanned barcode to an accessory
+#[ic_cdk::query]
+fn get_accessory_by_barcode(barcode: String) -> Result<Accessory, Error> {
+    let not_found = || Error::NotFound {
+        msg: format!("an accessory with barcode={} not found", barcode),
+    };
+    let key = _gtin_key(&barcode).ok_or_else(not_found)?;
+    BARCODE_INDEX
+        .with(|index| index.borrow().get(&key))
+        .and_then(|id| _get_accessory(&id))
+        .ok_or_else(not_found)
+}
+
+// Validate a GTIN-8, GTIN-12 (UPC-A), GTIN-13 (EAN-13) or GTIN-14 and its check digit
+pub(crate) fn validate_gtin(code: &str) -> Result<(), ValidationError> {
+    if !matches!(code.len(), 8 | 12 | 13 | 14) || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ValidationError::new(
+            "barcode must be a GTIN-8, GTIN-12, GTIN-13 or GTIN-14",
+        ));
+    }
+    let digits: Vec<u32> = code.bytes().map(|b| u32::from(b - b'0')).collect();
+    let (body, check) = digits.split_at(digits.len() - 1);
+    // Weights alternate 3, 1, 3, ... starting from the digit next to the check digit
+    let sum: u32 = body
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, digit)| if i % 2 == 0 { digit * 3 } else { *digit })
+        .sum();
+    if (10 - sum % 10) % 10 != check[0] {
+        return Err(ValidationError::new("barcode check digit is invalid"));
+    }
+    Ok(())
+}
+
+// Helper function to check that no other accessory uses a barcode
+pub(crate) fn check_barcode_available(barcode: &Option<String>, accessory_id: Option<u64>) -> Result<(), Error> {
+    let Some(key) = barcode.as_deref().and_then(_gtin_key) else {
+        return Ok(());
+    };
+    match BARCODE_INDEX.with(|index| index.borrow().get(&key)) {
+        Some(owner) if Some(owner) != accessory_id => Err(Error::ValidationFailed {
+            msg: format!(
+                "barcode {} is already used by the accessory with id={}",
+                barcode.as_deref().unwrap_or_default(),
+                owner
+            ),
+        }),
+        _ => Ok(()),
+    }
+}
+
+// Point the index at an accessory's new barcode, dropping the old one
+pub(crate) fn update_barcode_index(old: &Option<String>, new: &Option<String>, accessory_id: u64) {
+    BARCODE_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(key) = old.as_deref().and_then(_gtin_key) {
+            if index.get(&key) == Some(accessory_id) {
+                index.remove(&key);
+            }
+        }
+        if let Some(key) = new.as_deref().and_then(_gtin_key) {
+            index.insert(key, accessory_id);
+        }
+    });
+}
+
+// Normalise a GTIN to the 14-digit form used as index key
+pub(crate) fn _gtin_key(code: &str) -> Option<GtinKey> {
+    if code.is_empty() || code.len() > 14 || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut key = [b'0'; 14];
+    key[14 - code.len()..].copy_from_slice(code.as_bytes());
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_gtins_pass() {
+        for code in ["96385074", "036000291452", "4006381333931", "10614141000415"] {
+            assert!(validate_gtin(code).is_ok(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn invalid_gtins_are_rejected() {
+        for code in [
+            "4006381333932",
+            "400638133393",
+            "40063813339310000",
+            "40063813339a1",
+            "",
+        ] {
+            assert!(validate_gtin(code).is_err(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn keys_are_padded_to_fourteen_digits() {
+        assert_eq!(_gtin_key("96385074"), Some(*b"00000096385074"));
+        assert_eq!(_gtin_key("036000291452"), _gtin_key("0036000291452"));
+        assert_eq!(_gtin_key("123456789012345"), None);
+        assert_eq!(_gtin_key("12a4"), None);
+    }
+}
@@ -0,0 +1,384 @@
+// Batch (lot number) and expiry tracking for perishable accessories
+use crate::inventory::{record_stock_movement, StockMovement, StockReason};
+use crate::{
+    _check_if_seller_or_staff, _check_text_length, _get_accessory, do_insert_accessory, next_id, rate_limit, roles,
+    Accessory, Error, Memory, ACCESSORY_STORAGE, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+use validator::{Validate, ValidationError};
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+// Maximum length of a lot number in bytes, so a batch fits its storage bound
+const MAX_LOT_NUMBER_LENGTH: usize = 64;
+
+// Key of the expiry index: (expires_at, (accessory_id, batch_id))
+type ExpiryKey = (u64, (u64, u64));
+
+// Define the structure representing a batch of an accessory
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Batch {
+    id: u64,
+    accessory_id: u64,
+    lot_number: String,
+    quantity: u64,
+    remaining: u64,
+    manufactured_at: Option<u64>,
+    expires_at: Option<u64>,
+    expired: bool,
+    created_at: u64,
+}
+
+// Implement the Storable trait for Batch
+impl Storable for Batch {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for Batch
+impl BoundedStorable for Batch {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define a payload structure for receiving a batch
+#[derive(candid::CandidType, Serialize, Deserialize, Validate)]
+pub(crate) struct BatchPayload {
+    #[validate(length(min = 1), custom = "_validate_lot_number")]
+    lot_number: String,
+    #[validate(range(min = 1))]
+    quantity: u64,
+    manufactured_at: Option<u64>,
+    expires_at: Option<u64>,
+    unit_cost: Option<u64>,
+}
+
+fn _validate_lot_number(lot_number: &str) -> Result<(), ValidationError> {
+    _check_text_length(lot_number, MAX_LOT_NUMBER_LENGTH, "lot number is too long")
+}
+
+thread_local! {
+    // Batches are keyed by (accessory_id, batch_id) so they can be listed per accessory
+    static BATCH_STORAGE: RefCell<StableBTreeMap<(u64, u64), Batch, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14))))
+    );
+
+    // Batches that are not expired yet, in order of expiry
+    static EXPIRY_INDEX: RefCell<StableBTreeMap<ExpiryKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(50))))
+    );
+}
+
+// Update function to receive a new batch of an accessory into stock
+#[ic_cdk::update]
+fn add_batch(accessory_id: u64, payload: BatchPayload) -> Result<Batch, Error> {
+    rate_limit::_check_rate_limit("add_batch")?;
+    let accessory = _get_accessory(&accessory_id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", accessory_id),
+    })?;
+    _check_if_seller_or_staff(&accessory)?;
+    payload
+        .validate()
+        .map_err(|err| Error::ValidationFailed { msg: err.to_string() })?;
+    if let (Some(manufactured_at), Some(expires_at)) = (payload.manufactured_at, payload.expires_at) {
+        if expires_at <= manufactured_at {
+            return Err(Error::ValidationFailed {
+                msg: "expiry date must be after the manufacture date".to_string(),
+            });
+        }
+    }
+    if _get_batches(accessory_id)
+        .iter()
+        .any(|batch| batch.lot_number == payload.lot_number)
+    {
+        return Err(Error::ValidationFailed {
+            msg: format!(
+                "lot number {} already exists for accessory with id={}",
+                payload.lot_number, accessory_id
+            ),
+        });
+    }
+    let quantity = i64::try_from(payload.quantity).map_err(|_| Error::ValidationFailed {
+        msg: "batch quantity is too large".to_string(),
+    })?;
+    let (_, movement) = record_stock_movement(
+        accessory_id,
+        quantity,
+        StockReason::Received,
+        format!("lot {}", payload.lot_number),
+        payload.unit_cost,
+    )?;
+    let now = movement.created_at;
+    let batch = Batch {
+        id: next_id(),
+        accessory_id,
+        lot_number: payload.lot_number,
+        quantity: payload.quantity,
+        remaining: payload.quantity,
+        manufactured_at: payload.manufactured_at,
+        expires_at: payload.expires_at,
+        expired: payload.expires_at.is_some_and(|expires_at| expires_at <= now),
+        created_at: now,
+    };
+    BATCH_STORAGE.with(|service| service.borrow_mut().insert((accessory_id, batch.id), batch.clone()));
+    _index_expiry(&batch);
+    Ok(batch)
+}
+
+// Query function to get the batches of an accessory that still have stock
+#[ic_cdk::query]
+fn get_batches(accessory_id: u64) -> Vec<Batch> {
+    _get_batches(accessory_id)
+}
+
+// Query function to get the caller's batches expiring within the given number of days.
+// Admins and staff see the batches of every seller.
+#[ic_cdk::query]
+fn get_expiring_batches(days: u64) -> Vec<Batch> {
+    let seller = caller();
+    let see_all = roles::_is_staff(&caller());
+    let cutoff = time().saturating_add(days.saturating_mul(NANOS_PER_DAY));
+    let keys: Vec<(u64, u64)> = EXPIRY_INDEX.with(|index| {
+        index
+            .borrow()
+            .range(..=(cutoff, (u64::MAX, u64::MAX)))
+            .map(|((_, key), _)| key)
+            .collect()
+    });
+    let mut batches: Vec<Batch> = BATCH_STORAGE.with(|service| {
+        let service = service.borrow();
+        keys.iter().filter_map(|key| service.get(key)).collect()
+    });
+    batches.retain(|batch| {
+        see_all || _get_accessory(&batch.accessory_id).is_some_and(|accessory| accessory.seller == seller)
+    });
+    batches
+}
+
+// Check that a stock decrease can be served by the stock that is not expired.
+// Run before the movement is applied.
+pub(crate) fn check_stock_movement(accessory: &Accessory, delta: i64, reason: StockReason) -> Result<(), Error> {
+    if delta >= 0 || reason != StockReason::Sold {
+        return Ok(());
+    }
+    let expired: u64 = _get_batches(accessory.id)
+        .iter()
+        .filter(|batch| batch.expired)
+        .map(|batch| batch.remaining)
+        .sum();
+    let sellable = accessory.inventory_count.saturating_sub(expired);
+    if delta.unsigned_abs() > sellable {
+        return Err(Error::ValidationFailed {
+            msg: format!(
+                "cannot sell {} of accessory with id={}: only {} in stock are not expired",
+                delta.unsigned_abs(),
+                accessory.id,
+                sellable
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Take outgoing stock out of the batches of an accessory, first expiry first out.
+// Sales only draw from batches that are not expired; write-offs use up expired
+// batches first. Stock beyond what the batches hold is untracked and needs no update.
+pub(crate) fn apply_stock_movement(movement: &StockMovement) {
+    if movement.delta >= 0 {
+        return;
+    }
+    let batches = _get_batches(movement.accessory_id);
+    let drawn = _take_fefo(batches, movement.reason, movement.delta.unsigned_abs());
+    BATCH_STORAGE.with(|service| {
+        let mut storage = service.borrow_mut();
+        for batch in drawn {
+            storage.insert((batch.accessory_id, batch.id), batch);
+        }
+    });
+}
+
+// Draw a quantity from batches in the order described above and return the batches drawn from
+fn _take_fefo(mut batches: Vec<Batch>, reason: StockReason, quantity: u64) -> Vec<Batch> {
+    if reason == StockReason::Sold {
+        batches.retain(|batch| !batch.expired);
+    }
+    batches.sort_by_key(|batch| (!batch.expired, batch.expires_at.unwrap_or(u64::MAX), batch.id));
+    let mut left = quantity;
+    let mut drawn = Vec::new();
+    for mut batch in batches {
+        if left == 0 {
+            break;
+        }
+        let taken = left.min(batch.remaining);
+        left -= taken;
+        batch.remaining -= taken;
+        drawn.push(batch);
+    }
+    drawn
+}
+
+// Mark batches past their expiry date as expired and take accessories whose whole
+// stock has expired off sale. Run periodically by the scheduler.
+pub(crate) fn expire_batches(now: u64) {
+    let due: Vec<ExpiryKey> = EXPIRY_INDEX.with(|index| {
+        index
+            .borrow()
+            .range(..=(now, (u64::MAX, u64::MAX)))
+            .map(|(key, _)| key)
+            .collect()
+    });
+    EXPIRY_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for key in &due {
+            index.remove(key);
+        }
+    });
+    let newly_expired: Vec<Batch> = due
+        .into_iter()
+        .filter_map(|(_, key)| BATCH_STORAGE.with(|service| service.borrow().get(&key)))
+        .filter(|batch| !batch.expired && batch.remaining > 0)
+        .collect();
+    let mut accessory_ids: Vec<u64> = newly_expired.iter().map(|batch| batch.accessory_id).collect();
+    accessory_ids.sort_unstable();
+    accessory_ids.dedup();
+    BATCH_STORAGE.with(|service| {
+        let mut storage = service.borrow_mut();
+        for mut batch in newly_expired {
+            batch.expired = true;
+            storage.insert((batch.accessory_id, batch.id), batch);
+        }
+    });
+    for accessory_id in accessory_ids {
+        let Some(mut accessory) = ACCESSORY_STORAGE.with(|service| service.borrow().get(&accessory_id)) else {
+            continue;
+        };
+        let expired: u64 = _get_batches(accessory_id)
+            .iter()
+            .filter(|batch| batch.expired)
+            .map(|batch| batch.remaining)
+            .sum();
+        if accessory.is_available && accessory.inventory_count <= expired {
+            accessory.is_available = false;
+            accessory.updated_at = Some(now);
+            do_insert_accessory(&mut accessory);
+        }
+    }
+}
+
+// Remove the batches of a purged accessory
+pub(crate) fn purge_accessory(accessory_id: u64) {
+    let batches: Vec<Batch> = BATCH_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((accessory_id, 0)..=(accessory_id, u64::MAX))
+            .map(|(_, batch)| batch)
+            .collect()
+    });
+    for batch in batches {
+        if let Some(expires_at) = batch.expires_at {
+            EXPIRY_INDEX.with(|index| index.borrow_mut().remove(&(expires_at, (accessory_id, batch.id))));
+        }
+        BATCH_STORAGE.with(|service| service.borrow_mut().remove(&(accessory_id, batch.id)));
+    }
+}
+
+fn _index_expiry(batch: &Batch) {
+    if let Some(expires_at) = batch.expires_at.filter(|_| !batch.expired) {
+        EXPIRY_INDEX.with(|index| {
+            index
+                .borrow_mut()
+                .insert((expires_at, (batch.accessory_id, batch.id)), ())
+        });
+    }
+}
+
+// Batches of an accessory that still have stock
+fn _get_batches(accessory_id: u64) -> Vec<Batch> {
+    BATCH_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((accessory_id, 0)..=(accessory_id, u64::MAX))
+            .map(|(_, batch)| batch)
+            .filter(|batch| batch.remaining > 0)
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(id: u64, remaining: u64, expires_at: Option<u64>, expired: bool) -> Batch {
+        Batch {
+            id,
+            accessory_id: 1,
+            lot_number: format!("LOT-{}", id),
+            quantity: remaining,
+            remaining,
+            manufactured_at: None,
+            expires_at,
+            expired,
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn largest_batch_fits_its_storage_bound() {
+        let batch = Batch {
+            id: u64::MAX,
+            accessory_id: u64::MAX,
+            lot_number: "l".repeat(MAX_LOT_NUMBER_LENGTH),
+            quantity: u64::MAX,
+            remaining: u64::MAX,
+            manufactured_at: Some(u64::MAX),
+            expires_at: Some(u64::MAX),
+            expired: true,
+            created_at: u64::MAX,
+        };
+        let size = batch.to_bytes().len();
+        assert!(size <= Batch::MAX_SIZE as usize, "{} bytes", size);
+    }
+
+    fn drawn(batches: &[Batch]) -> Vec<(u64, u64)> {
+        batches.iter().map(|batch| (batch.id, batch.remaining)).collect()
+    }
+
+    #[test]
+    fn earliest_expiry_is_drawn_first() {
+        let batches = vec![
+            batch(1, 5, None, false),
+            batch(2, 5, Some(20), false),
+            batch(3, 5, Some(10), false),
+        ];
+        let taken = _take_fefo(batches, StockReason::Damaged, 7);
+        assert_eq!(drawn(&taken), vec![(3, 0), (2, 3)]);
+    }
+
+    #[test]
+    fn write_offs_use_up_expired_batches_first() {
+        let batches = vec![batch(1, 5, Some(10), false), batch(2, 5, Some(20), true)];
+        let taken = _take_fefo(batches, StockReason::Lost, 6);
+        assert_eq!(drawn(&taken), vec![(2, 0), (1, 4)]);
+    }
+
+    #[test]
+    fn sales_skip_expired_batches() {
+        let batches = vec![batch(1, 5, Some(5), true), batch(2, 5, Some(20), false)];
+        let taken = _take_fefo(batches, StockReason::Sold, 3);
+        assert_eq!(drawn(&taken), vec![(2, 2)]);
+    }
+
+    #[test]
+    fn stock_beyond_the_batches_is_untracked() {
+        let taken = _take_fefo(vec![batch(1, 2, None, false)], StockReason::Sold, 10);
+        assert_eq!(drawn(&taken), vec![(1, 0)]);
+    }
+}
@@ -0,0 +1,273 @@
+// Bulk changes to accessories, applied item by item or all-or-nothing
+use crate::barcodes::{self, GtinKey};
+use crate::{
+    _apply_delete, _apply_update, _insert_new_accessory, _prepare_delete, _prepare_new_accessory, _prepare_update,
+    rate_limit, Accessory, AccessoryPayload, Error,
+};
+use std::collections::BTreeSet;
+
+// Most items a single bulk call can contain
+const MAX_BULK_ITEMS: usize = 50;
+
+// Define how a bulk call deals with items that fail their checks
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum BulkMode {
+    // Apply every item that passes its checks and report the others
+    BestEffort,
+    // Check every item first and apply nothing if any of them fails
+    Atomic,
+}
+
+// Define the change made to a single accessory. For additions `before` is None;
+// in dry runs new accessories have id 0 as ids are assigned when they are stored.
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct BulkChange {
+    before: Option<Accessory>,
+    after: Accessory,
+}
+
+// Define the outcome of a bulk call
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct BulkReport {
+    dry_run: bool,
+    // Items that were stored; 0 for dry runs and for atomic calls with failed items
+    applied: u64,
+    failed: u64,
+    // One result per item, in the order of the request
+    results: Vec<Result<BulkChange, Error>>,
+}
+
+// Update function to add accessories in bulk
+#[ic_cdk::update]
+fn bulk_add(items: Vec<AccessoryPayload>, mode: BulkMode, dry_run: bool) -> Result<BulkReport, Error> {
+    rate_limit::_check_rate_limit_for("bulk_add", items.len())?;
+    let mut barcodes = BTreeSet::new();
+    _run(
+        items,
+        mode,
+        dry_run,
+        |payload| {
+            _claim_barcode(&mut barcodes, &payload.barcode)?;
+            _prepare_new_accessory(payload)
+        },
+        |accessory| BulkChange {
+            before: None,
+            after: accessory,
+        },
+        |accessory| BulkChange {
+            before: None,
+            after: _insert_new_accessory(accessory),
+        },
+    )
+}
+
+// Update function to update accessories in bulk
+#[ic_cdk::update]
+fn bulk_update(items: Vec<(u64, AccessoryPayload)>, mode: BulkMode, dry_run: bool) -> Result<BulkReport, Error> {
+    rate_limit::_check_rate_limit_for("bulk_update", items.len())?;
+    _update_all(items, mode, dry_run)
+}
+
+// Update function to update accessories one by one, kept for older clients. It works like
+// a best-effort bulk_update and returns the updated accessories.
+#[ic_cdk::update]
+fn bulk_update_accessories(updates: Vec<(u64, AccessoryPayload)>) -> Result<Vec<Result<Accessory, Error>>, Error> {
+    rate_limit::_check_rate_limit_for("bulk_update_accessories", updates.len())?;
+    let report = _update_all(updates, BulkMode::BestEffort, false)?;
+    Ok(report
+        .results
+        .into_iter()
+        .map(|result| result.map(|change| change.after))
+        .collect())
+}
+
+// Update function to move accessories to the trash in bulk
+#[ic_cdk::update]
+fn bulk_delete(ids: Vec<u64>, mode: BulkMode, dry_run: bool) -> Result<BulkReport, Error> {
+    rate_limit::_check_rate_limit_for("bulk_delete", ids.len())?;
+    let mut seen = BTreeSet::new();
+    _run(
+        ids,
+        mode,
+        dry_run,
+        |id| {
+            _claim_id(&mut seen, id)?;
+            _prepare_delete(id, None)
+        },
+        _deletion,
+        |mut deleted| {
+            let mut change = _deletion(deleted.clone());
+            _apply_delete(&mut deleted);
+            change.after = deleted;
+            change
+        },
+    )
+}
+
+fn _update_all(items: Vec<(u64, AccessoryPayload)>, mode: BulkMode, dry_run: bool) -> Result<BulkReport, Error> {
+    let mut ids = BTreeSet::new();
+    let mut barcodes = BTreeSet::new();
+    _run(
+        items,
+        mode,
+        dry_run,
+        |(id, payload)| {
+            _claim_id(&mut ids, id)?;
+            _claim_barcode(&mut barcodes, &payload.barcode)?;
+            _prepare_update(id, payload, None)
+        },
+        |(accessory, updated)| BulkChange {
+            before: Some(accessory),
+            after: updated,
+        },
+        |(accessory, mut updated)| {
+            _apply_update(&accessory, &mut updated);
+            BulkChange {
+                before: Some(accessory),
+                after: updated,
+            }
+        },
+    )
+}
+
+// Check and apply a batch. Best-effort calls apply each item right after its checks, so
+// later items see the effect of earlier ones. Atomic calls and dry runs check every item
+// against the current state before anything is stored.
+fn _run<T, P>(
+    items: Vec<T>,
+    mode: BulkMode,
+    dry_run: bool,
+    mut prepare: impl FnMut(T) -> Result<P, Error>,
+    preview: impl Fn(P) -> BulkChange,
+    apply: impl Fn(P) -> BulkChange,
+) -> Result<BulkReport, Error> {
+    if items.is_empty() || items.len() > MAX_BULK_ITEMS {
+        return Err(Error::ValidationFailed {
+            msg: format!(
+                "a batch must have between 1 and {} items, got {}",
+                MAX_BULK_ITEMS,
+                items.len()
+            ),
+        });
+    }
+    if mode == BulkMode::BestEffort && !dry_run {
+        let results = items.into_iter().map(|item| prepare(item).map(&apply)).collect();
+        return Ok(_report(false, true, results));
+    }
+    let prepared: Vec<Result<P, Error>> = items.into_iter().map(prepare).collect();
+    let store = !dry_run && prepared.iter().all(Result::is_ok);
+    let results = prepared
+        .into_iter()
+        .map(|item| item.map(|item| if store { apply(item) } else { preview(item) }))
+        .collect();
+    Ok(_report(dry_run, store, results))
+}
+
+fn _report(dry_run: bool, stored: bool, results: Vec<Result<BulkChange, Error>>) -> BulkReport {
+    let succeeded = results.iter().filter(|result| result.is_ok()).count() as u64;
+    BulkReport {
+        dry_run,
+        applied: if stored { succeeded } else { 0 },
+        failed: results.len() as u64 - succeeded,
+        results,
+    }
+}
+
+fn _deletion(deleted: Accessory) -> BulkChange {
+    let mut before = deleted.clone();
+    before.deleted_at = None;
+    before.deleted_by = None;
+    BulkChange {
+        before: Some(before),
+        after: deleted,
+    }
+}
+
+fn _claim_id(seen: &mut BTreeSet<u64>, id: u64) -> Result<(), Error> {
+    if seen.insert(id) {
+        Ok(())
+    } else {
+        Err(Error::ValidationFailed {
+            msg: format!("accessory with id={} appears more than once in the batch", id),
+        })
+    }
+}
+
+fn _claim_barcode(seen: &mut BTreeSet<GtinKey>, barcode: &Option<String>) -> Result<(), Error> {
+    match barcode.as_deref().and_then(barcodes::_gtin_key) {
+        Some(key) if !seen.insert(key) => Err(Error::ValidationFailed {
+            msg: format!(
+                "barcode {} appears more than once in the batch",
+                barcode.as_deref().unwrap_or_default()
+            ),
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::largest_accessory;
+    use std::cell::Cell;
+
+    fn change(_: u64) -> BulkChange {
+        BulkChange {
+            before: None,
+            after: largest_accessory(),
+        }
+    }
+
+    // Run a batch where odd items fail, counting the items that were applied
+    fn run(mode: BulkMode, dry_run: bool, items: Vec<u64>) -> (BulkReport, u64) {
+        let applied = Cell::new(0);
+        let prepare = |item: u64| {
+            if item % 2 == 1 {
+                Err(Error::ValidationFailed {
+                    msg: format!("item {} is odd", item),
+                })
+            } else {
+                Ok(item)
+            }
+        };
+        let apply = |item: u64| {
+            applied.set(applied.get() + 1);
+            change(item)
+        };
+        match _run(items, mode, dry_run, prepare, change, apply) {
+            Ok(report) => (report, applied.get()),
+            Err(_) => panic!("batch was rejected"),
+        }
+    }
+
+    #[test]
+    fn atomic_batches_apply_nothing_when_an_item_fails() {
+        let (report, applied) = run(BulkMode::Atomic, false, vec![0, 1, 2]);
+        assert_eq!((report.applied, report.failed, applied), (0, 1, 0));
+        let (report, applied) = run(BulkMode::Atomic, false, vec![0, 2]);
+        assert_eq!((report.applied, report.failed, applied), (2, 0, 2));
+    }
+
+    #[test]
+    fn best_effort_batches_apply_the_passing_items() {
+        let (report, applied) = run(BulkMode::BestEffort, false, vec![0, 1, 2]);
+        assert_eq!((report.applied, report.failed, applied), (2, 1, 2));
+    }
+
+    #[test]
+    fn dry_runs_apply_nothing() {
+        for mode in [BulkMode::Atomic, BulkMode::BestEffort] {
+            let (report, applied) = run(mode, true, vec![0, 2]);
+            assert!(report.dry_run);
+            assert_eq!((report.applied, report.failed, applied), (0, 0, 0));
+        }
+    }
+
+    #[test]
+    fn batches_over_the_item_limit_are_rejected() {
+        let items: Vec<u64> = (0..=MAX_BULK_ITEMS as u64).collect();
+        let result = _run(items, BulkMode::BestEffort, false, Ok, change, change);
+        assert!(matches!(result, Err(Error::ValidationFailed { .. })));
+        assert!(_run(Vec::<u64>::new(), BulkMode::BestEffort, false, Ok, change, change).is_err());
+    }
+}
@@ -0,0 +1,642 @@
+// Cost layers for stock on hand, cost of goods sold and inventory valuation
+use crate::inventory::{StockMovement, StockReason};
+use crate::suppliers::_get_reorder_supplier;
+use crate::{
+    _check_if_seller_or_staff, _get_accessory, currency, next_id, pricing, roles, sellers, Accessory, Error, Memory,
+    ACCESSORY_STORAGE, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::caller;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+// Define how outgoing stock is costed
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub(crate) enum CostingMethod {
+    #[default]
+    Fifo,
+    WeightedAverage,
+}
+
+// Implement the Storable trait for CostingMethod
+impl Storable for CostingMethod {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Define a lot of stock received at a single unit cost
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CostLot {
+    id: u64,
+    accessory_id: u64,
+    quantity: u64,
+    remaining: u64,
+    unit_cost: u64,
+    received_at: u64,
+}
+
+// Implement the Storable trait for CostLot
+impl Storable for CostLot {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for CostLot
+impl BoundedStorable for CostLot {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define the part of a lot a sale took, so the sale can be reversed
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ConsumedLot {
+    quantity: u64,
+    unit_cost: u64,
+    received_at: u64,
+}
+
+// Implement the Storable trait for ConsumedLot
+impl Storable for ConsumedLot {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for ConsumedLot
+impl BoundedStorable for ConsumedLot {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define the cost and revenue recorded for a sale
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SaleCost {
+    id: u64,
+    accessory_id: u64,
+    seller: Principal,
+    quantity: u64,
+    cost: u64,
+    revenue: u64,
+    sold_at: u64,
+    // Currency of cost and revenue; resolved from the accessory for older sales
+    currency: Option<String>,
+}
+
+// Implement the Storable trait for SaleCost
+impl Storable for SaleCost {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for SaleCost
+impl BoundedStorable for SaleCost {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define the value of stock on hand for one seller, category and currency
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct InventoryValuation {
+    seller: Principal,
+    category: String,
+    currency: Option<String>,
+    quantity: u64,
+    value: u64,
+}
+
+// Define the cost of goods sold for one accessory
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AccessoryCostOfGoodsSold {
+    accessory_id: u64,
+    currency: Option<String>,
+    quantity: u64,
+    cost: u64,
+    revenue: u64,
+}
+
+// Define the cost of goods sold in one currency
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CostOfGoodsSoldTotal {
+    currency: Option<String>,
+    quantity: u64,
+    cost: u64,
+    revenue: u64,
+}
+
+// Define the cost of goods sold over a date range
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CostOfGoodsSoldReport {
+    from: u64,
+    to: u64,
+    // One total per currency, amounts in different currencies are never added up
+    totals: Vec<CostOfGoodsSoldTotal>,
+    lines: Vec<AccessoryCostOfGoodsSold>,
+}
+
+// Define the gross margin of an accessory over all its sales
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct GrossMargin {
+    accessory_id: u64,
+    quantity_sold: u64,
+    revenue: u64,
+    cost_of_goods_sold: u64,
+    gross_margin: i64,
+    // Gross margin as basis points of revenue, None when nothing was sold
+    margin_bps: Option<i64>,
+}
+
+thread_local! {
+    // Lots are keyed by (accessory_id, lot_id) so they are consumed in receipt order
+    static COST_LOT_STORAGE: RefCell<StableBTreeMap<(u64, u64), CostLot, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11))))
+    );
+
+    static SALE_COST_STORAGE: RefCell<StableBTreeMap<u64, SaleCost, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12))))
+    );
+
+    static COSTING_METHOD: RefCell<Cell<CostingMethod, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13))), CostingMethod::default())
+            .expect("Cannot create the costing method cell")
+    );
+
+    // Lots taken by each sale, keyed by (sale_id, lot_id)
+    static CONSUMED_LOT_STORAGE: RefCell<StableBTreeMap<(u64, u64), ConsumedLot, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(53))))
+    );
+
+    // Sales keyed by (accessory_id, sale_id)
+    static SALE_ACCESSORY_INDEX: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(54))))
+    );
+
+    // Sales keyed by (sold_at, sale_id)
+    static SALE_DATE_INDEX: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(55))))
+    );
+}
+
+// Update function to choose how outgoing stock is costed (admin only)
+#[ic_cdk::update]
+fn set_costing_method(method: CostingMethod) -> Result<(), Error> {
+    roles::_check_if_admin()?;
+    COSTING_METHOD
+        .with(|cell| cell.borrow_mut().set(method))
+        .expect("cannot update the costing method");
+    Ok(())
+}
+
+// Query function to get the costing method in use
+#[ic_cdk::query]
+fn get_costing_method() -> CostingMethod {
+    _costing_method()
+}
+
+// Query function to value the stock on hand per seller and category.
+// Admins and staff see every seller, other callers only their own listings.
+#[ic_cdk::query]
+fn get_inventory_valuation() -> Vec<InventoryValuation> {
+    let accessories: Vec<Accessory> = if roles::_is_staff(&caller()) {
+        ACCESSORY_STORAGE.with(|service| {
+            service
+                .borrow()
+                .iter()
+                .map(|(_, accessory)| accessory)
+                .filter(|accessory| accessory.deleted_at.is_none())
+                .collect()
+        })
+    } else {
+        sellers::listing_ids(&caller())
+            .iter()
+            .filter_map(_get_accessory)
+            .collect()
+    };
+    let mut valuations: BTreeMap<(Principal, String, Option<String>), InventoryValuation> = BTreeMap::new();
+    for accessory in accessories {
+        let value = _stock_value(&accessory);
+        let currency = currency::accessory_currency(&accessory).ok();
+        let entry = valuations
+            .entry((accessory.seller, accessory.category.clone(), currency.clone()))
+            .or_insert_with(|| InventoryValuation {
+                seller: accessory.seller,
+                category: accessory.category.clone(),
+                currency,
+                quantity: 0,
+                value: 0,
+            });
+        entry.quantity = entry.quantity.saturating_add(accessory.inventory_count);
+        entry.value = entry.value.saturating_add(value);
+    }
+    valuations.into_values().collect()
+}
+
+// Query function to get the cost of goods sold between two timestamps (inclusive).
+// Admins and staff see every seller, other callers only their own sales.
+#[ic_cdk::query]
+fn get_cost_of_goods_sold(from: u64, to: u64) -> CostOfGoodsSoldReport {
+    let seller = caller();
+    let see_all = roles::_is_staff(&caller());
+    let mut lines: BTreeMap<(u64, Option<String>), AccessoryCostOfGoodsSold> = BTreeMap::new();
+    let sale_ids: Vec<u64> = if from > to {
+        Vec::new()
+    } else {
+        SALE_DATE_INDEX.with(|index| {
+            index
+                .borrow()
+                .range((from, 0)..=(to, u64::MAX))
+                .map(|((_, sale_id), _)| sale_id)
+                .collect()
+        })
+    };
+    SALE_COST_STORAGE.with(|service| {
+        let service = service.borrow();
+        for sale in sale_ids.iter().filter_map(|id| service.get(id)) {
+            if !(see_all || sale.seller == seller) {
+                continue;
+            }
+            let currency = _sale_currency(&sale);
+            let line = lines
+                .entry((sale.accessory_id, currency.clone()))
+                .or_insert(AccessoryCostOfGoodsSold {
+                    accessory_id: sale.accessory_id,
+                    currency,
+                    quantity: 0,
+                    cost: 0,
+                    revenue: 0,
+                });
+            line.quantity = line.quantity.saturating_add(sale.quantity);
+            line.cost = line.cost.saturating_add(sale.cost);
+            line.revenue = line.revenue.saturating_add(sale.revenue);
+        }
+    });
+    let lines: Vec<AccessoryCostOfGoodsSold> = lines.into_values().collect();
+    CostOfGoodsSoldReport {
+        from,
+        to,
+        totals: _totals_per_currency(&lines),
+        lines,
+    }
+}
+
+// Query function to get the gross margin of an accessory
+#[ic_cdk::query]
+fn get_gross_margin(accessory_id: u64) -> Result<GrossMargin, Error> {
+    let accessory = _get_accessory(&accessory_id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", accessory_id),
+    })?;
+    _check_if_seller_or_staff(&accessory)?;
+    let (quantity_sold, revenue, cost) = SALE_COST_STORAGE.with(|service| {
+        let service = service.borrow();
+        _sale_ids(accessory_id).iter().filter_map(|id| service.get(id)).fold(
+            (0u64, 0u64, 0u64),
+            |(quantity, revenue, cost), sale| {
+                (
+                    quantity.saturating_add(sale.quantity),
+                    revenue.saturating_add(sale.revenue),
+                    cost.saturating_add(sale.cost),
+                )
+            },
+        )
+    });
+    let gross_margin = i128::from(revenue) - i128::from(cost);
+    Ok(GrossMargin {
+        accessory_id,
+        quantity_sold,
+        revenue,
+        cost_of_goods_sold: cost,
+        gross_margin: gross_margin.clamp(i64::MIN.into(), i64::MAX.into()) as i64,
+        margin_bps: (revenue > 0)
+            .then(|| (gross_margin * 10_000 / i128::from(revenue)).clamp(i64::MIN.into(), i64::MAX.into()) as i64),
+    })
+}
+
+// Update the cost layers of an accessory after a stock movement.
+// Incoming stock opens a new lot; outgoing stock consumes lots with the
+// configured method and, for sales, records the cost of goods sold against
+// `revenue`, or the current price when the sale didn't come with one.
+pub(crate) fn apply_stock_movement(
+    accessory: &Accessory,
+    movement: &StockMovement,
+    unit_cost: Option<u64>,
+    revenue: Option<u64>,
+) {
+    let quantity = movement.delta.unsigned_abs();
+    if movement.delta > 0 {
+        let lot = CostLot {
+            id: next_id(),
+            accessory_id: accessory.id,
+            quantity,
+            remaining: quantity,
+            unit_cost: unit_cost.unwrap_or_else(|| _fallback_unit_cost(accessory.id)),
+            received_at: movement.created_at,
+        };
+        COST_LOT_STORAGE.with(|service| service.borrow_mut().insert((accessory.id, lot.id), lot));
+        return;
+    }
+    let inventory_before = movement.balance_after.saturating_add(quantity);
+    let sale_id = (movement.reason == StockReason::Sold).then_some(movement.id);
+    let cost = _consume_lots(accessory.id, quantity, inventory_before, sale_id);
+    if movement.reason == StockReason::Sold {
+        let sale = SaleCost {
+            id: movement.id,
+            accessory_id: accessory.id,
+            seller: accessory.seller,
+            quantity,
+            cost,
+            revenue: revenue.unwrap_or_else(|| pricing::effective_price(accessory).saturating_mul(quantity)),
+            sold_at: movement.created_at,
+            currency: currency::accessory_currency(accessory).ok(),
+        };
+        SALE_ACCESSORY_INDEX.with(|index| index.borrow_mut().insert((sale.accessory_id, sale.id), ()));
+        SALE_DATE_INDEX.with(|index| index.borrow_mut().insert((sale.sold_at, sale.id), ()));
+        SALE_COST_STORAGE.with(|service| service.borrow_mut().insert(sale.id, sale));
+    }
+}
+
+// Undo a sale, e.g. of a cancelled order: drop its cost of goods sold and put the
+// units it took back into the lots they came from. Units that were not covered by
+// a lot go back to stock uncovered.
+pub(crate) fn reverse_sale(accessory_id: u64, sale_id: u64) {
+    let Some(sale) = SALE_COST_STORAGE.with(|service| service.borrow_mut().remove(&sale_id)) else {
+        return;
+    };
+    SALE_ACCESSORY_INDEX.with(|index| index.borrow_mut().remove(&(sale.accessory_id, sale.id)));
+    SALE_DATE_INDEX.with(|index| index.borrow_mut().remove(&(sale.sold_at, sale.id)));
+    for (lot_id, consumed) in _remove_consumed_lots(sale_id) {
+        COST_LOT_STORAGE.with(|service| {
+            let mut storage = service.borrow_mut();
+            match storage.get(&(accessory_id, lot_id)) {
+                Some(mut lot) if lot.unit_cost == consumed.unit_cost => {
+                    lot.remaining = lot.remaining.saturating_add(consumed.quantity);
+                    storage.insert((accessory_id, lot_id), lot);
+                }
+                existing => {
+                    let lot = CostLot {
+                        id: if existing.is_some() { next_id() } else { lot_id },
+                        accessory_id,
+                        quantity: consumed.quantity,
+                        remaining: consumed.quantity,
+                        unit_cost: consumed.unit_cost,
+                        received_at: consumed.received_at,
+                    };
+                    storage.insert((accessory_id, lot.id), lot);
+                }
+            }
+        });
+    }
+}
+
+fn _remove_consumed_lots(sale_id: u64) -> Vec<(u64, ConsumedLot)> {
+    CONSUMED_LOT_STORAGE.with(|service| {
+        let mut storage = service.borrow_mut();
+        let keys: Vec<(u64, u64)> = storage
+            .range((sale_id, 0)..=(sale_id, u64::MAX))
+            .map(|(key, _)| key)
+            .collect();
+        keys.into_iter()
+            .filter_map(|key| storage.remove(&key).map(|consumed| (key.1, consumed)))
+            .collect()
+    })
+}
+
+fn _sale_ids(accessory_id: u64) -> Vec<u64> {
+    SALE_ACCESSORY_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((accessory_id, 0)..=(accessory_id, u64::MAX))
+            .map(|((_, sale_id), _)| sale_id)
+            .collect()
+    })
+}
+
+fn _sale_currency(sale: &SaleCost) -> Option<String> {
+    sale.currency.clone().or_else(|| {
+        ACCESSORY_STORAGE
+            .with(|service| service.borrow().get(&sale.accessory_id))
+            .and_then(|accessory| currency::accessory_currency(&accessory).ok())
+    })
+}
+
+fn _totals_per_currency(lines: &[AccessoryCostOfGoodsSold]) -> Vec<CostOfGoodsSoldTotal> {
+    let mut totals: BTreeMap<Option<String>, CostOfGoodsSoldTotal> = BTreeMap::new();
+    for line in lines {
+        let total = totals
+            .entry(line.currency.clone())
+            .or_insert_with(|| CostOfGoodsSoldTotal {
+                currency: line.currency.clone(),
+                quantity: 0,
+                cost: 0,
+                revenue: 0,
+            });
+        total.quantity = total.quantity.saturating_add(line.quantity);
+        total.cost = total.cost.saturating_add(line.cost);
+        total.revenue = total.revenue.saturating_add(line.revenue);
+    }
+    totals.into_values().collect()
+}
+
+fn _costing_method() -> CostingMethod {
+    COSTING_METHOD.with(|cell| *cell.borrow().get())
+}
+
+// Remove the cost lots of a purged accessory. Sale costs are kept for the reports,
+// but can no longer be reversed.
+pub(crate) fn purge_accessory(accessory_id: u64) {
+    for sale_id in _sale_ids(accessory_id) {
+        _remove_consumed_lots(sale_id);
+    }
+    let lots: Vec<(u64, u64)> = COST_LOT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((accessory_id, 0)..=(accessory_id, u64::MAX))
+            .map(|(key, _)| key)
+            .collect()
+    });
+    COST_LOT_STORAGE.with(|service| {
+        let mut service = service.borrow_mut();
+        for key in &lots {
+            service.remove(key);
+        }
+    });
+}
+
+fn _get_lots(accessory_id: u64) -> Vec<CostLot> {
+    COST_LOT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((accessory_id, 0)..=(accessory_id, u64::MAX))
+            .map(|(_, lot)| lot)
+            .collect()
+    })
+}
+
+// Take `quantity` units out of the cost lots of an accessory and return their total cost.
+// Stock that is not covered by any lot (e.g. from before cost tracking) is the
+// oldest, so it is used up first and costed at the fallback unit cost.
+// For a sale, the lots taken are recorded so the sale can be reversed.
+fn _consume_lots(accessory_id: u64, quantity: u64, inventory_before: u64, sale_id: Option<u64>) -> u64 {
+    let lots = _get_lots(accessory_id);
+    let on_hand: u64 = lots.iter().map(|lot| lot.remaining).sum();
+    let uncovered = quantity.min(inventory_before.saturating_sub(on_hand));
+    let covered = (quantity - uncovered).min(on_hand);
+    let uncovered_cost = uncovered.saturating_mul(_fallback_unit_cost(accessory_id));
+
+    let covered_cost = match _costing_method() {
+        CostingMethod::Fifo => {
+            let mut left = covered;
+            let mut cost = 0u64;
+            COST_LOT_STORAGE.with(|service| {
+                let mut storage = service.borrow_mut();
+                for mut lot in lots {
+                    if left == 0 {
+                        break;
+                    }
+                    let taken = left.min(lot.remaining);
+                    cost = cost.saturating_add(taken.saturating_mul(lot.unit_cost));
+                    if let Some(sale_id) = sale_id.filter(|_| taken > 0) {
+                        _record_consumed_lot(sale_id, &lot, taken, lot.unit_cost);
+                    }
+                    left -= taken;
+                    lot.remaining -= taken;
+                    if lot.remaining == 0 {
+                        storage.remove(&(accessory_id, lot.id));
+                    } else {
+                        storage.insert((accessory_id, lot.id), lot);
+                    }
+                }
+            });
+            cost
+        }
+        CostingMethod::WeightedAverage => {
+            if on_hand == 0 {
+                0
+            } else {
+                // Merge every lot into a single one at the average cost
+                let total_value: u128 = lots
+                    .iter()
+                    .map(|lot| u128::from(lot.remaining) * u128::from(lot.unit_cost))
+                    .sum();
+                let cost = total_value * u128::from(covered) / u128::from(on_hand);
+                let remaining = on_hand - covered;
+                if let (Some(sale_id), Some(newest)) = (sale_id.filter(|_| covered > 0), lots.last()) {
+                    let unit_cost = (cost / u128::from(covered)) as u64;
+                    _record_consumed_lot(sale_id, newest, covered, unit_cost);
+                }
+                COST_LOT_STORAGE.with(|service| {
+                    let mut storage = service.borrow_mut();
+                    for lot in &lots {
+                        storage.remove(&(accessory_id, lot.id));
+                    }
+                    if remaining > 0 {
+                        let merged = CostLot {
+                            id: next_id(),
+                            accessory_id,
+                            quantity: remaining,
+                            remaining,
+                            unit_cost: ((total_value - cost) / u128::from(remaining)) as u64,
+                            received_at: lots.last().map(|lot| lot.received_at).unwrap_or_default(),
+                        };
+                        storage.insert((accessory_id, merged.id), merged);
+                    }
+                });
+                cost as u64
+            }
+        }
+    };
+    covered_cost.saturating_add(uncovered_cost)
+}
+
+fn _record_consumed_lot(sale_id: u64, lot: &CostLot, quantity: u64, unit_cost: u64) {
+    let consumed = ConsumedLot {
+        quantity,
+        unit_cost,
+        received_at: lot.received_at,
+    };
+    CONSUMED_LOT_STORAGE.with(|service| service.borrow_mut().insert((sale_id, lot.id), consumed));
+}
+
+// Unit cost used when none is known: the latest lot, then the reorder supplier's price
+fn _fallback_unit_cost(accessory_id: u64) -> u64 {
+    _get_lots(accessory_id)
+        .last()
+        .map(|lot| lot.unit_cost)
+        .or_else(|| _get_reorder_supplier(accessory_id).map(|link| link.cost_price))
+        .unwrap_or_default()
+}
+
+// Value of the stock on hand of an accessory, including units not covered by a lot
+fn _stock_value(accessory: &Accessory) -> u64 {
+    let lots = _get_lots(accessory.id);
+    let lot_quantity: u64 = lots.iter().map(|lot| lot.remaining).sum();
+    let lot_value = lots.iter().fold(0u64, |value, lot| {
+        value.saturating_add(lot.remaining.saturating_mul(lot.unit_cost))
+    });
+    let uncovered = accessory.inventory_count.saturating_sub(lot_quantity);
+    lot_value.saturating_add(uncovered.saturating_mul(_fallback_unit_cost(accessory.id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(currency: &str, cost: u64, revenue: u64) -> AccessoryCostOfGoodsSold {
+        AccessoryCostOfGoodsSold {
+            accessory_id: 1,
+            currency: Some(currency.to_string()),
+            quantity: 1,
+            cost,
+            revenue,
+        }
+    }
+
+    #[test]
+    fn totals_are_kept_per_currency() {
+        let totals = _totals_per_currency(&[line("EUR", 10, 20), line("USD", 5, 7), line("EUR", 1, 2)]);
+        assert_eq!(totals.len(), 2);
+        assert_eq!(
+            (totals[0].currency.as_deref(), totals[0].cost, totals[0].revenue),
+            (Some("EUR"), 11, 22)
+        );
+        assert_eq!(
+            (totals[1].currency.as_deref(), totals[1].cost, totals[1].revenue),
+            (Some("USD"), 5, 7)
+        );
+    }
+
+    #[test]
+    fn largest_sale_cost_fits_its_storage_bound() {
+        let sale = SaleCost {
+            id: u64::MAX,
+            accessory_id: u64::MAX,
+            seller: Principal::from_slice(&[0xff; 29]),
+            quantity: u64::MAX,
+            cost: u64::MAX,
+            revenue: u64::MAX,
+            sold_at: u64::MAX,
+            currency: Some("C".repeat(8)),
+        };
+        let size = sale.to_bytes().len();
+        assert!(size <= SaleCost::MAX_SIZE as usize, "{} bytes", size);
+    }
+}
@@ -0,0 +1,412 @@
+// Currencies, exchange rates and conversion of prices between them.
+// Prices are stored as integers in the smallest unit of the accessory's currency.
+use crate::{
+    _get_accessory, do_insert_accessory, roles, Accessory, Error, Memory, StringKey, ACCESSORY_STORAGE, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Currency codes are 3 to 8 upper case letters, e.g. USD or CKBTC
+const MAX_CODE_LENGTH: usize = 8;
+const MAX_DECIMALS: u8 = 18;
+// Exchange rates are fixed point numbers with 8 decimals
+const RATE_SCALE: u128 = 100_000_000;
+
+type CurrencyKey = StringKey<{ MAX_CODE_LENGTH as u32 }>;
+
+// Define an amount of money in the smallest unit of its currency
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct Money {
+    pub(crate) amount: u64,
+    pub(crate) currency: String,
+    pub(crate) decimals: u8,
+}
+
+// Define how converted amounts are rounded to the smallest unit of the target currency
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub(crate) enum RoundingMode {
+    Down,
+    Up,
+    HalfUp,
+    #[default]
+    HalfEven,
+}
+
+// Define a currency known to the canister
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Currency {
+    code: String,
+    decimals: u8,
+    created_at: u64,
+}
+
+// Implement the Storable trait for Currency
+impl Storable for Currency {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for Currency
+impl BoundedStorable for Currency {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define the exchange rate from one currency to another
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ExchangeRate {
+    base: String,
+    quote: String,
+    // Units of the quote currency per unit of the base currency, times 10^8
+    rate: u64,
+    updated_by: Principal,
+    updated_at: u64,
+}
+
+// Implement the Storable trait for ExchangeRate
+impl Storable for ExchangeRate {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for ExchangeRate
+impl BoundedStorable for ExchangeRate {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define the currency settings of the canister
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct CurrencyConfig {
+    // Currency of accessories listed without one
+    default_currency: Option<String>,
+}
+
+// Implement the Storable trait for CurrencyConfig
+impl Storable for CurrencyConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static CURRENCY_CONFIG: RefCell<Cell<CurrencyConfig, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29))), CurrencyConfig::default())
+            .expect("Cannot create the currency config cell")
+    );
+
+    static CURRENCY_STORAGE: RefCell<StableBTreeMap<CurrencyKey, Currency, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30))))
+    );
+
+    static EXCHANGE_RATE_STORAGE: RefCell<StableBTreeMap<(CurrencyKey, CurrencyKey), ExchangeRate, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31))))
+    );
+}
+
+// Update function to register a currency (admin only). The decimals of a
+// currency can't change once it is registered, as prices depend on them.
+#[ic_cdk::update]
+fn add_currency(code: String, decimals: u8) -> Result<Currency, Error> {
+    roles::_check_if_admin()?;
+    let code = _normalize_code(&code)?;
+    if decimals > MAX_DECIMALS {
+        return Err(Error::ValidationFailed {
+            msg: format!("a currency can have at most {} decimals", MAX_DECIMALS),
+        });
+    }
+    if _get_currency(&code).is_ok() {
+        return Err(Error::ValidationFailed {
+            msg: format!("a currency with code={} already exists", code),
+        });
+    }
+    let currency = Currency {
+        code,
+        decimals,
+        created_at: time(),
+    };
+    CURRENCY_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert(StringKey(currency.code.clone()), currency.clone())
+    });
+    Ok(currency)
+}
+
+// Query function to get all registered currencies
+#[ic_cdk::query]
+fn get_currencies() -> Vec<Currency> {
+    CURRENCY_STORAGE.with(|service| service.borrow().iter().map(|(_, currency)| currency).collect())
+}
+
+// Update function to set the default currency (admin only). Accessories listed
+// without a currency are moved to it, so their prices keep their meaning when the
+// default changes later. It can't change while accessories still have no currency.
+#[ic_cdk::update]
+fn set_default_currency(code: String) -> Result<CurrencyConfig, Error> {
+    roles::_check_if_admin()?;
+    let code = _get_currency(&_normalize_code(&code)?)?.code;
+    let unpriced = _accessories_without_currency();
+    match _default_currency() {
+        Some(current) if current != code && !unpriced.is_empty() => {
+            return Err(Error::ValidationFailed {
+                msg: format!(
+                    "{} accessories are still priced in the default currency {}",
+                    unpriced.len(),
+                    current
+                ),
+            });
+        }
+        _ => (),
+    }
+    for mut accessory in unpriced {
+        accessory.currency = Some(code.clone());
+        do_insert_accessory(&mut accessory);
+    }
+    let config = CurrencyConfig {
+        default_currency: Some(code),
+    };
+    CURRENCY_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("cannot update the currency config");
+    Ok(config)
+}
+
+// Query function to get the currency settings
+#[ic_cdk::query]
+fn get_currency_config() -> CurrencyConfig {
+    CURRENCY_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+// Update function to set the exchange rate between two currencies (admin only).
+// `rate` is the number of `quote` units per `base` unit, times 10^8.
+#[ic_cdk::update]
+fn set_exchange_rate(base: String, quote: String, rate: u64) -> Result<ExchangeRate, Error> {
+    roles::_check_if_admin()?;
+    let base = _get_currency(&_normalize_code(&base)?)?.code;
+    let quote = _get_currency(&_normalize_code(&quote)?)?.code;
+    if base == quote || rate == 0 {
+        return Err(Error::ValidationFailed {
+            msg: "an exchange rate needs two different currencies and a positive rate".to_string(),
+        });
+    }
+    let exchange_rate = ExchangeRate {
+        base,
+        quote,
+        rate,
+        updated_by: caller(),
+        updated_at: time(),
+    };
+    EXCHANGE_RATE_STORAGE.with(|service| {
+        service.borrow_mut().insert(
+            (
+                StringKey(exchange_rate.base.clone()),
+                StringKey(exchange_rate.quote.clone()),
+            ),
+            exchange_rate.clone(),
+        )
+    });
+    Ok(exchange_rate)
+}
+
+// Query function to get all exchange rates
+#[ic_cdk::query]
+fn get_exchange_rates() -> Vec<ExchangeRate> {
+    EXCHANGE_RATE_STORAGE.with(|service| service.borrow().iter().map(|(_, rate)| rate).collect())
+}
+
+// Query function to get the effective price of an accessory in its own currency,
+// or converted to `currency`. Rounding defaults to half-even.
+#[ic_cdk::query]
+fn get_accessory_price_in_currency(
+    id: u64,
+    currency: Option<String>,
+    rounding: Option<RoundingMode>,
+) -> Result<Money, Error> {
+    let accessory = _get_accessory(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", id),
+    })?;
+    let base = accessory_currency(&accessory)?;
+    let price = crate::pricing::effective_price(&accessory);
+    match currency {
+        Some(target) => convert(price, &base, &target, rounding.unwrap_or_default()),
+        None => convert(price, &base, &base, RoundingMode::default()),
+    }
+}
+
+// Currency of an accessory's price. Only accessories listed before a default
+// currency was set have none.
+pub(crate) fn accessory_currency(accessory: &Accessory) -> Result<String, Error> {
+    accessory.currency.clone().ok_or_else(|| Error::ValidationFailed {
+        msg: format!(
+            "accessory with id={} has no currency; an admin has to set a default currency",
+            accessory.id
+        ),
+    })
+}
+
+// Currency for a new accessory: the requested one if it is registered, else the default
+pub(crate) fn check_currency(code: &Option<String>) -> Result<Option<String>, Error> {
+    match code {
+        Some(code) => Ok(Some(_get_currency(&_normalize_code(code)?)?.code)),
+        None => Ok(_default_currency()),
+    }
+}
+
+// Convert an amount from one currency to another with the given rounding
+pub(crate) fn convert(amount: u64, from: &str, to: &str, rounding: RoundingMode) -> Result<Money, Error> {
+    let from = _get_currency(&_normalize_code(from)?)?;
+    let to = _get_currency(&_normalize_code(to)?)?;
+    if from.code == to.code {
+        return Ok(Money {
+            amount,
+            currency: to.code,
+            decimals: to.decimals,
+        });
+    }
+    // amount * rate * 10^to.decimals / (10^from.decimals * 10^8), with the rate as a fraction
+    let (rate_numerator, rate_denominator) = _rate(&from.code, &to.code)?;
+    let too_large = || Error::ValidationFailed {
+        msg: format!("amount {} {} is too large to convert", amount, from.code),
+    };
+    let numerator = (amount as u128)
+        .checked_mul(rate_numerator)
+        .and_then(|value| value.checked_mul(10u128.pow(to.decimals as u32)))
+        .ok_or_else(too_large)?;
+    let denominator = rate_denominator
+        .checked_mul(10u128.pow(from.decimals as u32))
+        .ok_or_else(too_large)?;
+    let converted = divide_rounded(numerator, denominator, rounding);
+    Ok(Money {
+        amount: u64::try_from(converted).map_err(|_| too_large())?,
+        currency: to.code,
+        decimals: to.decimals,
+    })
+}
+
+// Exchange rate from one currency to another as a fraction, using the inverse rate if needed
+fn _rate(from: &str, to: &str) -> Result<(u128, u128), Error> {
+    let get = |base: &str, quote: &str| {
+        EXCHANGE_RATE_STORAGE.with(|service| {
+            service
+                .borrow()
+                .get(&(StringKey(base.to_string()), StringKey(quote.to_string())))
+        })
+    };
+    if let Some(rate) = get(from, to) {
+        Ok((rate.rate as u128, RATE_SCALE))
+    } else if let Some(rate) = get(to, from) {
+        Ok((RATE_SCALE, rate.rate as u128))
+    } else {
+        Err(Error::NotFound {
+            msg: format!("no exchange rate from {} to {}", from, to),
+        })
+    }
+}
+
+// Divide and round the quotient to an integer
+pub(crate) fn divide_rounded(numerator: u128, denominator: u128, rounding: RoundingMode) -> u128 {
+    let quotient = numerator / denominator;
+    let remainder = numerator % denominator;
+    let round_up = match rounding {
+        RoundingMode::Down => false,
+        RoundingMode::Up => remainder > 0,
+        RoundingMode::HalfUp => remainder * 2 >= denominator,
+        RoundingMode::HalfEven => remainder * 2 > denominator || (remainder * 2 == denominator && quotient % 2 == 1),
+    };
+    if round_up {
+        quotient + 1
+    } else {
+        quotient
+    }
+}
+
+// Number of decimals of a registered currency
+pub(crate) fn currency_decimals(code: &str) -> Option<u8> {
+    _get_currency(code).ok().map(|currency| currency.decimals)
+}
+
+// Accessories, including those in the trash, that were listed without a currency
+fn _accessories_without_currency() -> Vec<Accessory> {
+    ACCESSORY_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, accessory)| accessory)
+            .filter(|accessory| accessory.currency.is_none())
+            .collect()
+    })
+}
+
+fn _default_currency() -> Option<String> {
+    CURRENCY_CONFIG.with(|cell| cell.borrow().get().default_currency.clone())
+}
+
+// Currency codes are case-insensitive and stored in upper case
+fn _normalize_code(code: &str) -> Result<String, Error> {
+    let code = code.trim().to_ascii_uppercase();
+    if code.len() < 3 || code.len() > MAX_CODE_LENGTH || !code.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(Error::ValidationFailed {
+            msg: format!("a currency code must be 3 to {} letters", MAX_CODE_LENGTH),
+        });
+    }
+    Ok(code)
+}
+
+fn _get_currency(code: &str) -> Result<Currency, Error> {
+    CURRENCY_STORAGE
+        .with(|service| service.borrow().get(&StringKey(code.to_string())))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("a currency with code={} not found", code),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_quotients_are_not_rounded() {
+        for rounding in [
+            RoundingMode::Down,
+            RoundingMode::Up,
+            RoundingMode::HalfUp,
+            RoundingMode::HalfEven,
+        ] {
+            assert_eq!(divide_rounded(6, 2, rounding), 3);
+        }
+    }
+
+    #[test]
+    fn half_even_rounds_ties_to_the_even_quotient() {
+        assert_eq!(divide_rounded(5, 2, RoundingMode::HalfEven), 2);
+        assert_eq!(divide_rounded(7, 2, RoundingMode::HalfEven), 4);
+        assert_eq!(divide_rounded(16, 10, RoundingMode::HalfEven), 2);
+        assert_eq!(divide_rounded(14, 10, RoundingMode::HalfEven), 1);
+    }
+
+    #[test]
+    fn other_modes_round_as_named() {
+        assert_eq!(divide_rounded(5, 2, RoundingMode::HalfUp), 3);
+        assert_eq!(divide_rounded(14, 10, RoundingMode::HalfUp), 1);
+        assert_eq!(divide_rounded(19, 10, RoundingMode::Down), 1);
+        assert_eq!(divide_rounded(11, 10, RoundingMode::Up), 2);
+    }
+}
@@ -0,0 +1,50 @@
+// The HTTP gateway interface, used to serve documents to browsers
+use candid::CandidType;
+
+// Define an HTTP request as passed on by the gateway
+#[derive(CandidType, Deserialize)]
+pub(crate) struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+// Define an HTTP response returned to the gateway
+#[derive(CandidType, Serialize)]
+pub(crate) struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub(crate) fn new(status_code: u16, content_type: &str, body: Vec<u8>) -> Self {
+        HttpResponse {
+            status_code,
+            headers: vec![
+                ("Content-Type".to_string(), content_type.to_string()),
+                ("Cache-Control".to_string(), "no-store".to_string()),
+            ],
+            body,
+        }
+    }
+
+    pub(crate) fn text(status_code: u16, message: &str) -> Self {
+        HttpResponse::new(status_code, "text/plain; charset=utf-8", message.as_bytes().to_vec())
+    }
+}
+
+// Query function serving GET requests from the HTTP gateway
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    if request.method != "GET" {
+        return HttpResponse::text(405, "method not allowed");
+    }
+    let (path, query) = request.url.split_once('?').unwrap_or((&request.url, ""));
+    let params: Vec<(&str, &str)> = query.split('&').filter_map(|param| param.split_once('=')).collect();
+    match path.strip_prefix("/invoices/") {
+        Some(document) => crate::invoices::serve_invoice(document, &params),
+        None => HttpResponse::text(404, "not found"),
+    }
+}
@@ -0,0 +1,83 @@
+// Ingress message inspection. Calls rejected here are dropped before execution, so
+// they don't cost the canister any cycles. The methods still do their own checks.
+use crate::{rate_limit, roles, sellers};
+use candid::Principal;
+use ic_cdk::api::call::{accept_message, arg_data_raw_size, method_name};
+use ic_cdk::api::{caller, time};
+
+// Argument size limit for methods without a specific limit (4 KiB)
+const DEFAULT_MAX_ARG_BYTES: usize = 4 * 1024;
+
+// Define who may send an ingress message to a method
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Access {
+    // Any principal except the anonymous one
+    Authenticated,
+    // Registered sellers that are allowed to list accessories
+    Seller,
+    Admin,
+}
+
+// Define the restrictions applied to ingress messages for a method
+struct MethodPolicy {
+    access: Access,
+    max_arg_bytes: usize,
+}
+
+#[ic_cdk::inspect_message]
+fn inspect_message() {
+    let method = method_name();
+    let policy = _policy(&method);
+    if arg_data_raw_size() <= policy.max_arg_bytes
+        && _has_access(&caller(), policy.access)
+        && rate_limit::_throttled_for(&caller(), &method, time()).is_none()
+    {
+        accept_message();
+    }
+}
+
+// Queries only pass through here when called as update calls; they get the default policy too
+fn _policy(method: &str) -> MethodPolicy {
+    let access = match method {
+        "add_accessory" | "accept_ownership_transfer" => Access::Seller,
+        "assign_role"
+        | "revoke_role"
+        | "set_costing_method"
+        | "add_currency"
+        | "set_default_currency"
+        | "set_exchange_rate"
+        | "set_payment_ledger"
+        | "set_seller_verification"
+        | "set_seller_verification_required"
+        | "set_tax_rule"
+        | "remove_tax_rule"
+        | "set_default_jurisdiction"
+        | "set_rate_limit"
+        | "remove_rate_limit"
+        | "block_principal"
+        | "unblock_principal"
+        | "set_trash_retention" => Access::Admin,
+        _ => Access::Authenticated,
+    };
+    let max_arg_bytes = match method {
+        // A single accessory or review is stored in at most 1 KiB; the rest covers the
+        // type table of the argument and the expected version
+        "add_accessory" | "update_accessory" | "patch_accessory" | "add_review" => 2 * 1024,
+        "place_order" | "create_purchase_order" | "receive_purchase_order" | "record_stocktake_counts" => 16 * 1024,
+        "enable_serial_tracking" | "add_serials" => 32 * 1024,
+        "bulk_update_accessories" | "bulk_add" | "bulk_update" => 64 * 1024,
+        _ => DEFAULT_MAX_ARG_BYTES,
+    };
+    MethodPolicy { access, max_arg_bytes }
+}
+
+fn _has_access(principal: &Principal, access: Access) -> bool {
+    if *principal == Principal::anonymous() {
+        return false;
+    }
+    match access {
+        Access::Authenticated => true,
+        Access::Seller => sellers::_check_can_sell(principal).is_ok(),
+        Access::Admin => roles::_is_admin(principal),
+    }
+}
@@ -0,0 +1,333 @@
+// Stock movement ledger: every change to inventory_count goes through here
+use crate::{
+    _check_accessory_version, _check_if_seller_or_staff, _get_accessory, batches, costing, do_insert_accessory,
+    next_id, rate_limit, serials, Accessory, Error, Memory, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Define the reasons a stock level can change
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum StockReason {
+    Received,
+    Sold,
+    Damaged,
+    Lost,
+    Returned,
+    Correction,
+    // Posted when a stocktake session is approved
+    Stocktake,
+}
+
+// Define the structure representing a single stock movement
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct StockMovement {
+    pub(crate) id: u64,
+    pub(crate) accessory_id: u64,
+    pub(crate) delta: i64,
+    pub(crate) reason: StockReason,
+    pub(crate) note: String,
+    pub(crate) balance_after: u64,
+    pub(crate) actor: Principal,
+    pub(crate) created_at: u64,
+}
+
+// Implement the Storable trait for StockMovement
+impl Storable for StockMovement {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for StockMovement
+impl BoundedStorable for StockMovement {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Maximum length of the free-text note attached to a movement
+const MAX_NOTE_LENGTH: usize = 256;
+
+thread_local! {
+    // Movements are keyed by (accessory_id, movement_id) so they can be listed per accessory
+    static STOCK_MOVEMENT_STORAGE: RefCell<StableBTreeMap<(u64, u64), StockMovement, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5))))
+    );
+}
+
+// Update function to change the stock level of an accessory by a relative amount
+#[ic_cdk::update]
+fn adjust_inventory(
+    id: u64,
+    delta: i64,
+    reason: StockReason,
+    note: String,
+    unit_cost: Option<u64>,
+    expected_version: Option<u64>,
+) -> Result<Accessory, Error> {
+    rate_limit::_check_rate_limit("adjust_inventory")?;
+    let accessory = _get_accessory(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", id),
+    })?;
+    _check_if_seller_or_staff(&accessory)?;
+    _check_accessory_version(&accessory, expected_version)?;
+    _check_delta_matches_reason(delta, reason)?;
+    let (accessory, _) = record_stock_movement(id, delta, reason, note, unit_cost)?;
+    Ok(accessory)
+}
+
+// Query function to get the stock movements of an accessory, oldest first
+#[ic_cdk::query]
+fn get_stock_movements(accessory_id: u64) -> Vec<StockMovement> {
+    STOCK_MOVEMENT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((accessory_id, 0)..=(accessory_id, u64::MAX))
+            .map(|(_, movement)| movement)
+            .collect()
+    })
+}
+
+// Apply a stock change to an accessory and append it to the movement log.
+// Incoming stock is costed at `unit_cost` when given, see costing for the fallback.
+// Callers are responsible for authorising the change.
+pub(crate) fn record_stock_movement(
+    id: u64,
+    delta: i64,
+    reason: StockReason,
+    note: String,
+    unit_cost: Option<u64>,
+) -> Result<(Accessory, StockMovement), Error> {
+    _check_not_serialized(id)?;
+    _record_stock_movement(id, delta, reason, note, unit_cost, None)
+}
+
+// Record units sold on an order. `revenue` is what the buyer paid for them, net of
+// discounts and tax, and is booked against their cost of goods sold.
+pub(crate) fn record_sale(
+    id: u64,
+    quantity: u64,
+    note: String,
+    revenue: u64,
+) -> Result<(Accessory, StockMovement), Error> {
+    _check_not_serialized(id)?;
+    _record_stock_movement(
+        id,
+        -_quantity_delta(quantity)?,
+        StockReason::Sold,
+        note,
+        None,
+        Some(revenue),
+    )
+}
+
+// Run the checks of record_stock_movement without applying anything, so callers that
+// record several movements can validate all of them before storing the first
+pub(crate) fn check_stock_movement(id: u64, delta: i64, reason: StockReason, note: &str) -> Result<(), Error> {
+    _check_not_serialized(id)?;
+    _check_movement(id, delta, reason, note).map(|_| ())
+}
+
+// Record the stock change caused by a serialized unit entering or leaving stock
+pub(crate) fn record_serial_movement(
+    id: u64,
+    delta: i64,
+    reason: StockReason,
+    note: String,
+    unit_cost: Option<u64>,
+) -> Result<(Accessory, StockMovement), Error> {
+    _record_stock_movement(id, delta, reason, note, unit_cost, None)
+}
+
+// Record serialized units sold on an order, see record_sale
+pub(crate) fn record_serial_sale(
+    id: u64,
+    quantity: u64,
+    note: String,
+    revenue: u64,
+) -> Result<(Accessory, StockMovement), Error> {
+    _record_stock_movement(
+        id,
+        -_quantity_delta(quantity)?,
+        StockReason::Sold,
+        note,
+        None,
+        Some(revenue),
+    )
+}
+
+// Put back the units of a sale that is undone, e.g. on a cancelled order. Unlike a
+// return, the units go back into the cost lots they were sold from.
+pub(crate) fn reverse_sale(
+    id: u64,
+    sale_id: u64,
+    quantity: u64,
+    note: String,
+) -> Result<(Accessory, StockMovement), Error> {
+    let (accessory, movement) = _store_movement(id, _quantity_delta(quantity)?, StockReason::Returned, note)?;
+    costing::reverse_sale(id, sale_id);
+    Ok((accessory, movement))
+}
+
+fn _quantity_delta(quantity: u64) -> Result<i64, Error> {
+    i64::try_from(quantity).map_err(|_| Error::ValidationFailed {
+        msg: format!("invalid quantity {}", quantity),
+    })
+}
+
+fn _record_stock_movement(
+    id: u64,
+    delta: i64,
+    reason: StockReason,
+    note: String,
+    unit_cost: Option<u64>,
+    revenue: Option<u64>,
+) -> Result<(Accessory, StockMovement), Error> {
+    let (accessory, movement) = _store_movement(id, delta, reason, note)?;
+    costing::apply_stock_movement(&accessory, &movement, unit_cost, revenue);
+    batches::apply_stock_movement(&movement);
+    Ok((accessory, movement))
+}
+
+// Change the stock of an accessory and log the movement, without touching its cost lots or batches
+fn _store_movement(
+    id: u64,
+    delta: i64,
+    reason: StockReason,
+    note: String,
+) -> Result<(Accessory, StockMovement), Error> {
+    let (mut accessory, balance_after) = _check_movement(id, delta, reason, &note)?;
+    let now = time();
+    accessory.inventory_count = balance_after;
+    accessory.updated_at = Some(now);
+    do_insert_accessory(&mut accessory);
+
+    let movement = StockMovement {
+        id: next_id(),
+        accessory_id: id,
+        delta,
+        reason,
+        note,
+        balance_after,
+        actor: caller(),
+        created_at: now,
+    };
+    STOCK_MOVEMENT_STORAGE.with(|service| service.borrow_mut().insert((id, movement.id), movement.clone()));
+    Ok((accessory, movement))
+}
+
+// Check a stock change against the accessory and return it with its balance after the change
+fn _check_movement(id: u64, delta: i64, reason: StockReason, note: &str) -> Result<(Accessory, u64), Error> {
+    if note.len() > MAX_NOTE_LENGTH {
+        return Err(Error::ValidationFailed {
+            msg: format!("note must be at most {} bytes long", MAX_NOTE_LENGTH),
+        });
+    }
+    let accessory = _get_accessory(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", id),
+    })?;
+    batches::check_stock_movement(&accessory, delta, reason)?;
+    let balance_after = accessory
+        .inventory_count
+        .checked_add_signed(delta)
+        .ok_or_else(|| Error::ValidationFailed {
+            msg: format!(
+                "cannot change inventory of accessory with id={} by {}: only {} in stock",
+                id, delta, accessory.inventory_count
+            ),
+        })?;
+    Ok((accessory, balance_after))
+}
+
+// The stock of serialized accessories only changes through their units
+fn _check_not_serialized(id: u64) -> Result<(), Error> {
+    if serials::_is_serialized(id) {
+        return Err(Error::ValidationFailed {
+            msg: format!(
+                "accessory with id={} is tracked by serial number, change the status of its units instead",
+                id
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Helper function to check that the sign of a manual adjustment fits its reason
+fn _check_delta_matches_reason(delta: i64, reason: StockReason) -> Result<(), Error> {
+    let valid = match reason {
+        StockReason::Received | StockReason::Returned => delta > 0,
+        StockReason::Sold | StockReason::Damaged | StockReason::Lost => delta < 0,
+        StockReason::Correction => delta != 0,
+        // Stocktake corrections are only posted by approving a stocktake session
+        StockReason::Stocktake => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::ValidationFailed {
+            msg: format!("delta={} is not valid for this adjustment reason", delta),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::largest_accessory;
+
+    fn stock(id: u64, inventory_count: u64) {
+        do_insert_accessory(&mut Accessory {
+            id,
+            inventory_count,
+            deleted_at: None,
+            ..largest_accessory()
+        });
+    }
+
+    #[test]
+    fn stock_cannot_go_negative() {
+        stock(1, 5);
+        let balance_after = |delta, reason| _check_movement(1, delta, reason, "").ok().map(|(_, balance)| balance);
+        assert_eq!(balance_after(-5, StockReason::Damaged), Some(0));
+        assert_eq!(balance_after(-6, StockReason::Damaged), None);
+        assert_eq!(balance_after(3, StockReason::Received), Some(8));
+    }
+
+    #[test]
+    fn movements_of_missing_accessories_or_with_long_notes_are_rejected() {
+        stock(2, 5);
+        assert!(matches!(
+            _check_movement(3, 1, StockReason::Received, ""),
+            Err(Error::NotFound { .. })
+        ));
+        let note = "n".repeat(MAX_NOTE_LENGTH + 1);
+        assert!(matches!(
+            _check_movement(2, 1, StockReason::Received, &note),
+            Err(Error::ValidationFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn adjustment_sign_must_fit_its_reason() {
+        assert!(_check_delta_matches_reason(1, StockReason::Received).is_ok());
+        assert!(_check_delta_matches_reason(-1, StockReason::Received).is_err());
+        assert!(_check_delta_matches_reason(-1, StockReason::Damaged).is_ok());
+        assert!(_check_delta_matches_reason(1, StockReason::Lost).is_err());
+        assert!(_check_delta_matches_reason(-1, StockReason::Correction).is_ok());
+        assert!(_check_delta_matches_reason(0, StockReason::Correction).is_err());
+        assert!(_check_delta_matches_reason(1, StockReason::Stocktake).is_err());
+    }
+
+    #[test]
+    fn quantities_must_fit_a_delta() {
+        assert_eq!(_quantity_delta(7).ok(), Some(7));
+        assert!(_quantity_delta(u64::MAX).is_err());
+    }
+}
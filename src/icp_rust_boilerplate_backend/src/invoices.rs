@@ -0,0 +1,512 @@
+// Immutable, sequentially numbered invoices for paid or shipped orders
+use crate::currency::currency_decimals;
+use crate::http::HttpResponse;
+use crate::orders::{_get_order, Order, OrderLine, OrderStatus};
+use crate::shipping::ShippingCharge;
+use crate::{_truncate_text, rate_limit, roles, sellers, Error, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+// How long to wait before asking for the invoice secret again after a failed request
+const SECRET_RETRY_DELAY: Duration = Duration::from_secs(60);
+// Longest seller name and contact copied onto an invoice. Together with the order
+// limits they keep the largest invoice within Invoice::MAX_SIZE.
+const MAX_SELLER_NAME_LENGTH: usize = 64;
+const MAX_SELLER_CONTACT_LENGTH: usize = 128;
+
+// Define an invoice. Invoices are never changed once issued, except for being voided
+// when their order is cancelled; the number stays taken.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Invoice {
+    number: u64,
+    order_id: u64,
+    seller: Principal,
+    // Seller profile at the time the invoice was issued
+    seller_name: Option<String>,
+    seller_contact: Option<String>,
+    buyer: Principal,
+    lines: Vec<OrderLine>,
+    subtotal: u64,
+    discount_total: u64,
+    tax_total: u64,
+    shipping: Option<ShippingCharge>,
+    total: u64,
+    currency: Option<String>,
+    jurisdiction: Option<String>,
+    issued_at: u64,
+    voided_at: Option<u64>,
+}
+
+// Implement the Storable trait for Invoice
+impl Storable for Invoice {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for Invoice
+impl BoundedStorable for Invoice {
+    const MAX_SIZE: u32 = 8192;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define the links to the printable and JSON documents of an invoice
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct InvoiceLinks {
+    html: String,
+    json: String,
+}
+
+thread_local! {
+    // Last issued invoice number; invoice numbers have no gaps
+    static INVOICE_COUNTER: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35))), 0)
+            .expect("Cannot create the invoice counter")
+    );
+
+    static INVOICE_STORAGE: RefCell<StableBTreeMap<u64, Invoice, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(36))))
+    );
+
+    static ORDER_INVOICE_INDEX: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(37))))
+    );
+
+    // Random secret the access tokens of invoice documents are derived from
+    static INVOICE_SECRET: RefCell<Cell<Vec<u8>, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(38))), Vec::new())
+            .expect("Cannot create the invoice secret cell")
+    );
+}
+
+// Update function to issue the invoice of an order that was paid outside the payment
+// ledger or placed before invoices were issued automatically (seller or staff only)
+#[ic_cdk::update]
+fn issue_invoice(order_id: u64) -> Result<Invoice, Error> {
+    rate_limit::_check_rate_limit("issue_invoice")?;
+    let order = _get_order(&order_id)?;
+    if order.seller != caller() && !roles::_is_staff(&caller()) {
+        return Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} can't invoice order with id={}", caller(), order_id),
+        });
+    }
+    if order.status == OrderStatus::Cancelled {
+        return Err(Error::ValidationFailed {
+            msg: format!("order with id={} was cancelled", order_id),
+        });
+    }
+    if let Some(number) = ORDER_INVOICE_INDEX.with(|service| service.borrow().get(&order_id)) {
+        return Err(Error::ValidationFailed {
+            msg: format!(
+                "order with id={} already has invoice {}",
+                order_id,
+                _format_number(number)
+            ),
+        });
+    }
+    Ok(issue_invoice_for(&order))
+}
+
+// Query function to get an invoice by number (buyer, seller or staff only)
+#[ic_cdk::query]
+fn get_invoice(number: u64) -> Result<Invoice, Error> {
+    let invoice = _get_invoice(number)?;
+    _check_invoice_access(&invoice)?;
+    Ok(invoice)
+}
+
+// Query function to get the invoice of an order (buyer, seller or staff only)
+#[ic_cdk::query]
+fn get_order_invoice(order_id: u64) -> Result<Invoice, Error> {
+    let number = ORDER_INVOICE_INDEX
+        .with(|service| service.borrow().get(&order_id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("no invoice for order with id={}", order_id),
+        })?;
+    get_invoice(number)
+}
+
+// Query function to list the invoices of a principal as buyer or seller.
+// Defaults to the caller; only staff can list other principals' invoices.
+#[ic_cdk::query]
+fn get_invoices(principal: Option<Principal>) -> Result<Vec<Invoice>, Error> {
+    let principal = principal.unwrap_or_else(caller);
+    if principal != caller() && !roles::_is_staff(&caller()) {
+        return Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} can't list the invoices of {}", caller(), principal),
+        });
+    }
+    Ok(INVOICE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, invoice)| invoice.buyer == principal || invoice.seller == principal)
+            .map(|(_, invoice)| invoice)
+            .collect()
+    }))
+}
+
+// Query function to get the links that serve an invoice through the HTTP gateway
+// (buyer, seller or staff only). Anyone holding a link can open the document.
+#[ic_cdk::query]
+fn get_invoice_links(number: u64) -> Result<InvoiceLinks, Error> {
+    let invoice = _get_invoice(number)?;
+    _check_invoice_access(&invoice)?;
+    let token = _access_token(number).ok_or_else(|| Error::NotFound {
+        msg: "invoice links aren't available yet, try again shortly".to_string(),
+    })?;
+    Ok(InvoiceLinks {
+        html: format!("/invoices/{}.html?token={}", number, token),
+        json: format!("/invoices/{}.json?token={}", number, token),
+    })
+}
+
+// Issue the invoice of an order once it is paid or shipped, unless it already has one
+pub(crate) fn invoice_order(order: &Order) {
+    if !ORDER_INVOICE_INDEX.with(|service| service.borrow().contains_key(&order.id)) {
+        issue_invoice_for(order);
+    }
+}
+
+// Void the invoice of a cancelled order, if it has one
+pub(crate) fn void_order_invoice(order_id: u64) {
+    let Some(mut invoice) = ORDER_INVOICE_INDEX
+        .with(|service| service.borrow().get(&order_id))
+        .and_then(|number| _get_invoice(number).ok())
+    else {
+        return;
+    };
+    if invoice.voided_at.is_none() {
+        invoice.voided_at = Some(time());
+        INVOICE_STORAGE.with(|service| service.borrow_mut().insert(invoice.number, invoice));
+    }
+}
+
+// Issue the invoice of an order with the next invoice number
+fn issue_invoice_for(order: &Order) -> Invoice {
+    let number = INVOICE_COUNTER.with(|counter| {
+        let next = *counter.borrow().get() + 1;
+        counter
+            .borrow_mut()
+            .set(next)
+            .expect("Cannot increment the invoice counter");
+        next
+    });
+    // Orders placed before discounts, taxes and shipping existed only have a total
+    let subtotal = order.subtotal.unwrap_or(order.total);
+    let seller = sellers::_get_seller(&order.seller);
+    let invoice = Invoice {
+        number,
+        order_id: order.id,
+        seller: order.seller,
+        seller_name: seller
+            .as_ref()
+            .map(|seller| _truncate_text(&seller.display_name, MAX_SELLER_NAME_LENGTH)),
+        seller_contact: seller.map(|seller| _truncate_text(&seller.contact, MAX_SELLER_CONTACT_LENGTH)),
+        buyer: order.buyer,
+        lines: order.lines.clone(),
+        subtotal,
+        discount_total: order.discount_total.unwrap_or_default(),
+        tax_total: order.tax_total.unwrap_or_default(),
+        shipping: order.shipping.clone(),
+        total: order.total,
+        currency: order.currency.clone(),
+        jurisdiction: order.jurisdiction.clone(),
+        issued_at: time(),
+        voided_at: None,
+    };
+    INVOICE_STORAGE.with(|service| service.borrow_mut().insert(number, invoice.clone()));
+    ORDER_INVOICE_INDEX.with(|service| service.borrow_mut().insert(order.id, number));
+    invoice
+}
+
+// Serve `<number>.html` or `<number>.json` to a request carrying the invoice's access token
+pub(crate) fn serve_invoice(document: &str, params: &[(&str, &str)]) -> HttpResponse {
+    let Some((number, extension)) = document.split_once('.') else {
+        return HttpResponse::text(404, "not found");
+    };
+    let Some(invoice) = number.parse().ok().and_then(|number| _get_invoice(number).ok()) else {
+        return HttpResponse::text(404, "not found");
+    };
+    let token = params
+        .iter()
+        .find(|(name, _)| *name == "token")
+        .map(|(_, value)| *value);
+    if token.is_none() || token != _access_token(invoice.number).as_deref() {
+        return HttpResponse::text(403, "forbidden");
+    }
+    match extension {
+        "html" => HttpResponse::new(200, "text/html; charset=utf-8", _render_html(&invoice).into_bytes()),
+        "json" => HttpResponse::new(200, "application/json", _render_json(&invoice).into_bytes()),
+        _ => HttpResponse::text(404, "not found"),
+    }
+}
+
+// Fetch the access token secret from the management canister if it isn't stored yet.
+// Run once by the scheduler after install and upgrade.
+pub(crate) fn ensure_invoice_secret() {
+    if INVOICE_SECRET.with(|cell| !cell.borrow().get().is_empty()) {
+        return;
+    }
+    ic_cdk::spawn(async {
+        match ic_cdk::api::management_canister::main::raw_rand().await {
+            Ok((bytes,)) => {
+                INVOICE_SECRET
+                    .with(|cell| cell.borrow_mut().set(bytes))
+                    .expect("Cannot store the invoice secret");
+            }
+            Err(_) => {
+                ic_cdk_timers::set_timer(SECRET_RETRY_DELAY, ensure_invoice_secret);
+            }
+        }
+    });
+}
+
+fn _render_json(invoice: &Invoice) -> String {
+    let lines: Vec<serde_json::Value> = invoice
+        .lines
+        .iter()
+        .map(|line| {
+            json!({
+                "accessory_id": line.accessory_id,
+                "description": line.name,
+                "quantity": line.quantity,
+                "unit_price": line.unit_price,
+                "serials": line.serials,
+                "discounts": line.discounts.iter().flatten().map(|discount| json!({
+                    "code": discount.code,
+                    "description": discount.description,
+                    "amount": discount.amount,
+                })).collect::<Vec<_>>(),
+                "tax": line.tax.as_ref().map(|tax| json!({
+                    "rate_basis_points": tax.rate_basis_points,
+                    "inclusive": tax.inclusive,
+                    "amount": tax.amount,
+                })),
+                "line_total": line.line_total.unwrap_or(line.unit_price.saturating_mul(line.quantity)),
+            })
+        })
+        .collect();
+    json!({
+        "invoice_number": _format_number(invoice.number),
+        "order_id": invoice.order_id,
+        "issued_at": invoice.issued_at,
+        "voided_at": invoice.voided_at,
+        "seller": invoice.seller.to_text(),
+        "seller_name": invoice.seller_name,
+        "seller_contact": invoice.seller_contact,
+        "buyer": invoice.buyer.to_text(),
+        "currency": invoice.currency,
+        "jurisdiction": invoice.jurisdiction,
+        "lines": lines,
+        "subtotal": invoice.subtotal,
+        "discount_total": invoice.discount_total,
+        "tax_total": invoice.tax_total,
+        "shipping": invoice.shipping.as_ref().map(|shipping| json!({
+            "method": shipping.name,
+            "amount": shipping.amount,
+        })),
+        "total": invoice.total,
+    })
+    .to_string()
+}
+
+fn _render_html(invoice: &Invoice) -> String {
+    let money = |amount: u64| _format_amount(amount, &invoice.currency);
+    let mut rows = String::new();
+    for line in &invoice.lines {
+        let mut description = _escape_html(&line.name);
+        if !line.serials.is_empty() {
+            description.push_str(&format!(
+                "<br><small>S/N: {}</small>",
+                _escape_html(&line.serials.join(", "))
+            ));
+        }
+        for discount in line.discounts.iter().flatten() {
+            description.push_str(&format!(
+                "<br><small>{} ({}): -{}</small>",
+                _escape_html(&discount.code),
+                _escape_html(&discount.description),
+                money(discount.amount)
+            ));
+        }
+        let tax = line.tax.as_ref().map_or(String::new(), |tax| {
+            format!(
+                "{}.{:02}%{} {}",
+                tax.rate_basis_points / 100,
+                tax.rate_basis_points % 100,
+                if tax.inclusive { " incl." } else { "" },
+                money(tax.amount)
+            )
+        });
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td></tr>",
+            description,
+            line.quantity,
+            money(line.unit_price),
+            tax,
+            money(line.line_total.unwrap_or(line.unit_price.saturating_mul(line.quantity)))
+        ));
+    }
+    let mut totals = format!(
+        "<tr><td>Subtotal</td><td class=\"n\">{}</td></tr><tr><td>Discounts</td><td class=\"n\">-{}</td></tr><tr><td>Tax</td><td class=\"n\">{}</td></tr>",
+        money(invoice.subtotal),
+        money(invoice.discount_total),
+        money(invoice.tax_total)
+    );
+    if let Some(shipping) = &invoice.shipping {
+        totals.push_str(&format!(
+            "<tr><td>Shipping ({})</td><td class=\"n\">{}</td></tr>",
+            _escape_html(&shipping.name),
+            money(shipping.amount)
+        ));
+    }
+    totals.push_str(&format!(
+        "<tr><th>Total {}</th><th class=\"n\">{}</th></tr>",
+        _escape_html(invoice.currency.as_deref().unwrap_or("")),
+        money(invoice.total)
+    ));
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Invoice {number}</title>\
+<style>body{{font-family:sans-serif;margin:2em}}table{{border-collapse:collapse;width:100%}}\
+td,th{{border-bottom:1px solid #ccc;padding:4px;text-align:left}}.n{{text-align:right}}</style></head><body>\
+<h1>Invoice {number}{void}</h1><p>Order #{order_id}<br>Issued at {issued_at} (ns since epoch)</p>\
+<p><b>Seller:</b> {seller_name}{seller}{seller_contact}<br><b>Buyer:</b> {buyer}{jurisdiction}</p>\
+<table><tr><th>Item</th><th class=\"n\">Qty</th><th class=\"n\">Unit price</th><th class=\"n\">Tax</th><th class=\"n\">Amount</th></tr>{rows}</table>\
+<table style=\"width:auto;margin-left:auto;margin-top:1em\">{totals}</table></body></html>",
+        number = _format_number(invoice.number),
+        void = invoice
+            .voided_at
+            .map_or(String::new(), |voided_at| format!(" (void since {})", voided_at)),
+        order_id = invoice.order_id,
+        issued_at = invoice.issued_at,
+        seller = invoice.seller,
+        seller_name = invoice
+            .seller_name
+            .as_ref()
+            .map_or(String::new(), |name| format!("{}<br>", _escape_html(name))),
+        seller_contact = invoice
+            .seller_contact
+            .as_ref()
+            .map_or(String::new(), |contact| format!("<br>{}", _escape_html(contact))),
+        buyer = invoice.buyer,
+        jurisdiction = invoice
+            .jurisdiction
+            .as_ref()
+            .map_or(String::new(), |code| format!("<br><b>Tax jurisdiction:</b> {}", _escape_html(code))),
+        rows = rows,
+        totals = totals,
+    )
+}
+
+// Amount in major units of the currency, e.g. 1234 with 2 decimals is 12.34
+fn _format_amount(amount: u64, currency: &Option<String>) -> String {
+    match currency.as_deref().and_then(currency_decimals) {
+        Some(decimals) if decimals > 0 => {
+            let scale = 10u128.pow(decimals as u32);
+            format!(
+                "{}.{:0width$}",
+                amount as u128 / scale,
+                amount as u128 % scale,
+                width = decimals as usize
+            )
+        }
+        _ => amount.to_string(),
+    }
+}
+
+fn _escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn _format_number(number: u64) -> String {
+    format!("INV-{:06}", number)
+}
+
+// Token granting access to the documents of an invoice, None until the secret is known
+fn _access_token(number: u64) -> Option<String> {
+    let secret = INVOICE_SECRET.with(|cell| cell.borrow().get().clone());
+    if secret.is_empty() {
+        return None;
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(&secret);
+    hasher.update(number.to_be_bytes());
+    Some(hex::encode(hasher.finalize()))
+}
+
+fn _check_invoice_access(invoice: &Invoice) -> Result<(), Error> {
+    let caller = caller();
+    if invoice.buyer == caller || invoice.seller == caller || roles::_is_staff(&caller) {
+        Ok(())
+    } else {
+        Err(Error::AuthenticationFailed {
+            msg: format!(
+                "Caller={} can't access invoice {}",
+                caller,
+                _format_number(invoice.number)
+            ),
+        })
+    }
+}
+
+fn _get_invoice(number: u64) -> Result<Invoice, Error> {
+    INVOICE_STORAGE
+        .with(|service| service.borrow().get(&number))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("invoice {} not found", _format_number(number)),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orders::tests::largest_order;
+
+    #[test]
+    fn largest_invoice_fits_its_storage_bound() {
+        let order = largest_order();
+        let invoice = Invoice {
+            number: u64::MAX,
+            order_id: order.id,
+            seller: order.seller,
+            seller_name: Some("n".repeat(MAX_SELLER_NAME_LENGTH)),
+            seller_contact: Some("c".repeat(MAX_SELLER_CONTACT_LENGTH)),
+            buyer: order.buyer,
+            lines: order.lines,
+            subtotal: u64::MAX,
+            discount_total: u64::MAX,
+            tax_total: u64::MAX,
+            shipping: order.shipping,
+            total: u64::MAX,
+            currency: order.currency,
+            jurisdiction: order.jurisdiction,
+            issued_at: u64::MAX,
+            voided_at: Some(u64::MAX),
+        };
+        let size = invoice.to_bytes().len();
+        assert!(size <= Invoice::MAX_SIZE as usize, "{} bytes", size);
+    }
+
+    #[test]
+    fn seller_details_are_capped_on_char_boundaries() {
+        let name = _truncate_text(&"é".repeat(MAX_SELLER_NAME_LENGTH), MAX_SELLER_NAME_LENGTH);
+        assert_eq!(name.len(), MAX_SELLER_NAME_LENGTH);
+        let name = _truncate_text(&format!("a{}", "é".repeat(40)), MAX_SELLER_NAME_LENGTH);
+        assert_eq!(name.len(), MAX_SELLER_NAME_LENGTH - 1);
+    }
+}
@@ -2,23 +2,81 @@
 #[macro_use]
 extern crate serde;
 
-use candid::{Decode, Encode};
+use candid::{Decode, Encode, Principal};
 use ic_cdk::api::{time, caller};
-use validator::Validate;
+use validator::{Validate, ValidationError};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
 use ic_stable_structures::{BoundedStorable, Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
 use std::{borrow::Cow, cell::RefCell};
 use std::borrow::Borrow;
 
+mod audit;
+mod barcodes;
+mod batches;
+mod bulk;
+mod costing;
+mod currency;
+mod http;
+mod inspect;
+mod inventory;
+mod invoices;
+mod migrations;
+mod orders;
+mod payments;
+mod pricing;
+mod promotions;
+mod purchase_orders;
+mod rate_limit;
+mod returns;
+mod reorder;
+mod roles;
+mod scheduler;
+mod scorecards;
+mod sellers;
+mod serials;
+mod shipping;
+mod stocktakes;
+mod suppliers;
+mod taxes;
+mod trash;
+mod transfers;
+
+use audit::{AuditAction, AuditEntry};
+use batches::{Batch, BatchPayload};
+use bulk::{BulkMode, BulkReport};
+use costing::{CostOfGoodsSoldReport, CostingMethod, GrossMargin, InventoryValuation};
+use currency::{Currency, CurrencyConfig, ExchangeRate, Money, RoundingMode};
+use http::{HttpRequest, HttpResponse};
+use inventory::{record_stock_movement, StockMovement, StockReason};
+use invoices::{Invoice, InvoiceLinks};
+use reorder::{StockAlert, SupplierReorderSuggestion};
+use orders::{Order, OrderPayload, PriceQuote};
+use payments::PaymentConfig;
+use pricing::{PriceChange, PriceChangeReason, Sale};
+use promotions::{Coupon, CouponPayload};
+use purchase_orders::{GoodsReceiptLine, PurchaseOrder, PurchaseOrderPayload, PurchaseOrderStatus};
+use rate_limit::{Block, CallerStats, RateQuota};
+use returns::{ReturnDisposition, ReturnRequest, ReturnRequestPayload, ReturnStatus};
+use roles::Role;
+use scorecards::{SellerScorecard, SellerSort};
+use sellers::{Seller, SellerConfig, SellerPayload, Storefront, VerificationStatus};
+use serials::{SerialHistory, SerialStatus, SerialUnit};
+use shipping::{Dimensions, Shipment, ShipmentStatus, ShippingMethod, ShippingMethodPayload};
+use stocktakes::{Stocktake, StocktakeReport, StocktakeScope};
+use suppliers::{Supplier, SupplierLink, SupplierPayload};
+use taxes::{TaxConfig, TaxRule, TaxSummary};
+use trash::TrashConfig;
+use transfers::{OwnershipTransfer, TransferStatus};
+
 // Define type aliases for better readability
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 type IdCell = Cell<u64, Memory>;
 
 // Define the structure representing an accessory
-#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
 struct Accessory {
     id: u64,
-    seller: String,
+    seller: Principal,
     name: String,
     description: String,
     category: String,
@@ -27,11 +85,24 @@ struct Accessory {
     updated_at: Option<u64>,
     is_available: bool,
     inventory_count: u64,
+    reorder_point: Option<u64>,
+    reorder_quantity: Option<u64>,
+    barcode: Option<String>,
+    manufacturer_part_number: Option<String>,
+    // Currency of the price, see the currency module
+    currency: Option<String>,
+    weight_grams: Option<u64>,
+    dimensions: Option<Dimensions>,
+    // Set while the accessory is in the trash, see the trash module
+    deleted_at: Option<u64>,
+    deleted_by: Option<Principal>,
+    // Incremented on every change; None for accessories not changed since versions were added
+    version: Option<u64>,
 }
 
 // Implement trait for serializing and deserializing the accessory
 impl Storable for Accessory {
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 
@@ -58,7 +129,7 @@ struct Review {
 
 // Implement the Storable trait for Review
 impl Storable for Review {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 
@@ -72,6 +143,50 @@ impl BoundedStorable for Review {
     const MAX_SIZE: u32 = 1024; // Maximum size for the serialized data
     const IS_FIXED_SIZE: bool = false; // Data size is not fixed
 }
+
+// Wrapper that lets a principal be used as a stable map key
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct PrincipalKey(Principal);
+
+impl Default for PrincipalKey {
+    fn default() -> Self {
+        PrincipalKey(Principal::anonymous())
+    }
+}
+
+impl Storable for PrincipalKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.0.as_slice().to_vec())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        PrincipalKey(Principal::from_slice(bytes.as_ref()))
+    }
+}
+
+impl BoundedStorable for PrincipalKey {
+    const MAX_SIZE: u32 = 29; // Principals are at most 29 bytes long
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Wrapper that lets a string of at most MAX bytes be used as a stable map key
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+struct StringKey<const MAX: u32>(String);
+
+impl<const MAX: u32> Storable for StringKey<MAX> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.0.as_bytes())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        StringKey(String::from_utf8(bytes.into_owned()).unwrap())
+    }
+}
+
+impl<const MAX: u32> BoundedStorable for StringKey<MAX> {
+    const MAX_SIZE: u32 = MAX;
+    const IS_FIXED_SIZE: bool = false;
+}
 // Define thread-local variables for managing memory, ID counter, and accessory storage
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
@@ -92,19 +207,97 @@ thread_local! {
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3))) // Using a new MemoryId for reviews
         )
     );
+    // Reviews by (accessory_id, review_id)
+    static REVIEW_INDEX: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(52))))
+    );
 }
 
+// Text limits in bytes that keep the largest accessory within Accessory::MAX_SIZE
+const MAX_NAME_LENGTH: usize = 100;
+const MAX_DESCRIPTION_LENGTH: usize = 400;
+const MAX_CATEGORY_LENGTH: usize = 64;
+const MAX_PART_NUMBER_LENGTH: usize = 64;
+// Keeps the largest review within Review::MAX_SIZE
+const MAX_REVIEW_COMMENT_LENGTH: usize = 500;
+
 // Define a payload structure for adding or updating an accessory
 #[derive(candid::CandidType, Serialize, Deserialize, Default, Validate)]
 struct AccessoryPayload {
-    #[validate(length(min = 1))]
+    #[validate(length(min = 1), custom = "_validate_name")]
     name: String,
-    #[validate(length(min = 10))]
+    #[validate(length(min = 10), custom = "_validate_description")]
     description: String,
+    #[validate(custom = "_validate_category")]
     category: String,
     price: u64,  // New field: price
     is_available: bool,
+    // Sets the stock when adding; ignored on update, where stock changes go through adjust_inventory
     inventory_count: u64,
+    reorder_point: Option<u64>,
+    reorder_quantity: Option<u64>,
+    #[validate(custom = "barcodes::validate_gtin")]
+    barcode: Option<String>,
+    #[validate(length(min = 1), custom = "_validate_part_number")]
+    manufacturer_part_number: Option<String>,
+    // Defaults to the configured default currency
+    currency: Option<String>,
+    #[validate(range(min = 1))]
+    weight_grams: Option<u64>,
+    #[validate(custom = "shipping::validate_dimensions")]
+    dimensions: Option<Dimensions>,
+}
+
+// Define a payload structure for changing some fields of an accessory. Fields left
+// out are kept as they are; for optional fields Some(None) clears the value.
+#[derive(candid::CandidType, Serialize, Deserialize, Default, Validate)]
+struct AccessoryPatch {
+    #[validate(length(min = 1), custom = "_validate_name")]
+    name: Option<String>,
+    #[validate(length(min = 10), custom = "_validate_description")]
+    description: Option<String>,
+    #[validate(custom = "_validate_category")]
+    category: Option<String>,
+    price: Option<u64>,
+    is_available: Option<bool>,
+    // Recorded in the stock movement log as a correction
+    inventory_count: Option<u64>,
+    reorder_point: Option<Option<u64>>,
+    reorder_quantity: Option<Option<u64>>,
+    #[validate(custom = "barcodes::validate_gtin")]
+    barcode: Option<Option<String>>,
+    #[validate(length(min = 1), custom = "_validate_part_number")]
+    manufacturer_part_number: Option<Option<String>>,
+    currency: Option<String>,
+    #[validate(range(min = 1))]
+    weight_grams: Option<Option<u64>>,
+    #[validate(custom = "shipping::validate_dimensions")]
+    dimensions: Option<Option<Dimensions>>,
+}
+
+fn _validate_name(name: &str) -> Result<(), ValidationError> {
+    _check_text_length(name, MAX_NAME_LENGTH, "name is too long")
+}
+
+fn _validate_description(description: &str) -> Result<(), ValidationError> {
+    _check_text_length(description, MAX_DESCRIPTION_LENGTH, "description is too long")
+}
+
+fn _validate_category(category: &str) -> Result<(), ValidationError> {
+    _check_text_length(category, MAX_CATEGORY_LENGTH, "category is too long")
+}
+
+fn _validate_part_number(part_number: &str) -> Result<(), ValidationError> {
+    _check_text_length(part_number, MAX_PART_NUMBER_LENGTH, "manufacturer part number is too long")
+}
+
+pub(crate) fn _check_text_length(text: &str, max_len: usize, code: &'static str) -> Result<(), ValidationError> {
+    if text.len() > max_len {
+        let mut err = ValidationError::new(code);
+        err.add_param(Cow::from("max"), &max_len);
+        return Err(err);
+    }
+    Ok(())
 }
 
 // Define a structure for recording transaction history
@@ -122,11 +315,33 @@ struct ReviewPayload {
     rating: u8,
     comment: String,
 }
-// Function to insert an accessory into the storage
-fn do_insert_accessory(accessory: &Accessory) {
+// Helper function to cut a text to at most `max_len` bytes on a character boundary
+fn _truncate_text(text: &str, max_len: usize) -> String {
+    let mut end = text.len().min(max_len);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].to_string()
+}
+
+// Function to take the next value from the shared ID counter
+fn next_id() -> u64 {
+    ID_COUNTER
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .expect("cannot increment id counter")
+}
+
+// Function to insert an accessory into the storage, moving it to the next version
+fn do_insert_accessory(accessory: &mut Accessory) {
     ACCESSORY_STORAGE.with(|service| {
+        let stored_version = service.borrow().get(&accessory.id).and_then(|stored| stored.version);
+        accessory.version = Some(stored_version.unwrap_or_default() + 1);
         service.borrow_mut().insert(accessory.id, accessory.clone());
     });
+    reorder::queue_reorder_check(accessory.id);
 }
 
 // Query function to get an accessory by ID
@@ -147,7 +362,7 @@ fn get_accessories_by_category(category: String) -> Vec<Accessory> {
         service
             .borrow()
             .iter()
-            .filter(|(_, accessory)| accessory.category == category)
+            .filter(|(_, accessory)| accessory.deleted_at.is_none() && accessory.category == category)
             .map(|(_, accessory)| accessory.clone())
             .collect()
     })
@@ -160,23 +375,34 @@ fn get_available_accessories() -> Vec<Accessory> {
         service
             .borrow()
             .iter()
-            .filter(|(_, accessory)| accessory.is_available)
+            .filter(|(_, accessory)| accessory.deleted_at.is_none() && accessory.is_available)
             .map(|(_, accessory)| accessory.clone())
             .collect()
     })
 }
 
-// Query function to search for accessories based on a query string
+// Query function to search for accessories based on a query string,
+// optionally ordered by a seller scorecard metric
 #[ic_cdk::query]
-fn search_accessories(query: String) -> Vec<Accessory> {
-    ACCESSORY_STORAGE.with(|service| {
+fn search_accessories(query: String, sort: Option<SellerSort>) -> Vec<Accessory> {
+    let mut results: Vec<Accessory> = ACCESSORY_STORAGE.with(|service| {
         service
             .borrow()
             .iter()
+            .filter(|(_, accessory)| accessory.deleted_at.is_none())
             .filter(|(_, accessory)| accessory.name.contains(&query) || accessory.description.contains(&query))
             .map(|(_, accessory)| accessory.clone())
             .collect()
-    })
+    });
+    if let Some(sort) = sort {
+        let mut keyed: Vec<((bool, f64), Accessory)> = results
+            .into_iter()
+            .map(|accessory| (scorecards::sort_key(accessory.seller, sort), accessory))
+            .collect();
+        keyed.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+        results = keyed.into_iter().map(|(_, accessory)| accessory).collect();
+    }
+    results
 }
 
 // Query function to get transaction history of an accessory by ID
@@ -184,8 +410,9 @@ fn search_accessories(query: String) -> Vec<Accessory> {
 fn get_accessory_transaction_history(id: u64) -> Vec<TransactionRecord> {
     match _get_accessory(&id) {
         Some(accessory) => {
-            let mut history = Vec::new();
-            if let Some(updated_at) = accessory.updated_at {
+            let mut history = audit::transaction_history(id);
+            // Accessories last changed before changes were logged only have their timestamp
+            if let Some(updated_at) = accessory.updated_at.filter(|_| history.is_empty()) {
                 history.push(TransactionRecord {
                     timestamp: updated_at,
                     change_type: "Update".to_string(),
@@ -202,37 +429,37 @@ fn get_accessory_transaction_history(id: u64) -> Vec<TransactionRecord> {
         None => Vec::new(),
     }
 }
-// Update function to adjust the stock level for an accessory
-// Update function to adjust the stock level for an accessory
+// Update function to overwrite the stock level for an accessory
+// The difference is recorded in the stock movement log as a correction
 #[ic_cdk::update]
-fn update_inventory(id: u64, new_inventory_count: u64) -> Result<Accessory, Error> {
-    ACCESSORY_STORAGE.with(|service| {
-        let mut accessories = service.borrow_mut();
-        if let Some(accessory) = accessories.get(&id) {
-            // Clone the accessory to update it, since we can't modify it in place
-            let mut updated_accessory = accessory.clone();
-            updated_accessory.inventory_count = new_inventory_count;
-            
-            // Insert the updated accessory back into the map
-            accessories.insert(id, updated_accessory.clone());
-            Ok(updated_accessory)
-        } else {
-            Err(Error::NotFound {
-                msg: format!("Accessory with id={} not found", id),
-            })
-        }
-    })
+fn update_inventory(id: u64, new_inventory_count: u64, expected_version: Option<u64>) -> Result<Accessory, Error> {
+    rate_limit::_check_rate_limit("update_inventory")?;
+    let accessory = _get_accessory(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("Accessory with id={} not found", id),
+    })?;
+    _check_if_seller_or_staff(&accessory)?;
+    _check_accessory_version(&accessory, expected_version)?;
+    let delta = _inventory_delta(&accessory, new_inventory_count)?;
+    if delta == 0 {
+        return Ok(accessory);
+    }
+    let (accessory, _) = record_stock_movement(
+        id,
+        delta,
+        StockReason::Correction,
+        "inventory count overwritten".to_string(),
+        None,
+    )?;
+    Ok(accessory)
 }
 
-
-
 // Query function to return accessories with low stock levels
 #[ic_cdk::query]
 fn check_inventory_levels(threshold: u64) -> Vec<Accessory> {
     ACCESSORY_STORAGE.with(|service| {
         service.borrow()
             .iter()
-            .filter(|(_, accessory)| accessory.inventory_count <= threshold)
+            .filter(|(_, accessory)| accessory.deleted_at.is_none() && accessory.inventory_count <= threshold)
             .map(|(_, accessory)| accessory.clone())
             .collect()
     })
@@ -240,74 +467,87 @@ fn check_inventory_levels(threshold: u64) -> Vec<Accessory> {
 // Update function to add a new accessory
 #[ic_cdk::update]
 fn add_accessory(accessory_payload: AccessoryPayload) -> Result<Accessory, Error> {
-    let is_payload_valid = _check_input(&accessory_payload);
-    if is_payload_valid.is_err() {
-        return Err(is_payload_valid.unwrap_err())
-    }
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("cannot increment id counter");
-
-    let accessory = Accessory {
-        id,
-        seller: caller().to_string(),
-        name: accessory_payload.name,
-        description: accessory_payload.description,
-        category: accessory_payload.category,
-        price: accessory_payload.price,
-        created_at: time(),
-        updated_at: None,
-        is_available: accessory_payload.is_available,
-        inventory_count: accessory_payload.inventory_count,
-    };
-
-    do_insert_accessory(&accessory);
-    Ok(accessory)
+    rate_limit::_check_rate_limit("add_accessory")?;
+    let accessory = _prepare_new_accessory(accessory_payload)?;
+    Ok(_insert_new_accessory(accessory))
 }
 
 // Update function to update an existing accessory
 #[ic_cdk::update]
-fn update_accessory(id: u64, payload: AccessoryPayload) -> Result<Accessory, Error> {
+fn update_accessory(id: u64, payload: AccessoryPayload, expected_version: Option<u64>) -> Result<Accessory, Error> {
+    rate_limit::_check_rate_limit("update_accessory")?;
+    let (accessory, mut updated) = _prepare_update(id, payload, expected_version)?;
+    _apply_update(&accessory, &mut updated);
+    Ok(updated)
+}
 
-    match ACCESSORY_STORAGE.with(|service| service.borrow_mut().get(&id)) {
-        Some(mut accessory) => {
-            let can_update = _check_if_seller(&accessory);
-            if can_update.is_err(){
-                return Err(can_update.unwrap_err())
-            }
-            let is_payload_valid = _check_input(&payload);
-            if is_payload_valid.is_err() {
-                return Err(is_payload_valid.unwrap_err())
-            }
-            accessory.name = payload.name;
-            accessory.description = payload.description;
-            accessory.category = payload.category;
-            accessory.price = payload.price;
-            accessory.updated_at = Some(time());
-            accessory.is_available = payload.is_available;
-            do_insert_accessory(&accessory);
-            Ok(accessory.clone())
-        }
-        None => Err(Error::NotFound {
-            msg: format!("couldn't update an accessory with id={}. accessory not found", id),
-        }),
+// Update function to change only the supplied fields of an accessory. A new inventory
+// count goes through the stock movement log and the changed fields are audited.
+#[ic_cdk::update]
+fn patch_accessory(id: u64, patch: AccessoryPatch, expected_version: Option<u64>) -> Result<Accessory, Error> {
+    rate_limit::_check_rate_limit("patch_accessory")?;
+    let accessory = _get_accessory(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("couldn't patch an accessory with id={}. accessory not found", id),
+    })?;
+    _check_if_seller(&accessory)?;
+    _check_accessory_version(&accessory, expected_version)?;
+    patch.validate().map_err(|err| Error::ValidationFailed { msg: err.to_string() })?;
+    if let Some(barcode) = &patch.barcode {
+        barcodes::check_barcode_available(barcode, Some(id))?;
+    }
+    let currency = match patch.currency {
+        Some(_) => Some(currency::check_currency(&patch.currency)?),
+        None => None,
+    };
+    let delta = match patch.inventory_count {
+        Some(count) => _inventory_delta(&accessory, count)?,
+        None => 0,
+    };
+
+    // Everything is checked, so the stock movement is the only step left that can fail
+    let mut updated = if delta != 0 {
+        record_stock_movement(id, delta, StockReason::Correction, "inventory count patched".to_string(), None)?.0
+    } else {
+        accessory.clone()
+    };
+    let mut changed = Vec::new();
+    _patch_field(&mut updated.name, patch.name, "name", &mut changed);
+    _patch_field(&mut updated.description, patch.description, "description", &mut changed);
+    _patch_field(&mut updated.category, patch.category, "category", &mut changed);
+    _patch_field(&mut updated.price, patch.price, "price", &mut changed);
+    _patch_field(&mut updated.is_available, patch.is_available, "is_available", &mut changed);
+    _patch_field(&mut updated.reorder_point, patch.reorder_point, "reorder_point", &mut changed);
+    _patch_field(&mut updated.reorder_quantity, patch.reorder_quantity, "reorder_quantity", &mut changed);
+    _patch_field(&mut updated.barcode, patch.barcode, "barcode", &mut changed);
+    _patch_field(
+        &mut updated.manufacturer_part_number,
+        patch.manufacturer_part_number,
+        "manufacturer_part_number",
+        &mut changed,
+    );
+    _patch_field(&mut updated.currency, currency, "currency", &mut changed);
+    _patch_field(&mut updated.weight_grams, patch.weight_grams, "weight_grams", &mut changed);
+    _patch_field(&mut updated.dimensions, patch.dimensions, "dimensions", &mut changed);
+    // The audit entry lists the new inventory count with the other changed fields
+    if !changed.is_empty() {
+        updated.updated_at = Some(time());
+        _apply_update(&accessory, &mut updated);
+    } else if delta != 0 {
+        _record_changes(&accessory, &updated);
     }
+    Ok(updated)
 }
 
 // Update function to toggle an accessory's availability
 #[ic_cdk::update]
-fn toggle_accessory_availability(id: u64) -> Result<Accessory, Error> {
-    match ACCESSORY_STORAGE.with(|service| service.borrow_mut().get(&id)) {
+fn toggle_accessory_availability(id: u64, expected_version: Option<u64>) -> Result<Accessory, Error> {
+    rate_limit::_check_rate_limit("toggle_accessory_availability")?;
+    match _get_accessory(&id) {
         Some(mut accessory) => {
-            let can_toggle = _check_if_seller(&accessory);
-            if can_toggle.is_err(){
-                return Err(can_toggle.unwrap_err())
-            }
+            _check_if_seller(&accessory)?;
+            _check_accessory_version(&accessory, expected_version)?;
             accessory.is_available = !accessory.is_available;
-            do_insert_accessory(&accessory);
+            do_insert_accessory(&mut accessory);
             Ok(accessory.clone())
         }
         None => Err(Error::NotFound {
@@ -322,17 +562,50 @@ fn do_insert_review(review: &Review) {
     REVIEW_STORAGE.with(|service| {
         service.borrow_mut().insert(review.id, review.clone());
     });
+    REVIEW_INDEX.with(|index| index.borrow_mut().insert((review.accessory_id, review.id), ()));
+}
+
+// Ids of the reviews of an accessory, oldest first
+fn _review_ids(accessory_id: u64) -> Vec<u64> {
+    REVIEW_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((accessory_id, 0)..=(accessory_id, u64::MAX))
+            .map(|((_, review_id), _)| review_id)
+            .collect()
+    })
+}
+
+// Index the reviews stored before the index existed. Run once by a migration.
+fn index_reviews() {
+    let keys: Vec<(u64, u64)> = REVIEW_STORAGE
+        .with(|service| service.borrow().iter().map(|(id, review)| (review.accessory_id, id)).collect());
+    REVIEW_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for key in keys {
+            index.insert(key, ());
+        }
+    });
 }
 
 // Update function to add a new review
 #[ic_cdk::update]
 fn add_review(review_payload: ReviewPayload) -> Result<Review, Error> {
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("cannot increment id counter");
+    rate_limit::_check_rate_limit("add_review")?;
+    let accessory = _get_accessory(&review_payload.accessory_id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", review_payload.accessory_id),
+    })?;
+    if !(1..=5).contains(&review_payload.rating) {
+        return Err(Error::ValidationFailed {
+            msg: "a rating must be between 1 and 5".to_string(),
+        });
+    }
+    if review_payload.comment.len() > MAX_REVIEW_COMMENT_LENGTH {
+        return Err(Error::ValidationFailed {
+            msg: format!("a review comment must be at most {} bytes long", MAX_REVIEW_COMMENT_LENGTH),
+        });
+    }
+    let id = next_id();
 
     let review = Review {
         id,
@@ -344,48 +617,28 @@ fn add_review(review_payload: ReviewPayload) -> Result<Review, Error> {
     };
 
     do_insert_review(&review);
+    scorecards::record_rating(accessory.seller, review.rating);
     Ok(review)
 }
 // Query function to get all reviews for a specific accessory
 #[ic_cdk::query]
 fn get_reviews(accessory_id: u64) -> Vec<Review> {
+    if _get_accessory(&accessory_id).is_none() {
+        return Vec::new();
+    }
     REVIEW_STORAGE.with(|service| {
-        service.borrow()
-            .iter()
-            .filter(|(_, review)| review.accessory_id == accessory_id)
-            .map(|(_, review)| review.clone())
-            .collect()
+        let service = service.borrow();
+        _review_ids(accessory_id).iter().filter_map(|id| service.get(id)).collect()
     })
 }
-// Update function to delete an accessory
-#[ic_cdk::update]
-fn delete_accessory(id: u64) -> Result<Accessory, Error> {
-    let accessory = _get_accessory(&id);
-    if accessory.is_none() {
-        return Err(Error::NotFound { msg: format!("an accessory with id={} not found", id) })
-    }
-    let can_toggle = _check_if_seller(&accessory.unwrap());
-    if can_toggle.is_err(){
-        return Err(can_toggle.unwrap_err())
-    }
-    match ACCESSORY_STORAGE.with(|service| service.borrow_mut().remove(&id)) {
-        Some(accessory) => Ok(accessory)
-        ,
-        None => Err(Error::NotFound {
-            msg: format!("couldn't delete an accessory with id={}. accessory not found.", id),
-        }),
-    }
-}
-
-// Update function to bulk update accessories
+// Update function to move an accessory to the trash. It can be restored until
+// the trash is purged, see the trash module.
 #[ic_cdk::update]
-fn bulk_update_accessories(updates: Vec<(u64, AccessoryPayload)>) -> Vec<Result<Accessory, Error>> {
-    let mut results = Vec::new();
-    for (id, payload) in updates {
-        let result = update_accessory(id, payload);
-        results.push(result);
-    }
-    results
+fn delete_accessory(id: u64, expected_version: Option<u64>) -> Result<Accessory, Error> {
+    rate_limit::_check_rate_limit("delete_accessory")?;
+    let mut deleted = _prepare_delete(id, expected_version)?;
+    _apply_delete(&mut deleted);
+    Ok(deleted)
 }
 
 // Enum to represent possible error types
@@ -393,46 +646,358 @@ fn bulk_update_accessories(updates: Vec<(u64, AccessoryPayload)>) -> Vec<Result<
 enum Error {
     NotFound { msg: String },
     ValidationFailed {msg: String},
-    AuthenticationFailed {msg: String}
+    AuthenticationFailed {msg: String},
+    PaymentFailed { msg: String },
+    // retry_after is in nanoseconds
+    RateLimited { msg: String, retry_after: u64 },
+    // The record was changed since the caller read it
+    Conflict { msg: String, current_version: u64 },
 }
 
-// Internal function to get an accessory by ID
+// Internal function to get an accessory by ID; accessories in the trash aren't returned
 fn _get_accessory(id: &u64) -> Option<Accessory> {
     let accessory_storage = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)));
     StableBTreeMap::<u64, Accessory, Memory>::init(accessory_storage)
         .borrow()
         .get(id)
+        .filter(|accessory| accessory.deleted_at.is_none())
 }
 
-// Query function to get the price of an accessory by ID
+// Query function to get the price of an accessory by ID, including any active sale
 #[ic_cdk::query]
 fn get_accessory_price(id: u64) -> Result<u64, Error> {
     match _get_accessory(&id) {
-        Some(accessory) => Ok(accessory.price),
+        Some(accessory) => Ok(pricing::effective_price(&accessory)),
         None => Err(Error::NotFound {
             msg: format!("an accessory with id={} not found", id),
         }),
     }
 }
 
+// Check a new accessory and build it without storing it; the id is assigned on insert
+fn _prepare_new_accessory(accessory_payload: AccessoryPayload) -> Result<Accessory, Error> {
+    sellers::_check_can_sell(&caller())?;
+    _check_input(&accessory_payload)?;
+    barcodes::check_barcode_available(&accessory_payload.barcode, None)?;
+    let currency = currency::check_currency(&accessory_payload.currency)?;
+    Ok(Accessory {
+        id: 0,
+        seller: caller(),
+        name: accessory_payload.name,
+        description: accessory_payload.description,
+        category: accessory_payload.category,
+        price: accessory_payload.price,
+        created_at: time(),
+        updated_at: None,
+        is_available: accessory_payload.is_available,
+        inventory_count: accessory_payload.inventory_count,
+        reorder_point: accessory_payload.reorder_point,
+        reorder_quantity: accessory_payload.reorder_quantity,
+        barcode: accessory_payload.barcode,
+        manufacturer_part_number: accessory_payload.manufacturer_part_number,
+        currency,
+        weight_grams: accessory_payload.weight_grams,
+        dimensions: accessory_payload.dimensions,
+        deleted_at: None,
+        deleted_by: None,
+        version: None,
+    })
+}
+
+fn _insert_new_accessory(mut accessory: Accessory) -> Accessory {
+    accessory.id = next_id();
+    do_insert_accessory(&mut accessory);
+    barcodes::update_barcode_index(&None, &accessory.barcode, accessory.id);
+    sellers::index_listing(&accessory);
+    accessory
+}
+
+// Check an update and return the accessory before and after it, without storing anything
+fn _prepare_update(
+    id: u64,
+    payload: AccessoryPayload,
+    expected_version: Option<u64>,
+) -> Result<(Accessory, Accessory), Error> {
+    let accessory = _get_accessory(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("couldn't update an accessory with id={}. accessory not found", id),
+    })?;
+    _check_if_seller(&accessory)?;
+    _check_accessory_version(&accessory, expected_version)?;
+    _check_input(&payload)?;
+    barcodes::check_barcode_available(&payload.barcode, Some(id))?;
+    let mut updated = accessory.clone();
+    if payload.currency.is_some() {
+        updated.currency = currency::check_currency(&payload.currency)?;
+    }
+    updated.name = payload.name;
+    updated.description = payload.description;
+    updated.category = payload.category;
+    updated.price = payload.price;
+    updated.updated_at = Some(time());
+    updated.is_available = payload.is_available;
+    updated.reorder_point = payload.reorder_point;
+    updated.reorder_quantity = payload.reorder_quantity;
+    updated.barcode = payload.barcode;
+    updated.manufacturer_part_number = payload.manufacturer_part_number;
+    updated.weight_grams = payload.weight_grams;
+    updated.dimensions = payload.dimensions;
+    Ok((accessory, updated))
+}
+
+fn _apply_update(accessory: &Accessory, updated: &mut Accessory) {
+    barcodes::update_barcode_index(&accessory.barcode, &updated.barcode, accessory.id);
+    if updated.price != accessory.price {
+        pricing::record_price_change(accessory, accessory.price, updated.price, PriceChangeReason::Manual, caller());
+    }
+    do_insert_accessory(updated);
+    _record_changes(accessory, updated);
+}
+
+// Log the fields that changed between two versions of an accessory in the audit log
+fn _record_changes(before: &Accessory, after: &Accessory) {
+    let changed = _changed_fields(before, after);
+    if !changed.is_empty() {
+        audit::record(AuditAction::AccessoryUpdated, Some(after.id), format!("changed {}", changed.join(", ")));
+    }
+}
+
+fn _changed_fields(before: &Accessory, after: &Accessory) -> Vec<&'static str> {
+    let fields = [
+        ("inventory_count", before.inventory_count != after.inventory_count),
+        ("name", before.name != after.name),
+        ("description", before.description != after.description),
+        ("category", before.category != after.category),
+        ("price", before.price != after.price),
+        ("is_available", before.is_available != after.is_available),
+        ("reorder_point", before.reorder_point != after.reorder_point),
+        ("reorder_quantity", before.reorder_quantity != after.reorder_quantity),
+        ("barcode", before.barcode != after.barcode),
+        ("manufacturer_part_number", before.manufacturer_part_number != after.manufacturer_part_number),
+        ("currency", before.currency != after.currency),
+        ("weight_grams", before.weight_grams != after.weight_grams),
+        ("dimensions", before.dimensions != after.dimensions),
+    ];
+    fields.into_iter().filter(|(_, changed)| *changed).map(|(name, _)| name).collect()
+}
+
+// Set a field to the patched value, noting its name if the value changed
+fn _patch_field<T: PartialEq>(field: &mut T, value: Option<T>, name: &'static str, changed: &mut Vec<&'static str>) {
+    if let Some(value) = value {
+        if *field != value {
+            *field = value;
+            changed.push(name);
+        }
+    }
+}
+
+// Helper function to compute the stock movement that brings an accessory to a new inventory count
+fn _inventory_delta(accessory: &Accessory, new_inventory_count: u64) -> Result<i64, Error> {
+    i64::try_from(i128::from(new_inventory_count) - i128::from(accessory.inventory_count)).map_err(|_| {
+        Error::ValidationFailed {
+            msg: format!("inventory change for accessory with id={} is too large", accessory.id),
+        }
+    })
+}
+
+// Check a deletion and return the accessory as it will be in the trash, without storing it
+fn _prepare_delete(id: u64, expected_version: Option<u64>) -> Result<Accessory, Error> {
+    let mut accessory = _get_accessory(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", id),
+    })?;
+    _check_if_seller(&accessory)?;
+    _check_accessory_version(&accessory, expected_version)?;
+    accessory.deleted_at = Some(time());
+    accessory.deleted_by = Some(caller());
+    Ok(accessory)
+}
+
+fn _apply_delete(deleted: &mut Accessory) {
+    do_insert_accessory(deleted);
+    sellers::unindex_listing(deleted);
+    audit::record(AuditAction::AccessoryDeleted, Some(deleted.id), "moved to the trash".to_string());
+}
+
 // Helper function to check the input data of the payload
 fn _check_input(payload: &AccessoryPayload) -> Result<(), Error> {
     let check_payload = payload.validate();
-    if check_payload.is_err() {
-        return Err(Error:: ValidationFailed{ msg: check_payload.err().unwrap().to_string()})
+    if let Err(err) = check_payload {
+        Err(Error:: ValidationFailed{ msg: err.to_string()})
     }else{
         Ok(())
     }
 }
 
+// Helper function to check that a record is still at the version the caller last read.
+// Callers that don't pass an expected version aren't checked.
+fn _check_version(record: &str, current_version: Option<u64>, expected_version: Option<u64>) -> Result<(), Error> {
+    let current_version = current_version.unwrap_or_default();
+    match expected_version {
+        Some(expected) if expected != current_version => Err(Error::Conflict {
+            msg: format!("{} was changed since version {}", record, expected),
+            current_version,
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn _check_accessory_version(accessory: &Accessory, expected_version: Option<u64>) -> Result<(), Error> {
+    _check_version(&format!("accessory with id={}", accessory.id), accessory.version, expected_version)
+}
+
 // Helper function to check whether the caller is the seller of a accessory
 fn _check_if_seller(accessory: &Accessory) -> Result<(), Error> {
-    if accessory.seller.to_string() != caller().to_string(){
-        return Err(Error:: AuthenticationFailed{ msg: format!("Caller={} isn't the seller of the accessory with id={}", caller(), accessory.id) })  
+    if accessory.seller != caller() {
+        Err(Error:: AuthenticationFailed{ msg: format!("Caller={} isn't the seller of the accessory with id={}", caller(), accessory.id) })
     }else{
         Ok(())
     }
 }
 
+// Helper function to check whether the caller is the seller of an accessory or a staff member
+fn _check_if_seller_or_staff(accessory: &Accessory) -> Result<(), Error> {
+    if _check_if_seller(accessory).is_ok() || roles::_is_staff(&caller()) {
+        Ok(())
+    } else {
+        Err(Error::AuthenticationFailed {
+            msg: format!(
+                "Caller={} is neither the seller nor staff for the accessory with id={}",
+                caller(),
+                accessory.id
+            ),
+        })
+    }
+}
+
+#[ic_cdk::init]
+fn init() {
+    migrations::set_schema_version(migrations::SCHEMA_VERSION);
+    scheduler::start();
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    if let Err(err) = migrations::migrate() {
+        ic_cdk::trap(&format!("migration failed: {}", err));
+    }
+    scheduler::start();
+}
+
 // Export the canister interface definition
 ic_cdk::export_candid!();
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // An accessory with every field and text at its limit
+    pub(crate) fn largest_accessory() -> Accessory {
+        let principal = Principal::from_slice(&[0xff; 29]);
+        Accessory {
+            id: u64::MAX,
+            seller: principal,
+            name: "n".repeat(MAX_NAME_LENGTH),
+            description: "d".repeat(MAX_DESCRIPTION_LENGTH),
+            category: "c".repeat(MAX_CATEGORY_LENGTH),
+            price: u64::MAX,
+            created_at: u64::MAX,
+            updated_at: Some(u64::MAX),
+            is_available: true,
+            inventory_count: u64::MAX,
+            reorder_point: Some(u64::MAX),
+            reorder_quantity: Some(u64::MAX),
+            barcode: Some("1".repeat(14)),
+            manufacturer_part_number: Some("p".repeat(MAX_PART_NUMBER_LENGTH)),
+            currency: Some("C".repeat(8)),
+            weight_grams: Some(u64::MAX),
+            dimensions: Some(Dimensions {
+                length_mm: u64::MAX,
+                width_mm: u64::MAX,
+                height_mm: u64::MAX,
+            }),
+            deleted_at: Some(u64::MAX),
+            deleted_by: Some(principal),
+            version: Some(u64::MAX),
+        }
+    }
+
+    #[test]
+    fn largest_accessory_fits_its_storage_bound() {
+        assert!(largest_accessory().to_bytes().len() <= Accessory::MAX_SIZE as usize);
+    }
+
+    #[test]
+    fn changed_fields_are_listed_for_the_history() {
+        let before = largest_accessory();
+        let after = Accessory {
+            inventory_count: 1,
+            price: 2,
+            dimensions: None,
+            ..before.clone()
+        };
+        assert_eq!(_changed_fields(&before, &after), vec!["inventory_count", "price", "dimensions"]);
+        assert!(_changed_fields(&before, &before).is_empty());
+    }
+
+    #[test]
+    fn inventory_delta_is_the_signed_difference() {
+        let accessory = Accessory {
+            inventory_count: 10,
+            ..largest_accessory()
+        };
+        assert_eq!(_inventory_delta(&accessory, 15).ok(), Some(5));
+        assert_eq!(_inventory_delta(&accessory, 0).ok(), Some(-10));
+        assert!(_inventory_delta(&largest_accessory(), 0).is_err());
+    }
+
+    #[test]
+    fn versions_are_only_checked_when_expected() {
+        assert!(_check_version("record", Some(3), None).is_ok());
+        assert!(_check_version("record", Some(3), Some(3)).is_ok());
+        assert!(_check_version("record", None, Some(0)).is_ok());
+        assert!(matches!(
+            _check_version("record", Some(3), Some(2)),
+            Err(Error::Conflict { current_version: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn reviews_are_listed_per_accessory() {
+        for (id, accessory_id) in [(1, 10), (2, 11), (3, 10)] {
+            do_insert_review(&Review {
+                id,
+                accessory_id,
+                ..Default::default()
+            });
+        }
+        assert_eq!(_review_ids(10), vec![1, 3]);
+        assert_eq!(_review_ids(11), vec![2]);
+    }
+
+    #[test]
+    fn largest_review_fits_its_storage_bound() {
+        let review = Review {
+            id: u64::MAX,
+            accessory_id: u64::MAX,
+            user_id: u64::MAX,
+            rating: 5,
+            comment: "c".repeat(MAX_REVIEW_COMMENT_LENGTH),
+            created_at: u64::MAX,
+        };
+        assert!(review.to_bytes().len() <= Review::MAX_SIZE as usize);
+    }
+
+    #[test]
+    fn text_limits_count_bytes() {
+        let payload = AccessoryPatch {
+            name: Some("\u{e9}".repeat(MAX_NAME_LENGTH / 2 + 1)),
+            ..Default::default()
+        };
+        assert!(payload.validate().is_err());
+        let payload = AccessoryPatch {
+            name: Some("\u{e9}".repeat(MAX_NAME_LENGTH / 2)),
+            category: Some("c".repeat(MAX_CATEGORY_LENGTH)),
+            ..Default::default()
+        };
+        assert!(payload.validate().is_ok());
+    }
+}
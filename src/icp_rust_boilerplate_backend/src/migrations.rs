@@ -0,0 +1,209 @@
+// Schema migrations of stored records, run after every upgrade
+use crate::shipping::Dimensions;
+use crate::{Accessory, Memory, MEMORY_MANAGER};
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use serde::de::DeserializeOwned;
+use std::{borrow::Cow, cell::RefCell};
+
+// Schema version the code expects; bump it when adding a migration step
+pub(crate) const SCHEMA_VERSION: u64 = 2;
+
+thread_local! {
+    static STORED_SCHEMA_VERSION: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(45))), 0)
+            .expect("Cannot create the schema version cell")
+    );
+}
+
+// A stored record as raw bytes, so records the current types can't decode can be read.
+// MAX must be the MAX_SIZE of the record type the map was created with.
+struct RawRecord<const MAX: u32>(Vec<u8>);
+
+impl<const MAX: u32> Storable for RawRecord<MAX> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        RawRecord(bytes.into_owned())
+    }
+}
+
+impl<const MAX: u32> BoundedStorable for RawRecord<MAX> {
+    const MAX_SIZE: u32 = MAX;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Accessory as stored before version 1, with the seller in its text form
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyAccessory {
+    id: u64,
+    seller: String,
+    name: String,
+    description: String,
+    category: String,
+    price: u64,
+    created_at: u64,
+    updated_at: Option<u64>,
+    is_available: bool,
+    inventory_count: u64,
+    reorder_point: Option<u64>,
+    reorder_quantity: Option<u64>,
+    barcode: Option<String>,
+    manufacturer_part_number: Option<String>,
+    currency: Option<String>,
+    weight_grams: Option<u64>,
+    dimensions: Option<Dimensions>,
+}
+
+// Bring the stored records up to the current schema version. Steps that rewrite
+// records in place run before any of the maps they touch is opened.
+pub(crate) fn migrate() -> Result<(), String> {
+    let version = STORED_SCHEMA_VERSION.with(|cell| *cell.borrow().get());
+    // Version 1 stores the accessory seller as a principal and indexes listings by seller
+    if version < 1 {
+        rewrite_records::<u64, Accessory, LegacyAccessory, { Accessory::MAX_SIZE }>(1, _upgrade_accessory)?;
+        crate::sellers::rebuild_listing_index();
+    }
+    // Version 2 indexes reviews by accessory
+    if version < 2 {
+        crate::index_reviews();
+    }
+    if version != SCHEMA_VERSION {
+        set_schema_version(SCHEMA_VERSION);
+    }
+    Ok(())
+}
+
+// Record the schema version of the stored records. A fresh install starts at the
+// current version, so its first upgrade doesn't run any migration.
+pub(crate) fn set_schema_version(version: u64) {
+    STORED_SCHEMA_VERSION
+        .with(|cell| cell.borrow_mut().set(version))
+        .expect("cannot update the schema version");
+}
+
+// Rewrite every record of the map in `memory_id` that isn't stored as a `T`, reading it
+// as its legacy form `L` and converting it with `upgrade`. The map must not be open
+// elsewhere while this runs, as rewriting records can move its root node.
+pub(crate) fn rewrite_records<K, T, L, const MAX: u32>(
+    memory_id: u8,
+    upgrade: impl Fn(L) -> Result<T, String>,
+) -> Result<(), String>
+where
+    K: BoundedStorable + Ord + Clone,
+    T: BoundedStorable + CandidType + DeserializeOwned,
+    L: CandidType + DeserializeOwned,
+{
+    let memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(memory_id)));
+    let mut records: StableBTreeMap<K, RawRecord<MAX>, Memory> = StableBTreeMap::init(memory);
+    let legacy: Vec<(K, RawRecord<MAX>)> = records
+        .iter()
+        .filter(|(_, record)| Decode!(&record.0, T).is_err())
+        .collect();
+    for (key, record) in legacy {
+        let legacy =
+            Decode!(&record.0, L).map_err(|err| format!("undecodable record in memory {}: {}", memory_id, err))?;
+        let bytes = Encode!(&upgrade(legacy)?)
+            .map_err(|err| format!("cannot encode record in memory {}: {}", memory_id, err))?;
+        records.insert(key, RawRecord(bytes));
+    }
+    Ok(())
+}
+
+// Parse a principal stored in its text form
+pub(crate) fn parse_principal(text: &str) -> Result<Principal, String> {
+    Principal::from_text(text).map_err(|err| format!("invalid principal {}: {}", text, err))
+}
+
+// Convert an accessory written before version 1
+fn _upgrade_accessory(legacy: LegacyAccessory) -> Result<Accessory, String> {
+    Ok(Accessory {
+        id: legacy.id,
+        seller: parse_principal(&legacy.seller)?,
+        name: legacy.name,
+        description: legacy.description,
+        category: legacy.category,
+        price: legacy.price,
+        created_at: legacy.created_at,
+        updated_at: legacy.updated_at,
+        is_available: legacy.is_available,
+        inventory_count: legacy.inventory_count,
+        reorder_point: legacy.reorder_point,
+        reorder_quantity: legacy.reorder_quantity,
+        barcode: legacy.barcode,
+        manufacturer_part_number: legacy.manufacturer_part_number,
+        currency: legacy.currency,
+        weight_grams: legacy.weight_grams,
+        dimensions: legacy.dimensions,
+        deleted_at: None,
+        deleted_by: None,
+        version: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Memory not used by the canister, so the tests don't touch any of its maps
+    const TEST_MEMORY: u8 = 250;
+
+    fn legacy_accessory(id: u64, seller: &str) -> LegacyAccessory {
+        LegacyAccessory {
+            id,
+            seller: seller.to_string(),
+            name: "cable".to_string(),
+            description: "usb-c".to_string(),
+            category: "cables".to_string(),
+            price: 1_000,
+            created_at: 1,
+            updated_at: None,
+            is_available: true,
+            inventory_count: 5,
+            reorder_point: None,
+            reorder_quantity: None,
+            barcode: None,
+            manufacturer_part_number: None,
+            currency: None,
+            weight_grams: None,
+            dimensions: None,
+        }
+    }
+
+    fn raw_map() -> StableBTreeMap<u64, RawRecord<{ Accessory::MAX_SIZE }>, Memory> {
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(TEST_MEMORY))))
+    }
+
+    #[test]
+    fn legacy_records_are_rewritten_and_current_ones_kept() {
+        let seller = Principal::from_slice(&[1; 29]);
+        let mut current = _upgrade_accessory(legacy_accessory(2, &seller.to_text())).unwrap();
+        current.name = "current".to_string();
+        {
+            let mut map = raw_map();
+            map.insert(1, RawRecord(Encode!(&legacy_accessory(1, &seller.to_text())).unwrap()));
+            map.insert(2, RawRecord(Encode!(&current).unwrap()));
+        }
+        rewrite_records::<u64, Accessory, LegacyAccessory, { Accessory::MAX_SIZE }>(TEST_MEMORY, _upgrade_accessory)
+            .unwrap();
+
+        let map = raw_map();
+        let first = Decode!(&map.get(&1).unwrap().0, Accessory).unwrap();
+        assert_eq!((first.id, first.seller), (1, seller));
+        let second = Decode!(&map.get(&2).unwrap().0, Accessory).unwrap();
+        assert_eq!(second.name, "current");
+    }
+
+    #[test]
+    fn invalid_principals_fail_the_migration() {
+        raw_map().insert(3, RawRecord(Encode!(&legacy_accessory(3, "not a principal")).unwrap()));
+        let result = rewrite_records::<u64, Accessory, LegacyAccessory, { Accessory::MAX_SIZE }>(
+            TEST_MEMORY,
+            _upgrade_accessory,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,816 @@
+// Orders placed by buyers against a seller's listings
+use crate::currency::{self, Money, RoundingMode};
+use crate::inventory::{self, record_sale, record_stock_movement, StockReason};
+use crate::payments::{LedgerTransfer, TransferOutcome};
+use crate::shipping::ShippingCharge;
+use crate::taxes::LineTax;
+use crate::{
+    _check_version, _get_accessory, _truncate_text, batches, invoices, next_id, payments, pricing, promotions,
+    rate_limit, returns, roles, scorecards, serials, shipping, taxes, Error, Memory, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Maximum number of lines on an order and of serialized units it can allocate.
+// Together with the name limit they keep the largest order within Order::MAX_SIZE.
+const MAX_ORDER_LINES: usize = 10;
+const MAX_ORDER_SERIALS: usize = 20;
+// Longest accessory name copied onto an order line
+pub(crate) const MAX_LINE_NAME_LENGTH: usize = 100;
+
+// Define the lifecycle of an order
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum OrderStatus {
+    Placed,
+    Shipped,
+    Delivered,
+    Cancelled,
+}
+
+// Define a single line of an order
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct OrderLine {
+    pub(crate) accessory_id: u64,
+    pub(crate) name: String,
+    pub(crate) quantity: u64,
+    pub(crate) unit_price: u64,
+    // Serial numbers allocated to this line, empty for accessories without serial tracking
+    pub(crate) serials: Vec<String>,
+    pub(crate) discounts: Option<Vec<AppliedDiscount>>,
+    // unit_price * quantity minus the discounts
+    pub(crate) line_total: Option<u64>,
+    pub(crate) tax: Option<LineTax>,
+    // Stock movement that sold the line, reversed when the order is cancelled
+    pub(crate) sale_id: Option<u64>,
+}
+
+// Define a discount applied to an order line
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AppliedDiscount {
+    pub(crate) code: String,
+    pub(crate) description: String,
+    pub(crate) amount: u64,
+}
+
+// Define the priced lines of an order before it is placed
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PriceQuote {
+    lines: Vec<OrderLine>,
+    subtotal: u64,
+    discount_total: u64,
+    // Inclusive and exclusive tax; only exclusive tax is added to the total
+    tax_total: u64,
+    shipping: Option<ShippingCharge>,
+    total: u64,
+    // Currency of the amounts above
+    currency: Option<String>,
+    jurisdiction: Option<String>,
+    // Coupons that were applied, normalised
+    coupon_codes: Vec<String>,
+    // The amounts in the currency the quote was requested in
+    converted: Option<ConvertedAmounts>,
+}
+
+// Define the amounts of a quote converted to another currency
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ConvertedAmounts {
+    subtotal: Money,
+    discount_total: Money,
+    tax_total: Money,
+    shipping_total: Money,
+    total: Money,
+    rounding: RoundingMode,
+}
+
+// Define the structure representing an order
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Order {
+    pub(crate) id: u64,
+    pub(crate) buyer: Principal,
+    pub(crate) seller: Principal,
+    pub(crate) lines: Vec<OrderLine>,
+    pub(crate) subtotal: Option<u64>,
+    pub(crate) discount_total: Option<u64>,
+    pub(crate) tax_total: Option<u64>,
+    pub(crate) shipping: Option<ShippingCharge>,
+    // Amount due after discounts, including tax and shipping
+    pub(crate) total: u64,
+    pub(crate) currency: Option<String>,
+    pub(crate) jurisdiction: Option<String>,
+    // Sum of all refunds issued against the order
+    pub(crate) refunded_amount: Option<u64>,
+    pub(crate) status: OrderStatus,
+    pub(crate) created_at: u64,
+    pub(crate) updated_at: Option<u64>,
+    // Tokens collected from the buyer; pending while its block_index is None
+    pub(crate) payment: Option<LedgerTransfer>,
+    // Ledger units of the payment refunded so far
+    pub(crate) refunded_units: Option<u64>,
+    // Refund of the whole payment when a paid order is cancelled; pending while its block_index is None
+    pub(crate) cancellation_refund: Option<LedgerTransfer>,
+    // Incremented on every change
+    pub(crate) version: Option<u64>,
+}
+
+// Implement the Storable trait for Order
+impl Storable for Order {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for Order
+impl BoundedStorable for Order {
+    const MAX_SIZE: u32 = 8192;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define a payload structure for an order line
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct OrderLinePayload {
+    accessory_id: u64,
+    quantity: u64,
+    // Specific serials to sell; when empty, units in stock are picked automatically
+    serials: Vec<String>,
+}
+
+// Define a payload structure for placing an order
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct OrderPayload {
+    lines: Vec<OrderLinePayload>,
+    coupon_codes: Option<Vec<String>>,
+    // Where the order is taxed, e.g. DE or US-CA; defaults to the admin's default jurisdiction
+    jurisdiction: Option<String>,
+    // One of the seller's shipping methods; orders without one are collected
+    shipping_method_id: Option<u64>,
+}
+
+thread_local! {
+    static ORDER_STORAGE: RefCell<StableBTreeMap<u64, Order, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18))))
+    );
+}
+
+// Update function to place an order for one seller's accessories.
+// Everything is validated before any stock is taken, so an order either goes
+// through completely or not at all.
+#[ic_cdk::update]
+fn place_order(payload: OrderPayload) -> Result<Order, Error> {
+    rate_limit::_check_rate_limit("place_order")?;
+    let buyer = caller();
+    let (seller, quote) = _prepare_order(payload, buyer)?;
+    let mut order = Order {
+        id: next_id(),
+        buyer,
+        seller,
+        lines: quote.lines,
+        subtotal: Some(quote.subtotal),
+        discount_total: Some(quote.discount_total),
+        tax_total: Some(quote.tax_total),
+        shipping: quote.shipping,
+        total: quote.total,
+        currency: quote.currency,
+        jurisdiction: quote.jurisdiction,
+        refunded_amount: None,
+        status: OrderStatus::Placed,
+        created_at: time(),
+        updated_at: None,
+        payment: None,
+        refunded_units: None,
+        cancellation_refund: None,
+        version: None,
+    };
+    for line in &mut order.lines {
+        let sale_id = if line.serials.is_empty() {
+            record_sale(
+                line.accessory_id,
+                line.quantity,
+                format!("order #{}", order.id),
+                _line_revenue(line),
+            )?
+            .1
+            .id
+        } else {
+            serials::sell_serials(
+                line.accessory_id,
+                &line.serials,
+                order.id,
+                order.buyer,
+                _line_revenue(line),
+            )?
+        };
+        line.sale_id = Some(sale_id);
+    }
+    promotions::record_coupon_usage(&quote.coupon_codes, buyer);
+    do_insert_order(&mut order);
+    scorecards::record_order_placed(order.seller);
+    Ok(order)
+}
+
+// Update function for the buyer to pay an order in tokens. The canister collects the total,
+// converted to ledger units, using an allowance the buyer approved on the payment ledger.
+// When the ledger's answer is lost the payment stays pending and calling again retries it.
+#[ic_cdk::update]
+async fn pay_order(id: u64) -> Result<Order, Error> {
+    rate_limit::_check_rate_limit("pay_order")?;
+    let mut order = _get_order(&id)?;
+    if order.buyer != caller() {
+        return Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} isn't the buyer of order with id={}", caller(), order.id),
+        });
+    }
+    if order.status == OrderStatus::Cancelled {
+        return Err(Error::ValidationFailed {
+            msg: format!("order with id={} was cancelled", order.id),
+        });
+    }
+    let payment = match order.payment.clone() {
+        Some(payment) if payment.block_index.is_some() => {
+            return Err(Error::ValidationFailed {
+                msg: format!("order with id={} is already paid", order.id),
+            })
+        }
+        Some(pending) => pending,
+        None => {
+            // Recorded before calling the ledger so the order can't be cancelled or paid
+            // twice while the transfer is in flight
+            let payment = payments::new_payment(
+                order.buyer,
+                order.total,
+                &order.currency,
+                payments::memo(b"order", order.id),
+            )?;
+            order.payment = Some(payment.clone());
+            order.updated_at = Some(time());
+            do_insert_order(&mut order);
+            payment
+        }
+    };
+
+    let outcome = payments::collect(&payment).await;
+    let mut order = _get_order(&id)?;
+    match outcome {
+        TransferOutcome::Completed(block_index) => {
+            order.payment = Some(LedgerTransfer {
+                block_index: Some(block_index),
+                ..payment
+            });
+            order.updated_at = Some(time());
+            do_insert_order(&mut order);
+            invoices::invoice_order(&order);
+            Ok(order)
+        }
+        TransferOutcome::Failed(err) => {
+            order.payment = None;
+            order.updated_at = Some(time());
+            do_insert_order(&mut order);
+            Err(err)
+        }
+        TransferOutcome::Unknown(err) => Err(err),
+    }
+}
+
+// Update function to cancel an order that hasn't shipped yet (buyer, seller or staff only).
+// The items go back into stock and their sales are reversed. A paid order is refunded in
+// full; when the ledger's answer to the refund is lost, calling again retries it.
+#[ic_cdk::update]
+async fn cancel_order(id: u64, expected_version: Option<u64>) -> Result<Order, Error> {
+    rate_limit::_check_rate_limit("cancel_order")?;
+    let mut order = _get_order(&id)?;
+    _check_order_access(&order)?;
+    if order.status == OrderStatus::Cancelled
+        && order
+            .cancellation_refund
+            .as_ref()
+            .is_some_and(|refund| refund.block_index.is_none())
+    {
+        return _send_cancellation_refund(order).await;
+    }
+    _check_version(&format!("order with id={}", id), order.version, expected_version)?;
+    if order.status != OrderStatus::Placed {
+        return Err(Error::ValidationFailed {
+            msg: format!("order with id={} can't be cancelled after it was shipped", id),
+        });
+    }
+    if order
+        .payment
+        .as_ref()
+        .is_some_and(|payment| payment.block_index.is_none())
+    {
+        return Err(Error::PaymentFailed {
+            msg: format!(
+                "the payment of order with id={} is still pending, call pay_order to settle it",
+                id
+            ),
+        });
+    }
+    if returns::has_returns(order.id) {
+        return Err(Error::ValidationFailed {
+            msg: format!("order with id={} has returns, settle them instead of cancelling", id),
+        });
+    }
+    if let Some(line) = order
+        .lines
+        .iter()
+        .find(|line| _get_accessory(&line.accessory_id).is_none())
+    {
+        return Err(Error::NotFound {
+            msg: format!("an accessory with id={} not found", line.accessory_id),
+        });
+    }
+    let reference = format!("cancelled order #{}", order.id);
+    for line in &order.lines {
+        match (line.sale_id, line.serials.is_empty()) {
+            (Some(sale_id), true) => {
+                inventory::reverse_sale(line.accessory_id, sale_id, line.quantity, reference.clone())?;
+            }
+            (Some(sale_id), false) => {
+                serials::reverse_serial_sale(line.accessory_id, &line.serials, sale_id, &reference)?
+            }
+            // Orders placed before sales were linked to their lines go back as returns
+            (None, true) => {
+                record_stock_movement(
+                    line.accessory_id,
+                    line.quantity as i64,
+                    StockReason::Returned,
+                    reference.clone(),
+                    None,
+                )?;
+            }
+            (None, false) => serials::return_serials(line.accessory_id, &line.serials, true, &reference)?,
+        }
+    }
+    promotions::release_coupon_usage(&_coupon_codes(&order), order.buyer);
+    order.status = OrderStatus::Cancelled;
+    order.updated_at = Some(time());
+    // Record the refund before calling the ledger so it can't be issued twice
+    // while the transfer is in flight
+    if let Some(payment) = &order.payment {
+        let units = payment.amount.saturating_sub(order.refunded_units.unwrap_or_default());
+        if units > 0 {
+            order.cancellation_refund = Some(LedgerTransfer {
+                ledger: payment.ledger,
+                account: payment.account,
+                amount: units,
+                memo: payments::memo(b"cancel", order.id),
+                created_at_time: time(),
+                block_index: None,
+            });
+            order.refunded_units = Some(payment.amount);
+        }
+        order.refunded_amount = Some(order.total);
+    }
+    do_insert_order(&mut order);
+    invoices::void_order_invoice(order.id);
+    scorecards::record_order_cancelled(order.seller);
+    if order.cancellation_refund.is_some() {
+        return _send_cancellation_refund(order).await;
+    }
+    Ok(order)
+}
+
+// Send the refund of a cancelled order to the buyer. When the ledger rejects it, the refund
+// is created again with a new timestamp on the next call.
+async fn _send_cancellation_refund(order: Order) -> Result<Order, Error> {
+    let Some(transfer) = order.cancellation_refund.clone() else {
+        return Ok(order);
+    };
+    let outcome = payments::pay_out(&transfer).await;
+    let mut order = _get_order(&order.id)?;
+    match outcome {
+        TransferOutcome::Completed(block_index) => {
+            order.cancellation_refund = Some(LedgerTransfer {
+                block_index: Some(block_index),
+                ..transfer
+            });
+            order.updated_at = Some(time());
+            do_insert_order(&mut order);
+            Ok(order)
+        }
+        TransferOutcome::Failed(err) => {
+            order.cancellation_refund = Some(LedgerTransfer {
+                created_at_time: time(),
+                ..transfer
+            });
+            order.updated_at = Some(time());
+            do_insert_order(&mut order);
+            Err(err)
+        }
+        TransferOutcome::Unknown(err) => Err(err),
+    }
+}
+
+// Query function to price an order without placing it, showing every applied discount.
+// The amounts can also be converted to `currency`; rounding defaults to half-even.
+#[ic_cdk::query]
+fn price_quote(
+    payload: OrderPayload,
+    currency: Option<String>,
+    rounding: Option<RoundingMode>,
+) -> Result<PriceQuote, Error> {
+    let (_, mut quote) = _prepare_order(payload, caller())?;
+    if let Some(target) = currency {
+        let base = quote.currency.clone().ok_or_else(|| Error::ValidationFailed {
+            msg: "the order has no currency to convert from".to_string(),
+        })?;
+        let rounding = rounding.unwrap_or_default();
+        let shipping_total = quote.shipping.as_ref().map_or(0, |shipping| shipping.amount);
+        let exclusive_tax = quote.total - (quote.subtotal - quote.discount_total) - shipping_total;
+        let subtotal = currency::convert(quote.subtotal, &base, &target, rounding)?;
+        let net = currency::convert(quote.subtotal - quote.discount_total, &base, &target, rounding)?;
+        let exclusive_tax = currency::convert(exclusive_tax, &base, &target, rounding)?;
+        let tax_total = currency::convert(quote.tax_total, &base, &target, rounding)?;
+        let shipping_total = currency::convert(shipping_total, &base, &target, rounding)?;
+        // Derived from the converted amounts so subtotal - discounts + exclusive tax + shipping = total
+        let discount_total = Money {
+            amount: subtotal.amount - net.amount,
+            ..net.clone()
+        };
+        let total = Money {
+            amount: net
+                .amount
+                .saturating_add(exclusive_tax.amount)
+                .saturating_add(shipping_total.amount),
+            ..net
+        };
+        quote.converted = Some(ConvertedAmounts {
+            subtotal,
+            discount_total,
+            tax_total,
+            shipping_total,
+            total,
+            rounding,
+        });
+    }
+    Ok(quote)
+}
+
+// Query function to get an order by ID (buyer, seller or staff only)
+#[ic_cdk::query]
+fn get_order(id: u64) -> Result<Order, Error> {
+    let order = _get_order(&id)?;
+    _check_order_access(&order)?;
+    Ok(order)
+}
+
+// Query function to get the orders placed by the caller
+#[ic_cdk::query]
+fn get_my_orders() -> Vec<Order> {
+    let buyer = caller();
+    ORDER_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, order)| order.buyer == buyer)
+            .map(|(_, order)| order)
+            .collect()
+    })
+}
+
+// Query function to get the orders placed against the caller's listings
+#[ic_cdk::query]
+fn get_seller_orders() -> Vec<Order> {
+    let seller = caller();
+    ORDER_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, order)| order.seller == seller)
+            .map(|(_, order)| order)
+            .collect()
+    })
+}
+
+// Validate an order payload and price it. Returns the seller and the quote; nothing is changed.
+fn _prepare_order(payload: OrderPayload, buyer: Principal) -> Result<(Principal, PriceQuote), Error> {
+    if payload.lines.is_empty() || payload.lines.len() > MAX_ORDER_LINES {
+        return Err(Error::ValidationFailed {
+            msg: format!("an order must have between 1 and {} lines", MAX_ORDER_LINES),
+        });
+    }
+    let mut seller: Option<Principal> = None;
+    let mut order_currency: Option<Option<String>> = None;
+    let mut serial_count: u64 = 0;
+    let mut lines: Vec<OrderLine> = Vec::with_capacity(payload.lines.len());
+    for line in payload.lines {
+        let accessory = _get_accessory(&line.accessory_id).ok_or_else(|| Error::NotFound {
+            msg: format!("an accessory with id={} not found", line.accessory_id),
+        })?;
+        if !accessory.is_available {
+            return Err(Error::ValidationFailed {
+                msg: format!("accessory with id={} isn't available", accessory.id),
+            });
+        }
+        if *seller.get_or_insert(accessory.seller) != accessory.seller {
+            return Err(Error::ValidationFailed {
+                msg: "all lines of an order must belong to the same seller".to_string(),
+            });
+        }
+        let line_currency = currency::accessory_currency(&accessory).ok();
+        if order_currency.get_or_insert_with(|| line_currency.clone()) != &line_currency {
+            return Err(Error::ValidationFailed {
+                msg: "all lines of an order must be priced in the same currency".to_string(),
+            });
+        }
+        if lines.iter().any(|existing| existing.accessory_id == accessory.id) {
+            return Err(Error::ValidationFailed {
+                msg: format!("accessory with id={} appears more than once", accessory.id),
+            });
+        }
+        let delta = i64::try_from(line.quantity)
+            .ok()
+            .filter(|quantity| *quantity > 0)
+            .ok_or_else(|| Error::ValidationFailed {
+                msg: format!("invalid quantity for accessory with id={}", accessory.id),
+            })?;
+        if accessory.inventory_count < line.quantity {
+            return Err(Error::ValidationFailed {
+                msg: format!(
+                    "only {} of accessory with id={} in stock",
+                    accessory.inventory_count, accessory.id
+                ),
+            });
+        }
+        batches::check_stock_movement(&accessory, -delta, StockReason::Sold)?;
+        let serials = if serials::_is_serialized(accessory.id) {
+            serial_count = serial_count.saturating_add(line.quantity);
+            if serial_count > MAX_ORDER_SERIALS as u64 {
+                return Err(Error::ValidationFailed {
+                    msg: format!("an order can allocate at most {} serialized units", MAX_ORDER_SERIALS),
+                });
+            }
+            serials::select_serials(accessory.id, &line.serials, line.quantity)?
+        } else if line.serials.is_empty() {
+            Vec::new()
+        } else {
+            return Err(Error::ValidationFailed {
+                msg: format!("accessory with id={} isn't tracked by serial number", accessory.id),
+            });
+        };
+        let unit_price = pricing::effective_price(&accessory);
+        let subtotal = unit_price.saturating_mul(line.quantity);
+        lines.push(OrderLine {
+            accessory_id: accessory.id,
+            name: _truncate_text(&accessory.name, MAX_LINE_NAME_LENGTH),
+            quantity: line.quantity,
+            unit_price,
+            serials,
+            discounts: Some(Vec::new()),
+            line_total: Some(subtotal),
+            tax: None,
+            sale_id: None,
+        });
+    }
+    let seller = seller.unwrap_or_else(Principal::anonymous);
+    let order_currency = order_currency.flatten();
+    let coupon_codes = promotions::apply_coupons(
+        &payload.coupon_codes.unwrap_or_default(),
+        &seller,
+        &order_currency,
+        &mut lines,
+        buyer,
+    )?;
+    let jurisdiction = taxes::order_jurisdiction(payload.jurisdiction)?;
+    if let Some(jurisdiction) = &jurisdiction {
+        taxes::apply_taxes(jurisdiction, &mut lines);
+    }
+    let shipping = payload
+        .shipping_method_id
+        .map(|method_id| shipping::quote_shipping(method_id, &seller, &lines))
+        .transpose()?;
+
+    let subtotal = lines.iter().fold(0u64, |total, line| {
+        total.saturating_add(line.unit_price.saturating_mul(line.quantity))
+    });
+    let net = lines.iter().fold(0u64, |total, line| {
+        total.saturating_add(line.line_total.unwrap_or_default())
+    });
+    let tax_total = lines
+        .iter()
+        .filter_map(|line| line.tax.as_ref())
+        .fold(0u64, |total, tax| total.saturating_add(tax.amount));
+    let exclusive_tax = lines
+        .iter()
+        .filter_map(|line| line.tax.as_ref())
+        .filter(|tax| !tax.inclusive)
+        .fold(0u64, |total, tax| total.saturating_add(tax.amount));
+    Ok((
+        seller,
+        PriceQuote {
+            lines,
+            subtotal,
+            discount_total: subtotal - net,
+            tax_total,
+            total: net
+                .saturating_add(exclusive_tax)
+                .saturating_add(shipping.as_ref().map_or(0, |shipping| shipping.amount)),
+            shipping,
+            currency: order_currency,
+            jurisdiction,
+            coupon_codes,
+            converted: None,
+        },
+    ))
+}
+
+// What the seller earns on a line: its total after discounts, without tax
+fn _line_revenue(line: &OrderLine) -> u64 {
+    let line_total = line
+        .line_total
+        .unwrap_or_else(|| line.unit_price.saturating_mul(line.quantity));
+    let inclusive_tax = line
+        .tax
+        .as_ref()
+        .filter(|tax| tax.inclusive)
+        .map_or(0, |tax| tax.amount);
+    line_total.saturating_sub(inclusive_tax)
+}
+
+// Coupons used on an order; every applied coupon discounts at least one line
+fn _coupon_codes(order: &Order) -> Vec<String> {
+    let mut codes: Vec<String> = order
+        .lines
+        .iter()
+        .flat_map(|line| line.discounts.iter().flatten())
+        .map(|discount| discount.code.clone())
+        .collect();
+    codes.sort();
+    codes.dedup();
+    codes
+}
+
+// Whether the seller has placed orders for any of the accessories that haven't shipped yet
+pub(crate) fn has_unshipped_orders(seller: Principal, accessory_ids: &[u64]) -> bool {
+    ORDER_STORAGE.with(|service| {
+        service.borrow().iter().any(|(_, order)| {
+            order.seller == seller
+                && order.status == OrderStatus::Placed
+                && order
+                    .lines
+                    .iter()
+                    .any(|line| accessory_ids.contains(&line.accessory_id))
+        })
+    })
+}
+
+pub(crate) fn do_insert_order(order: &mut Order) {
+    order.version = Some(order.version.unwrap_or_default() + 1);
+    ORDER_STORAGE.with(|service| service.borrow_mut().insert(order.id, order.clone()));
+}
+
+// Orders placed between two timestamps (inclusive)
+pub(crate) fn _get_orders_between(from: u64, to: u64) -> Vec<Order> {
+    ORDER_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, order)| order.created_at >= from && order.created_at <= to)
+            .map(|(_, order)| order)
+            .collect()
+    })
+}
+
+pub(crate) fn _get_order(id: &u64) -> Result<Order, Error> {
+    ORDER_STORAGE
+        .with(|service| service.borrow().get(id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("an order with id={} not found", id),
+        })
+}
+
+// Helper function to check whether the caller is the buyer or seller of an order, or staff
+pub(crate) fn _check_order_access(order: &Order) -> Result<(), Error> {
+    let caller = caller();
+    if order.buyer == caller || order.seller == caller || roles::_is_staff(&caller) {
+        Ok(())
+    } else {
+        Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} can't access order with id={}", caller, order.id),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // An order with every line, serial, discount and text field at its limit
+    pub(crate) fn largest_order() -> Order {
+        let serials_per_line = MAX_ORDER_SERIALS.div_ceil(MAX_ORDER_LINES);
+        let line = OrderLine {
+            accessory_id: u64::MAX,
+            name: "n".repeat(MAX_LINE_NAME_LENGTH),
+            quantity: u64::MAX,
+            unit_price: u64::MAX,
+            serials: vec!["s".repeat(64); serials_per_line],
+            discounts: Some(vec![
+                AppliedDiscount {
+                    code: "c".repeat(32),
+                    description: format!("buy {} get {} free", u64::MAX, u64::MAX),
+                    amount: u64::MAX,
+                };
+                2
+            ]),
+            line_total: Some(u64::MAX),
+            tax: Some(LineTax {
+                rule_id: u64::MAX,
+                jurisdiction: "j".repeat(16),
+                category: Some("c".repeat(64)),
+                rate_basis_points: u64::MAX,
+                inclusive: true,
+                amount: u64::MAX,
+            }),
+            sale_id: Some(u64::MAX),
+        };
+        Order {
+            id: u64::MAX,
+            buyer: Principal::from_slice(&[0xff; 29]),
+            seller: Principal::from_slice(&[0xff; 29]),
+            lines: vec![line; MAX_ORDER_LINES],
+            subtotal: Some(u64::MAX),
+            discount_total: Some(u64::MAX),
+            tax_total: Some(u64::MAX),
+            shipping: Some(ShippingCharge {
+                method_id: u64::MAX,
+                name: "m".repeat(64),
+                amount: u64::MAX,
+            }),
+            total: u64::MAX,
+            currency: Some("c".repeat(8)),
+            jurisdiction: Some("j".repeat(16)),
+            refunded_amount: Some(u64::MAX),
+            status: OrderStatus::Cancelled,
+            created_at: u64::MAX,
+            updated_at: Some(u64::MAX),
+            payment: Some(LedgerTransfer {
+                ledger: Principal::from_slice(&[0xff; 29]),
+                account: Principal::from_slice(&[0xff; 29]),
+                amount: u64::MAX,
+                memo: payments::memo(b"order", u64::MAX),
+                created_at_time: u64::MAX,
+                block_index: Some(u64::MAX),
+            }),
+            refunded_units: Some(u64::MAX),
+            cancellation_refund: Some(LedgerTransfer {
+                ledger: Principal::from_slice(&[0xff; 29]),
+                account: Principal::from_slice(&[0xff; 29]),
+                amount: u64::MAX,
+                memo: payments::memo(b"cancel", u64::MAX),
+                created_at_time: u64::MAX,
+                block_index: Some(u64::MAX),
+            }),
+            version: Some(u64::MAX),
+        }
+    }
+
+    #[test]
+    fn line_revenue_is_net_of_discounts_and_tax() {
+        let mut line = largest_order().lines.remove(0);
+        line.quantity = 3;
+        line.unit_price = 1_000;
+        line.line_total = Some(2_500);
+        line.tax = None;
+        assert_eq!(_line_revenue(&line), 2_500);
+        line.tax = Some(LineTax {
+            rule_id: 1,
+            jurisdiction: "DE".to_string(),
+            category: None,
+            rate_basis_points: 2_500,
+            inclusive: true,
+            amount: 500,
+        });
+        assert_eq!(_line_revenue(&line), 2_000);
+        line.tax.as_mut().unwrap().inclusive = false;
+        assert_eq!(_line_revenue(&line), 2_500);
+        line.line_total = None;
+        assert_eq!(_line_revenue(&line), 3_000);
+    }
+
+    #[test]
+    fn orders_start_at_version_one_and_count_every_change() {
+        let mut order = Order {
+            id: 1,
+            version: None,
+            ..largest_order()
+        };
+        do_insert_order(&mut order);
+        assert_eq!(order.version, Some(1));
+        do_insert_order(&mut order);
+        assert_eq!(_get_order(&1).ok().and_then(|stored| stored.version), Some(2));
+        let stale = _check_version("order with id=1", order.version, Some(1));
+        assert!(matches!(stale, Err(Error::Conflict { current_version: 2, .. })));
+    }
+
+    #[test]
+    fn largest_order_fits_its_storage_bound() {
+        let size = largest_order().to_bytes().len();
+        assert!(size <= Order::MAX_SIZE as usize, "{} bytes", size);
+    }
+}
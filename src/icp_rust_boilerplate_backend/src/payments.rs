@@ -0,0 +1,314 @@
+// Optional token payments through an ICRC-1/ICRC-2 ledger
+use crate::currency::{self, RoundingMode};
+use crate::{roles, Error, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode, Nat, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Define the payment settings of the canister
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct PaymentConfig {
+    // ICRC-1 ledger used for token transfers; payments are disabled when None
+    ledger: Option<Principal>,
+    // Currency of the ledger's token. Its decimals must match the ledger's, so an
+    // amount in its smallest unit is an amount in ledger units.
+    currency: Option<String>,
+}
+
+// Implement the Storable trait for PaymentConfig
+impl Storable for PaymentConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Define a token transfer between the canister and a buyer. A transfer is identified by
+// its memo and created_at_time, so the ledger deduplicates a retry instead of moving the
+// tokens twice.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct LedgerTransfer {
+    pub(crate) ledger: Principal,
+    // The buyer's account the tokens come from or go to
+    pub(crate) account: Principal,
+    // In ledger units
+    pub(crate) amount: u64,
+    pub(crate) memo: Vec<u8>,
+    pub(crate) created_at_time: u64,
+    // Set once the ledger confirmed the transfer; None while its outcome is unknown
+    pub(crate) block_index: Option<u64>,
+}
+
+// Outcome of a call to the ledger
+pub(crate) enum TransferOutcome {
+    Completed(u64),
+    // The ledger rejected the transfer, so no tokens moved
+    Failed(Error),
+    // The transfer may or may not have happened; retry it unchanged to find out
+    Unknown(Error),
+}
+
+// ICRC-1 account
+#[derive(candid::CandidType, Deserialize)]
+struct Account {
+    owner: Principal,
+    subaccount: Option<Vec<u8>>,
+}
+
+// ICRC-1 icrc1_transfer argument
+#[derive(candid::CandidType, Deserialize)]
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+// ICRC-1 icrc1_transfer error
+#[derive(candid::CandidType, Deserialize, Debug)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+// ICRC-2 icrc2_transfer_from argument
+#[derive(candid::CandidType, Deserialize)]
+struct TransferFromArgs {
+    spender_subaccount: Option<Vec<u8>>,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+// ICRC-2 icrc2_transfer_from error
+#[derive(candid::CandidType, Deserialize, Debug)]
+enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+thread_local! {
+    static PAYMENT_CONFIG: RefCell<Cell<PaymentConfig, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21))), PaymentConfig::default())
+            .expect("Cannot create the payment config cell")
+    );
+}
+
+// Update function to configure, or disable with None, the ledger used for token payments
+// and the currency of its token (admin only)
+#[ic_cdk::update]
+fn set_payment_ledger(ledger: Option<Principal>, currency: Option<String>) -> Result<(), Error> {
+    roles::_check_if_admin()?;
+    let currency = match (&ledger, currency) {
+        (Some(_), Some(code)) => currency::check_currency(&Some(code))?,
+        (Some(_), None) => {
+            return Err(Error::ValidationFailed {
+                msg: "the currency of the ledger's token is required".to_string(),
+            })
+        }
+        (None, _) => None,
+    };
+    PAYMENT_CONFIG
+        .with(|cell| cell.borrow_mut().set(PaymentConfig { ledger, currency }))
+        .expect("cannot update the payment config");
+    Ok(())
+}
+
+// Query function to get the payment settings
+#[ic_cdk::query]
+fn get_payment_config() -> PaymentConfig {
+    PAYMENT_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+// Prepare the collection of `amount`, in `currency`, from a buyer through the configured
+// ledger. Nothing is transferred yet.
+pub(crate) fn new_payment(
+    buyer: Principal,
+    amount: u64,
+    currency: &Option<String>,
+    memo: Vec<u8>,
+) -> Result<LedgerTransfer, Error> {
+    let config = get_payment_config();
+    let (Some(ledger), Some(ledger_currency)) = (config.ledger, config.currency) else {
+        return Err(Error::PaymentFailed {
+            msg: "token payments are not configured".to_string(),
+        });
+    };
+    let currency = currency.as_ref().ok_or_else(|| Error::PaymentFailed {
+        msg: "amounts without a currency can't be paid in tokens".to_string(),
+    })?;
+    let amount = currency::convert(amount, currency, &ledger_currency, RoundingMode::HalfUp)?.amount;
+    if amount == 0 {
+        return Err(Error::ValidationFailed {
+            msg: "there is nothing to pay".to_string(),
+        });
+    }
+    Ok(LedgerTransfer {
+        ledger,
+        account: buyer,
+        amount,
+        memo,
+        created_at_time: time(),
+        block_index: None,
+    })
+}
+
+// Ledger units to send back for a refund of `amount` out of a `total` paid with `payment`.
+// Refunds use the rate the buyer paid at, rounded down.
+pub(crate) fn refund_units(payment: &LedgerTransfer, amount: u64, total: u64) -> u64 {
+    if total == 0 {
+        return 0;
+    }
+    let units = u128::from(payment.amount) * u128::from(amount.min(total)) / u128::from(total);
+    units as u64
+}
+
+// Memo identifying the transfers made for a record, e.g. b"order" and its ID
+pub(crate) fn memo(kind: &[u8], id: u64) -> Vec<u8> {
+    [kind, b":", &id.to_be_bytes()].concat()
+}
+
+// Move tokens from the buyer to the canister, using the allowance the buyer gave the
+// canister on the ledger (ICRC-2)
+pub(crate) async fn collect(transfer: &LedgerTransfer) -> TransferOutcome {
+    let arg = TransferFromArgs {
+        spender_subaccount: None,
+        from: Account {
+            owner: transfer.account,
+            subaccount: None,
+        },
+        to: Account {
+            owner: ic_cdk::id(),
+            subaccount: None,
+        },
+        amount: Nat::from(transfer.amount),
+        fee: None,
+        memo: Some(transfer.memo.clone()),
+        created_at_time: Some(transfer.created_at_time),
+    };
+    let result: Result<(Result<Nat, TransferFromError>,), _> =
+        ic_cdk::call(transfer.ledger, "icrc2_transfer_from", (arg,)).await;
+    match result {
+        Ok((Ok(block_index),))
+        | Ok((Err(TransferFromError::Duplicate {
+            duplicate_of: block_index,
+        }),)) => TransferOutcome::Completed(_block_index(block_index)),
+        Ok((Err(TransferFromError::TooOld),)) => TransferOutcome::Unknown(_too_old(transfer)),
+        Ok((Err(err),)) => TransferOutcome::Failed(Error::PaymentFailed {
+            msg: format!("ledger {} rejected the payment: {:?}", transfer.ledger, err),
+        }),
+        Err((code, msg)) => TransferOutcome::Unknown(_call_failed(transfer, code, msg)),
+    }
+}
+
+// Move tokens from the canister back to the buyer
+pub(crate) async fn pay_out(transfer: &LedgerTransfer) -> TransferOutcome {
+    let arg = TransferArg {
+        from_subaccount: None,
+        to: Account {
+            owner: transfer.account,
+            subaccount: None,
+        },
+        amount: Nat::from(transfer.amount),
+        fee: None,
+        memo: Some(transfer.memo.clone()),
+        created_at_time: Some(transfer.created_at_time),
+    };
+    let result: Result<(Result<Nat, TransferError>,), _> =
+        ic_cdk::call(transfer.ledger, "icrc1_transfer", (arg,)).await;
+    match result {
+        Ok((Ok(block_index),))
+        | Ok((Err(TransferError::Duplicate {
+            duplicate_of: block_index,
+        }),)) => TransferOutcome::Completed(_block_index(block_index)),
+        Ok((Err(TransferError::TooOld),)) => TransferOutcome::Unknown(_too_old(transfer)),
+        Ok((Err(err),)) => TransferOutcome::Failed(Error::PaymentFailed {
+            msg: format!("ledger {} rejected the transfer: {:?}", transfer.ledger, err),
+        }),
+        Err((code, msg)) => TransferOutcome::Unknown(_call_failed(transfer, code, msg)),
+    }
+}
+
+fn _block_index(block_index: Nat) -> u64 {
+    u64::try_from(block_index.0).unwrap_or(u64::MAX)
+}
+
+// The ledger only deduplicates within its transaction window; past it, whether an
+// earlier attempt went through has to be checked on the ledger by hand
+fn _too_old(transfer: &LedgerTransfer) -> Error {
+    Error::PaymentFailed {
+        msg: format!(
+            "transfer created at {} is too old to retry on ledger {}, check the ledger for its outcome",
+            transfer.created_at_time, transfer.ledger
+        ),
+    }
+}
+
+fn _call_failed(transfer: &LedgerTransfer, code: ic_cdk::api::call::RejectionCode, msg: String) -> Error {
+    Error::PaymentFailed {
+        msg: format!(
+            "call to ledger {} failed ({:?}): {}; the transfer is pending, retry to complete it",
+            transfer.ledger, code, msg
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payment(amount: u64) -> LedgerTransfer {
+        LedgerTransfer {
+            ledger: Principal::anonymous(),
+            account: Principal::anonymous(),
+            amount,
+            memo: memo(b"order", 1),
+            created_at_time: 0,
+            block_index: Some(0),
+        }
+    }
+
+    #[test]
+    fn refunds_are_a_rounded_down_share_of_the_payment() {
+        assert_eq!(refund_units(&payment(1_000), 250, 1_000), 250);
+        assert_eq!(refund_units(&payment(999), 1, 3), 333);
+        assert_eq!(refund_units(&payment(10), 1, 3), 3);
+        assert_eq!(refund_units(&payment(u64::MAX), u64::MAX, u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn refunds_never_exceed_the_payment() {
+        assert_eq!(refund_units(&payment(100), 500, 200), 100);
+        assert_eq!(refund_units(&payment(100), 5, 0), 0);
+    }
+
+    #[test]
+    fn memos_fit_the_ledger_limit_and_differ_per_record() {
+        assert!(memo(b"refund", u64::MAX).len() <= 32);
+        assert_ne!(memo(b"order", 1), memo(b"refund", 1));
+        assert_ne!(memo(b"order", 1), memo(b"order", 2));
+    }
+}
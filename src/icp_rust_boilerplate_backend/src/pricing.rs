@@ -0,0 +1,327 @@
+// Price history and scheduled sale prices
+use crate::{
+    _check_if_seller_or_staff, _get_accessory, next_id, rate_limit, roles, Accessory, Error, Memory, ACCESSORY_STORAGE,
+    MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Define why the price of an accessory changed
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum PriceChangeReason {
+    Manual,
+    SaleStarted,
+    SaleEnded,
+}
+
+// Define a single entry in the price history of an accessory
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PriceChange {
+    id: u64,
+    accessory_id: u64,
+    old_price: u64,
+    new_price: u64,
+    reason: PriceChangeReason,
+    // For sales, the principal that scheduled the sale
+    changed_by: Principal,
+    changed_at: u64,
+}
+
+// Implement the Storable trait for PriceChange
+impl Storable for PriceChange {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for PriceChange
+impl BoundedStorable for PriceChange {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define the lifecycle of a scheduled sale
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum SaleStatus {
+    Scheduled,
+    Active,
+    Ended,
+    Cancelled,
+}
+
+// Define the structure representing a scheduled sale price
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Sale {
+    id: u64,
+    accessory_id: u64,
+    sale_price: u64,
+    starts_at: u64,
+    ends_at: u64,
+    status: SaleStatus,
+    created_by: Principal,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Implement the Storable trait for Sale
+impl Storable for Sale {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for Sale
+impl BoundedStorable for Sale {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static PRICE_HISTORY: RefCell<StableBTreeMap<(u64, u64), PriceChange, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26))))
+    );
+
+    static SALE_STORAGE: RefCell<StableBTreeMap<u64, Sale, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27))))
+    );
+
+    // The active sale of each accessory
+    static ACTIVE_SALE_INDEX: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28))))
+    );
+
+    // Scheduled and active sales keyed by (time of their next transition, sale_id)
+    static SALE_DUE_INDEX: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(49))))
+    );
+}
+
+// Update function to schedule a sale price for an accessory. The sale is started and
+// ended by the scheduler; sales of one accessory can't overlap.
+#[ic_cdk::update]
+fn schedule_sale(accessory_id: u64, sale_price: u64, starts_at: u64, ends_at: u64) -> Result<Sale, Error> {
+    rate_limit::_check_rate_limit("schedule_sale")?;
+    let accessory = _get_accessory(&accessory_id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", accessory_id),
+    })?;
+    _check_if_seller_or_staff(&accessory)?;
+    let now = time();
+    if ends_at <= starts_at || ends_at <= now {
+        return Err(Error::ValidationFailed {
+            msg: "a sale must end after it starts and in the future".to_string(),
+        });
+    }
+    if sale_price >= accessory.price {
+        return Err(Error::ValidationFailed {
+            msg: format!("a sale price must be below the current price of {}", accessory.price),
+        });
+    }
+    if let Some(other) = _get_sales(accessory_id).into_iter().find(|sale| {
+        matches!(sale.status, SaleStatus::Scheduled | SaleStatus::Active)
+            && sale.starts_at < ends_at
+            && starts_at < sale.ends_at
+    }) {
+        return Err(Error::ValidationFailed {
+            msg: format!("the sale overlaps sale with id={}", other.id),
+        });
+    }
+    let sale = Sale {
+        id: next_id(),
+        accessory_id,
+        sale_price,
+        starts_at,
+        ends_at,
+        status: SaleStatus::Scheduled,
+        created_by: caller(),
+        created_at: now,
+        updated_at: None,
+    };
+    do_insert_sale(&sale);
+    Ok(sale)
+}
+
+// Update function to cancel a scheduled or active sale
+#[ic_cdk::update]
+fn cancel_sale(id: u64) -> Result<Sale, Error> {
+    rate_limit::_check_rate_limit("cancel_sale")?;
+    let mut sale = SALE_STORAGE
+        .with(|service| service.borrow().get(&id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("a sale with id={} not found", id),
+        })?;
+    // Trashed accessories still have their seller; purged ones can only be handled by staff
+    let accessory = ACCESSORY_STORAGE.with(|service| service.borrow().get(&sale.accessory_id));
+    match &accessory {
+        Some(accessory) => _check_if_seller_or_staff(accessory)?,
+        None if roles::_is_staff(&caller()) => {}
+        None => {
+            return Err(Error::AuthenticationFailed {
+                msg: format!("Caller={} can't cancel sale with id={}", caller(), id),
+            })
+        }
+    }
+    if !matches!(sale.status, SaleStatus::Scheduled | SaleStatus::Active) {
+        return Err(Error::ValidationFailed {
+            msg: format!("sale with id={} has already finished", id),
+        });
+    }
+    if sale.status == SaleStatus::Active {
+        ACTIVE_SALE_INDEX.with(|service| service.borrow_mut().remove(&sale.accessory_id));
+        if let Some(accessory) = &accessory {
+            record_price_change(
+                accessory,
+                sale.sale_price,
+                accessory.price,
+                PriceChangeReason::SaleEnded,
+                caller(),
+            );
+        }
+    }
+    sale.status = SaleStatus::Cancelled;
+    sale.updated_at = Some(time());
+    do_insert_sale(&sale);
+    Ok(sale)
+}
+
+// Query function to get the sales scheduled for an accessory
+#[ic_cdk::query]
+fn get_sales(accessory_id: u64) -> Vec<Sale> {
+    _get_sales(accessory_id)
+}
+
+// Query function to get the price history of an accessory, oldest first
+#[ic_cdk::query]
+fn get_price_history(accessory_id: u64) -> Result<Vec<PriceChange>, Error> {
+    if _get_accessory(&accessory_id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("an accessory with id={} not found", accessory_id),
+        });
+    }
+    Ok(PRICE_HISTORY.with(|service| {
+        service
+            .borrow()
+            .range((accessory_id, 0)..=(accessory_id, u64::MAX))
+            .map(|(_, change)| change)
+            .collect()
+    }))
+}
+
+// Price buyers pay right now: the active sale price, or the regular price
+pub(crate) fn effective_price(accessory: &Accessory) -> u64 {
+    ACTIVE_SALE_INDEX
+        .with(|service| service.borrow().get(&accessory.id))
+        .and_then(|id| SALE_STORAGE.with(|service| service.borrow().get(&id)))
+        .map(|sale| sale.sale_price)
+        .unwrap_or(accessory.price)
+}
+
+// Append an entry to the price history of an accessory
+pub(crate) fn record_price_change(
+    accessory: &Accessory,
+    old_price: u64,
+    new_price: u64,
+    reason: PriceChangeReason,
+    changed_by: Principal,
+) {
+    let change = PriceChange {
+        id: next_id(),
+        accessory_id: accessory.id,
+        old_price,
+        new_price,
+        reason,
+        changed_by,
+        changed_at: time(),
+    };
+    PRICE_HISTORY.with(|service| service.borrow_mut().insert((accessory.id, change.id), change));
+}
+
+// Start sales whose window opened and end those whose window closed. Called by the scheduler.
+pub(crate) fn apply_scheduled_prices(now: u64) {
+    let due_ids: Vec<u64> = SALE_DUE_INDEX.with(|index| {
+        index
+            .borrow()
+            .range(..=(now, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    let due = due_ids
+        .into_iter()
+        .filter_map(|id| SALE_STORAGE.with(|service| service.borrow().get(&id)));
+    for mut sale in due {
+        let accessory = _get_accessory(&sale.accessory_id);
+        match (&accessory, sale.status) {
+            (Some(accessory), SaleStatus::Scheduled) if sale.ends_at > now => {
+                ACTIVE_SALE_INDEX.with(|service| service.borrow_mut().insert(sale.accessory_id, sale.id));
+                record_price_change(
+                    accessory,
+                    accessory.price,
+                    sale.sale_price,
+                    PriceChangeReason::SaleStarted,
+                    sale.created_by,
+                );
+                sale.status = SaleStatus::Active;
+            }
+            (_, SaleStatus::Active) => {
+                ACTIVE_SALE_INDEX.with(|service| service.borrow_mut().remove(&sale.accessory_id));
+                if let Some(accessory) = &accessory {
+                    record_price_change(
+                        accessory,
+                        sale.sale_price,
+                        accessory.price,
+                        PriceChangeReason::SaleEnded,
+                        sale.created_by,
+                    );
+                }
+                sale.status = SaleStatus::Ended;
+            }
+            // Sales of deleted accessories, or whose window passed before they could start
+            _ => sale.status = SaleStatus::Ended,
+        }
+        sale.updated_at = Some(now);
+        do_insert_sale(&sale);
+    }
+}
+
+fn _get_sales(accessory_id: u64) -> Vec<Sale> {
+    SALE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, sale)| sale.accessory_id == accessory_id)
+            .map(|(_, sale)| sale)
+            .collect()
+    })
+}
+
+// Time at which the scheduler has to act on a sale next
+fn _due_at(sale: &Sale) -> Option<u64> {
+    match sale.status {
+        SaleStatus::Scheduled => Some(sale.starts_at),
+        SaleStatus::Active => Some(sale.ends_at),
+        SaleStatus::Ended | SaleStatus::Cancelled => None,
+    }
+}
+
+fn do_insert_sale(sale: &Sale) {
+    let previous = SALE_STORAGE.with(|service| service.borrow_mut().insert(sale.id, sale.clone()));
+    SALE_DUE_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(due_at) = previous.as_ref().and_then(_due_at) {
+            index.remove(&(due_at, sale.id));
+        }
+        if let Some(due_at) = _due_at(sale) {
+            index.insert((due_at, sale.id), ());
+        }
+    });
+}
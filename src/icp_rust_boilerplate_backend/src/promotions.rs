@@ -0,0 +1,558 @@
+// Coupons and promotional discounts applied at checkout
+use crate::currency::{self, RoundingMode};
+use crate::orders::{AppliedDiscount, OrderLine};
+use crate::{
+    _check_version, _get_accessory, rate_limit, roles, Error, Memory, PrincipalKey, StringKey, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Coupon codes are between 3 and 32 characters long
+const MIN_CODE_LENGTH: usize = 3;
+const MAX_CODE_LENGTH: usize = 32;
+// Maximum number of coupons that can be combined on one order
+const MAX_ORDER_COUPONS: usize = 2;
+
+type CodeKey = StringKey<{ MAX_CODE_LENGTH as u32 }>;
+
+// Define the kinds of discount a coupon can grant
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum DiscountKind {
+    // Percentage off in basis points, 1000 = 10%
+    Percentage { basis_points: u64 },
+    // Fixed amount off, spread over the eligible lines
+    FixedAmount { amount: u64 },
+    // For every `buy` units of a line, `get` more are free
+    BuyXGetY { buy: u64, get: u64 },
+}
+
+// Define the structure representing a coupon
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Coupon {
+    code: String,
+    created_by: Principal,
+    // Only orders from this seller can use the coupon
+    seller: Option<Principal>,
+    // Only lines of this category are discounted
+    category: Option<String>,
+    kind: DiscountKind,
+    // Minimum order subtotal before discounts
+    min_order_value: u64,
+    // Currency of the fixed amount and the minimum order value, converted to the
+    // currency of the order
+    currency: Option<String>,
+    max_uses: Option<u64>,
+    max_uses_per_principal: Option<u64>,
+    starts_at: u64,
+    ends_at: Option<u64>,
+    uses: u64,
+    active: bool,
+    created_at: u64,
+    // Incremented on every change
+    version: Option<u64>,
+}
+
+// Implement the Storable trait for Coupon
+impl Storable for Coupon {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for Coupon
+impl BoundedStorable for Coupon {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define a payload structure for creating a coupon
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct CouponPayload {
+    code: String,
+    // Ignored for non-admins, whose coupons are always limited to their own listings
+    seller: Option<Principal>,
+    category: Option<String>,
+    kind: DiscountKind,
+    min_order_value: Option<u64>,
+    // Defaults to the default currency; required for a fixed amount or a minimum order value
+    currency: Option<String>,
+    max_uses: Option<u64>,
+    max_uses_per_principal: Option<u64>,
+    // Defaults to now
+    starts_at: Option<u64>,
+    ends_at: Option<u64>,
+}
+
+thread_local! {
+    static COUPON_STORAGE: RefCell<StableBTreeMap<CodeKey, Coupon, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24))))
+    );
+
+    // Number of times each principal has used each coupon
+    static COUPON_USAGE: RefCell<StableBTreeMap<(CodeKey, PrincipalKey), u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25))))
+    );
+}
+
+// Update function to create a coupon. Admins can create marketplace-wide coupons,
+// everyone else can only discount their own listings.
+#[ic_cdk::update]
+fn create_coupon(payload: CouponPayload) -> Result<Coupon, Error> {
+    rate_limit::_check_rate_limit("create_coupon")?;
+    let code = _normalize_code(&payload.code)?;
+    if COUPON_STORAGE.with(|service| service.borrow().contains_key(&StringKey(code.clone()))) {
+        return Err(Error::ValidationFailed {
+            msg: format!("a coupon with code={} already exists", code),
+        });
+    }
+    let valid_kind = match payload.kind {
+        DiscountKind::Percentage { basis_points } => (1..=10_000).contains(&basis_points),
+        DiscountKind::FixedAmount { amount } => amount > 0,
+        DiscountKind::BuyXGetY { buy, get } => buy > 0 && get > 0 && buy.checked_add(get).is_some(),
+    };
+    if !valid_kind {
+        return Err(Error::ValidationFailed {
+            msg: "invalid discount for coupon".to_string(),
+        });
+    }
+    let now = time();
+    let starts_at = payload.starts_at.unwrap_or(now);
+    if payload.ends_at.is_some_and(|ends_at| ends_at <= starts_at) {
+        return Err(Error::ValidationFailed {
+            msg: "a coupon must end after it starts".to_string(),
+        });
+    }
+    if payload.max_uses == Some(0) || payload.max_uses_per_principal == Some(0) {
+        return Err(Error::ValidationFailed {
+            msg: "usage limits must be greater than zero".to_string(),
+        });
+    }
+    if payload
+        .category
+        .as_ref()
+        .is_some_and(|category| category.is_empty() || category.len() > 64)
+    {
+        return Err(Error::ValidationFailed {
+            msg: "a coupon category must be between 1 and 64 characters".to_string(),
+        });
+    }
+    let min_order_value = payload.min_order_value.unwrap_or_default();
+    let currency = currency::check_currency(&payload.currency)?;
+    if currency.is_none() && (matches!(payload.kind, DiscountKind::FixedAmount { .. }) || min_order_value > 0) {
+        return Err(Error::ValidationFailed {
+            msg: "a coupon with a fixed amount or a minimum order value needs a currency".to_string(),
+        });
+    }
+
+    let caller = caller();
+    let seller = if roles::_is_admin(&caller) {
+        payload.seller
+    } else {
+        Some(caller)
+    };
+    let mut coupon = Coupon {
+        code,
+        created_by: caller,
+        seller,
+        category: payload.category,
+        kind: payload.kind,
+        min_order_value,
+        currency,
+        max_uses: payload.max_uses,
+        max_uses_per_principal: payload.max_uses_per_principal,
+        starts_at,
+        ends_at: payload.ends_at,
+        uses: 0,
+        active: true,
+        created_at: now,
+        version: None,
+    };
+    do_insert_coupon(&mut coupon);
+    Ok(coupon)
+}
+
+// Update function to deactivate a coupon, by its creator or an admin
+#[ic_cdk::update]
+fn deactivate_coupon(code: String, expected_version: Option<u64>) -> Result<Coupon, Error> {
+    rate_limit::_check_rate_limit("deactivate_coupon")?;
+    let mut coupon = _get_coupon(&_normalize_code(&code)?)?;
+    if coupon.created_by != caller() && !roles::_is_admin(&caller()) {
+        return Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} can't manage coupon with code={}", caller(), coupon.code),
+        });
+    }
+    _check_version(
+        &format!("coupon with code={}", coupon.code),
+        coupon.version,
+        expected_version,
+    )?;
+    coupon.active = false;
+    do_insert_coupon(&mut coupon);
+    Ok(coupon)
+}
+
+// Query function to get a coupon by its code
+#[ic_cdk::query]
+fn get_coupon(code: String) -> Result<Coupon, Error> {
+    _get_coupon(&_normalize_code(&code)?)
+}
+
+// Query function to get the coupons created by the caller
+#[ic_cdk::query]
+fn get_my_coupons() -> Vec<Coupon> {
+    let caller = caller();
+    COUPON_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, coupon)| coupon.created_by == caller)
+            .map(|(_, coupon)| coupon)
+            .collect()
+    })
+}
+
+// Apply coupons to the priced lines of an order, in the given order. Each discount is
+// recorded on the lines it reduces and line_total is lowered accordingly. Amounts of
+// coupons in another currency are converted to the order's currency.
+// Returns the normalised codes; nothing is stored.
+pub(crate) fn apply_coupons(
+    codes: &[String],
+    seller: &Principal,
+    order_currency: &Option<String>,
+    lines: &mut [OrderLine],
+    buyer: Principal,
+) -> Result<Vec<String>, Error> {
+    if codes.len() > MAX_ORDER_COUPONS {
+        return Err(Error::ValidationFailed {
+            msg: format!("at most {} coupons can be used on an order", MAX_ORDER_COUPONS),
+        });
+    }
+    let subtotal = lines.iter().fold(0u64, |total, line| {
+        total.saturating_add(line.unit_price.saturating_mul(line.quantity))
+    });
+    let now = time();
+    let mut applied: Vec<String> = Vec::with_capacity(codes.len());
+    for code in codes {
+        let code = _normalize_code(code)?;
+        if applied.contains(&code) {
+            return Err(Error::ValidationFailed {
+                msg: format!("coupon with code={} appears more than once", code),
+            });
+        }
+        let coupon = _get_coupon(&code)?;
+        // The minimum is rounded up and the discount down, so converting never favours the order
+        let min_order_value = _in_order_currency(&coupon, coupon.min_order_value, order_currency, RoundingMode::Up)?;
+        let kind = match coupon.kind {
+            DiscountKind::FixedAmount { amount } => DiscountKind::FixedAmount {
+                amount: _in_order_currency(&coupon, amount, order_currency, RoundingMode::Down)?,
+            },
+            kind => kind,
+        };
+        _check_coupon_usable(&coupon, seller, subtotal, min_order_value, buyer, now)?;
+
+        let eligible: Vec<usize> = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| {
+                coupon.category.as_ref().is_none_or(|category| {
+                    _get_accessory(&line.accessory_id).is_some_and(|accessory| &accessory.category == category)
+                })
+            })
+            .map(|(index, _)| index)
+            .collect();
+        let mut amounts = _discount_amounts(&kind, lines, &eligible);
+        if amounts.iter().all(|(_, amount)| *amount == 0) {
+            return Err(Error::ValidationFailed {
+                msg: format!("coupon with code={} doesn't apply to this order", code),
+            });
+        }
+        amounts.retain(|(_, amount)| *amount > 0);
+        let description = _describe(&kind);
+        for (index, amount) in amounts {
+            let line = &mut lines[index];
+            line.line_total = Some(line.line_total.unwrap_or_default() - amount);
+            line.discounts.get_or_insert_with(Vec::new).push(AppliedDiscount {
+                code: code.clone(),
+                description: description.clone(),
+                amount,
+            });
+        }
+        applied.push(code);
+    }
+    Ok(applied)
+}
+
+// Count one use of each coupon for the buyer, once the order has been placed
+pub(crate) fn record_coupon_usage(codes: &[String], buyer: Principal) {
+    for code in codes {
+        if let Ok(mut coupon) = _get_coupon(code) {
+            coupon.uses += 1;
+            do_insert_coupon(&mut coupon);
+        }
+        let key = (StringKey(code.clone()), PrincipalKey(buyer));
+        COUPON_USAGE.with(|service| {
+            let mut usage = service.borrow_mut();
+            let uses = usage.get(&key).unwrap_or_default();
+            usage.insert(key, uses + 1);
+        });
+    }
+}
+
+// Give back one use of each coupon, when the order it was used on is cancelled
+pub(crate) fn release_coupon_usage(codes: &[String], buyer: Principal) {
+    for code in codes {
+        if let Ok(mut coupon) = _get_coupon(code) {
+            coupon.uses = coupon.uses.saturating_sub(1);
+            do_insert_coupon(&mut coupon);
+        }
+        let key = (StringKey(code.clone()), PrincipalKey(buyer));
+        COUPON_USAGE.with(|service| {
+            let mut usage = service.borrow_mut();
+            match usage.get(&key).unwrap_or_default() {
+                0 | 1 => usage.remove(&key),
+                uses => usage.insert(key, uses - 1),
+            };
+        });
+    }
+}
+
+// Convert an amount of a coupon to the currency of an order
+fn _in_order_currency(
+    coupon: &Coupon,
+    amount: u64,
+    order_currency: &Option<String>,
+    rounding: RoundingMode,
+) -> Result<u64, Error> {
+    match (&coupon.currency, order_currency) {
+        (None, _) => Ok(amount),
+        (Some(_), _) if amount == 0 => Ok(0),
+        (Some(from), Some(to)) => Ok(currency::convert(amount, from, to, rounding)?.amount),
+        (Some(from), None) => Err(Error::ValidationFailed {
+            msg: format!(
+                "coupon with code={} is in {} but the order has no currency",
+                coupon.code, from
+            ),
+        }),
+    }
+}
+
+// Check that a coupon can be used by the buyer on an order from the seller.
+// `min_order_value` is the coupon's minimum in the currency of the order.
+fn _check_coupon_usable(
+    coupon: &Coupon,
+    seller: &Principal,
+    subtotal: u64,
+    min_order_value: u64,
+    buyer: Principal,
+    now: u64,
+) -> Result<(), Error> {
+    let reason = if !coupon.active {
+        Some("is no longer active".to_string())
+    } else if now < coupon.starts_at || coupon.ends_at.is_some_and(|ends_at| now >= ends_at) {
+        Some("isn't valid at this time".to_string())
+    } else if coupon
+        .seller
+        .as_ref()
+        .is_some_and(|coupon_seller| coupon_seller != seller)
+    {
+        Some("doesn't apply to this seller".to_string())
+    } else if subtotal < min_order_value {
+        Some(format!("requires an order of at least {}", min_order_value))
+    } else if coupon.max_uses.is_some_and(|max_uses| coupon.uses >= max_uses) {
+        Some("has been used up".to_string())
+    } else if coupon
+        .max_uses_per_principal
+        .is_some_and(|max_uses| _get_usage(&coupon.code, buyer) >= max_uses)
+    {
+        Some("has already been used the maximum number of times by the caller".to_string())
+    } else {
+        None
+    };
+    match reason {
+        Some(reason) => Err(Error::ValidationFailed {
+            msg: format!("coupon with code={} {}", coupon.code, reason),
+        }),
+        None => Ok(()),
+    }
+}
+
+// Discount for each eligible line, never more than what is left of the line
+fn _discount_amounts(kind: &DiscountKind, lines: &[OrderLine], eligible: &[usize]) -> Vec<(usize, u64)> {
+    let remaining = |index: usize| lines[index].line_total.unwrap_or_default();
+    match *kind {
+        DiscountKind::Percentage { basis_points } => eligible
+            .iter()
+            .map(|&index| (index, (remaining(index) as u128 * basis_points as u128 / 10_000) as u64))
+            .collect(),
+        DiscountKind::FixedAmount { amount } => {
+            // Spread the amount in proportion to each line, the last line takes the rounding remainder
+            let eligible_total = eligible.iter().map(|&index| remaining(index) as u128).sum::<u128>();
+            let mut left = (amount as u128).min(eligible_total);
+            let mut amounts = Vec::with_capacity(eligible.len());
+            for (position, &index) in eligible.iter().enumerate() {
+                let share = if position + 1 == eligible.len() {
+                    left
+                } else {
+                    (amount as u128).min(eligible_total) * remaining(index) as u128 / eligible_total
+                };
+                let share = share.min(left);
+                left -= share;
+                amounts.push((index, share as u64));
+            }
+            amounts
+        }
+        DiscountKind::BuyXGetY { buy, get } => eligible
+            .iter()
+            .map(|&index| {
+                let line = &lines[index];
+                let free_units = buy
+                    .checked_add(get)
+                    .map_or(0, |group| (line.quantity / group).saturating_mul(get));
+                (index, free_units.saturating_mul(line.unit_price).min(remaining(index)))
+            })
+            .collect(),
+    }
+}
+
+// Short description of a discount shown on quotes and orders
+fn _describe(kind: &DiscountKind) -> String {
+    match *kind {
+        DiscountKind::Percentage { basis_points } => {
+            format!("{}.{:02}% off", basis_points / 100, basis_points % 100)
+        }
+        DiscountKind::FixedAmount { amount } => format!("{} off", amount),
+        DiscountKind::BuyXGetY { buy, get } => format!("buy {} get {} free", buy, get),
+    }
+}
+
+// Coupon codes are case-insensitive and stored in upper case
+fn _normalize_code(code: &str) -> Result<String, Error> {
+    let code = code.trim().to_ascii_uppercase();
+    if code.len() < MIN_CODE_LENGTH
+        || code.len() > MAX_CODE_LENGTH
+        || !code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(Error::ValidationFailed {
+            msg: format!(
+                "a coupon code must be {} to {} letters, digits, '-' or '_'",
+                MIN_CODE_LENGTH, MAX_CODE_LENGTH
+            ),
+        });
+    }
+    Ok(code)
+}
+
+fn _get_usage(code: &str, buyer: Principal) -> u64 {
+    COUPON_USAGE
+        .with(|service| {
+            service
+                .borrow()
+                .get(&(StringKey(code.to_string()), PrincipalKey(buyer)))
+        })
+        .unwrap_or_default()
+}
+
+fn _get_coupon(code: &str) -> Result<Coupon, Error> {
+    COUPON_STORAGE
+        .with(|service| service.borrow().get(&StringKey(code.to_string())))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("a coupon with code={} not found", code),
+        })
+}
+
+// Whether the seller has active coupons
+pub(crate) fn has_active_coupons(seller: Principal) -> bool {
+    COUPON_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .any(|(_, coupon)| coupon.active && coupon.seller == Some(seller))
+    })
+}
+
+fn do_insert_coupon(coupon: &mut Coupon) {
+    coupon.version = Some(coupon.version.unwrap_or_default() + 1);
+    COUPON_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert(StringKey(coupon.code.clone()), coupon.clone())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(quantity: u64, unit_price: u64) -> OrderLine {
+        OrderLine {
+            accessory_id: 1,
+            name: "cable".to_string(),
+            quantity,
+            unit_price,
+            serials: Vec::new(),
+            discounts: Some(Vec::new()),
+            line_total: Some(quantity * unit_price),
+            tax: None,
+            sale_id: None,
+        }
+    }
+
+    #[test]
+    fn buy_x_get_y_discounts_whole_groups() {
+        let kind = DiscountKind::BuyXGetY { buy: 2, get: 1 };
+        assert_eq!(_discount_amounts(&kind, &[line(7, 100)], &[0]), vec![(0, 200)]);
+        assert_eq!(_discount_amounts(&kind, &[line(2, 100)], &[0]), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn buy_x_get_y_does_not_overflow() {
+        let kind = DiscountKind::BuyXGetY {
+            buy: u64::MAX,
+            get: u64::MAX,
+        };
+        assert_eq!(_discount_amounts(&kind, &[line(7, 100)], &[0]), vec![(0, 0)]);
+        let kind = DiscountKind::BuyXGetY {
+            buy: 1,
+            get: u64::MAX - 1,
+        };
+        assert_eq!(
+            _discount_amounts(&kind, &[line(u64::MAX, 1)], &[0]),
+            vec![(0, u64::MAX - 1)]
+        );
+    }
+
+    #[test]
+    fn coupon_amounts_need_an_order_currency() {
+        let mut coupon = Coupon {
+            code: "SAVE10".to_string(),
+            created_by: Principal::anonymous(),
+            seller: None,
+            category: None,
+            kind: DiscountKind::FixedAmount { amount: 1_000 },
+            min_order_value: 5_000,
+            currency: None,
+            max_uses: None,
+            max_uses_per_principal: None,
+            starts_at: 0,
+            ends_at: None,
+            uses: 0,
+            active: true,
+            created_at: 0,
+            version: None,
+        };
+        assert_eq!(
+            _in_order_currency(&coupon, 1_000, &None, RoundingMode::Down).ok(),
+            Some(1_000)
+        );
+        coupon.currency = Some("EUR".to_string());
+        assert!(_in_order_currency(&coupon, 1_000, &None, RoundingMode::Down).is_err());
+        assert_eq!(_in_order_currency(&coupon, 0, &None, RoundingMode::Up).ok(), Some(0));
+    }
+}
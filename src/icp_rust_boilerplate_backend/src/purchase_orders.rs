@@ -0,0 +1,406 @@
+// Purchase orders raised against suppliers and the goods receipts that fulfil them
+use crate::inventory::{check_stock_movement, record_stock_movement, StockReason};
+use crate::suppliers::{_get_supplier, _get_supplier_link};
+use crate::{
+    _check_if_seller_or_staff, _check_version, _get_accessory, next_id, rate_limit, roles, Error, Memory, PrincipalKey,
+    MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Maximum number of lines on a single purchase order
+const MAX_PURCHASE_ORDER_LINES: usize = 50;
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+// Seller, expected date and ID of a purchase order that is still waiting for goods
+type OpenOrderKey = (PrincipalKey, (u64, u64));
+
+// Define the lifecycle of a purchase order
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum PurchaseOrderStatus {
+    Open,
+    PartiallyReceived,
+    Received,
+    Cancelled,
+}
+
+// Define a single line of a purchase order
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PurchaseOrderLine {
+    accessory_id: u64,
+    quantity: u64,
+    unit_cost: u64,
+    received_quantity: u64,
+}
+
+// Define the structure representing a purchase order
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PurchaseOrder {
+    id: u64,
+    supplier_id: u64,
+    seller: Principal,
+    lines: Vec<PurchaseOrderLine>,
+    status: PurchaseOrderStatus,
+    expected_at: u64,
+    created_at: u64,
+    updated_at: Option<u64>,
+    // Incremented on every change
+    version: Option<u64>,
+}
+
+// Implement the Storable trait for PurchaseOrder
+impl Storable for PurchaseOrder {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for PurchaseOrder
+impl BoundedStorable for PurchaseOrder {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define a payload structure for a purchase order line
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct PurchaseOrderLinePayload {
+    accessory_id: u64,
+    quantity: u64,
+    // Defaults to the cost price of the accessory's link to the supplier
+    unit_cost: Option<u64>,
+}
+
+// Define a payload structure for raising a purchase order
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct PurchaseOrderPayload {
+    supplier_id: u64,
+    lines: Vec<PurchaseOrderLinePayload>,
+    // Defaults to now plus the supplier's lead time
+    expected_at: Option<u64>,
+}
+
+// Define a single line of a goods receipt
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct GoodsReceiptLine {
+    accessory_id: u64,
+    quantity: u64,
+}
+
+thread_local! {
+    static PURCHASE_ORDER_STORAGE: RefCell<StableBTreeMap<u64, PurchaseOrder, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10))))
+    );
+
+    // Purchase orders keyed by (seller, purchase_order_id)
+    static SELLER_PURCHASE_ORDER_INDEX: RefCell<StableBTreeMap<(PrincipalKey, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(62))))
+    );
+
+    // Purchase orders still waiting for goods, in order of their expected date per seller
+    static OPEN_PURCHASE_ORDER_INDEX: RefCell<StableBTreeMap<OpenOrderKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(63))))
+    );
+}
+
+// Update function to raise a purchase order for one seller's accessories
+#[ic_cdk::update]
+fn create_purchase_order(payload: PurchaseOrderPayload) -> Result<PurchaseOrder, Error> {
+    rate_limit::_check_rate_limit("create_purchase_order")?;
+    let supplier = _get_supplier(&payload.supplier_id).ok_or_else(|| Error::NotFound {
+        msg: format!("a supplier with id={} not found", payload.supplier_id),
+    })?;
+    if payload.lines.is_empty() || payload.lines.len() > MAX_PURCHASE_ORDER_LINES {
+        return Err(Error::ValidationFailed {
+            msg: format!(
+                "a purchase order must have between 1 and {} lines",
+                MAX_PURCHASE_ORDER_LINES
+            ),
+        });
+    }
+
+    let mut seller: Option<Principal> = None;
+    let mut lines = Vec::with_capacity(payload.lines.len());
+    for line in payload.lines {
+        let accessory = _get_accessory(&line.accessory_id).ok_or_else(|| Error::NotFound {
+            msg: format!("an accessory with id={} not found", line.accessory_id),
+        })?;
+        _check_if_seller_or_staff(&accessory)?;
+        if *seller.get_or_insert(accessory.seller) != accessory.seller {
+            return Err(Error::ValidationFailed {
+                msg: "all lines of a purchase order must belong to the same seller".to_string(),
+            });
+        }
+        if line.quantity == 0 {
+            return Err(Error::ValidationFailed {
+                msg: format!("quantity for accessory with id={} must be positive", line.accessory_id),
+            });
+        }
+        if lines
+            .iter()
+            .any(|existing: &PurchaseOrderLine| existing.accessory_id == line.accessory_id)
+        {
+            return Err(Error::ValidationFailed {
+                msg: format!("accessory with id={} appears more than once", line.accessory_id),
+            });
+        }
+        let unit_cost = match line.unit_cost {
+            Some(unit_cost) => unit_cost,
+            None => _get_supplier_link(line.accessory_id, supplier.id)
+                .map(|link| link.cost_price)
+                .ok_or_else(|| Error::ValidationFailed {
+                    msg: format!(
+                        "accessory with id={} isn't linked to supplier with id={}, a unit cost is required",
+                        line.accessory_id, supplier.id
+                    ),
+                })?,
+        };
+        lines.push(PurchaseOrderLine {
+            accessory_id: line.accessory_id,
+            quantity: line.quantity,
+            unit_cost,
+            received_quantity: 0,
+        });
+    }
+
+    let now = time();
+    let mut order = PurchaseOrder {
+        id: next_id(),
+        supplier_id: supplier.id,
+        seller: seller.unwrap_or_else(Principal::anonymous),
+        lines,
+        status: PurchaseOrderStatus::Open,
+        expected_at: payload
+            .expected_at
+            .unwrap_or_else(|| now.saturating_add(u64::from(supplier.lead_time_days).saturating_mul(NANOS_PER_DAY))),
+        created_at: now,
+        updated_at: None,
+        version: None,
+    };
+    do_insert_purchase_order(&mut order);
+    Ok(order)
+}
+
+// Update function to record a partial or full goods receipt against a purchase order.
+// Received quantities are added to inventory_count and logged as stock movements.
+#[ic_cdk::update]
+fn receive_purchase_order(
+    id: u64,
+    receipt: Vec<GoodsReceiptLine>,
+    expected_version: Option<u64>,
+) -> Result<PurchaseOrder, Error> {
+    rate_limit::_check_rate_limit("receive_purchase_order")?;
+    let mut order = _get_purchase_order(&id)?;
+    _check_purchase_order_access(&order)?;
+    _check_version(
+        &format!("purchase order with id={}", id),
+        order.version,
+        expected_version,
+    )?;
+    if !matches!(
+        order.status,
+        PurchaseOrderStatus::Open | PurchaseOrderStatus::PartiallyReceived
+    ) {
+        return Err(Error::ValidationFailed {
+            msg: format!("purchase order with id={} is no longer open", id),
+        });
+    }
+    if receipt.is_empty() {
+        return Err(Error::ValidationFailed {
+            msg: "a goods receipt must have at least one line".to_string(),
+        });
+    }
+
+    // Validate the whole receipt before touching any stock
+    for received in &receipt {
+        let outstanding: u64 = order
+            .lines
+            .iter()
+            .find(|line| line.accessory_id == received.accessory_id)
+            .map(|line| line.quantity - line.received_quantity)
+            .ok_or_else(|| Error::ValidationFailed {
+                msg: format!(
+                    "accessory with id={} isn't on purchase order with id={}",
+                    received.accessory_id, id
+                ),
+            })?;
+        let total_received: u64 = receipt
+            .iter()
+            .filter(|other| other.accessory_id == received.accessory_id)
+            .map(|other| other.quantity)
+            .sum();
+        if received.quantity == 0 || total_received > outstanding {
+            return Err(Error::ValidationFailed {
+                msg: format!(
+                    "cannot receive {} of accessory with id={}: {} outstanding",
+                    total_received, received.accessory_id, outstanding
+                ),
+            });
+        }
+        let delta = i64::try_from(total_received).map_err(|_| Error::ValidationFailed {
+            msg: format!(
+                "received quantity of accessory with id={} is too large",
+                received.accessory_id
+            ),
+        })?;
+        check_stock_movement(received.accessory_id, delta, StockReason::Received, &_receipt_note(id))?;
+    }
+
+    for received in receipt {
+        if let Some(line) = order
+            .lines
+            .iter_mut()
+            .find(|line| line.accessory_id == received.accessory_id)
+        {
+            // Checked above, so this can't fail halfway through the receipt
+            let delta = i64::try_from(received.quantity).unwrap_or_default();
+            record_stock_movement(
+                received.accessory_id,
+                delta,
+                StockReason::Received,
+                _receipt_note(id),
+                Some(line.unit_cost),
+            )?;
+            line.received_quantity += received.quantity;
+        }
+    }
+    order.status = if order.lines.iter().all(|line| line.received_quantity >= line.quantity) {
+        PurchaseOrderStatus::Received
+    } else {
+        PurchaseOrderStatus::PartiallyReceived
+    };
+    order.updated_at = Some(time());
+    do_insert_purchase_order(&mut order);
+    Ok(order)
+}
+
+// Update function to cancel a purchase order that has not been received yet
+#[ic_cdk::update]
+fn cancel_purchase_order(id: u64, expected_version: Option<u64>) -> Result<PurchaseOrder, Error> {
+    rate_limit::_check_rate_limit("cancel_purchase_order")?;
+    let mut order = _get_purchase_order(&id)?;
+    _check_purchase_order_access(&order)?;
+    _check_version(
+        &format!("purchase order with id={}", id),
+        order.version,
+        expected_version,
+    )?;
+    if order.status != PurchaseOrderStatus::Open {
+        return Err(Error::ValidationFailed {
+            msg: format!("only open purchase orders can be cancelled, id={}", id),
+        });
+    }
+    order.status = PurchaseOrderStatus::Cancelled;
+    order.updated_at = Some(time());
+    do_insert_purchase_order(&mut order);
+    Ok(order)
+}
+
+// Query function to get a purchase order by ID
+#[ic_cdk::query]
+fn get_purchase_order(id: u64) -> Result<PurchaseOrder, Error> {
+    let order = _get_purchase_order(&id)?;
+    _check_purchase_order_access(&order)?;
+    Ok(order)
+}
+
+// Query function to get the caller's purchase orders, optionally filtered by status
+#[ic_cdk::query]
+fn get_purchase_orders(status: Option<PurchaseOrderStatus>) -> Vec<PurchaseOrder> {
+    let seller = PrincipalKey(caller());
+    let ids: Vec<u64> = SELLER_PURCHASE_ORDER_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((seller, 0)..=(seller, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    _get_purchase_orders(&ids)
+        .into_iter()
+        .filter(|order| status.is_none_or(|status| order.status == status))
+        .collect()
+}
+
+// Query function to get the caller's purchase orders that are past their expected date
+#[ic_cdk::query]
+fn get_overdue_purchase_orders() -> Vec<PurchaseOrder> {
+    _get_open_purchase_orders(caller(), time())
+}
+
+// Whether the seller has a purchase order still waiting for any of the accessories
+pub(crate) fn has_open_orders(seller: Principal, accessory_ids: &[u64]) -> bool {
+    _get_open_purchase_orders(seller, u64::MAX).iter().any(|order| {
+        order
+            .lines
+            .iter()
+            .any(|line| accessory_ids.contains(&line.accessory_id))
+    })
+}
+
+fn do_insert_purchase_order(order: &mut PurchaseOrder) {
+    order.version = Some(order.version.unwrap_or_default() + 1);
+    let seller = PrincipalKey(order.seller);
+    SELLER_PURCHASE_ORDER_INDEX.with(|index| index.borrow_mut().insert((seller, order.id), ()));
+    OPEN_PURCHASE_ORDER_INDEX.with(|index| {
+        let key = (seller, (order.expected_at, order.id));
+        if matches!(
+            order.status,
+            PurchaseOrderStatus::Open | PurchaseOrderStatus::PartiallyReceived
+        ) {
+            index.borrow_mut().insert(key, ());
+        } else {
+            index.borrow_mut().remove(&key);
+        }
+    });
+    PURCHASE_ORDER_STORAGE.with(|service| service.borrow_mut().insert(order.id, order.clone()));
+}
+
+// The seller's purchase orders still waiting for goods that were expected before `before`
+fn _get_open_purchase_orders(seller: Principal, before: u64) -> Vec<PurchaseOrder> {
+    let seller = PrincipalKey(seller);
+    let ids: Vec<u64> = OPEN_PURCHASE_ORDER_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((seller, (0, 0))..(seller, (before, 0)))
+            .map(|((_, (_, id)), _)| id)
+            .collect()
+    });
+    _get_purchase_orders(&ids)
+}
+
+fn _get_purchase_orders(ids: &[u64]) -> Vec<PurchaseOrder> {
+    PURCHASE_ORDER_STORAGE.with(|service| {
+        let service = service.borrow();
+        ids.iter().filter_map(|id| service.get(id)).collect()
+    })
+}
+
+fn _get_purchase_order(id: &u64) -> Result<PurchaseOrder, Error> {
+    PURCHASE_ORDER_STORAGE
+        .with(|service| service.borrow().get(id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("a purchase order with id={} not found", id),
+        })
+}
+
+fn _receipt_note(order_id: u64) -> String {
+    format!("purchase order #{}", order_id)
+}
+
+// Helper function to check whether the caller may manage a purchase order
+fn _check_purchase_order_access(order: &PurchaseOrder) -> Result<(), Error> {
+    if order.seller == caller() || roles::_is_staff(&caller()) {
+        Ok(())
+    } else {
+        Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} can't access purchase order with id={}", caller(), order.id),
+        })
+    }
+}
@@ -0,0 +1,421 @@
+// Per-principal rate limiting of update calls with token buckets
+use crate::{roles, Error, Memory, PrincipalKey, StringKey, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+// Quota for methods without one of their own: bursts of 30 calls, then one call every 2 seconds
+const DEFAULT_QUOTA: RateQuota = RateQuota {
+    capacity: 30,
+    refill_interval: 2_000_000_000,
+};
+// Most buckets kept on the heap; past this, full buckets are dropped and then the
+// least recently refilled ones, which only makes those principals start over with a full bucket
+const MAX_BUCKETS: usize = 20_000;
+// Most principals whose call counts are kept; past this the least active one is dropped
+const MAX_TRACKED_CALLERS: usize = 10_000;
+// Longest a principal can be blocked for (30 days)
+const MAX_BLOCK_DURATION: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+// Define the quota of calls a principal may make to a method
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct RateQuota {
+    // Calls that can be made in a burst
+    capacity: u32,
+    // Nanoseconds until another call is allowed
+    refill_interval: u64,
+}
+
+// Implement the Storable trait for RateQuota
+impl Storable for RateQuota {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for RateQuota
+impl BoundedStorable for RateQuota {
+    const MAX_SIZE: u32 = 64;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define a principal that is blocked from making update calls
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Block {
+    principal: Principal,
+    blocked_by: Principal,
+    blocked_until: u64,
+    reason: String,
+}
+
+// Implement the Storable trait for Block
+impl Storable for Block {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for Block
+impl BoundedStorable for Block {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define the call counts of a principal since the last upgrade
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CallerStats {
+    principal: Principal,
+    calls: u64,
+    rate_limited: u64,
+}
+
+// Tokens left in the bucket of a principal and method
+#[derive(Clone, Copy)]
+struct Bucket {
+    tokens: u32,
+    refilled_at: u64,
+}
+
+thread_local! {
+    static QUOTA_STORAGE: RefCell<StableBTreeMap<StringKey<64>, RateQuota, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(46))))
+    );
+
+    static BLOCK_STORAGE: RefCell<StableBTreeMap<PrincipalKey, Block, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(47))))
+    );
+
+    // Buckets and call counts live on the heap; after an upgrade every bucket starts full
+    static BUCKETS: RefCell<BTreeMap<(Principal, String), Bucket>> = const { RefCell::new(BTreeMap::new()) };
+    static CALLER_STATS: RefCell<BTreeMap<Principal, CallerStats>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+// Update function to set the quota of a method (admin only)
+#[ic_cdk::update]
+fn set_rate_limit(method: String, quota: RateQuota) -> Result<(), Error> {
+    roles::_check_if_admin()?;
+    if method.is_empty() || method.len() > 64 {
+        return Err(Error::ValidationFailed {
+            msg: "a method name must be between 1 and 64 bytes long".to_string(),
+        });
+    }
+    if quota.capacity == 0 || quota.refill_interval == 0 {
+        return Err(Error::ValidationFailed {
+            msg: "a quota needs a capacity and a refill interval above zero".to_string(),
+        });
+    }
+    QUOTA_STORAGE.with(|service| service.borrow_mut().insert(StringKey(method), quota));
+    Ok(())
+}
+
+// Update function to make a method fall back to the default quota (admin only)
+#[ic_cdk::update]
+fn remove_rate_limit(method: String) -> Result<(), Error> {
+    roles::_check_if_admin()?;
+    match QUOTA_STORAGE.with(|service| service.borrow_mut().remove(&StringKey(method.clone()))) {
+        Some(_) => Ok(()),
+        None => Err(Error::NotFound {
+            msg: format!("method={} has no quota of its own", method),
+        }),
+    }
+}
+
+// Query function to get the quotas configured per method and the default quota
+#[ic_cdk::query]
+fn get_rate_limits() -> (RateQuota, Vec<(String, RateQuota)>) {
+    let quotas = QUOTA_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(method, quota)| (method.0, quota))
+            .collect()
+    });
+    (DEFAULT_QUOTA, quotas)
+}
+
+// Update function to block a principal from update calls for `duration` nanoseconds (admin only)
+#[ic_cdk::update]
+fn block_principal(principal: Principal, duration: u64, reason: String) -> Result<Block, Error> {
+    roles::_check_if_admin()?;
+    if roles::_is_admin(&principal) {
+        return Err(Error::ValidationFailed {
+            msg: format!("admin {} can't be blocked", principal),
+        });
+    }
+    if duration == 0 || duration > MAX_BLOCK_DURATION || reason.len() > 256 {
+        return Err(Error::ValidationFailed {
+            msg: "a block needs a duration of up to 30 days and a reason of at most 256 bytes".to_string(),
+        });
+    }
+    let block = Block {
+        principal,
+        blocked_by: caller(),
+        blocked_until: time().saturating_add(duration),
+        reason,
+    };
+    BLOCK_STORAGE.with(|service| service.borrow_mut().insert(PrincipalKey(principal), block.clone()));
+    Ok(block)
+}
+
+// Update function to lift the block of a principal (admin only)
+#[ic_cdk::update]
+fn unblock_principal(principal: Principal) -> Result<(), Error> {
+    roles::_check_if_admin()?;
+    match BLOCK_STORAGE.with(|service| service.borrow_mut().remove(&PrincipalKey(principal))) {
+        Some(_) => Ok(()),
+        None => Err(Error::NotFound {
+            msg: format!("principal={} isn't blocked", principal),
+        }),
+    }
+}
+
+// Query function to get the principals that are blocked right now (admin only)
+#[ic_cdk::query]
+fn get_blocked_principals() -> Result<Vec<Block>, Error> {
+    roles::_check_if_admin()?;
+    let now = time();
+    Ok(BLOCK_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, block)| block)
+            .filter(|block| block.blocked_until > now)
+            .collect()
+    }))
+}
+
+// Query function to get the principals making the most rate limited calls since the last upgrade (admin only)
+#[ic_cdk::query]
+fn get_top_callers(limit: u64) -> Result<Vec<CallerStats>, Error> {
+    roles::_check_if_admin()?;
+    let mut stats: Vec<CallerStats> = CALLER_STATS.with(|stats| stats.borrow().values().cloned().collect());
+    stats.sort_by(|a, b| b.calls.cmp(&a.calls).then(b.rate_limited.cmp(&a.rate_limited)));
+    stats.truncate(limit.clamp(1, 100) as usize);
+    Ok(stats)
+}
+
+// Take a call to `method` out of the caller's bucket. Admins aren't limited.
+pub(crate) fn _check_rate_limit(method: &str) -> Result<(), Error> {
+    _check_rate_limit_for(method, 1)
+}
+
+// Take `calls` calls to `method` out of the caller's bucket at once, e.g. one per item of
+// a bulk call. More calls than the bucket holds need a full bucket.
+pub(crate) fn _check_rate_limit_for(method: &str, calls: usize) -> Result<(), Error> {
+    let principal = caller();
+    if roles::_is_admin(&principal) {
+        return Ok(());
+    }
+    let now = time();
+    let quota = _quota(method);
+    let cost = _cost(calls, quota);
+    let result = match _wait_for(&principal, method, quota, cost, now) {
+        Some(retry_after) => Err(Error::RateLimited {
+            msg: format!("Caller={} made too many calls to {}", principal, method),
+            retry_after,
+        }),
+        None => {
+            BUCKETS.with(|buckets| {
+                let mut buckets = buckets.borrow_mut();
+                let key = (principal, method.to_string());
+                let mut bucket = _refill(buckets.get(&key).copied(), quota, now);
+                bucket.tokens -= cost;
+                buckets.insert(key, bucket);
+                if buckets.len() > MAX_BUCKETS {
+                    _evict_buckets(&mut buckets, now);
+                }
+            });
+            Ok(())
+        }
+    };
+    CALLER_STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        if !stats.contains_key(&principal) && stats.len() >= MAX_TRACKED_CALLERS {
+            _evict_least_active(&mut stats);
+        }
+        let entry = stats.entry(principal).or_insert_with(|| CallerStats {
+            principal,
+            calls: 0,
+            rate_limited: 0,
+        });
+        entry.calls += 1;
+        if result.is_err() {
+            entry.rate_limited += 1;
+        }
+    });
+    result
+}
+
+// Nanoseconds until a principal may call `method` again, or None if it may call it now.
+// Doesn't change any state, so it can be used while inspecting messages.
+pub(crate) fn _throttled_for(principal: &Principal, method: &str, now: u64) -> Option<u64> {
+    _wait_for(principal, method, _quota(method), 1, now)
+}
+
+// Nanoseconds until the bucket of a principal holds `cost` tokens, or None if it does now
+fn _wait_for(principal: &Principal, method: &str, quota: RateQuota, cost: u32, now: u64) -> Option<u64> {
+    if let Some(block) = BLOCK_STORAGE.with(|service| service.borrow().get(&PrincipalKey(*principal))) {
+        if block.blocked_until > now {
+            return Some(block.blocked_until - now);
+        }
+    }
+    let bucket = BUCKETS.with(|buckets| buckets.borrow().get(&(*principal, method.to_string())).copied());
+    let bucket = _refill(bucket, quota, now);
+    (bucket.tokens < cost).then(|| {
+        let missing = (cost - bucket.tokens) as u64;
+        (bucket.refilled_at + missing * quota.refill_interval).saturating_sub(now)
+    })
+}
+
+fn _cost(calls: usize, quota: RateQuota) -> u32 {
+    calls.clamp(1, quota.capacity as usize) as u32
+}
+
+// Drop the buckets that have refilled completely, as a missing bucket starts out full anyway.
+// If that isn't enough, drop the least recently refilled buckets too.
+fn _evict_buckets(buckets: &mut BTreeMap<(Principal, String), Bucket>, now: u64) {
+    let mut quotas = BTreeMap::new();
+    buckets.retain(|(_, method), bucket| {
+        let quota = *quotas.entry(method.clone()).or_insert_with(|| _quota(method));
+        _refill(Some(*bucket), quota, now).tokens < quota.capacity
+    });
+    // Leave some room so the next calls don't sweep again right away
+    let keep = MAX_BUCKETS * 3 / 4;
+    if buckets.len() > keep {
+        let mut refills: Vec<u64> = buckets.values().map(|bucket| bucket.refilled_at).collect();
+        refills.sort_unstable();
+        let cutoff = refills[buckets.len() - keep];
+        buckets.retain(|_, bucket| bucket.refilled_at >= cutoff);
+    }
+}
+
+fn _evict_least_active(stats: &mut BTreeMap<Principal, CallerStats>) {
+    let least_active = stats
+        .values()
+        .min_by_key(|entry| (entry.calls, entry.rate_limited))
+        .map(|entry| entry.principal);
+    if let Some(principal) = least_active {
+        stats.remove(&principal);
+    }
+}
+
+fn _quota(method: &str) -> RateQuota {
+    QUOTA_STORAGE
+        .with(|service| service.borrow().get(&StringKey(method.to_string())))
+        .unwrap_or(DEFAULT_QUOTA)
+}
+
+// Add the tokens earned since the last refill, up to the capacity of the quota
+fn _refill(bucket: Option<Bucket>, quota: RateQuota, now: u64) -> Bucket {
+    let Some(bucket) = bucket else {
+        return Bucket {
+            tokens: quota.capacity,
+            refilled_at: now,
+        };
+    };
+    let earned = now.saturating_sub(bucket.refilled_at) / quota.refill_interval;
+    let tokens = (bucket.tokens as u64).saturating_add(earned);
+    if tokens >= quota.capacity as u64 {
+        Bucket {
+            tokens: quota.capacity,
+            refilled_at: now,
+        }
+    } else {
+        Bucket {
+            tokens: tokens as u32,
+            refilled_at: bucket.refilled_at + earned * quota.refill_interval,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUOTA: RateQuota = RateQuota {
+        capacity: 3,
+        refill_interval: 10,
+    };
+
+    #[test]
+    fn buckets_refill_one_token_per_interval_up_to_capacity() {
+        let bucket = _refill(None, QUOTA, 100);
+        assert_eq!(bucket.tokens, 3);
+        let drained = Bucket {
+            tokens: 0,
+            refilled_at: 100,
+        };
+        let bucket = _refill(Some(drained), QUOTA, 125);
+        assert_eq!(bucket.tokens, 2);
+        // The part of an interval that hasn't passed yet carries over
+        assert_eq!(bucket.refilled_at, 120);
+        assert_eq!(_refill(Some(drained), QUOTA, 1_000).tokens, 3);
+    }
+
+    #[test]
+    fn bulk_calls_cost_one_token_per_item_up_to_a_full_bucket() {
+        assert_eq!(_cost(0, QUOTA), 1);
+        assert_eq!(_cost(2, QUOTA), 2);
+        assert_eq!(_cost(50, QUOTA), 3);
+    }
+
+    #[test]
+    fn eviction_drops_full_buckets_first() {
+        let mut buckets = BTreeMap::new();
+        let method = "eviction_test".to_string();
+        for i in 0..(MAX_BUCKETS + 1) as u64 {
+            let principal = Principal::from_slice(&i.to_be_bytes());
+            buckets.insert(
+                (principal, method.clone()),
+                Bucket {
+                    tokens: 0,
+                    refilled_at: i,
+                },
+            );
+        }
+        let idle = Principal::from_slice(&[0xff]);
+        buckets.insert(
+            (idle, method.clone()),
+            Bucket {
+                tokens: DEFAULT_QUOTA.capacity,
+                refilled_at: 0,
+            },
+        );
+        _evict_buckets(&mut buckets, MAX_BUCKETS as u64);
+        assert!(!buckets.contains_key(&(idle, method.clone())));
+        assert_eq!(buckets.len(), MAX_BUCKETS * 3 / 4);
+        // The most recently refilled buckets are kept
+        let newest = Principal::from_slice(&(MAX_BUCKETS as u64).to_be_bytes());
+        assert!(buckets.contains_key(&(newest, method)));
+    }
+
+    #[test]
+    fn the_least_active_caller_is_dropped() {
+        let mut stats = BTreeMap::new();
+        for (i, calls) in [5u64, 1, 9].into_iter().enumerate() {
+            let principal = Principal::from_slice(&[i as u8]);
+            stats.insert(
+                principal,
+                CallerStats {
+                    principal,
+                    calls,
+                    rate_limited: 0,
+                },
+            );
+        }
+        _evict_least_active(&mut stats);
+        assert_eq!(stats.len(), 2);
+        assert!(!stats.contains_key(&Principal::from_slice(&[1])));
+    }
+}
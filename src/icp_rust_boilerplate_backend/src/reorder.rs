@@ -0,0 +1,345 @@
+// Reorder points, low-stock alerts and purchase suggestions
+use crate::suppliers::{_get_reorder_supplier, _get_supplier};
+use crate::{
+    _check_if_seller_or_staff, _get_accessory, next_id, rate_limit, sellers, Accessory, Error, Memory, PrincipalKey,
+    ACCESSORY_STORAGE, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+// Accessories evaluated per scheduler run, to stay within the instruction limit
+const REORDER_CHECK_BATCH_SIZE: usize = 500;
+// Resolved alerts are kept for 30 days, then pruned by the scheduler
+const RESOLVED_ALERT_RETENTION: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+// Define the lifecycle of a low-stock alert
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum AlertStatus {
+    New,
+    Acknowledged,
+    Resolved,
+}
+
+// Define the structure representing a low-stock alert
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct StockAlert {
+    id: u64,
+    accessory_id: u64,
+    seller: Principal,
+    inventory_count: u64,
+    reorder_point: u64,
+    status: AlertStatus,
+    created_at: u64,
+    acknowledged_at: Option<u64>,
+    resolved_at: Option<u64>,
+}
+
+// Implement the Storable trait for StockAlert
+impl Storable for StockAlert {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for StockAlert
+impl BoundedStorable for StockAlert {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define a single line of a suggested purchase
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ReorderLine {
+    accessory_id: u64,
+    name: String,
+    inventory_count: u64,
+    reorder_point: u64,
+    suggested_quantity: u64,
+    unit_cost: Option<u64>,
+}
+
+// Define the suggested purchases for one supplier
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SupplierReorderSuggestion {
+    // None groups the accessories that are not linked to any supplier
+    supplier_id: Option<u64>,
+    supplier_name: Option<String>,
+    lines: Vec<ReorderLine>,
+}
+
+thread_local! {
+    static ALERT_STORAGE: RefCell<StableBTreeMap<u64, StockAlert, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6))))
+    );
+
+    // Index of alerts that are not resolved yet, keyed by accessory id
+    static OPEN_ALERT_INDEX: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7))))
+    );
+
+    // Accessories whose stock or reorder point changed since they were last evaluated
+    static REORDER_CHECK_QUEUE: RefCell<StableBTreeMap<u64, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(51))))
+    );
+
+    // Alerts keyed by (seller, alert_id)
+    static SELLER_ALERT_INDEX: RefCell<StableBTreeMap<(PrincipalKey, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(60))))
+    );
+
+    // Resolved alerts keyed by (resolved_at, alert_id), for pruning
+    static RESOLVED_ALERT_INDEX: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(61))))
+    );
+}
+
+// Query function to get the low-stock alerts of the caller's listings, newest first.
+// Resolved alerts are only kept for RESOLVED_ALERT_RETENTION.
+#[ic_cdk::query]
+fn get_stock_alerts(include_resolved: bool) -> Vec<StockAlert> {
+    let seller = caller();
+    let ids: Vec<u64> = SELLER_ALERT_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((PrincipalKey(seller), 0)..=(PrincipalKey(seller), u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    let mut alerts: Vec<StockAlert> = ALERT_STORAGE.with(|service| {
+        let service = service.borrow();
+        ids.iter()
+            .filter_map(|id| service.get(id))
+            .filter(|alert| include_resolved || alert.status != AlertStatus::Resolved)
+            .collect()
+    });
+    alerts.reverse();
+    alerts
+}
+
+// Update function to acknowledge a new low-stock alert
+#[ic_cdk::update]
+fn acknowledge_stock_alert(id: u64) -> Result<StockAlert, Error> {
+    rate_limit::_check_rate_limit("acknowledge_stock_alert")?;
+    let mut alert = ALERT_STORAGE
+        .with(|service| service.borrow().get(&id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("an alert with id={} not found", id),
+        })?;
+    let accessory = _get_accessory(&alert.accessory_id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", alert.accessory_id),
+    })?;
+    _check_if_seller_or_staff(&accessory)?;
+    if alert.status != AlertStatus::New {
+        return Err(Error::ValidationFailed {
+            msg: format!("alert with id={} was already acknowledged or resolved", id),
+        });
+    }
+    alert.status = AlertStatus::Acknowledged;
+    alert.acknowledged_at = Some(time());
+    ALERT_STORAGE.with(|service| service.borrow_mut().insert(id, alert.clone()));
+    Ok(alert)
+}
+
+// Query function to get the caller's suggested purchases grouped by supplier
+#[ic_cdk::query]
+fn get_reorder_suggestions() -> Vec<SupplierReorderSuggestion> {
+    let low_stock: Vec<Accessory> = sellers::listing_ids(&caller())
+        .iter()
+        .filter_map(_get_accessory)
+        .collect();
+    let mut by_supplier: BTreeMap<Option<u64>, Vec<ReorderLine>> = BTreeMap::new();
+    for accessory in low_stock {
+        if let Some(mut line) = _reorder_line(&accessory) {
+            let link = _get_reorder_supplier(accessory.id);
+            line.unit_cost = link.as_ref().map(|link| link.cost_price);
+            by_supplier
+                .entry(link.map(|link| link.supplier_id))
+                .or_default()
+                .push(line);
+        }
+    }
+    by_supplier
+        .into_iter()
+        .map(|(supplier_id, lines)| SupplierReorderSuggestion {
+            supplier_id,
+            supplier_name: supplier_id
+                .and_then(|id| _get_supplier(&id))
+                .map(|supplier| supplier.name),
+            lines,
+        })
+        .collect()
+}
+
+// Queue an accessory for the next evaluation of reorder points. Called whenever an
+// accessory is stored.
+pub(crate) fn queue_reorder_check(accessory_id: u64) {
+    REORDER_CHECK_QUEUE.with(|queue| queue.borrow_mut().insert(accessory_id, ()));
+}
+
+// Open alerts for queued accessories at or below their reorder point, resolve the
+// ones whose stock has recovered and prune old resolved alerts. Run periodically by
+// the scheduler.
+pub(crate) fn evaluate_reorder_points(now: u64) {
+    _prune_resolved_alerts(now);
+    let ids: Vec<u64> = REORDER_CHECK_QUEUE.with(|queue| {
+        queue
+            .borrow()
+            .iter()
+            .take(REORDER_CHECK_BATCH_SIZE)
+            .map(|(id, _)| id)
+            .collect()
+    });
+    for id in ids {
+        REORDER_CHECK_QUEUE.with(|queue| queue.borrow_mut().remove(&id));
+        // Accessories in the trash don't need restocking, so their alerts are resolved
+        let Some(accessory) = ACCESSORY_STORAGE
+            .with(|service| service.borrow().get(&id))
+            .filter(|accessory| accessory.deleted_at.is_none())
+        else {
+            resolve_open_alert(id, now);
+            continue;
+        };
+        let open_alert = OPEN_ALERT_INDEX.with(|index| index.borrow().get(&accessory.id));
+        let is_low = matches!(accessory.reorder_point, Some(point) if accessory.inventory_count <= point);
+        match (is_low, open_alert) {
+            (true, None) => {
+                let alert = StockAlert {
+                    id: next_id(),
+                    accessory_id: accessory.id,
+                    seller: accessory.seller,
+                    inventory_count: accessory.inventory_count,
+                    reorder_point: accessory.reorder_point.unwrap_or_default(),
+                    status: AlertStatus::New,
+                    created_at: now,
+                    acknowledged_at: None,
+                    resolved_at: None,
+                };
+                ALERT_STORAGE.with(|service| service.borrow_mut().insert(alert.id, alert.clone()));
+                OPEN_ALERT_INDEX.with(|index| index.borrow_mut().insert(accessory.id, alert.id));
+                SELLER_ALERT_INDEX.with(|index| index.borrow_mut().insert((PrincipalKey(alert.seller), alert.id), ()));
+            }
+            (false, Some(_)) => resolve_open_alert(accessory.id, now),
+            _ => {}
+        }
+    }
+}
+
+// Resolve the open alert of an accessory, if it has one
+pub(crate) fn resolve_open_alert(accessory_id: u64, now: u64) {
+    let Some(alert_id) = OPEN_ALERT_INDEX.with(|index| index.borrow_mut().remove(&accessory_id)) else {
+        return;
+    };
+    ALERT_STORAGE.with(|service| {
+        let mut alerts = service.borrow_mut();
+        if let Some(mut alert) = alerts.get(&alert_id) {
+            alert.status = AlertStatus::Resolved;
+            alert.resolved_at = Some(now);
+            alerts.insert(alert_id, alert);
+            RESOLVED_ALERT_INDEX.with(|index| index.borrow_mut().insert((now, alert_id), ()));
+        }
+    });
+}
+
+// Remove alerts resolved more than RESOLVED_ALERT_RETENTION ago, a batch at a time
+fn _prune_resolved_alerts(now: u64) {
+    let cutoff = now.saturating_sub(RESOLVED_ALERT_RETENTION);
+    let due: Vec<(u64, u64)> = RESOLVED_ALERT_INDEX.with(|index| {
+        index
+            .borrow()
+            .range(..(cutoff, 0))
+            .take(REORDER_CHECK_BATCH_SIZE)
+            .map(|(key, _)| key)
+            .collect()
+    });
+    for key in due {
+        RESOLVED_ALERT_INDEX.with(|index| index.borrow_mut().remove(&key));
+        if let Some(alert) = ALERT_STORAGE.with(|service| service.borrow_mut().remove(&key.1)) {
+            SELLER_ALERT_INDEX.with(|index| index.borrow_mut().remove(&(PrincipalKey(alert.seller), alert.id)));
+        }
+    }
+}
+
+// Hand the open alerts of accessories to their new seller
+pub(crate) fn reassign_open_alerts(accessory_ids: &[u64], seller: Principal) {
+    for accessory_id in accessory_ids {
+        let Some(alert_id) = OPEN_ALERT_INDEX.with(|index| index.borrow().get(accessory_id)) else {
+            continue;
+        };
+        ALERT_STORAGE.with(|service| {
+            let mut alerts = service.borrow_mut();
+            if let Some(mut alert) = alerts.get(&alert_id) {
+                SELLER_ALERT_INDEX.with(|index| {
+                    let mut index = index.borrow_mut();
+                    index.remove(&(PrincipalKey(alert.seller), alert_id));
+                    index.insert((PrincipalKey(seller), alert_id), ());
+                });
+                alert.seller = seller;
+                alerts.insert(alert_id, alert);
+            }
+        });
+    }
+}
+
+// Build the purchase suggestion for an accessory that is at or below its reorder point
+fn _reorder_line(accessory: &Accessory) -> Option<ReorderLine> {
+    let reorder_point = accessory.reorder_point?;
+    if accessory.inventory_count > reorder_point {
+        return None;
+    }
+    // Without an explicit reorder quantity, suggest enough to get back above the reorder point
+    let suggested_quantity = accessory
+        .reorder_quantity
+        .unwrap_or_else(|| (reorder_point - accessory.inventory_count).saturating_add(1));
+    Some(ReorderLine {
+        accessory_id: accessory.id,
+        name: accessory.name.clone(),
+        inventory_count: accessory.inventory_count,
+        reorder_point,
+        suggested_quantity,
+        unit_cost: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{do_insert_accessory, tests::largest_accessory};
+
+    #[test]
+    fn alerts_of_trashed_accessories_are_resolved() {
+        let mut accessory = Accessory {
+            id: 1,
+            inventory_count: 1,
+            reorder_point: Some(5),
+            deleted_at: None,
+            ..largest_accessory()
+        };
+        do_insert_accessory(&mut accessory);
+        evaluate_reorder_points(1);
+        let alert_id = OPEN_ALERT_INDEX
+            .with(|index| index.borrow().get(&1))
+            .expect("an open alert");
+
+        accessory.deleted_at = Some(2);
+        do_insert_accessory(&mut accessory);
+        evaluate_reorder_points(3);
+        assert!(OPEN_ALERT_INDEX.with(|index| index.borrow().get(&1)).is_none());
+        let alert = ALERT_STORAGE.with(|service| service.borrow().get(&alert_id)).unwrap();
+        assert!(alert.status == AlertStatus::Resolved);
+        assert_eq!(alert.resolved_at, Some(3));
+
+        evaluate_reorder_points(3 + RESOLVED_ALERT_RETENTION);
+        assert!(ALERT_STORAGE.with(|service| service.borrow().get(&alert_id)).is_some());
+        evaluate_reorder_points(4 + RESOLVED_ALERT_RETENTION);
+        assert!(ALERT_STORAGE.with(|service| service.borrow().get(&alert_id)).is_none());
+        assert!(RESOLVED_ALERT_INDEX.with(|index| index.borrow().is_empty()));
+    }
+}
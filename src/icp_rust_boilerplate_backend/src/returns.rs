@@ -0,0 +1,631 @@
+// Returns and refunds (RMA) for order lines
+use crate::inventory::{record_stock_movement, StockReason};
+use crate::orders::{_get_order, do_insert_order, Order, OrderLine, OrderStatus};
+use crate::payments::{LedgerTransfer, TransferOutcome};
+use crate::{
+    _check_if_seller_or_staff, _check_version, _get_accessory, next_id, payments, rate_limit, roles, scorecards,
+    serials, Error, Memory, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+// Maximum length of the comments attached to a return
+const MAX_COMMENT_LENGTH: usize = 500;
+
+// Define why a buyer returns an item
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum ReturnReason {
+    Defective,
+    WrongItem,
+    NotAsDescribed,
+    ChangedMind,
+    Other,
+}
+
+// Define the condition a returned item is in
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum ItemCondition {
+    Unopened,
+    Opened,
+    Damaged,
+}
+
+// Define the lifecycle of a return
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum ReturnStatus {
+    Requested,
+    Approved,
+    Rejected,
+    Received,
+    // The refund transfer was sent but the ledger's answer was lost
+    RefundPending,
+    Refunded,
+}
+
+// Define what happens to returned items once received
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum ReturnDisposition {
+    // Back into sellable stock
+    Restock,
+    // Into the damaged bucket, outside sellable stock
+    Damaged,
+}
+
+// Define the structure representing a return request
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ReturnRequest {
+    id: u64,
+    order_id: u64,
+    accessory_id: u64,
+    buyer: Principal,
+    seller: Principal,
+    quantity: u64,
+    serials: Vec<String>,
+    reason: ReturnReason,
+    condition: ItemCondition,
+    comment: String,
+    status: ReturnStatus,
+    seller_note: Option<String>,
+    disposition: Option<ReturnDisposition>,
+    refund_amount: Option<u64>,
+    refund_block_index: Option<u64>,
+    created_at: u64,
+    updated_at: Option<u64>,
+    // Tokens sent back to the buyer, for orders paid through the ledger
+    refund_transfer: Option<LedgerTransfer>,
+    // Incremented on every change
+    version: Option<u64>,
+}
+
+// Implement the Storable trait for ReturnRequest
+impl Storable for ReturnRequest {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for ReturnRequest
+impl BoundedStorable for ReturnRequest {
+    const MAX_SIZE: u32 = 8192;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define a payload structure for requesting a return
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ReturnRequestPayload {
+    order_id: u64,
+    accessory_id: u64,
+    quantity: u64,
+    // The serials being returned, required for serialized accessories
+    serials: Vec<String>,
+    reason: ReturnReason,
+    condition: ItemCondition,
+    comment: String,
+}
+
+thread_local! {
+    static RETURN_STORAGE: RefCell<StableBTreeMap<u64, ReturnRequest, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22))))
+    );
+
+    // Returns keyed by (order_id, return_id)
+    static RETURN_ORDER_INDEX: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(56))))
+    );
+
+    // Returned units that can't be sold again, per accessory
+    static DAMAGED_STOCK: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23))))
+    );
+}
+
+// Update function for a buyer to request the return of (part of) an order line
+#[ic_cdk::update]
+fn request_return(payload: ReturnRequestPayload) -> Result<ReturnRequest, Error> {
+    rate_limit::_check_rate_limit("request_return")?;
+    let order = _get_order(&payload.order_id)?;
+    if order.buyer != caller() {
+        return Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} isn't the buyer of order with id={}", caller(), order.id),
+        });
+    }
+    if order.status == OrderStatus::Cancelled {
+        return Err(Error::ValidationFailed {
+            msg: format!("order with id={} was cancelled", order.id),
+        });
+    }
+    let line = order
+        .lines
+        .iter()
+        .find(|line| line.accessory_id == payload.accessory_id)
+        .ok_or_else(|| Error::NotFound {
+            msg: format!(
+                "order with id={} has no line for accessory with id={}",
+                order.id, payload.accessory_id
+            ),
+        })?;
+    _check_comment(&payload.comment)?;
+
+    // Returns that were not rejected count against the line
+    let active_returns: Vec<ReturnRequest> = _get_returns_for_line(order.id, line.accessory_id)
+        .into_iter()
+        .filter(|existing| existing.status != ReturnStatus::Rejected)
+        .collect();
+    let already_returned: u64 = active_returns.iter().map(|existing| existing.quantity).sum();
+    if payload.quantity == 0 || payload.quantity > line.quantity - already_returned {
+        return Err(Error::ValidationFailed {
+            msg: format!(
+                "can return between 1 and {} of accessory with id={}",
+                line.quantity - already_returned,
+                line.accessory_id
+            ),
+        });
+    }
+    if line.serials.is_empty() != payload.serials.is_empty()
+        || (!payload.serials.is_empty() && payload.serials.len() as u64 != payload.quantity)
+    {
+        return Err(Error::ValidationFailed {
+            msg: "serials must be given for every returned unit of a serialized accessory, and only then".to_string(),
+        });
+    }
+    for (index, serial) in payload.serials.iter().enumerate() {
+        if !line.serials.contains(serial)
+            || payload.serials[..index].contains(serial)
+            || active_returns.iter().any(|existing| existing.serials.contains(serial))
+        {
+            return Err(Error::ValidationFailed {
+                msg: format!("serial {} can't be returned on this order", serial),
+            });
+        }
+    }
+
+    let mut request = ReturnRequest {
+        id: next_id(),
+        order_id: order.id,
+        accessory_id: line.accessory_id,
+        buyer: order.buyer,
+        seller: order.seller,
+        quantity: payload.quantity,
+        serials: payload.serials,
+        reason: payload.reason,
+        condition: payload.condition,
+        comment: payload.comment,
+        status: ReturnStatus::Requested,
+        seller_note: None,
+        disposition: None,
+        refund_amount: None,
+        refund_block_index: None,
+        created_at: time(),
+        updated_at: None,
+        refund_transfer: None,
+        version: None,
+    };
+    if _get_returns_for_order(request.order_id).is_empty() {
+        scorecards::record_order_returned(request.seller);
+    }
+    do_insert_return(&mut request);
+    Ok(request)
+}
+
+// Update function for the seller to approve a requested return
+#[ic_cdk::update]
+fn approve_return(id: u64, note: Option<String>, expected_version: Option<u64>) -> Result<ReturnRequest, Error> {
+    rate_limit::_check_rate_limit("approve_return")?;
+    _decide_return(id, ReturnStatus::Approved, note, expected_version)
+}
+
+// Update function for the seller to reject a requested return
+#[ic_cdk::update]
+fn reject_return(id: u64, note: Option<String>, expected_version: Option<u64>) -> Result<ReturnRequest, Error> {
+    rate_limit::_check_rate_limit("reject_return")?;
+    _decide_return(id, ReturnStatus::Rejected, note, expected_version)
+}
+
+// Update function to record that the returned items arrived, restocking them or
+// moving them to the damaged bucket
+#[ic_cdk::update]
+fn receive_return(
+    id: u64,
+    disposition: ReturnDisposition,
+    expected_version: Option<u64>,
+) -> Result<ReturnRequest, Error> {
+    rate_limit::_check_rate_limit("receive_return")?;
+    let mut request = _get_return(&id)?;
+    _check_return_seller(&request)?;
+    _check_return_version(&request, expected_version)?;
+    _check_return_status(&request, ReturnStatus::Approved)?;
+    if serials::_is_serialized(request.accessory_id) {
+        serials::return_serials(
+            request.accessory_id,
+            &request.serials,
+            disposition == ReturnDisposition::Restock,
+            &format!("return #{}", request.id),
+        )?;
+    } else if disposition == ReturnDisposition::Restock {
+        record_stock_movement(
+            request.accessory_id,
+            request.quantity as i64,
+            StockReason::Returned,
+            format!("return #{}", request.id),
+            None,
+        )?;
+    } else {
+        DAMAGED_STOCK.with(|service| {
+            let mut damaged = service.borrow_mut();
+            let count = damaged.get(&request.accessory_id).unwrap_or_default();
+            damaged.insert(request.accessory_id, count + request.quantity);
+        });
+    }
+    request.status = ReturnStatus::Received;
+    request.disposition = Some(disposition);
+    request.updated_at = Some(time());
+    do_insert_return(&mut request);
+    Ok(request)
+}
+
+// Update function to refund a received return. The amount defaults to the price paid
+// for the returned units and is recorded against the order. When the order was paid
+// through the payment ledger, the matching share of the payment is transferred back to
+// the buyer; a refund whose transfer has an unknown outcome stays pending and calling
+// again retries it.
+#[ic_cdk::update]
+async fn issue_refund(id: u64, amount: Option<u64>) -> Result<ReturnRequest, Error> {
+    rate_limit::_check_rate_limit("issue_refund")?;
+    let mut request = _get_return(&id)?;
+    _check_return_seller(&request)?;
+    if request.status == ReturnStatus::RefundPending {
+        if amount.is_some_and(|amount| Some(amount) != request.refund_amount) {
+            return Err(Error::ValidationFailed {
+                msg: format!(
+                    "return with id={} has a pending refund of a different amount",
+                    request.id
+                ),
+            });
+        }
+        return _send_refund(request).await;
+    }
+    _check_return_status(&request, ReturnStatus::Received)?;
+    let mut order = _get_order(&request.order_id)?;
+    let max_amount = _refund_due(&order, &request);
+    let amount = amount.unwrap_or(max_amount);
+    if amount == 0 || amount > max_amount {
+        return Err(Error::ValidationFailed {
+            msg: format!("refund must be between 1 and {}", max_amount),
+        });
+    }
+    let transfer = match &order.payment {
+        None => None,
+        Some(payment) if payment.block_index.is_none() => {
+            return Err(Error::PaymentFailed {
+                msg: format!("the payment of order with id={} is still pending", order.id),
+            })
+        }
+        Some(payment) => {
+            let refundable = payment.amount.saturating_sub(order.refunded_units.unwrap_or_default());
+            let refunded = order.refunded_amount.unwrap_or_default();
+            // The refund that completes the order sends back whatever is left of the payment
+            let units = if refunded.saturating_add(amount) >= order.total {
+                refundable
+            } else {
+                payments::refund_units(payment, amount, order.total).min(refundable)
+            };
+            (units > 0).then(|| LedgerTransfer {
+                ledger: payment.ledger,
+                account: payment.account,
+                amount: units,
+                memo: payments::memo(b"refund", request.id),
+                created_at_time: time(),
+                block_index: None,
+            })
+        }
+    };
+
+    // Record the refund before calling the ledger so it can't be issued twice
+    // while the transfer is in flight
+    order.refunded_amount = Some(order.refunded_amount.unwrap_or_default().saturating_add(amount));
+    if let Some(transfer) = &transfer {
+        order.refunded_units = Some(order.refunded_units.unwrap_or_default() + transfer.amount);
+    }
+    do_insert_order(&mut order);
+    request.refund_amount = Some(amount);
+    request.updated_at = Some(time());
+    if transfer.is_none() {
+        // Nothing was collected through the ledger, so there are no tokens to send back
+        request.status = ReturnStatus::Refunded;
+        do_insert_return(&mut request);
+        return Ok(request);
+    }
+    request.status = ReturnStatus::RefundPending;
+    request.refund_transfer = transfer;
+    do_insert_return(&mut request);
+    _send_refund(request).await
+}
+
+// Query function to get a return by ID (buyer, seller or staff only)
+#[ic_cdk::query]
+fn get_return(id: u64) -> Result<ReturnRequest, Error> {
+    let request = _get_return(&id)?;
+    if request.buyer != caller() {
+        _check_return_seller(&request)?;
+    }
+    Ok(request)
+}
+
+// Query function to get the returns requested by the caller
+#[ic_cdk::query]
+fn get_my_returns() -> Vec<ReturnRequest> {
+    let buyer = caller();
+    RETURN_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, request)| request.buyer == buyer)
+            .map(|(_, request)| request)
+            .collect()
+    })
+}
+
+// Query function to get the returns on the caller's orders, optionally filtered by status
+#[ic_cdk::query]
+fn get_seller_returns(status: Option<ReturnStatus>) -> Vec<ReturnRequest> {
+    let seller = caller();
+    RETURN_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, request)| request.seller == seller)
+            .filter(|(_, request)| status.is_none_or(|status| request.status == status))
+            .map(|(_, request)| request)
+            .collect()
+    })
+}
+
+// Query function to get how many returned units of an accessory are in the damaged bucket
+#[ic_cdk::query]
+fn get_damaged_stock(accessory_id: u64) -> Result<u64, Error> {
+    let accessory = _get_accessory(&accessory_id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", accessory_id),
+    })?;
+    _check_if_seller_or_staff(&accessory)?;
+    Ok(DAMAGED_STOCK.with(|service| service.borrow().get(&accessory_id).unwrap_or_default()))
+}
+
+// Send, or resend unchanged, the pending refund transfer of a return
+async fn _send_refund(request: ReturnRequest) -> Result<ReturnRequest, Error> {
+    let Some(transfer) = request.refund_transfer.clone() else {
+        return Err(Error::PaymentFailed {
+            msg: format!("return with id={} has no refund transfer", request.id),
+        });
+    };
+    let outcome = payments::pay_out(&transfer).await;
+    let mut request = _get_return(&request.id)?;
+    match outcome {
+        TransferOutcome::Completed(block_index) => {
+            request.status = ReturnStatus::Refunded;
+            request.refund_block_index = Some(block_index);
+            request.refund_transfer = Some(LedgerTransfer {
+                block_index: Some(block_index),
+                ..transfer
+            });
+            request.updated_at = Some(time());
+            do_insert_return(&mut request);
+            Ok(request)
+        }
+        TransferOutcome::Failed(err) => {
+            let amount = request.refund_amount.unwrap_or_default();
+            request.status = ReturnStatus::Received;
+            request.refund_amount = None;
+            request.refund_transfer = None;
+            request.updated_at = Some(time());
+            do_insert_return(&mut request);
+            if let Ok(mut order) = _get_order(&request.order_id) {
+                order.refunded_amount = order.refunded_amount.map(|refunded| refunded.saturating_sub(amount));
+                order.refunded_units = order
+                    .refunded_units
+                    .map(|refunded| refunded.saturating_sub(transfer.amount));
+                do_insert_order(&mut order);
+            }
+            Err(err)
+        }
+        TransferOutcome::Unknown(err) => Err(err),
+    }
+}
+
+// Amounts refunded or being refunded, per order and accessory
+pub(crate) fn refunded_amounts() -> BTreeMap<(u64, u64), u64> {
+    let mut amounts = BTreeMap::new();
+    RETURN_STORAGE.with(|service| {
+        for (_, request) in service.borrow().iter() {
+            if let Some(amount) = request.refund_amount {
+                let refunded: &mut u64 = amounts.entry((request.order_id, request.accessory_id)).or_default();
+                *refunded = refunded.saturating_add(amount);
+            }
+        }
+    });
+    amounts
+}
+
+fn _check_return_version(request: &ReturnRequest, expected_version: Option<u64>) -> Result<(), Error> {
+    _check_version(
+        &format!("return with id={}", request.id),
+        request.version,
+        expected_version,
+    )
+}
+
+fn do_insert_return(request: &mut ReturnRequest) {
+    request.version = Some(request.version.unwrap_or_default() + 1);
+    RETURN_ORDER_INDEX.with(|index| index.borrow_mut().insert((request.order_id, request.id), ()));
+    RETURN_STORAGE.with(|service| service.borrow_mut().insert(request.id, request.clone()));
+}
+
+// Whether an order has returns that were not rejected
+pub(crate) fn has_returns(order_id: u64) -> bool {
+    _get_returns_for_order(order_id)
+        .iter()
+        .any(|request| request.status != ReturnStatus::Rejected)
+}
+
+fn _get_return(id: &u64) -> Result<ReturnRequest, Error> {
+    RETURN_STORAGE
+        .with(|service| service.borrow().get(id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("a return with id={} not found", id),
+        })
+}
+
+fn _get_returns_for_order(order_id: u64) -> Vec<ReturnRequest> {
+    let ids: Vec<u64> = RETURN_ORDER_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((order_id, 0)..=(order_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    RETURN_STORAGE.with(|service| {
+        let service = service.borrow();
+        ids.iter().filter_map(|id| service.get(id)).collect()
+    })
+}
+
+fn _get_returns_for_line(order_id: u64, accessory_id: u64) -> Vec<ReturnRequest> {
+    let mut returns = _get_returns_for_order(order_id);
+    returns.retain(|request| request.accessory_id == accessory_id);
+    returns
+}
+
+fn _decide_return(
+    id: u64,
+    status: ReturnStatus,
+    note: Option<String>,
+    expected_version: Option<u64>,
+) -> Result<ReturnRequest, Error> {
+    let mut request = _get_return(&id)?;
+    _check_return_seller(&request)?;
+    _check_return_version(&request, expected_version)?;
+    _check_return_status(&request, ReturnStatus::Requested)?;
+    if let Some(note) = &note {
+        _check_comment(note)?;
+    }
+    request.status = status;
+    request.seller_note = note;
+    request.updated_at = Some(time());
+    do_insert_return(&mut request);
+    Ok(request)
+}
+
+// Price paid for the units of a return. Each return takes its share of what is left of the
+// line, so returning every unit refunds the whole line; the return that completes the
+// order also refunds the shipping charge.
+fn _refund_due(order: &Order, request: &ReturnRequest) -> u64 {
+    let returns = _get_returns_for_order(order.id);
+    // Units refunded or being refunded per line, counting this return as refunded
+    let refunded_units = |accessory_id: u64| -> u64 {
+        returns
+            .iter()
+            .filter(|other| {
+                other.accessory_id == accessory_id && other.id != request.id && other.refund_amount.is_some()
+            })
+            .map(|other| other.quantity)
+            .sum()
+    };
+    let Some(line) = order
+        .lines
+        .iter()
+        .find(|line| line.accessory_id == request.accessory_id)
+    else {
+        return 0;
+    };
+    let before = refunded_units(line.accessory_id);
+    let due = _line_share(line, before.saturating_add(request.quantity)) - _line_share(line, before);
+    let completes_order = order.lines.iter().all(|other| {
+        let this_return = if other.accessory_id == request.accessory_id {
+            request.quantity
+        } else {
+            0
+        };
+        refunded_units(other.accessory_id).saturating_add(this_return) >= other.quantity
+    });
+    match &order.shipping {
+        Some(shipping) if completes_order => due.saturating_add(shipping.amount),
+        _ => due,
+    }
+}
+
+// What the buyer paid for the first `quantity` units of a line, including exclusive tax
+fn _line_share(line: &OrderLine, quantity: u64) -> u64 {
+    if line.quantity == 0 {
+        return 0;
+    }
+    let paid = match line.line_total {
+        Some(line_total) => {
+            let exclusive_tax = line
+                .tax
+                .as_ref()
+                .filter(|tax| !tax.inclusive)
+                .map_or(0, |tax| tax.amount);
+            line_total.saturating_add(exclusive_tax)
+        }
+        None => line.unit_price.saturating_mul(line.quantity),
+    };
+    (u128::from(paid) * u128::from(quantity.min(line.quantity)) / u128::from(line.quantity)) as u64
+}
+
+// Helper function to check whether the caller is the seller of a return or staff
+fn _check_return_seller(request: &ReturnRequest) -> Result<(), Error> {
+    if request.seller == caller() || roles::_is_staff(&caller()) {
+        Ok(())
+    } else {
+        Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} can't manage return with id={}", caller(), request.id),
+        })
+    }
+}
+
+fn _check_return_status(request: &ReturnRequest, expected: ReturnStatus) -> Result<(), Error> {
+    if request.status == expected {
+        Ok(())
+    } else {
+        Err(Error::ValidationFailed {
+            msg: format!(
+                "return with id={} isn't in the expected state for this action",
+                request.id
+            ),
+        })
+    }
+}
+
+fn _check_comment(comment: &str) -> Result<(), Error> {
+    if comment.len() > MAX_COMMENT_LENGTH {
+        return Err(Error::ValidationFailed {
+            msg: format!("comments must be at most {} bytes long", MAX_COMMENT_LENGTH),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orders::tests::largest_order;
+
+    #[test]
+    fn line_shares_add_up_to_the_line_paid() {
+        let mut line = largest_order().lines.remove(0);
+        line.quantity = 3;
+        line.line_total = Some(1_000);
+        line.tax = None;
+        assert_eq!(_line_share(&line, 1), 333);
+        assert_eq!(_line_share(&line, 2) - _line_share(&line, 1), 333);
+        assert_eq!(_line_share(&line, 3) - _line_share(&line, 2), 334);
+        assert_eq!(_line_share(&line, 4), 1_000);
+    }
+}
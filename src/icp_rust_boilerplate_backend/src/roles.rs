@@ -0,0 +1,88 @@
+// Roles granted to principals on top of plain seller ownership
+use crate::{Error, Memory, PrincipalKey, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, is_controller};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Define the roles a principal can hold
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum Role {
+    Admin,
+    Staff,
+}
+
+// Implement the Storable trait for Role
+impl Storable for Role {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for Role
+impl BoundedStorable for Role {
+    const MAX_SIZE: u32 = 64;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static ROLE_STORAGE: RefCell<StableBTreeMap<PrincipalKey, Role, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))))
+    );
+}
+
+// Update function to grant a role to a principal (admin only)
+#[ic_cdk::update]
+fn assign_role(principal: Principal, role: Role) -> Result<(), Error> {
+    _check_if_admin()?;
+    ROLE_STORAGE.with(|service| service.borrow_mut().insert(PrincipalKey(principal), role));
+    Ok(())
+}
+
+// Update function to take away the role of a principal (admin only)
+#[ic_cdk::update]
+fn revoke_role(principal: Principal) -> Result<(), Error> {
+    _check_if_admin()?;
+    match ROLE_STORAGE.with(|service| service.borrow_mut().remove(&PrincipalKey(principal))) {
+        Some(_) => Ok(()),
+        None => Err(Error::NotFound {
+            msg: format!("principal={} has no role assigned", principal),
+        }),
+    }
+}
+
+// Query function to get the role of a principal
+#[ic_cdk::query]
+fn get_role(principal: Principal) -> Option<Role> {
+    _get_role(&principal)
+}
+
+fn _get_role(principal: &Principal) -> Option<Role> {
+    ROLE_STORAGE.with(|service| service.borrow().get(&PrincipalKey(*principal)))
+}
+
+// Controllers of the canister are always treated as admins
+pub(crate) fn _is_admin(principal: &Principal) -> bool {
+    is_controller(principal) || _get_role(principal) == Some(Role::Admin)
+}
+
+// Admins have every permission staff members have
+pub(crate) fn _is_staff(principal: &Principal) -> bool {
+    _is_admin(principal) || _get_role(principal) == Some(Role::Staff)
+}
+
+// Helper function to check whether the caller is an admin
+pub(crate) fn _check_if_admin() -> Result<(), Error> {
+    if _is_admin(&caller()) {
+        Ok(())
+    } else {
+        Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} isn't an admin", caller()),
+        })
+    }
+}
@@ -0,0 +1,28 @@
+// Periodic jobs run on canister timers
+use ic_cdk::api::time;
+use ic_cdk_timers::{set_timer, set_timer_interval};
+use std::time::Duration;
+
+// How often stock levels are evaluated against reorder points (10 minutes)
+const REORDER_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+// How often scheduled sale prices are started and ended (1 minute)
+const PRICE_SCHEDULE_INTERVAL: Duration = Duration::from_secs(60);
+// How often batches are checked for expiry (1 hour)
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// How often the trash is purged of accessories past their retention period (1 hour)
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Register the periodic jobs. Timers don't survive an upgrade, so this is called
+// from both init and post_upgrade.
+pub(crate) fn start() {
+    // init and post_upgrade can't make calls, so the invoice secret is fetched from a timer
+    set_timer(Duration::ZERO, crate::invoices::ensure_invoice_secret);
+    set_timer_interval(PRICE_SCHEDULE_INTERVAL, || {
+        crate::pricing::apply_scheduled_prices(time())
+    });
+    set_timer_interval(EXPIRY_CHECK_INTERVAL, || crate::batches::expire_batches(time()));
+    set_timer_interval(REORDER_CHECK_INTERVAL, || {
+        crate::reorder::evaluate_reorder_points(time())
+    });
+    set_timer_interval(TRASH_PURGE_INTERVAL, || crate::trash::purge_trash(time()));
+}
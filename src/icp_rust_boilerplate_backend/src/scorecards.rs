@@ -0,0 +1,189 @@
+// Seller scorecards, kept as running totals updated as orders, returns and reviews come in
+use crate::{Error, Memory, PrincipalKey, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Define the running totals behind a seller's scorecard
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct SellerStats {
+    rating_sum: u64,
+    rating_count: u64,
+    orders_placed: u64,
+    orders_shipped: u64,
+    orders_cancelled: u64,
+    // Orders with at least one return request
+    orders_returned: u64,
+    // Nanoseconds from order to first shipment, summed over shipped orders
+    ship_time_total: u64,
+}
+
+// Implement the Storable trait for SellerStats
+impl Storable for SellerStats {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for SellerStats
+impl BoundedStorable for SellerStats {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define the reliability metrics of a seller. Metrics without data are None.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SellerScorecard {
+    seller: Principal,
+    // Average review rating over all of the seller's listings, 1 to 5
+    average_rating: Option<f64>,
+    review_count: u64,
+    orders_placed: u64,
+    // Shipped orders out of the orders that were shipped or cancelled
+    fulfilment_rate: Option<f64>,
+    average_ship_time_ns: Option<u64>,
+    cancellation_rate: Option<f64>,
+    return_rate: Option<f64>,
+}
+
+// Define the seller metrics search results can be sorted by
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum SellerSort {
+    // Highest first
+    Rating,
+    FulfilmentRate,
+    // Lowest first
+    ShipTime,
+    CancellationRate,
+    ReturnRate,
+}
+
+thread_local! {
+    static SELLER_STATS: RefCell<StableBTreeMap<PrincipalKey, SellerStats, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(41))))
+    );
+}
+
+// Query function to get the scorecard of a seller
+#[ic_cdk::query]
+fn get_seller_scorecard(seller: Principal) -> Result<SellerScorecard, Error> {
+    if crate::sellers::_get_seller(&seller).is_none()
+        && SELLER_STATS.with(|service| !service.borrow().contains_key(&PrincipalKey(seller)))
+    {
+        return Err(Error::NotFound {
+            msg: format!("a seller with principal={} not found", seller),
+        });
+    }
+    Ok(scorecard(seller))
+}
+
+// Scorecard of a seller; all metrics are None for sellers without activity
+pub(crate) fn scorecard(seller: Principal) -> SellerScorecard {
+    let stats = SELLER_STATS
+        .with(|service| service.borrow().get(&PrincipalKey(seller)))
+        .unwrap_or_default();
+    let ratio = |part: u64, whole: u64| (whole > 0).then(|| part as f64 / whole as f64);
+    SellerScorecard {
+        seller,
+        average_rating: ratio(stats.rating_sum, stats.rating_count),
+        review_count: stats.rating_count,
+        orders_placed: stats.orders_placed,
+        fulfilment_rate: ratio(stats.orders_shipped, stats.orders_shipped + stats.orders_cancelled),
+        average_ship_time_ns: (stats.orders_shipped > 0).then(|| stats.ship_time_total / stats.orders_shipped),
+        cancellation_rate: ratio(stats.orders_cancelled, stats.orders_placed),
+        return_rate: ratio(stats.orders_returned, stats.orders_placed),
+    }
+}
+
+// Sort key of a seller for the given order, smaller sorts first; sellers without data sort last
+pub(crate) fn sort_key(seller: Principal, sort: SellerSort) -> (bool, f64) {
+    let card = scorecard(seller);
+    let value = match sort {
+        SellerSort::Rating => card.average_rating.map(|rating| -rating),
+        SellerSort::FulfilmentRate => card.fulfilment_rate.map(|rate| -rate),
+        SellerSort::ShipTime => card.average_ship_time_ns.map(|time| time as f64),
+        SellerSort::CancellationRate => card.cancellation_rate,
+        SellerSort::ReturnRate => card.return_rate,
+    };
+    (value.is_none(), value.unwrap_or_default())
+}
+
+pub(crate) fn record_rating(seller: Principal, rating: u8) {
+    _update(seller, |stats| {
+        stats.rating_sum += rating as u64;
+        stats.rating_count += 1;
+    });
+}
+
+pub(crate) fn record_order_placed(seller: Principal) {
+    _update(seller, |stats| stats.orders_placed += 1);
+}
+
+pub(crate) fn record_order_shipped(seller: Principal, ship_time: u64) {
+    _update(seller, |stats| {
+        stats.orders_shipped += 1;
+        stats.ship_time_total = stats.ship_time_total.saturating_add(ship_time);
+    });
+}
+
+pub(crate) fn record_order_cancelled(seller: Principal) {
+    _update(seller, |stats| stats.orders_cancelled += 1);
+}
+
+pub(crate) fn record_order_returned(seller: Principal) {
+    _update(seller, |stats| stats.orders_returned += 1);
+}
+
+// Move the track record of a seller to the principal that took over all its listings
+pub(crate) fn merge_stats(from: Principal, to: Principal) {
+    let Some(moved) = SELLER_STATS.with(|service| service.borrow_mut().remove(&PrincipalKey(from))) else {
+        return;
+    };
+    _update(to, |stats| {
+        stats.rating_sum = stats.rating_sum.saturating_add(moved.rating_sum);
+        stats.rating_count = stats.rating_count.saturating_add(moved.rating_count);
+        stats.orders_placed = stats.orders_placed.saturating_add(moved.orders_placed);
+        stats.orders_shipped = stats.orders_shipped.saturating_add(moved.orders_shipped);
+        stats.orders_cancelled = stats.orders_cancelled.saturating_add(moved.orders_cancelled);
+        stats.orders_returned = stats.orders_returned.saturating_add(moved.orders_returned);
+        stats.ship_time_total = stats.ship_time_total.saturating_add(moved.ship_time_total);
+    });
+}
+
+fn _update(principal: Principal, update: impl FnOnce(&mut SellerStats)) {
+    SELLER_STATS.with(|service| {
+        let mut service = service.borrow_mut();
+        let mut stats = service.get(&PrincipalKey(principal)).unwrap_or_default();
+        update(&mut stats);
+        service.insert(PrincipalKey(principal), stats);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merged_stats_move_to_the_new_seller() {
+        let from = Principal::from_slice(&[1]);
+        let to = Principal::from_slice(&[2]);
+        record_rating(from, 5);
+        record_order_placed(from);
+        record_order_shipped(from, 10);
+        record_rating(to, 3);
+        record_order_placed(to);
+        merge_stats(from, to);
+
+        let card = scorecard(to);
+        assert_eq!(card.review_count, 2);
+        assert_eq!(card.average_rating, Some(4.0));
+        assert_eq!(card.orders_placed, 2);
+        assert_eq!(card.average_ship_time_ns, Some(10));
+        assert_eq!(scorecard(from).orders_placed, 0);
+    }
+}
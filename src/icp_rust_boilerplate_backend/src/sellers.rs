@@ -0,0 +1,379 @@
+// Seller profiles and the seller registry
+use crate::{
+    _check_text_length, _check_version, _get_accessory, rate_limit, roles, Accessory, Error, Memory, PrincipalKey,
+    ACCESSORY_STORAGE, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+use validator::{Validate, ValidationError};
+
+// Maximum number of listings on one storefront page
+const MAX_PAGE_SIZE: u64 = 100;
+// Maximum lengths of the profile fields in bytes, so a seller fits its storage bound
+const MAX_DISPLAY_NAME_LENGTH: usize = 64;
+const MAX_CONTACT_LENGTH: usize = 128;
+const MAX_RETURN_POLICY_LENGTH: usize = 1024;
+
+// Define the review state of a seller
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum VerificationStatus {
+    Unverified,
+    Verified,
+    Rejected,
+    Suspended,
+}
+
+// Define the structure representing a seller
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Seller {
+    pub(crate) principal: Principal,
+    pub(crate) display_name: String,
+    pub(crate) contact: String,
+    return_policy: String,
+    verification_status: VerificationStatus,
+    created_at: u64,
+    updated_at: Option<u64>,
+    // Incremented on every change
+    version: Option<u64>,
+}
+
+// Implement the Storable trait for Seller
+impl Storable for Seller {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for Seller
+impl BoundedStorable for Seller {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define a payload structure for registering or updating a seller profile
+#[derive(candid::CandidType, Serialize, Deserialize, Validate)]
+pub(crate) struct SellerPayload {
+    #[validate(length(min = 1), custom = "_validate_display_name")]
+    display_name: String,
+    #[validate(length(min = 3), custom = "_validate_contact")]
+    contact: String,
+    #[validate(custom = "_validate_return_policy")]
+    return_policy: String,
+}
+
+fn _validate_display_name(display_name: &str) -> Result<(), ValidationError> {
+    _check_text_length(display_name, MAX_DISPLAY_NAME_LENGTH, "display name is too long")
+}
+
+fn _validate_contact(contact: &str) -> Result<(), ValidationError> {
+    _check_text_length(contact, MAX_CONTACT_LENGTH, "contact is too long")
+}
+
+fn _validate_return_policy(return_policy: &str) -> Result<(), ValidationError> {
+    _check_text_length(return_policy, MAX_RETURN_POLICY_LENGTH, "return policy is too long")
+}
+
+// Define the seller registry settings
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct SellerConfig {
+    // Whether sellers must be verified by an admin before they can list accessories
+    require_verification: bool,
+}
+
+// Implement the Storable trait for SellerConfig
+impl Storable for SellerConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Define a seller's profile with one page of their listings
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Storefront {
+    seller: Seller,
+    listings: Vec<Accessory>,
+    // Total number of listings of the seller
+    total: u64,
+    offset: u64,
+    limit: u64,
+}
+
+thread_local! {
+    static SELLER_STORAGE: RefCell<StableBTreeMap<PrincipalKey, Seller, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(39))))
+    );
+
+    static SELLER_CONFIG: RefCell<Cell<SellerConfig, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(40))), SellerConfig::default())
+            .expect("Cannot create the seller config cell")
+    );
+
+    // The listings of each seller, oldest first
+    static LISTING_INDEX: RefCell<StableBTreeMap<(PrincipalKey, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(44))))
+    );
+}
+
+// Update function to register the caller as a seller
+#[ic_cdk::update]
+fn register_seller(payload: SellerPayload) -> Result<Seller, Error> {
+    rate_limit::_check_rate_limit("register_seller")?;
+    _check_payload(&payload)?;
+    let principal = caller();
+    if _get_seller(&principal).is_some() {
+        return Err(Error::ValidationFailed {
+            msg: format!("Caller={} is already registered as a seller", principal),
+        });
+    }
+    let mut seller = Seller {
+        principal,
+        display_name: payload.display_name,
+        contact: payload.contact,
+        return_policy: payload.return_policy,
+        verification_status: VerificationStatus::Unverified,
+        created_at: time(),
+        updated_at: None,
+        version: None,
+    };
+    do_insert_seller(&mut seller);
+    Ok(seller)
+}
+
+// Update function to update the caller's seller profile
+#[ic_cdk::update]
+fn update_seller_profile(payload: SellerPayload, expected_version: Option<u64>) -> Result<Seller, Error> {
+    rate_limit::_check_rate_limit("update_seller_profile")?;
+    _check_payload(&payload)?;
+    let mut seller = _get_seller(&caller()).ok_or_else(|| Error::NotFound {
+        msg: format!("Caller={} isn't registered as a seller", caller()),
+    })?;
+    _check_version(
+        &format!("seller {}", seller.principal),
+        seller.version,
+        expected_version,
+    )?;
+    seller.display_name = payload.display_name;
+    seller.contact = payload.contact;
+    seller.return_policy = payload.return_policy;
+    seller.updated_at = Some(time());
+    do_insert_seller(&mut seller);
+    Ok(seller)
+}
+
+// Update function to set the verification status of a seller (admin only)
+#[ic_cdk::update]
+fn set_seller_verification(principal: Principal, status: VerificationStatus) -> Result<Seller, Error> {
+    roles::_check_if_admin()?;
+    let mut seller = _get_seller(&principal).ok_or_else(|| Error::NotFound {
+        msg: format!("a seller with principal={} not found", principal),
+    })?;
+    seller.verification_status = status;
+    seller.updated_at = Some(time());
+    do_insert_seller(&mut seller);
+    Ok(seller)
+}
+
+// Update function to choose whether sellers need admin verification before listing (admin only)
+#[ic_cdk::update]
+fn set_seller_verification_required(required: bool) -> Result<(), Error> {
+    roles::_check_if_admin()?;
+    SELLER_CONFIG
+        .with(|cell| {
+            cell.borrow_mut().set(SellerConfig {
+                require_verification: required,
+            })
+        })
+        .expect("cannot update the seller config");
+    Ok(())
+}
+
+// Query function to get the seller registry settings
+#[ic_cdk::query]
+fn get_seller_config() -> SellerConfig {
+    SELLER_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+// Query function to get a seller's profile
+#[ic_cdk::query]
+fn get_seller(principal: Principal) -> Result<Seller, Error> {
+    _get_seller(&principal).ok_or_else(|| Error::NotFound {
+        msg: format!("a seller with principal={} not found", principal),
+    })
+}
+
+// Query function to get the sellers, optionally with one verification status (staff only)
+#[ic_cdk::query]
+fn get_sellers(status: Option<VerificationStatus>) -> Result<Vec<Seller>, Error> {
+    if !roles::_is_staff(&caller()) {
+        return Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} isn't staff", caller()),
+        });
+    }
+    Ok(SELLER_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, seller)| seller)
+            .filter(|seller| status.is_none_or(|status| seller.verification_status == status))
+            .collect()
+    }))
+}
+
+// Query function to get a seller's profile and a page of their listings, oldest first
+#[ic_cdk::query]
+fn get_seller_storefront(principal: Principal, offset: u64, limit: u64) -> Result<Storefront, Error> {
+    let seller = get_seller(principal)?;
+    if matches!(
+        seller.verification_status,
+        VerificationStatus::Rejected | VerificationStatus::Suspended
+    ) {
+        return Err(Error::NotFound {
+            msg: format!("a seller with principal={} not found", principal),
+        });
+    }
+    let limit = limit.clamp(1, MAX_PAGE_SIZE);
+    let ids = listing_ids(&principal);
+    let listings = ids
+        .iter()
+        .skip(offset.try_into().unwrap_or(usize::MAX))
+        .take(limit as usize)
+        .filter_map(_get_accessory)
+        .collect();
+    let total = ids.len() as u64;
+    Ok(Storefront {
+        seller,
+        listings,
+        total,
+        offset,
+        limit,
+    })
+}
+
+// Check that a principal may list accessories: it must be a registered seller that
+// isn't rejected or suspended, and verified if the registry requires it
+pub(crate) fn _check_can_sell(principal: &Principal) -> Result<(), Error> {
+    let seller = _get_seller(principal).ok_or_else(|| Error::AuthenticationFailed {
+        msg: format!("Caller={} has to register as a seller first", principal),
+    })?;
+    let require_verification = SELLER_CONFIG.with(|cell| cell.borrow().get().require_verification);
+    match seller.verification_status {
+        VerificationStatus::Verified => Ok(()),
+        VerificationStatus::Unverified if !require_verification => Ok(()),
+        VerificationStatus::Unverified => Err(Error::AuthenticationFailed {
+            msg: format!("seller {} is waiting for verification by an admin", principal),
+        }),
+        VerificationStatus::Rejected | VerificationStatus::Suspended => Err(Error::AuthenticationFailed {
+            msg: format!("seller {} isn't allowed to list accessories", principal),
+        }),
+    }
+}
+
+// Ids of the listings of a seller, oldest first
+pub(crate) fn listing_ids(principal: &Principal) -> Vec<u64> {
+    LISTING_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((PrincipalKey(*principal), 0)..=(PrincipalKey(*principal), u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    })
+}
+
+// Add an accessory to the listing index of its seller
+pub(crate) fn index_listing(accessory: &Accessory) {
+    LISTING_INDEX.with(|index| {
+        index
+            .borrow_mut()
+            .insert((PrincipalKey(accessory.seller), accessory.id), ())
+    });
+}
+
+// Remove an accessory from the listing index of its seller
+pub(crate) fn unindex_listing(accessory: &Accessory) {
+    LISTING_INDEX.with(|index| {
+        index
+            .borrow_mut()
+            .remove(&(PrincipalKey(accessory.seller), accessory.id))
+    });
+}
+
+// Rebuild the listing index from the accessory storage. Run once by a migration.
+pub(crate) fn rebuild_listing_index() {
+    LISTING_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        let keys: Vec<(PrincipalKey, u64)> = index.iter().map(|(key, _)| key).collect();
+        for key in keys {
+            index.remove(&key);
+        }
+    });
+    ACCESSORY_STORAGE.with(|service| {
+        for (_, accessory) in service
+            .borrow()
+            .iter()
+            .filter(|(_, accessory)| accessory.deleted_at.is_none())
+        {
+            index_listing(&accessory);
+        }
+    });
+}
+
+pub(crate) fn _get_seller(principal: &Principal) -> Option<Seller> {
+    SELLER_STORAGE.with(|service| service.borrow().get(&PrincipalKey(*principal)))
+}
+
+fn _check_payload(payload: &SellerPayload) -> Result<(), Error> {
+    payload
+        .validate()
+        .map_err(|err| Error::ValidationFailed { msg: err.to_string() })
+}
+
+fn do_insert_seller(seller: &mut Seller) {
+    seller.version = Some(seller.version.unwrap_or_default() + 1);
+    SELLER_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert(PrincipalKey(seller.principal), seller.clone())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn largest_seller_fits_its_storage_bound() {
+        let seller = Seller {
+            principal: Principal::from_slice(&[0xff; 29]),
+            display_name: "d".repeat(MAX_DISPLAY_NAME_LENGTH),
+            contact: "c".repeat(MAX_CONTACT_LENGTH),
+            return_policy: "r".repeat(MAX_RETURN_POLICY_LENGTH),
+            verification_status: VerificationStatus::Unverified,
+            created_at: u64::MAX,
+            updated_at: Some(u64::MAX),
+            version: Some(u64::MAX),
+        };
+        let size = seller.to_bytes().len();
+        assert!(size <= Seller::MAX_SIZE as usize, "{} bytes", size);
+    }
+
+    #[test]
+    fn seller_text_limits_count_bytes() {
+        let payload = SellerPayload {
+            display_name: "seller".to_string(),
+            contact: "seller@example.com".to_string(),
+            return_policy: "\u{e9}".repeat(MAX_RETURN_POLICY_LENGTH / 2 + 1),
+        };
+        assert!(payload.validate().is_err());
+    }
+}
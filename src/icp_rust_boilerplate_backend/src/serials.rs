@@ -0,0 +1,589 @@
+// Serial number tracking for high-value accessories.
+// The inventory_count of a serialized accessory is the number of its units in stock.
+use crate::inventory::{record_serial_movement, record_serial_sale, reverse_sale, StockReason};
+use crate::{
+    _check_if_seller_or_staff, _get_accessory, next_id, rate_limit, Accessory, Error, Memory, StringKey, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::collections::BTreeSet;
+use std::{borrow::Cow, cell::RefCell};
+
+// Maximum length of a serial number, of a location and of the note on a status change
+const MAX_SERIAL_LENGTH: usize = 64;
+const MAX_LOCATION_LENGTH: usize = 100;
+const MAX_NOTE_LENGTH: usize = 256;
+// Most serials registered in one call
+const MAX_SERIALS_PER_CALL: usize = 500;
+
+// Define the states a serialized unit can be in
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum SerialStatus {
+    InStock,
+    Reserved,
+    Sold,
+    Returned,
+    Defective,
+}
+
+// Define the structure representing a single serialized unit
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SerialUnit {
+    id: u64,
+    serial: String,
+    accessory_id: u64,
+    status: SerialStatus,
+    location: String,
+    order_id: Option<u64>,
+    sold_to: Option<Principal>,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Implement the Storable trait for SerialUnit
+impl Storable for SerialUnit {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for SerialUnit
+impl BoundedStorable for SerialUnit {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define a single entry in the history of a serialized unit
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SerialEvent {
+    id: u64,
+    status: SerialStatus,
+    location: String,
+    order_id: Option<u64>,
+    actor: Principal,
+    note: String,
+    created_at: u64,
+}
+
+// Implement the Storable trait for SerialEvent
+impl Storable for SerialEvent {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for SerialEvent
+impl BoundedStorable for SerialEvent {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define a serialized unit together with its full history
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SerialHistory {
+    unit: SerialUnit,
+    events: Vec<SerialEvent>,
+}
+
+// Serial numbers are the key of the unit storage
+type SerialKey = StringKey<{ MAX_SERIAL_LENGTH as u32 }>;
+
+thread_local! {
+    // Accessories that are tracked by serial number
+    static SERIALIZED_ACCESSORIES: RefCell<StableBTreeMap<u64, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15))))
+    );
+
+    static SERIAL_UNIT_STORAGE: RefCell<StableBTreeMap<SerialKey, SerialUnit, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16))))
+    );
+
+    // Events are keyed by (unit_id, event_id) so a unit's history can be read in order
+    static SERIAL_EVENT_STORAGE: RefCell<StableBTreeMap<(u64, u64), SerialEvent, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17))))
+    );
+
+    // Serials keyed by (accessory_id, serial)
+    static SERIAL_ACCESSORY_INDEX: RefCell<StableBTreeMap<(u64, SerialKey), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(59))))
+    );
+}
+
+// Update function to switch an accessory to serial number tracking.
+// One serial has to be given for every unit currently in stock, so accessories
+// with more than MAX_SERIALS_PER_CALL units in stock can't switch.
+#[ic_cdk::update]
+fn enable_serial_tracking(accessory_id: u64, serials: Vec<String>, location: String) -> Result<Accessory, Error> {
+    rate_limit::_check_rate_limit("enable_serial_tracking")?;
+    let accessory = _get_accessory(&accessory_id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", accessory_id),
+    })?;
+    _check_if_seller_or_staff(&accessory)?;
+    if _is_serialized(accessory_id) {
+        return Err(Error::ValidationFailed {
+            msg: format!("accessory with id={} is already tracked by serial number", accessory_id),
+        });
+    }
+    if serials.len() as u64 != accessory.inventory_count {
+        return Err(Error::ValidationFailed {
+            msg: format!(
+                "expected {} serials for accessory with id={}, got {}",
+                accessory.inventory_count,
+                accessory_id,
+                serials.len()
+            ),
+        });
+    }
+    _check_new_serials(&serials, &location)?;
+    for serial in serials {
+        _insert_unit(
+            accessory_id,
+            serial,
+            location.clone(),
+            "serial tracking enabled".to_string(),
+        );
+    }
+    SERIALIZED_ACCESSORIES.with(|service| service.borrow_mut().insert(accessory_id, ()));
+    Ok(accessory)
+}
+
+// Update function to receive new serialized units into stock
+#[ic_cdk::update]
+fn add_serials(
+    accessory_id: u64,
+    serials: Vec<String>,
+    location: String,
+    unit_cost: Option<u64>,
+) -> Result<Vec<SerialUnit>, Error> {
+    rate_limit::_check_rate_limit("add_serials")?;
+    let accessory = _get_accessory(&accessory_id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", accessory_id),
+    })?;
+    _check_if_seller_or_staff(&accessory)?;
+    _check_serialized(accessory_id)?;
+    if serials.is_empty() {
+        return Err(Error::ValidationFailed {
+            msg: "at least one serial is required".to_string(),
+        });
+    }
+    _check_new_serials(&serials, &location)?;
+    record_serial_movement(
+        accessory_id,
+        serials.len() as i64,
+        StockReason::Received,
+        format!("{} serialized units received", serials.len()),
+        unit_cost,
+    )?;
+    Ok(serials
+        .into_iter()
+        .map(|serial| _insert_unit(accessory_id, serial, location.clone(), "received".to_string()))
+        .collect())
+}
+
+// Update function to change the status and/or location of a serialized unit.
+// Units entering or leaving stock update inventory_count through the stock ledger.
+// Units sold outside of an order need the buyer they were sold to.
+#[ic_cdk::update]
+fn update_serial_status(
+    serial: String,
+    status: SerialStatus,
+    location: Option<String>,
+    note: String,
+    sold_to: Option<Principal>,
+) -> Result<SerialUnit, Error> {
+    rate_limit::_check_rate_limit("update_serial_status")?;
+    if note.len() > MAX_NOTE_LENGTH {
+        return Err(Error::ValidationFailed {
+            msg: format!("note must be at most {} bytes long", MAX_NOTE_LENGTH),
+        });
+    }
+    let mut unit = _get_unit(&serial)?;
+    let accessory = _get_accessory(&unit.accessory_id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", unit.accessory_id),
+    })?;
+    _check_if_seller_or_staff(&accessory)?;
+    if let Some(location) = &location {
+        _check_location(location)?;
+    }
+    let selling = status == SerialStatus::Sold && unit.status != SerialStatus::Sold;
+    if selling && sold_to.is_none() {
+        return Err(Error::ValidationFailed {
+            msg: format!("serial {} can't be sold without a buyer", serial),
+        });
+    }
+    let stock_change = match (unit.status, status) {
+        (SerialStatus::InStock, SerialStatus::InStock) => None,
+        (SerialStatus::InStock, SerialStatus::Reserved) => Some((-1, StockReason::Correction)),
+        (SerialStatus::InStock, SerialStatus::Sold) => Some((-1, StockReason::Sold)),
+        (SerialStatus::InStock, SerialStatus::Defective) => Some((-1, StockReason::Damaged)),
+        (SerialStatus::Reserved, SerialStatus::InStock) => Some((1, StockReason::Correction)),
+        (SerialStatus::Reserved, SerialStatus::Sold) => None,
+        (SerialStatus::Sold, SerialStatus::Returned) => None,
+        (SerialStatus::Returned, SerialStatus::InStock) => Some((1, StockReason::Returned)),
+        (SerialStatus::Returned, SerialStatus::Defective) => None,
+        (SerialStatus::Defective, SerialStatus::InStock) => Some((1, StockReason::Correction)),
+        (from, to) if from == to => None,
+        _ => {
+            return Err(Error::ValidationFailed {
+                msg: format!("serial {} can't move to the requested status", serial),
+            })
+        }
+    };
+    if let Some((delta, reason)) = stock_change {
+        record_serial_movement(unit.accessory_id, delta, reason, format!("serial {}", serial), None)?;
+    }
+    unit.status = status;
+    if let Some(location) = location {
+        unit.location = location;
+    }
+    if selling {
+        unit.order_id = None;
+        unit.sold_to = sold_to;
+    }
+    unit.updated_at = Some(time());
+    _save_unit(&unit, note);
+    Ok(unit)
+}
+
+// Query function to look up a serialized unit with its full history.
+// Available to the seller, staff and the buyer of the unit.
+#[ic_cdk::query]
+fn get_serial(serial: String) -> Result<SerialHistory, Error> {
+    let unit = _get_unit(&serial)?;
+    if unit.sold_to != Some(caller()) {
+        let accessory = _get_accessory(&unit.accessory_id).ok_or_else(|| Error::NotFound {
+            msg: format!("an accessory with id={} not found", unit.accessory_id),
+        })?;
+        _check_if_seller_or_staff(&accessory)?;
+    }
+    let events = SERIAL_EVENT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((unit.id, 0)..=(unit.id, u64::MAX))
+            .map(|(_, event)| event)
+            .collect()
+    });
+    Ok(SerialHistory { unit, events })
+}
+
+// Query function to list the serialized units of an accessory, optionally by status
+#[ic_cdk::query]
+fn get_serials(accessory_id: u64, status: Option<SerialStatus>) -> Result<Vec<SerialUnit>, Error> {
+    let accessory = _get_accessory(&accessory_id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", accessory_id),
+    })?;
+    _check_if_seller_or_staff(&accessory)?;
+    Ok(_get_units(accessory_id)
+        .into_iter()
+        .filter(|unit| status.is_none_or(|status| unit.status == status))
+        .collect())
+}
+
+pub(crate) fn _is_serialized(accessory_id: u64) -> bool {
+    SERIALIZED_ACCESSORIES.with(|service| service.borrow().contains_key(&accessory_id))
+}
+
+// Pick the units an order line will receive: the requested serials, or the oldest
+// units in stock when none were requested. Nothing is changed. Callers bound
+// `quantity` to the serials an order can allocate.
+pub(crate) fn select_serials(accessory_id: u64, requested: &[String], quantity: u64) -> Result<Vec<String>, Error> {
+    if requested.is_empty() {
+        let in_stock: Vec<String> = _get_units(accessory_id)
+            .into_iter()
+            .filter(|unit| unit.status == SerialStatus::InStock)
+            .map(|unit| unit.serial)
+            .take(quantity as usize)
+            .collect();
+        if (in_stock.len() as u64) < quantity {
+            return Err(Error::ValidationFailed {
+                msg: format!(
+                    "only {} units of accessory with id={} in stock",
+                    in_stock.len(),
+                    accessory_id
+                ),
+            });
+        }
+        return Ok(in_stock);
+    }
+    if requested.len() as u64 != quantity {
+        return Err(Error::ValidationFailed {
+            msg: format!("expected {} serials for accessory with id={}", quantity, accessory_id),
+        });
+    }
+    for (index, serial) in requested.iter().enumerate() {
+        let unit = _get_unit(serial)?;
+        if unit.accessory_id != accessory_id || unit.status != SerialStatus::InStock {
+            return Err(Error::ValidationFailed {
+                msg: format!(
+                    "serial {} is not in stock for accessory with id={}",
+                    serial, accessory_id
+                ),
+            });
+        }
+        if requested[..index].contains(serial) {
+            return Err(Error::ValidationFailed {
+                msg: format!("serial {} is requested more than once", serial),
+            });
+        }
+    }
+    Ok(requested.to_vec())
+}
+
+// Mark units selected with `select_serials` as sold on an order and take them out of stock.
+// Returns the ID of the sale's stock movement.
+pub(crate) fn sell_serials(
+    accessory_id: u64,
+    serials: &[String],
+    order_id: u64,
+    buyer: Principal,
+    revenue: u64,
+) -> Result<u64, Error> {
+    let (_, movement) = record_serial_sale(
+        accessory_id,
+        serials.len() as u64,
+        format!("order #{}", order_id),
+        revenue,
+    )?;
+    for serial in serials {
+        let mut unit = _get_unit(serial)?;
+        unit.status = SerialStatus::Sold;
+        unit.order_id = Some(order_id);
+        unit.sold_to = Some(buyer);
+        unit.updated_at = Some(time());
+        _save_unit(&unit, format!("sold on order #{}", order_id));
+    }
+    Ok(movement.id)
+}
+
+// Undo the sale of units on a cancelled order and put them back in stock
+pub(crate) fn reverse_serial_sale(
+    accessory_id: u64,
+    serials: &[String],
+    sale_id: u64,
+    reference: &str,
+) -> Result<(), Error> {
+    _check_sold_units(accessory_id, serials)?;
+    reverse_sale(accessory_id, sale_id, serials.len() as u64, reference.to_string())?;
+    for serial in serials {
+        let mut unit = _get_unit(serial)?;
+        unit.status = SerialStatus::InStock;
+        unit.order_id = None;
+        unit.sold_to = None;
+        unit.updated_at = Some(time());
+        _save_unit(&unit, format!("back in stock on {}", reference));
+    }
+    Ok(())
+}
+
+// Take back sold units on a return and either restock them or mark them defective
+pub(crate) fn return_serials(
+    accessory_id: u64,
+    serials: &[String],
+    restock: bool,
+    reference: &str,
+) -> Result<(), Error> {
+    _check_sold_units(accessory_id, serials)?;
+    if restock {
+        record_serial_movement(
+            accessory_id,
+            serials.len() as i64,
+            StockReason::Returned,
+            reference.to_string(),
+            None,
+        )?;
+    }
+    for serial in serials {
+        let mut unit = _get_unit(serial)?;
+        unit.status = SerialStatus::Returned;
+        unit.updated_at = Some(time());
+        _save_unit(&unit, format!("returned on {}", reference));
+        unit.status = if restock {
+            SerialStatus::InStock
+        } else {
+            SerialStatus::Defective
+        };
+        _save_unit(&unit, format!("inspected on {}", reference));
+    }
+    Ok(())
+}
+
+fn _check_sold_units(accessory_id: u64, serials: &[String]) -> Result<(), Error> {
+    for serial in serials {
+        let unit = _get_unit(serial)?;
+        if unit.accessory_id != accessory_id || unit.status != SerialStatus::Sold {
+            return Err(Error::ValidationFailed {
+                msg: format!(
+                    "serial {} isn't a sold unit of accessory with id={}",
+                    serial, accessory_id
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Remove the units of a purged accessory with their history
+pub(crate) fn purge_accessory(accessory_id: u64) {
+    for unit in _get_units(accessory_id) {
+        let events: Vec<(u64, u64)> = SERIAL_EVENT_STORAGE.with(|service| {
+            service
+                .borrow()
+                .range((unit.id, 0)..=(unit.id, u64::MAX))
+                .map(|(key, _)| key)
+                .collect()
+        });
+        SERIAL_EVENT_STORAGE.with(|service| {
+            let mut service = service.borrow_mut();
+            for key in &events {
+                service.remove(key);
+            }
+        });
+        SERIAL_ACCESSORY_INDEX.with(|index| {
+            index
+                .borrow_mut()
+                .remove(&(accessory_id, StringKey(unit.serial.clone())))
+        });
+        SERIAL_UNIT_STORAGE.with(|service| service.borrow_mut().remove(&StringKey(unit.serial.clone())));
+    }
+    SERIALIZED_ACCESSORIES.with(|service| service.borrow_mut().remove(&accessory_id));
+}
+
+fn _get_unit(serial: &str) -> Result<SerialUnit, Error> {
+    SERIAL_UNIT_STORAGE
+        .with(|service| service.borrow().get(&StringKey(serial.to_string())))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("serial {} not found", serial),
+        })
+}
+
+fn _get_units(accessory_id: u64) -> Vec<SerialUnit> {
+    let serials: Vec<SerialKey> = SERIAL_ACCESSORY_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((accessory_id, StringKey(String::new()))..)
+            .take_while(|((id, _), _)| *id == accessory_id)
+            .map(|((_, serial), _)| serial)
+            .collect()
+    });
+    let mut units: Vec<SerialUnit> = SERIAL_UNIT_STORAGE.with(|service| {
+        let service = service.borrow();
+        serials.iter().filter_map(|serial| service.get(serial)).collect()
+    });
+    units.sort_by_key(|unit| unit.id);
+    units
+}
+
+fn _insert_unit(accessory_id: u64, serial: String, location: String, note: String) -> SerialUnit {
+    let unit = SerialUnit {
+        id: next_id(),
+        serial,
+        accessory_id,
+        status: SerialStatus::InStock,
+        location,
+        order_id: None,
+        sold_to: None,
+        created_at: time(),
+        updated_at: None,
+    };
+    SERIAL_ACCESSORY_INDEX.with(|index| {
+        index
+            .borrow_mut()
+            .insert((accessory_id, StringKey(unit.serial.clone())), ())
+    });
+    _save_unit(&unit, note);
+    unit
+}
+
+// Store a unit and append its current state to its history
+fn _save_unit(unit: &SerialUnit, note: String) {
+    let now = time();
+    let event = SerialEvent {
+        id: next_id(),
+        status: unit.status,
+        location: unit.location.clone(),
+        order_id: unit.order_id,
+        actor: caller(),
+        note,
+        created_at: now,
+    };
+    SERIAL_EVENT_STORAGE.with(|service| service.borrow_mut().insert((unit.id, event.id), event));
+    SERIAL_UNIT_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert(StringKey(unit.serial.clone()), unit.clone())
+    });
+}
+
+// Helper function to check that an accessory is tracked by serial number
+fn _check_serialized(accessory_id: u64) -> Result<(), Error> {
+    if _is_serialized(accessory_id) {
+        Ok(())
+    } else {
+        Err(Error::ValidationFailed {
+            msg: format!("accessory with id={} isn't tracked by serial number", accessory_id),
+        })
+    }
+}
+
+// Helper function to check that serials are well-formed and not registered yet
+fn _check_new_serials(serials: &[String], location: &str) -> Result<(), Error> {
+    _check_location(location)?;
+    if serials.len() > MAX_SERIALS_PER_CALL {
+        return Err(Error::ValidationFailed {
+            msg: format!("at most {} serials can be registered at once", MAX_SERIALS_PER_CALL),
+        });
+    }
+    let mut seen = BTreeSet::new();
+    for serial in serials {
+        if serial.is_empty() || serial.len() > MAX_SERIAL_LENGTH {
+            return Err(Error::ValidationFailed {
+                msg: format!("serials must be between 1 and {} bytes long", MAX_SERIAL_LENGTH),
+            });
+        }
+        if !seen.insert(serial) || _get_unit(serial).is_ok() {
+            return Err(Error::ValidationFailed {
+                msg: format!("serial {} is already registered", serial),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn _check_location(location: &str) -> Result<(), Error> {
+    if location.len() > MAX_LOCATION_LENGTH {
+        return Err(Error::ValidationFailed {
+            msg: format!("location must be at most {} bytes long", MAX_LOCATION_LENGTH),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longest_serial_event_fits_its_storage_bound() {
+        let event = SerialEvent {
+            id: u64::MAX,
+            status: SerialStatus::Defective,
+            location: "l".repeat(MAX_LOCATION_LENGTH),
+            order_id: Some(u64::MAX),
+            actor: Principal::from_slice(&[0xff; 29]),
+            note: "n".repeat(MAX_NOTE_LENGTH),
+            created_at: u64::MAX,
+        };
+        let size = event.to_bytes().len();
+        assert!(size <= SerialEvent::MAX_SIZE as usize, "{} bytes", size);
+    }
+}
@@ -0,0 +1,465 @@
+// Shipping methods, shipping rates and shipment tracking
+use crate::orders::{_check_order_access, _get_order, do_insert_order, OrderLine, OrderStatus};
+use crate::{
+    _check_version, _get_accessory, invoices, next_id, rate_limit, roles, scorecards, Error, Memory, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+use validator::ValidationError;
+
+const MAX_RATE_TIERS: usize = 10;
+const MAX_NAME_LENGTH: usize = 64;
+// Together with the name limit these keep the largest shipment within Shipment::MAX_SIZE
+const MAX_SHIPMENT_EVENTS: usize = 20;
+const MAX_EVENT_NOTE_LENGTH: usize = 128;
+
+// Define the package dimensions of an accessory in millimetres
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct Dimensions {
+    pub(crate) length_mm: u64,
+    pub(crate) width_mm: u64,
+    pub(crate) height_mm: u64,
+}
+
+// Define one tier of a rate table: `amount` applies up to and including `up_to`
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct RateTier {
+    up_to: u64,
+    amount: u64,
+}
+
+// Define how the cost of a shipping method is worked out
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum ShippingRate {
+    Flat { amount: u64 },
+    // Tiers by total weight in grams
+    ByWeight { tiers: Vec<RateTier> },
+    // Tiers by order value after discounts
+    ByOrderValue { tiers: Vec<RateTier> },
+}
+
+// Define a shipping method offered by a seller
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ShippingMethod {
+    id: u64,
+    seller: Principal,
+    name: String,
+    rate: ShippingRate,
+    // Orders worth at least this much after discounts ship for free
+    free_shipping_threshold: Option<u64>,
+    active: bool,
+    created_at: u64,
+    updated_at: Option<u64>,
+    // Incremented on every change
+    version: Option<u64>,
+}
+
+// Implement the Storable trait for ShippingMethod
+impl Storable for ShippingMethod {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for ShippingMethod
+impl BoundedStorable for ShippingMethod {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define a payload structure for adding or updating a shipping method
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ShippingMethodPayload {
+    name: String,
+    rate: ShippingRate,
+    free_shipping_threshold: Option<u64>,
+    active: bool,
+}
+
+// Define the shipping charged on an order
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ShippingCharge {
+    pub(crate) method_id: u64,
+    pub(crate) name: String,
+    pub(crate) amount: u64,
+}
+
+// Define the states of a shipment
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum ShipmentStatus {
+    InTransit,
+    OutForDelivery,
+    Delivered,
+    Failed,
+}
+
+// Define a status update of a shipment
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ShipmentEvent {
+    status: ShipmentStatus,
+    note: String,
+    created_at: u64,
+}
+
+// Define a parcel sent for an order
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Shipment {
+    id: u64,
+    order_id: u64,
+    carrier: String,
+    tracking_number: String,
+    status: ShipmentStatus,
+    events: Vec<ShipmentEvent>,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Implement the Storable trait for Shipment
+impl Storable for Shipment {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for Shipment
+impl BoundedStorable for Shipment {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static SHIPPING_METHOD_STORAGE: RefCell<StableBTreeMap<u64, ShippingMethod, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33))))
+    );
+
+    static SHIPMENT_STORAGE: RefCell<StableBTreeMap<(u64, u64), Shipment, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34))))
+    );
+}
+
+// Update function to add a shipping method for the caller's listings
+#[ic_cdk::update]
+fn add_shipping_method(payload: ShippingMethodPayload) -> Result<ShippingMethod, Error> {
+    rate_limit::_check_rate_limit("add_shipping_method")?;
+    _check_shipping_method(&payload)?;
+    let mut method = ShippingMethod {
+        id: next_id(),
+        seller: caller(),
+        name: payload.name,
+        rate: payload.rate,
+        free_shipping_threshold: payload.free_shipping_threshold,
+        active: payload.active,
+        created_at: time(),
+        updated_at: None,
+        version: None,
+    };
+    do_insert_shipping_method(&mut method);
+    Ok(method)
+}
+
+// Update function to update a shipping method (seller or staff only)
+#[ic_cdk::update]
+fn update_shipping_method(
+    id: u64,
+    payload: ShippingMethodPayload,
+    expected_version: Option<u64>,
+) -> Result<ShippingMethod, Error> {
+    rate_limit::_check_rate_limit("update_shipping_method")?;
+    let mut method = _get_shipping_method(id)?;
+    if method.seller != caller() && !roles::_is_staff(&caller()) {
+        return Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} can't manage shipping method with id={}", caller(), id),
+        });
+    }
+    _check_version(
+        &format!("shipping method with id={}", id),
+        method.version,
+        expected_version,
+    )?;
+    _check_shipping_method(&payload)?;
+    method.name = payload.name;
+    method.rate = payload.rate;
+    method.free_shipping_threshold = payload.free_shipping_threshold;
+    method.active = payload.active;
+    method.updated_at = Some(time());
+    do_insert_shipping_method(&mut method);
+    Ok(method)
+}
+
+// Query function to get the shipping methods of a seller
+#[ic_cdk::query]
+fn get_shipping_methods(seller: Principal) -> Vec<ShippingMethod> {
+    SHIPPING_METHOD_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, method)| method.seller == seller)
+            .map(|(_, method)| method)
+            .collect()
+    })
+}
+
+// Update function to record a shipment for an order (seller or staff only).
+// The order moves to Shipped.
+#[ic_cdk::update]
+fn create_shipment(order_id: u64, carrier: String, tracking_number: String) -> Result<Shipment, Error> {
+    rate_limit::_check_rate_limit("create_shipment")?;
+    let mut order = _get_order(&order_id)?;
+    _check_order_seller(&order.seller, order_id)?;
+    if matches!(order.status, OrderStatus::Delivered | OrderStatus::Cancelled) {
+        return Err(Error::ValidationFailed {
+            msg: format!("order with id={} can't be shipped any more", order_id),
+        });
+    }
+    for (field, value) in [("carrier", &carrier), ("tracking number", &tracking_number)] {
+        if value.trim().is_empty() || value.len() > MAX_NAME_LENGTH {
+            return Err(Error::ValidationFailed {
+                msg: format!("a {} must be between 1 and {} characters", field, MAX_NAME_LENGTH),
+            });
+        }
+    }
+    let now = time();
+    let shipment = Shipment {
+        id: next_id(),
+        order_id,
+        carrier,
+        tracking_number,
+        status: ShipmentStatus::InTransit,
+        events: vec![ShipmentEvent {
+            status: ShipmentStatus::InTransit,
+            note: String::new(),
+            created_at: now,
+        }],
+        created_at: now,
+        updated_at: None,
+    };
+    do_insert_shipment(&shipment);
+    if order.status == OrderStatus::Placed {
+        scorecards::record_order_shipped(order.seller, now.saturating_sub(order.created_at));
+    }
+    order.status = OrderStatus::Shipped;
+    order.updated_at = Some(now);
+    do_insert_order(&mut order);
+    invoices::invoice_order(&order);
+    Ok(shipment)
+}
+
+// Update function to record a status update of a shipment (seller or staff only).
+// The order moves to Delivered once all of its shipments are delivered.
+#[ic_cdk::update]
+fn update_shipment_status(order_id: u64, id: u64, status: ShipmentStatus, note: String) -> Result<Shipment, Error> {
+    rate_limit::_check_rate_limit("update_shipment_status")?;
+    let mut order = _get_order(&order_id)?;
+    _check_order_seller(&order.seller, order_id)?;
+    let mut shipment = SHIPMENT_STORAGE
+        .with(|service| service.borrow().get(&(order_id, id)))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("a shipment with id={} not found for order with id={}", id, order_id),
+        })?;
+    if matches!(shipment.status, ShipmentStatus::Delivered | ShipmentStatus::Failed) {
+        return Err(Error::ValidationFailed {
+            msg: format!("shipment with id={} is already closed", id),
+        });
+    }
+    if note.len() > MAX_EVENT_NOTE_LENGTH {
+        return Err(Error::ValidationFailed {
+            msg: format!("a note can have at most {} bytes", MAX_EVENT_NOTE_LENGTH),
+        });
+    }
+    if shipment.events.len() >= MAX_SHIPMENT_EVENTS {
+        shipment.events.remove(1);
+    }
+    let now = time();
+    shipment.status = status;
+    shipment.events.push(ShipmentEvent {
+        status,
+        note,
+        created_at: now,
+    });
+    shipment.updated_at = Some(now);
+    do_insert_shipment(&shipment);
+
+    if _get_shipments(order_id)
+        .iter()
+        .all(|shipment| shipment.status == ShipmentStatus::Delivered)
+    {
+        order.status = OrderStatus::Delivered;
+        order.updated_at = Some(now);
+        do_insert_order(&mut order);
+    }
+    Ok(shipment)
+}
+
+// Query function to get the shipments of an order (buyer, seller or staff only)
+#[ic_cdk::query]
+fn get_order_shipments(order_id: u64) -> Result<Vec<Shipment>, Error> {
+    _check_order_access(&_get_order(&order_id)?)?;
+    Ok(_get_shipments(order_id))
+}
+
+// Work out the shipping cost of an order with one of the seller's methods.
+// Rate tiers are matched against the order value after discounts or the total weight.
+pub(crate) fn quote_shipping(method_id: u64, seller: &Principal, lines: &[OrderLine]) -> Result<ShippingCharge, Error> {
+    let method = _get_shipping_method(method_id)?;
+    if method.seller != *seller || !method.active {
+        return Err(Error::ValidationFailed {
+            msg: format!("shipping method with id={} isn't available for this order", method_id),
+        });
+    }
+    let order_value = lines.iter().fold(0u64, |total, line| {
+        total.saturating_add(line.line_total.unwrap_or_default())
+    });
+    let amount = if method
+        .free_shipping_threshold
+        .is_some_and(|threshold| order_value >= threshold)
+    {
+        0
+    } else {
+        match &method.rate {
+            ShippingRate::Flat { amount } => *amount,
+            ShippingRate::ByOrderValue { tiers } => _tier_amount(tiers, order_value, method_id)?,
+            ShippingRate::ByWeight { tiers } => {
+                let mut weight = 0u64;
+                for line in lines {
+                    let unit_weight = _get_accessory(&line.accessory_id)
+                        .and_then(|accessory| accessory.weight_grams)
+                        .ok_or_else(|| Error::ValidationFailed {
+                            msg: format!("accessory with id={} has no weight", line.accessory_id),
+                        })?;
+                    weight = weight.saturating_add(unit_weight.saturating_mul(line.quantity));
+                }
+                _tier_amount(tiers, weight, method_id)?
+            }
+        }
+    };
+    Ok(ShippingCharge {
+        method_id,
+        name: method.name,
+        amount,
+    })
+}
+
+// Validator for the package dimensions of an accessory
+pub(crate) fn validate_dimensions(dimensions: &Dimensions) -> Result<(), ValidationError> {
+    if dimensions.length_mm == 0 || dimensions.width_mm == 0 || dimensions.height_mm == 0 {
+        return Err(ValidationError::new("dimensions must be greater than zero"));
+    }
+    Ok(())
+}
+
+fn _tier_amount(tiers: &[RateTier], value: u64, method_id: u64) -> Result<u64, Error> {
+    tiers
+        .iter()
+        .find(|tier| value <= tier.up_to)
+        .map(|tier| tier.amount)
+        .ok_or_else(|| Error::ValidationFailed {
+            msg: format!(
+                "the order is outside the rate table of shipping method with id={}",
+                method_id
+            ),
+        })
+}
+
+fn _check_shipping_method(payload: &ShippingMethodPayload) -> Result<(), Error> {
+    if payload.name.trim().is_empty() || payload.name.len() > MAX_NAME_LENGTH {
+        return Err(Error::ValidationFailed {
+            msg: format!(
+                "a shipping method name must be between 1 and {} characters",
+                MAX_NAME_LENGTH
+            ),
+        });
+    }
+    if let ShippingRate::ByWeight { tiers } | ShippingRate::ByOrderValue { tiers } = &payload.rate {
+        if tiers.is_empty() || tiers.len() > MAX_RATE_TIERS {
+            return Err(Error::ValidationFailed {
+                msg: format!("a rate table must have between 1 and {} tiers", MAX_RATE_TIERS),
+            });
+        }
+        if tiers.windows(2).any(|pair| pair[0].up_to >= pair[1].up_to) {
+            return Err(Error::ValidationFailed {
+                msg: "rate tiers must be in increasing order".to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn _check_order_seller(seller: &Principal, order_id: u64) -> Result<(), Error> {
+    if *seller == caller() || roles::_is_staff(&caller()) {
+        Ok(())
+    } else {
+        Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} can't ship order with id={}", caller(), order_id),
+        })
+    }
+}
+
+fn _get_shipping_method(id: u64) -> Result<ShippingMethod, Error> {
+    SHIPPING_METHOD_STORAGE
+        .with(|service| service.borrow().get(&id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("a shipping method with id={} not found", id),
+        })
+}
+
+fn _get_shipments(order_id: u64) -> Vec<Shipment> {
+    SHIPMENT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((order_id, 0)..=(order_id, u64::MAX))
+            .map(|(_, shipment)| shipment)
+            .collect()
+    })
+}
+
+fn do_insert_shipping_method(method: &mut ShippingMethod) {
+    method.version = Some(method.version.unwrap_or_default() + 1);
+    SHIPPING_METHOD_STORAGE.with(|service| service.borrow_mut().insert(method.id, method.clone()));
+}
+
+fn do_insert_shipment(shipment: &Shipment) {
+    SHIPMENT_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert((shipment.order_id, shipment.id), shipment.clone())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn largest_shipment_fits_its_storage_bound() {
+        let shipment = Shipment {
+            id: u64::MAX,
+            order_id: u64::MAX,
+            carrier: "c".repeat(MAX_NAME_LENGTH),
+            tracking_number: "t".repeat(MAX_NAME_LENGTH),
+            status: ShipmentStatus::OutForDelivery,
+            events: vec![
+                ShipmentEvent {
+                    status: ShipmentStatus::OutForDelivery,
+                    note: "n".repeat(MAX_EVENT_NOTE_LENGTH),
+                    created_at: u64::MAX,
+                };
+                MAX_SHIPMENT_EVENTS
+            ],
+            created_at: u64::MAX,
+            updated_at: Some(u64::MAX),
+        };
+        let size = shipment.to_bytes().len();
+        assert!(size <= Shipment::MAX_SIZE as usize, "{} bytes", size);
+    }
+}
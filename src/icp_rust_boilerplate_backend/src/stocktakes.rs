@@ -0,0 +1,356 @@
+// Stocktake (cycle count) sessions and their variance reports
+use crate::inventory::{check_stock_movement, record_stock_movement, StockReason};
+use crate::{
+    _check_if_seller_or_staff, _check_version, _get_accessory, next_id, rate_limit, roles, sellers, serials, Accessory,
+    Error, Memory, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Maximum number of accessories counted in one session
+const MAX_STOCKTAKE_LINES: usize = 100;
+
+// Define what a stocktake session counts
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum StocktakeScope {
+    Accessories(Vec<u64>),
+    // Every listing of the caller in the category
+    Category(String),
+}
+
+// Define the lifecycle of a stocktake session
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum StocktakeStatus {
+    Open,
+    Approved,
+    Cancelled,
+}
+
+// Define a single counted accessory of a stocktake session
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct StocktakeLine {
+    accessory_id: u64,
+    // Stock level when the session was started
+    expected: u64,
+    counted: Option<u64>,
+    // counted - expected, once counted
+    variance: Option<i64>,
+    counted_by: Option<Principal>,
+}
+
+// Define the structure representing a stocktake session
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Stocktake {
+    id: u64,
+    seller: Principal,
+    status: StocktakeStatus,
+    lines: Vec<StocktakeLine>,
+    created_by: Principal,
+    created_at: u64,
+    approved_by: Option<Principal>,
+    closed_at: Option<u64>,
+    // Incremented on every change
+    version: Option<u64>,
+}
+
+// Implement the Storable trait for Stocktake
+impl Storable for Stocktake {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for Stocktake
+impl BoundedStorable for Stocktake {
+    const MAX_SIZE: u32 = 16384;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define the variance report of a stocktake session
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct StocktakeReport {
+    stocktake: Stocktake,
+    counted_lines: u64,
+    uncounted_lines: u64,
+    lines_with_variance: u64,
+    total_variance: i64,
+}
+
+thread_local! {
+    static STOCKTAKE_STORAGE: RefCell<StableBTreeMap<u64, Stocktake, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20))))
+    );
+}
+
+// Update function to start a stocktake session, snapshotting the expected quantities
+#[ic_cdk::update]
+fn start_stocktake(scope: StocktakeScope) -> Result<Stocktake, Error> {
+    rate_limit::_check_rate_limit("start_stocktake")?;
+    let accessories: Vec<Accessory> = match scope {
+        StocktakeScope::Accessories(ids) => {
+            let mut accessories = Vec::with_capacity(ids.len());
+            for id in ids {
+                let accessory = _get_accessory(&id).ok_or_else(|| Error::NotFound {
+                    msg: format!("an accessory with id={} not found", id),
+                })?;
+                _check_if_seller_or_staff(&accessory)?;
+                if accessories.iter().any(|other: &Accessory| other.id == id) {
+                    return Err(Error::ValidationFailed {
+                        msg: format!("accessory with id={} appears more than once", id),
+                    });
+                }
+                accessories.push(accessory);
+            }
+            accessories
+        }
+        StocktakeScope::Category(category) => sellers::listing_ids(&caller())
+            .iter()
+            .filter_map(_get_accessory)
+            .filter(|accessory| accessory.category == category)
+            .collect(),
+    };
+    if accessories.is_empty() || accessories.len() > MAX_STOCKTAKE_LINES {
+        return Err(Error::ValidationFailed {
+            msg: format!(
+                "a stocktake must cover between 1 and {} accessories",
+                MAX_STOCKTAKE_LINES
+            ),
+        });
+    }
+    let seller = accessories[0].seller;
+    if accessories.iter().any(|accessory| accessory.seller != seller) {
+        return Err(Error::ValidationFailed {
+            msg: "all accessories of a stocktake must belong to the same seller".to_string(),
+        });
+    }
+    if let Some(accessory) = accessories
+        .iter()
+        .find(|accessory| serials::_is_serialized(accessory.id))
+    {
+        return Err(Error::ValidationFailed {
+            msg: format!(
+                "accessory with id={} is tracked by serial number and can't be counted in a stocktake",
+                accessory.id
+            ),
+        });
+    }
+    let mut stocktake = Stocktake {
+        id: next_id(),
+        seller,
+        status: StocktakeStatus::Open,
+        lines: accessories
+            .iter()
+            .map(|accessory| StocktakeLine {
+                accessory_id: accessory.id,
+                expected: accessory.inventory_count,
+                counted: None,
+                variance: None,
+                counted_by: None,
+            })
+            .collect(),
+        created_by: caller(),
+        created_at: time(),
+        approved_by: None,
+        closed_at: None,
+        version: None,
+    };
+    do_insert_stocktake(&mut stocktake);
+    Ok(stocktake)
+}
+
+// Update function to record counted quantities as (accessory_id, counted) pairs.
+// Can be called several times; a later count of the same accessory replaces the earlier one.
+#[ic_cdk::update]
+fn record_stocktake_counts(
+    id: u64,
+    counts: Vec<(u64, u64)>,
+    expected_version: Option<u64>,
+) -> Result<Stocktake, Error> {
+    rate_limit::_check_rate_limit("record_stocktake_counts")?;
+    let mut stocktake = _get_open_stocktake(id)?;
+    _check_stocktake_access(&stocktake)?;
+    _check_stocktake_version(&stocktake, expected_version)?;
+    // Validate every count before recording any of them
+    if let Some((accessory_id, _)) = counts
+        .iter()
+        .find(|(accessory_id, _)| !stocktake.lines.iter().any(|line| line.accessory_id == *accessory_id))
+    {
+        return Err(Error::ValidationFailed {
+            msg: format!(
+                "accessory with id={} isn't part of stocktake with id={}",
+                accessory_id, id
+            ),
+        });
+    }
+    for (accessory_id, counted) in counts {
+        if let Some(line) = stocktake
+            .lines
+            .iter_mut()
+            .find(|line| line.accessory_id == accessory_id)
+        {
+            let variance = i128::from(counted) - i128::from(line.expected);
+            line.variance = Some(i64::try_from(variance).map_err(|_| Error::ValidationFailed {
+                msg: format!("count for accessory with id={} is out of range", accessory_id),
+            })?);
+            line.counted = Some(counted);
+            line.counted_by = Some(caller());
+        }
+    }
+    do_insert_stocktake(&mut stocktake);
+    Ok(stocktake)
+}
+
+// Update function to approve a stocktake and post its variances as stock adjustments.
+// Variances are applied on top of any movements since the session started; if one of
+// them can't be posted, none are. Uncounted accessories are left untouched.
+#[ic_cdk::update]
+fn approve_stocktake(id: u64, expected_version: Option<u64>) -> Result<StocktakeReport, Error> {
+    rate_limit::_check_rate_limit("approve_stocktake")?;
+    let mut stocktake = _get_open_stocktake(id)?;
+    if stocktake.seller != caller() && !roles::_is_admin(&caller()) {
+        return Err(Error::AuthenticationFailed {
+            msg: format!("only the seller or an admin can approve stocktake with id={}", id),
+        });
+    }
+    _check_stocktake_version(&stocktake, expected_version)?;
+    let adjustments: Vec<(u64, i64)> = stocktake
+        .lines
+        .iter()
+        .filter_map(|line| {
+            line.variance
+                .filter(|variance| *variance != 0)
+                .map(|variance| (line.accessory_id, variance))
+        })
+        .collect();
+    // Accessories can be trashed or switched to serial tracking while the session is
+    // open, so every line gets the full ledger checks before anything is posted
+    let note = format!("stocktake #{}", id);
+    for (accessory_id, variance) in &adjustments {
+        check_stock_movement(*accessory_id, *variance, StockReason::Stocktake, &note)?;
+    }
+    for (accessory_id, variance) in adjustments {
+        record_stock_movement(accessory_id, variance, StockReason::Stocktake, note.clone(), None)?;
+    }
+    stocktake.status = StocktakeStatus::Approved;
+    stocktake.approved_by = Some(caller());
+    stocktake.closed_at = Some(time());
+    do_insert_stocktake(&mut stocktake);
+    Ok(_report(stocktake))
+}
+
+// Update function to cancel an open stocktake without posting anything
+#[ic_cdk::update]
+fn cancel_stocktake(id: u64, expected_version: Option<u64>) -> Result<Stocktake, Error> {
+    rate_limit::_check_rate_limit("cancel_stocktake")?;
+    let mut stocktake = _get_open_stocktake(id)?;
+    _check_stocktake_access(&stocktake)?;
+    _check_stocktake_version(&stocktake, expected_version)?;
+    stocktake.status = StocktakeStatus::Cancelled;
+    stocktake.closed_at = Some(time());
+    do_insert_stocktake(&mut stocktake);
+    Ok(stocktake)
+}
+
+// Query function to get the variance report of a stocktake session
+#[ic_cdk::query]
+fn get_stocktake_report(id: u64) -> Result<StocktakeReport, Error> {
+    let stocktake = _get_stocktake(&id)?;
+    _check_stocktake_access(&stocktake)?;
+    Ok(_report(stocktake))
+}
+
+// Query function to list the caller's stocktake sessions, newest first.
+// Admins and staff see the sessions of every seller.
+#[ic_cdk::query]
+fn get_stocktakes() -> Vec<Stocktake> {
+    let seller = caller();
+    let see_all = roles::_is_staff(&caller());
+    let mut stocktakes: Vec<Stocktake> = STOCKTAKE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, stocktake)| see_all || stocktake.seller == seller)
+            .map(|(_, stocktake)| stocktake)
+            .collect()
+    });
+    stocktakes.reverse();
+    stocktakes
+}
+
+// Whether the seller has an open stocktake counting any of the accessories
+pub(crate) fn has_open_stocktake(seller: Principal, accessory_ids: &[u64]) -> bool {
+    STOCKTAKE_STORAGE.with(|service| {
+        service.borrow().iter().any(|(_, stocktake)| {
+            stocktake.seller == seller
+                && stocktake.status == StocktakeStatus::Open
+                && stocktake
+                    .lines
+                    .iter()
+                    .any(|line| accessory_ids.contains(&line.accessory_id))
+        })
+    })
+}
+
+fn _check_stocktake_version(stocktake: &Stocktake, expected_version: Option<u64>) -> Result<(), Error> {
+    _check_version(
+        &format!("stocktake with id={}", stocktake.id),
+        stocktake.version,
+        expected_version,
+    )
+}
+
+fn do_insert_stocktake(stocktake: &mut Stocktake) {
+    stocktake.version = Some(stocktake.version.unwrap_or_default() + 1);
+    STOCKTAKE_STORAGE.with(|service| service.borrow_mut().insert(stocktake.id, stocktake.clone()));
+}
+
+fn _get_stocktake(id: &u64) -> Result<Stocktake, Error> {
+    STOCKTAKE_STORAGE
+        .with(|service| service.borrow().get(id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("a stocktake with id={} not found", id),
+        })
+}
+
+fn _get_open_stocktake(id: u64) -> Result<Stocktake, Error> {
+    let stocktake = _get_stocktake(&id)?;
+    if stocktake.status != StocktakeStatus::Open {
+        return Err(Error::ValidationFailed {
+            msg: format!("stocktake with id={} is already closed", id),
+        });
+    }
+    Ok(stocktake)
+}
+
+// Helper function to check whether the caller is the seller of a stocktake or staff
+fn _check_stocktake_access(stocktake: &Stocktake) -> Result<(), Error> {
+    if stocktake.seller == caller() || roles::_is_staff(&caller()) {
+        Ok(())
+    } else {
+        Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} can't access stocktake with id={}", caller(), stocktake.id),
+        })
+    }
+}
+
+fn _report(stocktake: Stocktake) -> StocktakeReport {
+    let counted_lines = stocktake.lines.iter().filter(|line| line.counted.is_some()).count() as u64;
+    StocktakeReport {
+        counted_lines,
+        uncounted_lines: stocktake.lines.len() as u64 - counted_lines,
+        lines_with_variance: stocktake
+            .lines
+            .iter()
+            .filter(|line| line.variance.is_some_and(|variance| variance != 0))
+            .count() as u64,
+        total_variance: stocktake.lines.iter().filter_map(|line| line.variance).sum(),
+        stocktake,
+    }
+}
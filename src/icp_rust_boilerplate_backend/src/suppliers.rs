@@ -0,0 +1,311 @@
+// Supplier registry and the links between accessories and their suppliers
+use crate::{
+    _check_if_seller_or_staff, _check_text_length, _check_version, _get_accessory, next_id, rate_limit, roles, Error,
+    Memory, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+use validator::{Validate, ValidationError};
+
+// Maximum lengths of the supplier fields in bytes, so a supplier fits its storage bound
+const MAX_SUPPLIER_NAME_LENGTH: usize = 100;
+const MAX_CONTACT_LENGTH: usize = 200;
+
+// Define the structure representing a supplier
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Supplier {
+    pub(crate) id: u64,
+    pub(crate) owner: Principal,
+    pub(crate) name: String,
+    pub(crate) contact: String,
+    pub(crate) lead_time_days: u32,
+    pub(crate) created_at: u64,
+    pub(crate) updated_at: Option<u64>,
+    // Incremented on every change
+    pub(crate) version: Option<u64>,
+}
+
+// Implement the Storable trait for Supplier
+impl Storable for Supplier {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for Supplier
+impl BoundedStorable for Supplier {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define the structure linking an accessory to one of its suppliers
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SupplierLink {
+    pub(crate) accessory_id: u64,
+    pub(crate) supplier_id: u64,
+    pub(crate) cost_price: u64,
+    pub(crate) preferred: bool,
+}
+
+// Implement the Storable trait for SupplierLink
+impl Storable for SupplierLink {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for SupplierLink
+impl BoundedStorable for SupplierLink {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define a payload structure for adding or updating a supplier
+#[derive(candid::CandidType, Serialize, Deserialize, Default, Validate)]
+pub(crate) struct SupplierPayload {
+    #[validate(length(min = 1), custom = "_validate_supplier_name")]
+    name: String,
+    #[validate(length(min = 1), custom = "_validate_contact")]
+    contact: String,
+    lead_time_days: u32,
+}
+
+thread_local! {
+    static SUPPLIER_STORAGE: RefCell<StableBTreeMap<u64, Supplier, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8))))
+    );
+
+    // Links are keyed by (accessory_id, supplier_id) so they can be listed per accessory
+    static SUPPLIER_LINK_STORAGE: RefCell<StableBTreeMap<(u64, u64), SupplierLink, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9))))
+    );
+}
+
+// Update function to register a new supplier
+#[ic_cdk::update]
+fn add_supplier(payload: SupplierPayload) -> Result<Supplier, Error> {
+    rate_limit::_check_rate_limit("add_supplier")?;
+    _check_supplier_input(&payload)?;
+    let mut supplier = Supplier {
+        id: next_id(),
+        owner: caller(),
+        name: payload.name,
+        contact: payload.contact,
+        lead_time_days: payload.lead_time_days,
+        created_at: time(),
+        updated_at: None,
+        version: None,
+    };
+    do_insert_supplier(&mut supplier);
+    Ok(supplier)
+}
+
+// Update function to update an existing supplier
+#[ic_cdk::update]
+fn update_supplier(id: u64, payload: SupplierPayload, expected_version: Option<u64>) -> Result<Supplier, Error> {
+    rate_limit::_check_rate_limit("update_supplier")?;
+    let mut supplier = _get_supplier(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("a supplier with id={} not found", id),
+    })?;
+    if supplier.owner != caller() && !roles::_is_admin(&caller()) {
+        return Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} isn't the owner of the supplier with id={}", caller(), id),
+        });
+    }
+    _check_version(&format!("supplier with id={}", id), supplier.version, expected_version)?;
+    _check_supplier_input(&payload)?;
+    supplier.name = payload.name;
+    supplier.contact = payload.contact;
+    supplier.lead_time_days = payload.lead_time_days;
+    supplier.updated_at = Some(time());
+    do_insert_supplier(&mut supplier);
+    Ok(supplier)
+}
+
+// Query function to get a supplier by ID
+#[ic_cdk::query]
+fn get_supplier(id: u64) -> Result<Supplier, Error> {
+    _get_supplier(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("a supplier with id={} not found", id),
+    })
+}
+
+// Query function to list all registered suppliers
+#[ic_cdk::query]
+fn get_suppliers() -> Vec<Supplier> {
+    SUPPLIER_STORAGE.with(|service| service.borrow().iter().map(|(_, supplier)| supplier).collect())
+}
+
+// Update function to link an accessory to a supplier, or update an existing link
+#[ic_cdk::update]
+fn link_accessory_supplier(
+    accessory_id: u64,
+    supplier_id: u64,
+    cost_price: u64,
+    preferred: bool,
+) -> Result<SupplierLink, Error> {
+    rate_limit::_check_rate_limit("link_accessory_supplier")?;
+    let accessory = _get_accessory(&accessory_id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", accessory_id),
+    })?;
+    _check_if_seller_or_staff(&accessory)?;
+    if _get_supplier(&supplier_id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("a supplier with id={} not found", supplier_id),
+        });
+    }
+    let link = SupplierLink {
+        accessory_id,
+        supplier_id,
+        cost_price,
+        preferred,
+    };
+    SUPPLIER_LINK_STORAGE.with(|service| {
+        let mut links = service.borrow_mut();
+        // Only one supplier can be preferred for an accessory
+        if preferred {
+            let others: Vec<SupplierLink> = links
+                .range((accessory_id, 0)..=(accessory_id, u64::MAX))
+                .map(|(_, other)| other)
+                .filter(|other| other.preferred && other.supplier_id != supplier_id)
+                .collect();
+            for mut other in others {
+                other.preferred = false;
+                links.insert((accessory_id, other.supplier_id), other);
+            }
+        }
+        links.insert((accessory_id, supplier_id), link.clone());
+    });
+    Ok(link)
+}
+
+// Update function to remove the link between an accessory and a supplier
+#[ic_cdk::update]
+fn unlink_accessory_supplier(accessory_id: u64, supplier_id: u64) -> Result<SupplierLink, Error> {
+    rate_limit::_check_rate_limit("unlink_accessory_supplier")?;
+    let accessory = _get_accessory(&accessory_id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", accessory_id),
+    })?;
+    _check_if_seller_or_staff(&accessory)?;
+    SUPPLIER_LINK_STORAGE
+        .with(|service| service.borrow_mut().remove(&(accessory_id, supplier_id)))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!(
+                "accessory with id={} isn't linked to supplier with id={}",
+                accessory_id, supplier_id
+            ),
+        })
+}
+
+// Query function to get the suppliers linked to an accessory
+#[ic_cdk::query]
+fn get_accessory_suppliers(accessory_id: u64) -> Vec<SupplierLink> {
+    _get_supplier_links(accessory_id)
+}
+
+fn do_insert_supplier(supplier: &mut Supplier) {
+    supplier.version = Some(supplier.version.unwrap_or_default() + 1);
+    SUPPLIER_STORAGE.with(|service| service.borrow_mut().insert(supplier.id, supplier.clone()));
+}
+
+pub(crate) fn _get_supplier(id: &u64) -> Option<Supplier> {
+    SUPPLIER_STORAGE.with(|service| service.borrow().get(id))
+}
+
+pub(crate) fn _get_supplier_link(accessory_id: u64, supplier_id: u64) -> Option<SupplierLink> {
+    SUPPLIER_LINK_STORAGE.with(|service| service.borrow().get(&(accessory_id, supplier_id)))
+}
+
+pub(crate) fn _get_supplier_links(accessory_id: u64) -> Vec<SupplierLink> {
+    SUPPLIER_LINK_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((accessory_id, 0)..=(accessory_id, u64::MAX))
+            .map(|(_, link)| link)
+            .collect()
+    })
+}
+
+// Remove the supplier links of a purged accessory
+pub(crate) fn purge_accessory(accessory_id: u64) {
+    let links: Vec<(u64, u64)> = SUPPLIER_LINK_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((accessory_id, 0)..=(accessory_id, u64::MAX))
+            .map(|(key, _)| key)
+            .collect()
+    });
+    SUPPLIER_LINK_STORAGE.with(|service| {
+        let mut service = service.borrow_mut();
+        for key in &links {
+            service.remove(key);
+        }
+    });
+}
+
+// The supplier an accessory is reordered from: the preferred one, otherwise the cheapest
+pub(crate) fn _get_reorder_supplier(accessory_id: u64) -> Option<SupplierLink> {
+    let links = _get_supplier_links(accessory_id);
+    links
+        .iter()
+        .find(|link| link.preferred)
+        .or_else(|| links.iter().min_by_key(|link| link.cost_price))
+        .cloned()
+}
+
+fn _validate_supplier_name(name: &str) -> Result<(), ValidationError> {
+    _check_text_length(name, MAX_SUPPLIER_NAME_LENGTH, "name is too long")
+}
+
+fn _validate_contact(contact: &str) -> Result<(), ValidationError> {
+    _check_text_length(contact, MAX_CONTACT_LENGTH, "contact is too long")
+}
+
+// Helper function to check the input data of the supplier payload
+fn _check_supplier_input(payload: &SupplierPayload) -> Result<(), Error> {
+    payload
+        .validate()
+        .map_err(|err| Error::ValidationFailed { msg: err.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn largest_supplier_fits_its_storage_bound() {
+        let supplier = Supplier {
+            id: u64::MAX,
+            owner: Principal::from_slice(&[0xff; 29]),
+            name: "n".repeat(MAX_SUPPLIER_NAME_LENGTH),
+            contact: "c".repeat(MAX_CONTACT_LENGTH),
+            lead_time_days: u32::MAX,
+            created_at: u64::MAX,
+            updated_at: Some(u64::MAX),
+            version: Some(u64::MAX),
+        };
+        let size = supplier.to_bytes().len();
+        assert!(size <= Supplier::MAX_SIZE as usize, "{} bytes", size);
+    }
+
+    #[test]
+    fn supplier_text_limits_count_bytes() {
+        let payload = SupplierPayload {
+            name: "\u{1f50c}".repeat(MAX_SUPPLIER_NAME_LENGTH / 4 + 1),
+            contact: "c".repeat(MAX_CONTACT_LENGTH),
+            lead_time_days: 1,
+        };
+        assert!(payload.validate().is_err());
+    }
+}
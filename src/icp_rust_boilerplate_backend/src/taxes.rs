@@ -0,0 +1,408 @@
+// Sales tax and VAT rules per jurisdiction and category
+use crate::currency::{divide_rounded, RoundingMode};
+use crate::orders::{_get_orders_between, OrderLine, OrderStatus};
+use crate::{_get_accessory, next_id, returns, roles, Error, Memory, StringKey, MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+// Jurisdictions are short codes such as DE or US-CA
+const MAX_JURISDICTION_LENGTH: usize = 16;
+const MAX_CATEGORY_LENGTH: usize = 64;
+
+// Currency, jurisdiction, category, rate and inclusiveness of a tax summary line
+type SummaryKey = (Option<String>, String, Option<String>, u64, bool);
+
+// Jurisdiction and category of a rule; rules without a category use an empty one
+type RuleKey = (
+    StringKey<{ MAX_JURISDICTION_LENGTH as u32 }>,
+    StringKey<{ MAX_CATEGORY_LENGTH as u32 }>,
+);
+
+// Define a tax rule. A rule without a category applies to every category of the
+// jurisdiction that has no rule of its own.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct TaxRule {
+    id: u64,
+    jurisdiction: String,
+    category: Option<String>,
+    // 1900 = 19%
+    rate_basis_points: u64,
+    // Whether prices already include the tax
+    inclusive: bool,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Implement the Storable trait for TaxRule
+impl Storable for TaxRule {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for TaxRule
+impl BoundedStorable for TaxRule {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define the tax settings of the canister
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct TaxConfig {
+    // Where orders are taxed when the buyer gives no jurisdiction
+    default_jurisdiction: Option<String>,
+}
+
+// Implement the Storable trait for TaxConfig
+impl Storable for TaxConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Define the tax charged on an order line
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct LineTax {
+    pub(crate) rule_id: u64,
+    pub(crate) jurisdiction: String,
+    pub(crate) category: Option<String>,
+    pub(crate) rate_basis_points: u64,
+    pub(crate) inclusive: bool,
+    pub(crate) amount: u64,
+}
+
+// Define the tax collected for one rate over a date range
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct TaxSummaryLine {
+    currency: Option<String>,
+    jurisdiction: String,
+    category: Option<String>,
+    rate_basis_points: u64,
+    inclusive: bool,
+    order_count: u64,
+    // Line amounts after discounts, excluding tax
+    taxable_amount: u64,
+    tax_amount: u64,
+}
+
+// Define the tax collected over a date range
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct TaxSummary {
+    from: u64,
+    to: u64,
+    lines: Vec<TaxSummaryLine>,
+}
+
+thread_local! {
+    static TAX_RULE_STORAGE: RefCell<StableBTreeMap<u64, TaxRule, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32))))
+    );
+
+    // Rule IDs keyed by jurisdiction and category
+    static TAX_RULE_INDEX: RefCell<StableBTreeMap<RuleKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(57))))
+    );
+
+    static TAX_CONFIG: RefCell<Cell<TaxConfig, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(58))), TaxConfig::default())
+            .expect("Cannot create the tax config cell")
+    );
+}
+
+// Update function to add or replace the tax rule of a jurisdiction and category (admin only)
+#[ic_cdk::update]
+fn set_tax_rule(
+    jurisdiction: String,
+    category: Option<String>,
+    rate_basis_points: u64,
+    inclusive: bool,
+) -> Result<TaxRule, Error> {
+    roles::_check_if_admin()?;
+    let jurisdiction = normalize_jurisdiction(&jurisdiction)?;
+    if category
+        .as_ref()
+        .is_some_and(|category| category.is_empty() || category.len() > MAX_CATEGORY_LENGTH)
+    {
+        return Err(Error::ValidationFailed {
+            msg: format!(
+                "a tax category must be between 1 and {} characters",
+                MAX_CATEGORY_LENGTH
+            ),
+        });
+    }
+    if rate_basis_points > 10_000 {
+        return Err(Error::ValidationFailed {
+            msg: "a tax rate can't be above 100%".to_string(),
+        });
+    }
+    let now = time();
+    let rule = match _find_rule(&jurisdiction, category.as_deref()) {
+        Some(existing) => TaxRule {
+            rate_basis_points,
+            inclusive,
+            updated_at: Some(now),
+            ..existing
+        },
+        None => TaxRule {
+            id: next_id(),
+            jurisdiction,
+            category,
+            rate_basis_points,
+            inclusive,
+            created_at: now,
+            updated_at: None,
+        },
+    };
+    TAX_RULE_INDEX.with(|index| {
+        index
+            .borrow_mut()
+            .insert(_rule_key(&rule.jurisdiction, rule.category.as_deref()), rule.id)
+    });
+    TAX_RULE_STORAGE.with(|service| service.borrow_mut().insert(rule.id, rule.clone()));
+    Ok(rule)
+}
+
+// Update function to remove a tax rule (admin only)
+#[ic_cdk::update]
+fn remove_tax_rule(id: u64) -> Result<TaxRule, Error> {
+    roles::_check_if_admin()?;
+    let rule = TAX_RULE_STORAGE
+        .with(|service| service.borrow_mut().remove(&id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("a tax rule with id={} not found", id),
+        })?;
+    TAX_RULE_INDEX.with(|index| {
+        index
+            .borrow_mut()
+            .remove(&_rule_key(&rule.jurisdiction, rule.category.as_deref()))
+    });
+    Ok(rule)
+}
+
+// Query function to get the tax rules, optionally for one jurisdiction
+#[ic_cdk::query]
+fn get_tax_rules(jurisdiction: Option<String>) -> Result<Vec<TaxRule>, Error> {
+    let Some(jurisdiction) = jurisdiction.map(|code| normalize_jurisdiction(&code)).transpose()? else {
+        return Ok(TAX_RULE_STORAGE.with(|service| service.borrow().iter().map(|(_, rule)| rule).collect()));
+    };
+    let ids: Vec<u64> = TAX_RULE_INDEX.with(|index| {
+        index
+            .borrow()
+            .range(_rule_key(&jurisdiction, None)..)
+            .take_while(|((code, _), _)| code.0 == jurisdiction)
+            .map(|(_, id)| id)
+            .collect()
+    });
+    Ok(TAX_RULE_STORAGE.with(|service| {
+        let service = service.borrow();
+        ids.iter().filter_map(|id| service.get(id)).collect()
+    }))
+}
+
+// Update function to set where orders without a jurisdiction are taxed (admin only)
+#[ic_cdk::update]
+fn set_default_jurisdiction(code: Option<String>) -> Result<TaxConfig, Error> {
+    roles::_check_if_admin()?;
+    let config = TaxConfig {
+        default_jurisdiction: code.map(|code| normalize_jurisdiction(&code)).transpose()?,
+    };
+    TAX_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("cannot update the tax config");
+    Ok(config)
+}
+
+// Query function to get the tax settings
+#[ic_cdk::query]
+fn get_tax_config() -> TaxConfig {
+    TAX_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+// Query function to summarise the tax on orders placed between two timestamps (inclusive).
+// Cancelled orders are left out and refunded amounts are taken off their lines.
+// Admins and staff see every seller, other callers only their own sales.
+#[ic_cdk::query]
+fn get_tax_summary(from: u64, to: u64) -> TaxSummary {
+    let seller = caller();
+    let see_all = roles::_is_staff(&caller());
+    let refunds = returns::refunded_amounts();
+    let mut lines: BTreeMap<SummaryKey, TaxSummaryLine> = BTreeMap::new();
+    for order in _get_orders_between(from, to) {
+        if !(see_all || order.seller == seller) || order.status == OrderStatus::Cancelled {
+            continue;
+        }
+        let mut counted = Vec::new();
+        for line in &order.lines {
+            let Some(tax) = &line.tax else { continue };
+            let key = (
+                order.currency.clone(),
+                tax.jurisdiction.clone(),
+                tax.category.clone(),
+                tax.rate_basis_points,
+                tax.inclusive,
+            );
+            let summary = lines.entry(key.clone()).or_insert(TaxSummaryLine {
+                currency: order.currency.clone(),
+                jurisdiction: tax.jurisdiction.clone(),
+                category: tax.category.clone(),
+                rate_basis_points: tax.rate_basis_points,
+                inclusive: tax.inclusive,
+                order_count: 0,
+                taxable_amount: 0,
+                tax_amount: 0,
+            });
+            if !counted.contains(&key) {
+                summary.order_count += 1;
+                counted.push(key);
+            }
+            let line_total = line.line_total.unwrap_or_default();
+            let taxable = if tax.inclusive {
+                line_total - tax.amount
+            } else {
+                line_total
+            };
+            // Refunds are of the price paid, which includes exclusive tax
+            let paid = if tax.inclusive {
+                line_total
+            } else {
+                line_total.saturating_add(tax.amount)
+            };
+            let refunded = refunds.get(&(order.id, line.accessory_id)).copied().unwrap_or_default();
+            summary.taxable_amount = summary
+                .taxable_amount
+                .saturating_add(_net_of_refund(taxable, paid, refunded));
+            summary.tax_amount = summary
+                .tax_amount
+                .saturating_add(_net_of_refund(tax.amount, paid, refunded));
+        }
+    }
+    TaxSummary {
+        from,
+        to,
+        lines: lines.into_values().collect(),
+    }
+}
+
+// Share of `amount` left after refunding `refunded` out of the `paid` price it is part of
+fn _net_of_refund(amount: u64, paid: u64, refunded: u64) -> u64 {
+    if paid == 0 {
+        return amount;
+    }
+    let kept = paid.saturating_sub(refunded);
+    divide_rounded(
+        u128::from(amount) * u128::from(kept),
+        u128::from(paid),
+        RoundingMode::HalfUp,
+    ) as u64
+}
+
+// Work out the tax of each order line for the jurisdiction, on the line total after discounts.
+// Lines without a matching rule are not taxed.
+pub(crate) fn apply_taxes(jurisdiction: &str, lines: &mut [OrderLine]) {
+    for line in lines.iter_mut() {
+        let category = _get_accessory(&line.accessory_id).map(|accessory| accessory.category);
+        let rule = category
+            .as_deref()
+            .and_then(|category| _find_rule(jurisdiction, Some(category)))
+            .or_else(|| _find_rule(jurisdiction, None));
+        line.tax = rule.map(|rule| {
+            let line_total = line.line_total.unwrap_or_default() as u128;
+            let rate = rule.rate_basis_points as u128;
+            // Inclusive prices contain the tax: tax = total * rate / (1 + rate)
+            let amount = if rule.inclusive {
+                divide_rounded(line_total * rate, 10_000 + rate, RoundingMode::HalfUp)
+            } else {
+                divide_rounded(line_total * rate, 10_000, RoundingMode::HalfUp)
+            };
+            LineTax {
+                rule_id: rule.id,
+                jurisdiction: rule.jurisdiction,
+                category: rule.category,
+                rate_basis_points: rule.rate_basis_points,
+                inclusive: rule.inclusive,
+                amount: amount as u64,
+            }
+        });
+    }
+}
+
+// Jurisdiction an order is taxed in: the buyer's, else the default one. Once there are
+// tax rules, an order can't leave it out when no default is set.
+pub(crate) fn order_jurisdiction(requested: Option<String>) -> Result<Option<String>, Error> {
+    let jurisdiction = match requested {
+        Some(code) => Some(normalize_jurisdiction(&code)?),
+        None => TAX_CONFIG.with(|cell| cell.borrow().get().default_jurisdiction.clone()),
+    };
+    if jurisdiction.is_none() && !TAX_RULE_STORAGE.with(|service| service.borrow().is_empty()) {
+        return Err(Error::ValidationFailed {
+            msg: "a jurisdiction is required to tax the order".to_string(),
+        });
+    }
+    Ok(jurisdiction)
+}
+
+// Jurisdiction codes are case-insensitive and stored in upper case
+pub(crate) fn normalize_jurisdiction(code: &str) -> Result<String, Error> {
+    let code = code.trim().to_ascii_uppercase();
+    if code.len() < 2
+        || code.len() > MAX_JURISDICTION_LENGTH
+        || !code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err(Error::ValidationFailed {
+            msg: format!(
+                "a jurisdiction must be 2 to {} letters, digits or '-'",
+                MAX_JURISDICTION_LENGTH
+            ),
+        });
+    }
+    Ok(code)
+}
+
+fn _find_rule(jurisdiction: &str, category: Option<&str>) -> Option<TaxRule> {
+    // Longer values can't have a rule
+    if jurisdiction.len() > MAX_JURISDICTION_LENGTH
+        || category.is_some_and(|category| category.len() > MAX_CATEGORY_LENGTH)
+    {
+        return None;
+    }
+    let id = TAX_RULE_INDEX.with(|index| index.borrow().get(&_rule_key(jurisdiction, category)))?;
+    TAX_RULE_STORAGE.with(|service| service.borrow().get(&id))
+}
+
+fn _rule_key(jurisdiction: &str, category: Option<&str>) -> RuleKey {
+    (
+        StringKey(jurisdiction.to_string()),
+        StringKey(category.unwrap_or_default().to_string()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refunds_reduce_tax_in_proportion() {
+        assert_eq!(_net_of_refund(190, 1_190, 0), 190);
+        assert_eq!(_net_of_refund(190, 1_190, 595), 95);
+        assert_eq!(_net_of_refund(1_000, 1_190, 1_190), 0);
+        assert_eq!(_net_of_refund(190, 1_190, 5_000), 0);
+        assert_eq!(_net_of_refund(u64::MAX, u64::MAX, 1), u64::MAX - 1);
+    }
+
+    #[test]
+    fn lines_without_a_price_keep_their_tax() {
+        assert_eq!(_net_of_refund(0, 0, 10), 0);
+    }
+}
@@ -0,0 +1,286 @@
+// Two-step transfers of listing ownership between seller principals
+use crate::audit::{self, AuditAction};
+use crate::{
+    _check_if_seller, _get_accessory, do_insert_accessory, next_id, orders, promotions, purchase_orders, rate_limit,
+    reorder, roles, scorecards, sellers, stocktakes, Error, Memory, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Define which listings a transfer covers
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum TransferScope {
+    Accessory { id: u64 },
+    // Every listing the seller has when the transfer is accepted
+    AllListings,
+}
+
+// Define the lifecycle of an ownership transfer
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum TransferStatus {
+    Pending,
+    Accepted,
+    Cancelled,
+}
+
+// Define the structure representing an ownership transfer
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct OwnershipTransfer {
+    id: u64,
+    scope: TransferScope,
+    from: Principal,
+    to: Principal,
+    // The seller or an admin
+    proposed_by: Principal,
+    status: TransferStatus,
+    created_at: u64,
+    resolved_at: Option<u64>,
+    // Number of listings that changed hands on acceptance
+    transferred: u64,
+}
+
+// Implement the Storable trait for OwnershipTransfer
+impl Storable for OwnershipTransfer {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement the BoundedStorable trait for OwnershipTransfer
+impl BoundedStorable for OwnershipTransfer {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static TRANSFER_STORAGE: RefCell<StableBTreeMap<u64, OwnershipTransfer, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(43))))
+    );
+}
+
+// Update function to propose handing one listing to another principal (seller or admin only).
+// The listing moves once the new principal accepts.
+#[ic_cdk::update]
+fn propose_listing_transfer(accessory_id: u64, to: Principal) -> Result<OwnershipTransfer, Error> {
+    rate_limit::_check_rate_limit("propose_listing_transfer")?;
+    let accessory = _get_accessory(&accessory_id).ok_or_else(|| Error::NotFound {
+        msg: format!("an accessory with id={} not found", accessory_id),
+    })?;
+    if !roles::_is_admin(&caller()) {
+        _check_if_seller(&accessory)?;
+    }
+    _propose(TransferScope::Accessory { id: accessory_id }, accessory.seller, to)
+}
+
+// Update function to propose handing all listings of a seller to another principal
+// (the seller or an admin only). The listings move once the new principal accepts.
+#[ic_cdk::update]
+fn propose_seller_transfer(from: Principal, to: Principal) -> Result<OwnershipTransfer, Error> {
+    rate_limit::_check_rate_limit("propose_seller_transfer")?;
+    if from != caller() && !roles::_is_admin(&caller()) {
+        return Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} can't transfer the listings of seller {}", caller(), from),
+        });
+    }
+    if sellers::listing_ids(&from).is_empty() {
+        return Err(Error::ValidationFailed {
+            msg: format!("seller {} has no listings", from),
+        });
+    }
+    _propose(TransferScope::AllListings, from, to)
+}
+
+// Update function for the new principal to accept a transfer; the listings are theirs afterwards
+#[ic_cdk::update]
+fn accept_ownership_transfer(id: u64) -> Result<OwnershipTransfer, Error> {
+    rate_limit::_check_rate_limit("accept_ownership_transfer")?;
+    let mut transfer = _get_pending_transfer(id)?;
+    if transfer.to != caller() {
+        return Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} isn't the recipient of transfer with id={}", caller(), id),
+        });
+    }
+    sellers::_check_can_sell(&transfer.to)?;
+    let from = transfer.from;
+    let accessory_ids = match transfer.scope {
+        TransferScope::Accessory { id: accessory_id } => {
+            let accessory = _get_accessory(&accessory_id).ok_or_else(|| Error::NotFound {
+                msg: format!("an accessory with id={} not found", accessory_id),
+            })?;
+            if accessory.seller != from {
+                return Err(Error::ValidationFailed {
+                    msg: format!(
+                        "accessory with id={} no longer belongs to seller {}",
+                        accessory_id, from
+                    ),
+                });
+            }
+            vec![accessory_id]
+        }
+        TransferScope::AllListings => sellers::listing_ids(&transfer.from),
+    };
+    _check_no_open_records(&transfer, &accessory_ids)?;
+    let now = time();
+    let to = transfer.to;
+    for accessory_id in &accessory_ids {
+        let Some(mut accessory) = _get_accessory(accessory_id) else {
+            continue;
+        };
+        sellers::unindex_listing(&accessory);
+        accessory.seller = to;
+        accessory.updated_at = Some(now);
+        do_insert_accessory(&mut accessory);
+        sellers::index_listing(&accessory);
+        audit::record(
+            AuditAction::OwnerChanged,
+            Some(accessory.id),
+            format!("seller {} -> {} by transfer #{}", from, to, id),
+        );
+    }
+    reorder::reassign_open_alerts(&accessory_ids, to);
+    if transfer.scope == TransferScope::AllListings {
+        scorecards::merge_stats(from, to);
+    }
+    transfer.status = TransferStatus::Accepted;
+    transfer.resolved_at = Some(now);
+    transfer.transferred = accessory_ids.len() as u64;
+    do_insert_transfer(&transfer);
+    audit::record(
+        AuditAction::TransferAccepted,
+        _scope_accessory(&transfer),
+        format!("transfer #{} accepted, {} listings moved", id, transfer.transferred),
+    );
+    Ok(transfer)
+}
+
+// Update function to withdraw or decline a pending transfer (either party or an admin)
+#[ic_cdk::update]
+fn cancel_ownership_transfer(id: u64) -> Result<OwnershipTransfer, Error> {
+    rate_limit::_check_rate_limit("cancel_ownership_transfer")?;
+    let mut transfer = _get_pending_transfer(id)?;
+    let caller = caller();
+    if ![transfer.from, transfer.to, transfer.proposed_by].contains(&caller) && !roles::_is_admin(&caller) {
+        return Err(Error::AuthenticationFailed {
+            msg: format!("Caller={} isn't a party to transfer with id={}", caller, id),
+        });
+    }
+    transfer.status = TransferStatus::Cancelled;
+    transfer.resolved_at = Some(time());
+    do_insert_transfer(&transfer);
+    audit::record(
+        AuditAction::TransferCancelled,
+        _scope_accessory(&transfer),
+        format!("transfer #{} cancelled", id),
+    );
+    Ok(transfer)
+}
+
+// Query function to get the transfers the caller is a party to; staff see all transfers
+#[ic_cdk::query]
+fn get_ownership_transfers(status: Option<TransferStatus>) -> Vec<OwnershipTransfer> {
+    let caller = caller();
+    let see_all = roles::_is_staff(&caller);
+    TRANSFER_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, transfer)| transfer)
+            .filter(|transfer| see_all || [transfer.from, transfer.to, transfer.proposed_by].contains(&caller))
+            .filter(|transfer| status.is_none_or(|status| transfer.status == status))
+            .collect()
+    })
+}
+
+fn _propose(scope: TransferScope, from: Principal, to: Principal) -> Result<OwnershipTransfer, Error> {
+    if to == from || to == Principal::anonymous() {
+        return Err(Error::ValidationFailed {
+            msg: format!("listings can't be transferred to {}", to),
+        });
+    }
+    let pending = TRANSFER_STORAGE.with(|service| {
+        service.borrow().iter().map(|(_, transfer)| transfer).find(|transfer| {
+            transfer.status == TransferStatus::Pending && transfer.scope == scope && transfer.from == from
+        })
+    });
+    if let Some(pending) = pending {
+        return Err(Error::ValidationFailed {
+            msg: format!("transfer with id={} is already pending for these listings", pending.id),
+        });
+    }
+    let transfer = OwnershipTransfer {
+        id: next_id(),
+        scope,
+        from,
+        to,
+        proposed_by: caller(),
+        status: TransferStatus::Pending,
+        created_at: time(),
+        resolved_at: None,
+        transferred: 0,
+    };
+    do_insert_transfer(&transfer);
+    audit::record(
+        AuditAction::TransferProposed,
+        _scope_accessory(&transfer),
+        format!("transfer #{} proposed from {} to {}", transfer.id, from, to),
+    );
+    Ok(transfer)
+}
+
+// Records of the old seller that name the listings can't follow them to the new one,
+// so a transfer waits until they are closed
+fn _check_no_open_records(transfer: &OwnershipTransfer, accessory_ids: &[u64]) -> Result<(), Error> {
+    let from = transfer.from;
+    let open = if orders::has_unshipped_orders(from, accessory_ids) {
+        Some("orders that haven't shipped")
+    } else if purchase_orders::has_open_orders(from, accessory_ids) {
+        Some("open purchase orders")
+    } else if stocktakes::has_open_stocktake(from, accessory_ids) {
+        Some("an open stocktake")
+    } else if transfer.scope == TransferScope::AllListings && promotions::has_active_coupons(from) {
+        Some("active coupons")
+    } else {
+        None
+    };
+    match open {
+        Some(records) => Err(Error::ValidationFailed {
+            msg: format!(
+                "seller {} has {} for the transferred listings; close them first",
+                from, records
+            ),
+        }),
+        None => Ok(()),
+    }
+}
+
+fn _get_pending_transfer(id: u64) -> Result<OwnershipTransfer, Error> {
+    let transfer = TRANSFER_STORAGE
+        .with(|service| service.borrow().get(&id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("a transfer with id={} not found", id),
+        })?;
+    if transfer.status != TransferStatus::Pending {
+        return Err(Error::ValidationFailed {
+            msg: format!("transfer with id={} is no longer pending", id),
+        });
+    }
+    Ok(transfer)
+}
+
+fn _scope_accessory(transfer: &OwnershipTransfer) -> Option<u64> {
+    match transfer.scope {
+        TransferScope::Accessory { id } => Some(id),
+        TransferScope::AllListings => None,
+    }
+}
+
+fn do_insert_transfer(transfer: &OwnershipTransfer) {
+    TRANSFER_STORAGE.with(|service| service.borrow_mut().insert(transfer.id, transfer.clone()));
+}
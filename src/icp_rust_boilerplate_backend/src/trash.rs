@@ -0,0 +1,166 @@
+// The trash: deleted accessories can be restored until their retention period ends
+use crate::audit::{self, AuditAction};
+use crate::{
+    _check_if_seller_or_staff, _review_ids, barcodes, batches, costing, do_insert_accessory, rate_limit, reorder,
+    roles, sellers, serials, suppliers, Accessory, Error, Memory, ACCESSORY_STORAGE, MEMORY_MANAGER, REVIEW_INDEX,
+    REVIEW_STORAGE,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+// Longest accessories can be kept in the trash
+const MAX_RETENTION_DAYS: u64 = 365;
+// Accessories purged per scheduler run, to stay within the instruction limit
+const PURGE_BATCH_SIZE: usize = 100;
+
+// Define the trash settings
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct TrashConfig {
+    // Days a deleted accessory is kept before it's purged
+    retention_days: u64,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        TrashConfig { retention_days: 30 }
+    }
+}
+
+// Implement the Storable trait for TrashConfig
+impl Storable for TrashConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static TRASH_CONFIG: RefCell<Cell<TrashConfig, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(48))), TrashConfig::default())
+            .expect("Cannot create the trash config cell")
+    );
+}
+
+// Update function to take an accessory out of the trash (seller or staff only)
+#[ic_cdk::update]
+fn restore_accessory(id: u64) -> Result<Accessory, Error> {
+    rate_limit::_check_rate_limit("restore_accessory")?;
+    let mut accessory = _get_deleted_accessory(id)?;
+    _check_if_seller_or_staff(&accessory)?;
+    accessory.deleted_at = None;
+    accessory.deleted_by = None;
+    accessory.updated_at = Some(time());
+    do_insert_accessory(&mut accessory);
+    sellers::index_listing(&accessory);
+    audit::record(
+        AuditAction::AccessoryRestored,
+        Some(id),
+        "restored from the trash".to_string(),
+    );
+    Ok(accessory)
+}
+
+// Query function to get the caller's accessories in the trash, oldest deletion first.
+// Staff see the trash of every seller.
+#[ic_cdk::query]
+fn list_trash() -> Vec<Accessory> {
+    let caller = caller();
+    let see_all = roles::_is_staff(&caller);
+    let mut trash: Vec<Accessory> = ACCESSORY_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, accessory)| accessory)
+            .filter(|accessory| accessory.deleted_at.is_some() && (see_all || accessory.seller == caller))
+            .collect()
+    });
+    trash.sort_by_key(|accessory| accessory.deleted_at);
+    trash
+}
+
+// Update function to set how many days deleted accessories are kept (admin only)
+#[ic_cdk::update]
+fn set_trash_retention(retention_days: u64) -> Result<(), Error> {
+    roles::_check_if_admin()?;
+    if retention_days == 0 || retention_days > MAX_RETENTION_DAYS {
+        return Err(Error::ValidationFailed {
+            msg: format!("the retention period must be between 1 and {} days", MAX_RETENTION_DAYS),
+        });
+    }
+    TRASH_CONFIG
+        .with(|cell| cell.borrow_mut().set(TrashConfig { retention_days }))
+        .expect("cannot update the trash config");
+    Ok(())
+}
+
+// Query function to get the trash settings
+#[ic_cdk::query]
+fn get_trash_config() -> TrashConfig {
+    TRASH_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+// Remove accessories whose retention period ended, with their reviews, serials, batches,
+// cost lots, supplier links and index entries. Run periodically by the scheduler.
+pub(crate) fn purge_trash(now: u64) {
+    let retention = TRASH_CONFIG
+        .with(|cell| cell.borrow().get().retention_days)
+        .saturating_mul(NANOS_PER_DAY);
+    let expired: Vec<Accessory> = ACCESSORY_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, accessory)| accessory)
+            .filter(|accessory| {
+                accessory
+                    .deleted_at
+                    .is_some_and(|deleted_at| deleted_at.saturating_add(retention) <= now)
+            })
+            .take(PURGE_BATCH_SIZE)
+            .collect()
+    });
+    for accessory in expired {
+        ACCESSORY_STORAGE.with(|service| service.borrow_mut().remove(&accessory.id));
+        barcodes::update_barcode_index(&accessory.barcode, &None, accessory.id);
+        sellers::unindex_listing(&accessory);
+        let reviews = _review_ids(accessory.id);
+        REVIEW_STORAGE.with(|service| {
+            let mut service = service.borrow_mut();
+            for id in &reviews {
+                service.remove(id);
+            }
+        });
+        REVIEW_INDEX.with(|index| {
+            let mut index = index.borrow_mut();
+            for id in &reviews {
+                index.remove(&(accessory.id, *id));
+            }
+        });
+        serials::purge_accessory(accessory.id);
+        batches::purge_accessory(accessory.id);
+        costing::purge_accessory(accessory.id);
+        suppliers::purge_accessory(accessory.id);
+        reorder::resolve_open_alert(accessory.id, now);
+        audit::record_by(
+            ic_cdk::id(),
+            AuditAction::AccessoryPurged,
+            Some(accessory.id),
+            format!("purged from the trash with {} reviews", reviews.len()),
+        );
+    }
+}
+
+fn _get_deleted_accessory(id: u64) -> Result<Accessory, Error> {
+    ACCESSORY_STORAGE
+        .with(|service| service.borrow().get(&id))
+        .filter(|accessory| accessory.deleted_at.is_some())
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("an accessory with id={} not found in the trash", id),
+        })
+}